@@ -0,0 +1,716 @@
+//! This mod holds the step debugger behind `--tui`, the terminal UI drawing it is in [crate::tui].
+//! The debugger steps the VM one instruction at a time with [Vm::step], recording the state before
+//! every step so that it can be stepped backwards, and follows calls and returns to keep a call stack.
+use core::{Page, Registers, PAGE_SIZE};
+use emulator_sdk::{
+    compressed::is_compressed,
+    csr::CsrFile,
+    disassembler::{Disassembler, REGISTER_NAMES},
+    gdb::{WatchKind, Watchpoint, WatchpointObserver},
+    htif::Htif,
+    instructions::{DecodedInstruction, InstructionDecoder, JALR_CLASS, JAL_CLASS},
+    observer::ExecutionObserver,
+    syscalls::SyscallHandler,
+    trace::{AccessKind, MemoryAccess},
+    vm::Vm,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::Arc,
+};
+
+/// The number of steps that can be stepped backwards
+pub const HISTORY_LEN: usize = 4096;
+/// The number of instructions run between two checks for an interrupt from the user
+const INTERRUPT_CHECK_INTERVAL: u64 = 0x10000;
+/// The ecall instruction, the only one changing the state of the syscall handler
+const ECALL: u32 = 0x0000_0073;
+/// The most bytes decoded to find the instructions before the pc
+const MAX_LISTING_WALK: u32 = 0x1000;
+
+/// This is a call the program has not returned from yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// The pc of the call instruction
+    pub call_site: u32,
+    /// The function called
+    pub target: u32,
+    pub return_addr: u32,
+}
+
+/// This is how a step changed the memory.
+#[derive(Debug, Clone)]
+enum MemoryUndo {
+    /// The writes of the step in order, undone in reverse with their old values
+    Writes(Vec<MemoryAccess>),
+    /// Every page before an ecall, since syscalls may also map and unmap memory. The pages are
+    /// shared with the VM and only copied when the VM writes to them
+    Pages(BTreeMap<u32, Arc<Page>>),
+}
+
+/// This observer logs the memory writes of the current step.
+#[derive(Debug, Clone, Default)]
+struct WriteLog {
+    writes: Vec<MemoryAccess>,
+}
+
+impl ExecutionObserver for WriteLog {
+    fn on_memory_access(&mut self, access: &MemoryAccess) {
+        if access.kind == AccessKind::Write {
+            self.writes.push(access.clone());
+        }
+    }
+
+    fn box_clone(&self) -> Box<dyn ExecutionObserver> {
+        Box::new(self.clone())
+    }
+}
+
+/// This is the state before a step, restored when stepping backwards.
+#[derive(Debug, Clone)]
+struct HistoryEntry {
+    pc: u32,
+    registers: Registers,
+    csrs: CsrFile,
+    reservation: Option<u32>,
+    exit_code: u32,
+    memory: MemoryUndo,
+    /// Only saved before an ecall
+    syscall_handler: Option<Box<dyn SyscallHandler>>,
+    /// Only saved before an instruction that may store, the store may be a command to the host
+    htif: Option<Htif>,
+    call_stack: Vec<Frame>,
+}
+
+/// Why the debugger stopped running the program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    /// The requested steps were done
+    Stepped,
+    Breakpoint(u32),
+    /// A watched location was accessed at this address
+    Watchpoint(u32),
+    /// The program can not run any further, with the reason
+    Halted(String),
+    /// The user interrupted the program
+    Interrupted,
+}
+
+/// This is the step debugger.
+#[derive(Debug, Clone)]
+pub struct Debugger {
+    pub vm: Vm,
+    pub breakpoints: BTreeSet<u32>,
+    pub call_stack: Vec<Frame>,
+    /// The address the memory view starts at
+    pub memory_view: u32,
+    /// Why the program can not run any further, if it can not
+    pub halted: Option<String>,
+    /// Disassembles the code around the pc, with the symbols of the program if it has them
+    pub disassembler: Disassembler,
+    history: VecDeque<HistoryEntry>,
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new(mut vm: Vm) -> Self {
+        vm.add_observer(WriteLog::default());
+        Self {
+            memory_view: vm.pc,
+            vm,
+            breakpoints: BTreeSet::new(),
+            call_stack: Vec::new(),
+            halted: None,
+            disassembler: Disassembler::new(),
+            history: VecDeque::new(),
+            last_command: String::new(),
+        }
+    }
+
+    /// Returns the byte at `addr`, regardless of the memory permissions.
+    pub fn read_byte(&self, addr: u32) -> u8 {
+        self.vm
+            .memory
+            .pages
+            .get(&(addr / PAGE_SIZE))
+            .map_or(0, |page| page[(addr % PAGE_SIZE) as usize])
+    }
+
+    /// Write the byte at `addr` of an allocated page, regardless of the memory permissions.
+    fn write_byte(&mut self, addr: u32, value: u8) {
+        if let Some(page) = self.vm.memory.pages.get_mut(&(addr / PAGE_SIZE)) {
+            Arc::make_mut(page)[(addr % PAGE_SIZE) as usize] = value;
+        }
+    }
+
+    /// Returns the instruction at `addr` and its size in bytes.
+    pub fn instruction(&self, addr: u32) -> (u32, u32) {
+        let half = |addr: u32| {
+            self.read_byte(addr) as u32 | (self.read_byte(addr.wrapping_add(1)) as u32) << 8
+        };
+        let low = half(addr);
+        if is_compressed(low) {
+            (low, 2)
+        } else {
+            (low | half(addr.wrapping_add(2)) << 16, 4)
+        }
+    }
+
+    /// Returns where a listing showing up to `context` instructions before `pc` starts.
+    /// With compressed instructions the bytes before `pc` may decode to anything, so they are
+    /// decoded forwards from a known instruction: the start of the function holding `pc` or an
+    /// instruction the program ran. Without one the listing starts at `pc`.
+    pub fn listing_start(&self, pc: u32, context: usize) -> u32 {
+        let mut known: Vec<u32> = self
+            .disassembler
+            .symbol_start(pc)
+            .into_iter()
+            .chain(self.history.iter().map(|entry| entry.pc))
+            .filter(|start| *start <= pc && pc - start <= MAX_LISTING_WALK)
+            .collect();
+        known.sort_unstable();
+        known.dedup();
+
+        for start in known {
+            let mut boundaries = Vec::new();
+            let mut addr = start;
+            while (start..pc).contains(&addr) {
+                boundaries.push(addr);
+                addr = addr.wrapping_add(self.instruction(addr).1);
+            }
+            // Data between the two addresses makes the decoding skip over the pc
+            if addr == pc {
+                return boundaries
+                    .get(boundaries.len().saturating_sub(context))
+                    .copied()
+                    .unwrap_or(pc);
+            }
+        }
+        pc
+    }
+
+    /// Returns the registers as they were before the last step, if it can be stepped back.
+    pub fn previous_registers(&self) -> Option<&Registers> {
+        self.history.back().map(|entry| &entry.registers)
+    }
+
+    /// Execute a command, returning the message to show.
+    /// An empty command repeats the last one, `interrupted` is polled while the program runs.
+    pub fn execute(&mut self, command: &str, interrupted: &mut dyn FnMut() -> bool) -> String {
+        let command = if command.trim().is_empty() {
+            self.last_command.clone()
+        } else {
+            command.trim().to_string()
+        };
+        self.last_command.clone_from(&command);
+
+        let mut words = command.split_whitespace();
+        let Some(name) = words.next() else {
+            return String::new();
+        };
+        let args: Vec<&str> = words.collect();
+
+        let result = match name {
+            "s" | "step" => self.count(&args).map(|count| self.step(count, interrupted)),
+            "n" | "next" => self.count(&args).map(|count| self.next(count, interrupted)),
+            "c" | "continue" => Ok(self.resume(None, interrupted)),
+            "rs" | "reverse-step" => self.count(&args).map(|count| self.reverse_step(count)),
+            "b" | "break" => self.address(&args).map(|addr| {
+                self.breakpoints.insert(addr);
+                format!("Breakpoint at {:08x}", addr)
+            }),
+            "d" | "delete" => self.address(&args).map(|addr| {
+                if self.breakpoints.remove(&addr) {
+                    format!("Deleted the breakpoint at {:08x}", addr)
+                } else {
+                    format!("No breakpoint at {:08x}", addr)
+                }
+            }),
+            "w" | "watch" => self.watch(&args),
+            "x" | "examine" => self.address(&args).map(|addr| {
+                self.memory_view = addr;
+                format!("Examining {:08x}", addr)
+            }),
+            "h" | "help" => Ok(String::from(
+                "step [n], next [n], continue, reverse-step [n], break <addr>, delete <addr>, \
+                 watch <addr> [len], examine <addr>, quit",
+            )),
+            _ => Err(format!("Unknown command `{}`, try `help`", name)),
+        };
+
+        result.unwrap_or_else(|message| message)
+    }
+
+    /// Parse an optional repeat count
+    fn count(&self, args: &[&str]) -> Result<u64, String> {
+        match args.first() {
+            None => Ok(1),
+            Some(count) => count
+                .parse()
+                .map_err(|_| format!("Invalid count `{}`", count)),
+        }
+    }
+
+    /// Parse an address, given in hex or as a register holding it
+    fn address(&self, args: &[&str]) -> Result<u32, String> {
+        let Some(arg) = args.first() else {
+            return Err(String::from("Missing address"));
+        };
+        self.value(arg)
+            .ok_or_else(|| format!("Invalid address `{}`", arg))
+    }
+
+    /// Returns the value of `arg`: `pc`, a register name or a hex number
+    pub fn value(&self, arg: &str) -> Option<u32> {
+        if arg == "pc" {
+            return Some(self.vm.pc);
+        }
+        let register = REGISTER_NAMES
+            .iter()
+            .position(|name| *name == arg)
+            .or_else(|| arg.strip_prefix('x')?.parse().ok().filter(|n| *n < 32))
+            .or((arg == "fp").then_some(8));
+        if let Some(register) = register {
+            return Some(self.vm.registers.read_reg(register as u32));
+        }
+
+        u32::from_str_radix(arg.trim_start_matches("0x"), 16).ok()
+    }
+
+    fn watch(&mut self, args: &[&str]) -> Result<String, String> {
+        let addr = self.address(args)?;
+        let len = match args.get(1) {
+            None => 4,
+            Some(len) => self
+                .value(len)
+                .ok_or_else(|| format!("Invalid length `{}`", len))?,
+        };
+
+        if self.vm.observer::<WatchpointObserver>().is_none() {
+            self.vm.add_observer(WatchpointObserver::default());
+        }
+        let observer = self.vm.observer_mut::<WatchpointObserver>().unwrap();
+        observer.watchpoints.push(Watchpoint {
+            addr,
+            len,
+            kind: WatchKind::Write,
+        });
+        Ok(format!("Watching {} bytes at {:08x}", len, addr))
+    }
+
+    fn step(&mut self, count: u64, interrupted: &mut dyn FnMut() -> bool) -> String {
+        for i in 0..count {
+            if i > 0 && i.is_multiple_of(INTERRUPT_CHECK_INTERVAL) && interrupted() {
+                return describe(&Stop::Interrupted);
+            }
+            match self.step_one() {
+                Stop::Stepped => {}
+                stop => return describe(&stop),
+            }
+        }
+        String::new()
+    }
+
+    /// Step over calls
+    fn next(&mut self, count: u64, interrupted: &mut dyn FnMut() -> bool) -> String {
+        for _ in 0..count {
+            let (instruction, size) = self.instruction(self.vm.pc);
+            let stop = if call(instruction).is_some() {
+                let depth = self.call_stack.len();
+                self.resume(Some((self.vm.pc.wrapping_add(size), depth)), interrupted)
+            } else {
+                describe(&self.step_one())
+            };
+            if !stop.is_empty() {
+                return stop;
+            }
+        }
+        String::new()
+    }
+
+    fn reverse_step(&mut self, count: u64) -> String {
+        for i in 0..count {
+            let Some(entry) = self.history.pop_back() else {
+                return format!(
+                    "Stepped back {} instructions, there is no history before this one",
+                    i
+                );
+            };
+            self.vm.pc = entry.pc;
+            self.vm.registers = entry.registers;
+            self.vm.csrs = entry.csrs;
+            self.vm.reservation = entry.reservation;
+            self.vm.exit_code = entry.exit_code;
+            match entry.memory {
+                MemoryUndo::Writes(writes) => {
+                    for write in writes.iter().rev() {
+                        let bytes = write.old_value.to_le_bytes();
+                        for (i, byte) in bytes.iter().take(write.size as usize).enumerate() {
+                            self.write_byte(write.addr.wrapping_add(i as u32), *byte);
+                        }
+                    }
+                }
+                MemoryUndo::Pages(pages) => self.vm.memory.pages = pages,
+            }
+            if let Some(handler) = entry.syscall_handler {
+                self.vm.syscall_handler = handler;
+            }
+            if let Some(htif) = entry.htif {
+                self.vm.htif = Some(htif);
+            }
+            self.call_stack = entry.call_stack;
+        }
+
+        self.vm.running = false;
+        self.halted = None;
+        String::new()
+    }
+
+    /// Run until a breakpoint, a watchpoint, the end of the program or an interrupt.
+    /// With `until`, also stop once the pc reaches the address with the call stack at the depth.
+    fn resume(
+        &mut self,
+        until: Option<(u32, usize)>,
+        interrupted: &mut dyn FnMut() -> bool,
+    ) -> String {
+        let mut executed = 0u64;
+        loop {
+            let stop = self.step_one();
+            executed += 1;
+            if stop != Stop::Stepped {
+                return describe(&stop);
+            }
+            if until == Some((self.vm.pc, self.call_stack.len())) {
+                return String::new();
+            }
+            if self.breakpoints.contains(&self.vm.pc) {
+                return describe(&Stop::Breakpoint(self.vm.pc));
+            }
+            if executed.is_multiple_of(INTERRUPT_CHECK_INTERVAL) && interrupted() {
+                return describe(&Stop::Interrupted);
+            }
+        }
+    }
+
+    /// Execute the instruction at the pc, recording the state before it.
+    pub fn step_one(&mut self) -> Stop {
+        if let Some(reason) = &self.halted {
+            return Stop::Halted(reason.clone());
+        }
+
+        let pc = self.vm.pc;
+        let (instruction, size) = self.instruction(pc);
+        let pages = (instruction == ECALL).then(|| self.vm.memory.pages.clone());
+        let syscall_handler = (instruction == ECALL).then(|| self.vm.syscall_handler.clone());
+        let htif = self.vm.htif.clone().filter(|_| may_store(instruction));
+        let mut entry = HistoryEntry {
+            pc,
+            registers: self.vm.registers.clone(),
+            csrs: self.vm.csrs.clone(),
+            reservation: self.vm.reservation,
+            exit_code: self.vm.exit_code,
+            memory: MemoryUndo::Writes(Vec::new()),
+            syscall_handler,
+            htif,
+            call_stack: self.call_stack.clone(),
+        };
+
+        let result = self.vm.step();
+        let writes = self
+            .vm
+            .observer_mut::<WriteLog>()
+            .map(|log| std::mem::take(&mut log.writes))
+            .unwrap_or_default();
+        entry.memory = pages.map_or(MemoryUndo::Writes(writes), MemoryUndo::Pages);
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(entry);
+        let stop = match result {
+            Ok(true) => {
+                match call(instruction) {
+                    Some(true) => self.call_stack.push(Frame {
+                        call_site: pc,
+                        target: self.vm.pc,
+                        return_addr: pc.wrapping_add(size),
+                    }),
+                    Some(false) => {
+                        self.call_stack.pop();
+                    }
+                    None => {}
+                }
+                Stop::Stepped
+            }
+            // Only ebreak halts without moving the pc
+            Ok(false) if self.vm.pc == pc => Stop::Halted(format!("ebreak at {:08x}", pc)),
+            Ok(false) => Stop::Halted(format!(
+                "The program exited with code {}",
+                self.vm.exit_code
+            )),
+            Err(error) => Stop::Halted(format!("Fault at {:08x}: {:?}", pc, error)),
+        };
+        if let Stop::Halted(reason) = &stop {
+            self.halted = Some(reason.clone());
+        }
+
+        let hit = self
+            .vm
+            .observer_mut::<WatchpointObserver>()
+            .and_then(|observer| observer.hit.take());
+        match hit {
+            Some((_, addr)) if stop == Stop::Stepped => Stop::Watchpoint(addr),
+            _ => stop,
+        }
+    }
+}
+
+/// Returns `Some(true)` for calls, `Some(false)` for returns and `None` for other instructions.
+/// Calls link to ra or t0, returns jump back through them.
+fn call(instruction: u32) -> Option<bool> {
+    let decoded = InstructionDecoder::decode(&instruction).ok()?;
+    let link = |register: usize| register == 1 || register == 5;
+
+    match (decoded.opcode, &decoded.decoded_instruction) {
+        (JAL_CLASS, DecodedInstruction::JType(j)) if link(j.rd) => Some(true),
+        (JALR_CLASS, DecodedInstruction::IType(i)) if link(i.rd) => Some(true),
+        (JALR_CLASS, DecodedInstruction::IType(i)) if i.rd == 0 && link(i.rs1) => Some(false),
+        _ => None,
+    }
+}
+
+/// Returns whether `instruction` may write to memory: a store, an AMO or an ecall.
+fn may_store(instruction: u32) -> bool {
+    if is_compressed(instruction) {
+        // c.sw and c.swsp
+        matches!(
+            (instruction & 0b11, instruction >> 13 & 0b111),
+            (0b00, 0b110) | (0b10, 0b110)
+        )
+    } else {
+        matches!(instruction & 0x7f, 0b0100011 | 0b0101111 | 0b1110011)
+    }
+}
+
+fn describe(stop: &Stop) -> String {
+    match stop {
+        Stop::Stepped => String::new(),
+        Stop::Breakpoint(pc) => format!("Breakpoint at {:08x}", pc),
+        Stop::Watchpoint(addr) => format!("Watchpoint hit, {:08x} was written", addr),
+        Stop::Halted(reason) => reason.clone(),
+        Stop::Interrupted => String::from("Interrupted"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emulator_sdk::asm::{addi, jal, lui, sw, EBREAK, RET};
+    use emulator_sdk::syscalls::{DefaultSyscallHandler, SYS_EXIT, SYS_WRITE};
+    use std::any::Any;
+
+    fn debugger(program: Vec<u32>) -> Debugger {
+        let mut vm = Vm::from_bin(program).unwrap();
+        vm.set_syscall_handler(DefaultSyscallHandler::default());
+        Debugger::new(vm)
+    }
+
+    fn execute(debugger: &mut Debugger, command: &str) -> String {
+        debugger.execute(command, &mut || false)
+    }
+
+    fn stdout(debugger: &Debugger) -> Vec<u8> {
+        (debugger.vm.syscall_handler.as_ref() as &dyn Any)
+            .downcast_ref::<DefaultSyscallHandler>()
+            .unwrap()
+            .stdout
+            .clone()
+    }
+
+    /// main calls f twice, f adds 1 to a0 and stores it to 0x100
+    fn calls() -> Vec<u32> {
+        vec![
+            jal(1, 16), // call f
+            jal(1, 12), // call f
+            addi(17, 0, SYS_EXIT as i32),
+            ECALL,
+            // f:
+            addi(10, 10, 1),
+            sw(0, 10, 0x100),
+            RET,
+        ]
+    }
+
+    #[test]
+    fn test_step_and_reverse_step() {
+        let mut debugger = debugger(calls());
+
+        assert_eq!(execute(&mut debugger, "step 3"), "");
+        assert_eq!(debugger.vm.pc, 24);
+        assert_eq!(debugger.vm.registers.read_reg(10), 1);
+        assert_eq!(debugger.read_byte(0x100), 1);
+        assert_eq!(debugger.call_stack.len(), 1);
+
+        assert_eq!(execute(&mut debugger, "rs"), "");
+        assert_eq!(debugger.vm.pc, 20);
+        assert_eq!(debugger.read_byte(0x100), 0);
+        // An empty command repeats the last one
+        assert_eq!(execute(&mut debugger, ""), "");
+        assert_eq!(debugger.vm.pc, 16);
+        assert_eq!(execute(&mut debugger, "rs"), "");
+        assert_eq!(debugger.vm.pc, 0);
+        assert_eq!(debugger.vm.registers.read_reg(1), 0);
+        assert!(debugger.call_stack.is_empty());
+        assert_eq!(debugger.vm.csrs.instret, 0);
+        assert!(execute(&mut debugger, "rs").contains("no history"));
+    }
+
+    #[test]
+    fn test_history_holds_only_the_writes() {
+        let mut debugger = debugger(calls());
+
+        assert_eq!(execute(&mut debugger, "step 3"), "");
+        let Some(HistoryEntry {
+            memory: MemoryUndo::Writes(writes),
+            ..
+        }) = debugger.history.back()
+        else {
+            panic!("The store did not log its write");
+        };
+        assert_eq!(writes.len(), 1);
+        assert_eq!((writes[0].addr, writes[0].old_value), (0x100, 0));
+        assert!(matches!(
+            &debugger.history[0].memory,
+            MemoryUndo::Writes(writes) if writes.is_empty()
+        ));
+    }
+
+    #[test]
+    fn test_next_steps_over_calls() {
+        let mut debugger = debugger(calls());
+
+        assert_eq!(execute(&mut debugger, "next"), "");
+        assert_eq!(debugger.vm.pc, 4);
+        assert_eq!(debugger.vm.registers.read_reg(10), 1);
+        assert_eq!(execute(&mut debugger, "n 2"), "");
+        assert_eq!(debugger.vm.pc, 12);
+        assert_eq!(
+            execute(&mut debugger, "n"),
+            "The program exited with code 2"
+        );
+        assert_eq!(
+            execute(&mut debugger, "step"),
+            "The program exited with code 2"
+        );
+        assert_eq!(debugger.vm.pc, 16);
+
+        // Stepping back resumes the program
+        assert_eq!(execute(&mut debugger, "rs 2"), "");
+        assert_eq!(debugger.vm.pc, 8);
+        assert_eq!(debugger.halted, None);
+    }
+
+    #[test]
+    fn test_breakpoints_and_call_stack() {
+        let mut debugger = debugger(calls());
+
+        assert_eq!(execute(&mut debugger, "break 14"), "Breakpoint at 00000014");
+        assert_eq!(execute(&mut debugger, "c"), "Breakpoint at 00000014");
+        assert_eq!(
+            debugger.call_stack,
+            [Frame {
+                call_site: 0,
+                target: 16,
+                return_addr: 4
+            }]
+        );
+        assert_eq!(execute(&mut debugger, "c"), "Breakpoint at 00000014");
+        assert_eq!(debugger.call_stack[0].call_site, 4);
+        assert_eq!(
+            execute(&mut debugger, "delete 14"),
+            "Deleted the breakpoint at 00000014"
+        );
+        assert_eq!(
+            execute(&mut debugger, "c"),
+            "The program exited with code 2"
+        );
+    }
+
+    #[test]
+    fn test_watch_and_examine() {
+        let mut debugger = debugger(calls());
+
+        assert_eq!(
+            execute(&mut debugger, "watch 0x100"),
+            "Watching 4 bytes at 00000100"
+        );
+        assert_eq!(
+            execute(&mut debugger, "continue"),
+            "Watchpoint hit, 00000100 was written"
+        );
+        assert_eq!(debugger.vm.pc, 24);
+        assert_eq!(execute(&mut debugger, "x sp"), "Examining 00000000");
+        assert_eq!(execute(&mut debugger, "x ra"), "Examining 00000004");
+        assert_eq!(debugger.memory_view, 4);
+        assert_eq!(execute(&mut debugger, "x"), "Missing address");
+        assert_eq!(execute(&mut debugger, "x zz"), "Invalid address `zz`");
+        assert!(execute(&mut debugger, "frobnicate").starts_with("Unknown command"));
+    }
+
+    #[test]
+    fn test_reverse_step_undoes_host_commands() {
+        // Write the console command for 'A' to tohost at 0x200
+        let mut debugger = debugger(vec![
+            addi(5, 0, 0x200),
+            addi(6, 0, 0x41),
+            lui(7, 0x01010),
+            sw(5, 6, 0),
+            sw(5, 7, 4),
+            EBREAK,
+        ]);
+        debugger.vm.htif = Some(Htif::new(0x200, None));
+
+        assert_eq!(execute(&mut debugger, "c"), "ebreak at 00000014");
+        assert_eq!(debugger.vm.htif.as_ref().unwrap().console, b"A");
+        assert_eq!(execute(&mut debugger, "rs 2"), "");
+        assert!(debugger.vm.htif.as_ref().unwrap().console.is_empty());
+        assert_eq!(execute(&mut debugger, "s"), "");
+        assert_eq!(debugger.vm.htif.as_ref().unwrap().console, b"A");
+    }
+
+    #[test]
+    fn test_listing_starts_on_an_instruction() {
+        // A compressed instruction before the pc, c.addi a0, 1 next to c.nop
+        let mut debugger = debugger(vec![addi(10, 0, 1), 0x0001_0505, addi(10, 10, 1), EBREAK]);
+
+        assert_eq!(execute(&mut debugger, "s 3"), "");
+        assert_eq!(debugger.vm.pc, 8);
+        assert_eq!(debugger.listing_start(8, 2), 4);
+        assert_eq!(debugger.listing_start(8, 10), 0);
+        // Nothing is known before this address
+        assert_eq!(debugger.listing_start(0x2000, 2), 0x2000);
+        debugger.disassembler.add_symbol(0x1ff0, 0, "f");
+        assert_eq!(debugger.listing_start(0x2000, 2), 0x1ffc);
+    }
+
+    #[test]
+    fn test_reverse_step_undoes_syscalls() {
+        let mut debugger = debugger(vec![
+            addi(10, 0, 1),
+            addi(11, 0, 0x100),
+            addi(12, 0, 2),
+            addi(17, 0, SYS_WRITE as i32),
+            ECALL,
+            EBREAK,
+        ]);
+        debugger.vm.memory.load_bytes(0x100, b"hi");
+
+        assert_eq!(execute(&mut debugger, "c"), "ebreak at 00000014");
+        assert_eq!(stdout(&debugger), b"hi");
+        assert_eq!(execute(&mut debugger, "rs 2"), "");
+        assert!(stdout(&debugger).is_empty());
+        assert_eq!(debugger.vm.registers.read_reg(10), 1);
+        assert_eq!(execute(&mut debugger, "s"), "");
+        assert_eq!(stdout(&debugger), b"hi");
+        assert_eq!(debugger.vm.registers.read_reg(10), 2);
+    }
+}
@@ -1,24 +1,276 @@
-use clap::Parser;
-use emulator_sdk::{instructions, vm::Vm};
-use std::path::PathBuf;
+mod debugger;
+mod tui;
+
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
+use debugger::Debugger;
+use elf_parser::Elf;
+use emulator_sdk::{
+    disassembler::Disassembler,
+    gdb::GdbStub,
+    linux::LinuxSyscallHandler,
+    observer::LoggingObserver,
+    signature::SignatureRange,
+    sp1::Sp1SyscallHandler,
+    syscalls::DefaultSyscallHandler,
+    vm::{RunConfig, RunOutcome, Vm},
+};
+use std::{
+    any::Any,
+    io::Write,
+    net::TcpListener,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 /// CLI tool for processing RISC-V ELF binaries
 #[derive(Parser)]
 #[command(
     name = "riscv-elf-emulator",
     version = "1.0",
-    about = "RISC-V IM32 Emulator running any corresponding ELF binary"
+    about = "RISC-V IM32 Emulator running any corresponding ELF binary",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Path to the RISC-V ELF binary
-    path: PathBuf,
+    #[arg(required = true)]
+    path: Option<PathBuf>,
+    /// Write the signature of an architectural test to this file once it stops, in RISCOF format
+    #[arg(long, conflicts_with_all = ["gdb", "tui"])]
+    signature: Option<PathBuf>,
+    /// Wait for a debugger on this localhost port and let it drive the VM (GDB remote protocol)
+    #[arg(long)]
+    gdb: Option<u16>,
+    /// Debug the program in the terminal, stepping it forwards and backwards
+    #[arg(long)]
+    tui: bool,
+    /// Log every executed instruction, with its register writes and memory accesses, to stderr
+    #[arg(long)]
+    log: bool,
+    /// Run a statically linked Linux program, servicing its syscalls like Linux would
+    #[arg(long)]
+    linux: bool,
+    /// Run an SP1 zkVM program, servicing its syscalls like the SP1 executor would. Its
+    /// precompiles are not supported
+    #[arg(long, conflicts_with = "linux")]
+    sp1: bool,
+    /// Give an SP1 program the content of this host file as its next input, in the order of the
+    /// flags
+    #[arg(long, requires = "sp1", value_name = "PATH", value_parser = parse_hint)]
+    hint: Vec<Vec<u8>>,
+    /// Add a NAME=value variable to the environment of a Linux program
+    #[arg(long, requires = "linux")]
+    env: Vec<String>,
+    /// Seed the random numbers a Linux program gets from getrandom
+    #[arg(long, requires = "linux", default_value_t = 0)]
+    seed: u64,
+    /// Let a Linux program read the host directory HOST at the path GUEST, as in `--map-dir
+    /// fixtures:/data`. The directory is read-only
+    #[arg(long, requires = "linux", value_name = "HOST:GUEST", value_parser = parse_map_dir)]
+    map_dir: Vec<(PathBuf, String)>,
+    /// Give a Linux program an in-memory file at the path NAME, holding a copy of the host file
+    /// PATH. The program may write to it, the host file is left as it is
+    #[arg(long, requires = "linux", value_name = "NAME=PATH", value_parser = parse_file)]
+    file: Vec<(String, Vec<u8>)>,
+    /// The arguments of a Linux program, after `--`
+    #[arg(last = true, requires = "linux")]
+    args: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Disassemble the code of a RISC-V ELF binary instead of running it
+    Disasm {
+        /// Path to the RISC-V ELF binary
+        path: PathBuf,
+    },
 }
 
 fn main() {
     let args = Cli::parse();
-    let mut vm =
-        Vm::from_bin_elf(args.path.to_str().unwrap().to_string()).expect("Failed to init VM");
-    vm.run(true);
+    if let Some(Command::Disasm { path }) = &args.command {
+        let elf = read_elf(path);
+        let listing = Disassembler::from_elf(&elf).disassemble_elf(&elf);
+        // Piping the listing into `head` closes stdout early, that is not an error
+        let _ = std::io::stdout().write_all(listing.as_bytes());
+        return;
+    }
+
+    let path = args.path.expect("clap requires the path");
+    let elf = read_elf(&path);
+    let disassembler = Disassembler::from_elf(&elf);
+    let mut vm = if args.linux {
+        let mut handler = LinuxSyscallHandler::with_seed(args.seed);
+        handler.realtime_start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+        for (host, guest) in &args.map_dir {
+            handler
+                .vfs
+                .map_dir(host, guest)
+                .expect("clap checks the mapped directory");
+        }
+        for (name, data) in args.file {
+            handler.vfs.add_file(&name, data);
+        }
+        let mut argv = vec![path.display().to_string()];
+        argv.extend(args.args.iter().cloned());
+        Vm::from_linux_elf(&elf, &argv, &args.env, handler)
+    } else if args.sp1 {
+        Vm::from_sp1_elf(&elf, args.hint)
+    } else {
+        Vm::from_elf(&elf)
+    };
+    let signature_range = args.signature.as_ref().map(|_| {
+        SignatureRange::from_elf(&elf).unwrap_or_else(|| {
+            Cli::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    "the program has no begin_signature and end_signature symbols",
+                )
+                .exit()
+        })
+    });
+    if args.log {
+        vm.add_observer(LoggingObserver {
+            registers: true,
+            memory: true,
+            disassembler: disassembler.clone(),
+        });
+    }
+    if args.tui {
+        // Guest output would garble the screen, the debugger shows it instead
+        if args.linux {
+            let handler = vm.syscall_handler.as_mut() as &mut dyn Any;
+            if let Some(handler) = handler.downcast_mut::<LinuxSyscallHandler>() {
+                handler.echo = false;
+            }
+        } else if args.sp1 {
+            let handler = vm.syscall_handler.as_mut() as &mut dyn Any;
+            if let Some(handler) = handler.downcast_mut::<Sp1SyscallHandler>() {
+                handler.echo = false;
+            }
+        } else {
+            vm.set_syscall_handler(DefaultSyscallHandler::default());
+        }
+        let mut debugger = Debugger::new(vm);
+        debugger.disassembler = disassembler;
+        let code = tui::run(debugger).expect("Failed to run the debugger");
+        std::process::exit(code);
+    }
+    if let Some(port) = args.gdb {
+        std::process::exit(debug(vm, port));
+    }
+    let outcome = vm.run(RunConfig {
+        jit: cfg!(feature = "jit"),
+        ..RunConfig::default()
+    });
+
+    if let (Some(path), Some(range)) = (args.signature, signature_range) {
+        let signature = vm.dump_signature(range).expect("Failed to read signature");
+        std::fs::write(path, signature).expect("Failed to write signature");
+    }
+
+    if let Some(htif) = &vm.htif {
+        let _ = std::io::stdout().write_all(&htif.console);
+    }
+
+    let code = match outcome {
+        RunOutcome::Exited(code) => {
+            if let Some(Err(test)) = vm.htif.as_ref().and_then(|htif| htif.test_result()) {
+                eprintln!("Test {} failed", test);
+            }
+            code as i32
+        }
+        RunOutcome::Faulted { pc, error } => {
+            eprintln!(
+                "Error at pc: {:x}{} - error: {:?}",
+                pc,
+                locate(&elf, pc),
+                error
+            );
+            1
+        }
+        RunOutcome::BudgetExhausted => {
+            eprintln!(
+                "Instruction budget exhausted at pc: {:x}{}",
+                vm.pc,
+                locate(&elf, vm.pc)
+            );
+            1
+        }
+        RunOutcome::Breakpoint(pc) => {
+            eprintln!("Halted on ebreak at pc: {:x}{}", pc, locate(&elf, pc));
+            vm.exit_code as i32
+        }
+        RunOutcome::InfiniteLoop(pc) => {
+            eprintln!("Infinite loop at pc: {:x}{}", pc, locate(&elf, pc));
+            1
+        }
+    };
+    std::process::exit(code);
+}
+
+/// Parses `--map-dir host:guest`, where `host` is an existing directory.
+fn parse_map_dir(value: &str) -> Result<(PathBuf, String), String> {
+    let (host, guest) = value
+        .split_once(':')
+        .ok_or("expected HOST:GUEST, as in fixtures:/data")?;
+    if guest.is_empty() {
+        return Err("the guest path is empty".to_string());
+    }
+    let host = PathBuf::from(host);
+    if !host.is_dir() {
+        return Err(format!("{} is not a directory", host.display()));
+    }
+
+    Ok((host, guest.to_string()))
+}
+
+/// Parses `--file name=path` into the name and the contents of the host file at `path`.
+fn parse_file(value: &str) -> Result<(String, Vec<u8>), String> {
+    let (name, path) = value
+        .split_once('=')
+        .ok_or("expected NAME=PATH, as in /input.txt=input.txt")?;
+    if name.is_empty() {
+        return Err("the guest path is empty".to_string());
+    }
+    let data = std::fs::read(path).map_err(|e| format!("can not read {}: {}", path, e))?;
+
+    Ok((name.to_string(), data))
+}
+
+/// Reads the host file of `--hint path`.
+fn parse_hint(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("can not read {}: {}", path, e))
+}
+
+fn read_elf(path: &PathBuf) -> Elf {
+    let data = std::fs::read(path).expect("Failed to read ELF");
+    Elf::decode(&data).expect("Failed to decode ELF")
+}
+
+/// Returns where `pc` is in the program, as in ` in fibonacci::main+0x1c (src/main.rs:27)`
+fn locate(elf: &Elf, pc: u32) -> String {
+    elf.symbolize(pc)
+        .map(|location| format!(" in {}", location))
+        .unwrap_or_default()
+}
+
+/// Serve a debugger on `port` and return the exit code of the guest
+fn debug(vm: Vm, port: u16) -> i32 {
+    let listener = TcpListener::bind(("127.0.0.1", port)).expect("Failed to bind the gdb port");
+    eprintln!("Waiting for gdb on 127.0.0.1:{}", port);
+    let (mut stream, _) = listener.accept().expect("Failed to accept gdb");
+    let _ = stream.set_nodelay(true);
+
+    let mut stub = GdbStub::new(vm);
+    if let Err(e) = stub.serve(&mut stream) {
+        eprintln!("gdb connection lost: {}", e);
+    }
+    stub.vm.exit_code as i32
 }
 
 // fn main() {
@@ -26,3 +278,50 @@ fn main() {
 //     let mut vm = Vm::from_bin(instructions).unwrap();
 //     vm.run(true);
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_map_dir() {
+        assert_eq!(
+            parse_map_dir("src:/data"),
+            Ok((PathBuf::from("src"), "/data".to_string()))
+        );
+        assert!(parse_map_dir("src").is_err());
+        assert!(parse_map_dir("src:").is_err());
+        assert!(parse_map_dir("missing:/data").is_err());
+        assert!(parse_map_dir("Cargo.toml:/data").is_err());
+    }
+
+    #[test]
+    fn test_parse_file() {
+        let data = std::fs::read("Cargo.toml").unwrap();
+        assert_eq!(
+            parse_file("/in.toml=Cargo.toml"),
+            Ok(("/in.toml".to_string(), data))
+        );
+        assert!(parse_file("Cargo.toml").is_err());
+        assert!(parse_file("=Cargo.toml").is_err());
+        assert!(parse_file("/in.toml=missing").is_err());
+    }
+
+    #[test]
+    fn test_signature_is_only_dumped_by_a_plain_run() {
+        for debugger in ["--gdb=1234", "--tui"] {
+            let result = Cli::try_parse_from(["emulator", "--signature=out", debugger, "elf"]);
+            assert_eq!(result.err().unwrap().kind(), ErrorKind::ArgumentConflict);
+        }
+    }
+
+    #[test]
+    fn test_bad_values_are_usage_errors() {
+        let result = Cli::try_parse_from(["emulator", "--linux", "--map-dir", "src", "elf"]);
+        assert!(result.is_err());
+        let result = Cli::try_parse_from(["emulator", "--sp1", "--hint", "missing", "elf"]);
+        assert!(result.is_err());
+        let result = Cli::try_parse_from(["emulator", "--sp1", "--linux", "elf"]);
+        assert_eq!(result.err().unwrap().kind(), ErrorKind::ArgumentConflict);
+    }
+}
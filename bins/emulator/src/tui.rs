@@ -0,0 +1,270 @@
+//! This mod holds the terminal UI of the step debugger, see [crate::debugger].
+//! Commands are typed at the bottom of the screen, Enter on an empty line repeats the last one and
+//! Esc interrupts a running program.
+use crate::debugger::Debugger;
+use emulator_sdk::{
+    disassembler::REGISTER_NAMES, linux::LinuxSyscallHandler, sp1::Sp1SyscallHandler,
+    syscalls::DefaultSyscallHandler,
+};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Paragraph},
+    DefaultTerminal, Frame,
+};
+use std::{any::Any, io, time::Duration};
+
+/// The number of instructions shown before the pc
+const DISASSEMBLY_CONTEXT: usize = 6;
+
+/// Run the debugger until the user quits, returning the exit code of the guest.
+pub fn run(debugger: Debugger) -> io::Result<i32> {
+    let mut terminal = ratatui::init();
+    let mut app = App {
+        debugger,
+        input: String::new(),
+        message: String::from("Type `help` for the commands, `quit` to exit"),
+    };
+    let result = app.event_loop(&mut terminal);
+    ratatui::restore();
+
+    result.map(|()| app.debugger.vm.exit_code as i32)
+}
+
+struct App {
+    debugger: Debugger,
+    input: String,
+    message: String,
+}
+
+impl App {
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(())
+                }
+                KeyCode::Char(c) => self.input.push(c),
+                KeyCode::Backspace => {
+                    self.input.pop();
+                }
+                KeyCode::Esc => self.input.clear(),
+                KeyCode::Enter => {
+                    let command = std::mem::take(&mut self.input);
+                    if matches!(command.trim(), "q" | "quit") {
+                        return Ok(());
+                    }
+                    self.message = self.debugger.execute(&command, &mut interrupted);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, output, command] = Layout::vertical([
+            Constraint::Min(10),
+            Constraint::Length(6),
+            Constraint::Length(3),
+        ])
+        .areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Min(40), Constraint::Length(40)]).areas(main);
+        let [disassembly, memory] =
+            Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(left);
+        let [registers, call_stack] =
+            Layout::vertical([Constraint::Length(19), Constraint::Min(3)]).areas(right);
+
+        frame.render_widget(self.disassembly(disassembly), disassembly);
+        frame.render_widget(self.memory(memory), memory);
+        frame.render_widget(self.registers(), registers);
+        frame.render_widget(self.call_stack(), call_stack);
+        frame.render_widget(self.output(output), output);
+
+        let status = match &self.debugger.halted {
+            Some(reason) => format!(" {} ", reason),
+            None => String::from(" Stopped "),
+        };
+        frame.render_widget(
+            Paragraph::new(vec![
+                Line::from(vec![
+                    Span::from("> ").bold(),
+                    Span::from(self.input.as_str()),
+                ]),
+                Line::from(self.message.as_str()).dim(),
+            ])
+            .block(Block::bordered().title(status)),
+            command,
+        );
+        frame.set_cursor_position((command.x + 3 + self.input.len() as u16, command.y + 1));
+    }
+
+    fn disassembly(&self, area: Rect) -> Paragraph<'_> {
+        let debugger = &self.debugger;
+        let pc = debugger.vm.pc;
+        let mut addr = debugger.listing_start(pc, DISASSEMBLY_CONTEXT);
+        let mut lines = Vec::new();
+
+        for _ in 0..area.height.saturating_sub(2) {
+            if let Some(name) = debugger.disassembler.symbol_at(addr) {
+                lines.push(Line::from(format!("{}:", name)).bold());
+            }
+            let (instruction, size) = debugger.instruction(addr);
+            let text = debugger
+                .disassembler
+                .disassemble(addr, instruction)
+                .unwrap_or_else(|_| String::from("<invalid>"));
+            let marker = match (addr == pc, debugger.breakpoints.contains(&addr)) {
+                (true, _) => "=>",
+                (false, true) => " *",
+                (false, false) => "  ",
+            };
+            let encoding = if size == 2 {
+                format!("    {:04x}", instruction)
+            } else {
+                format!("{:08x}", instruction)
+            };
+            let line = Line::from(format!("{} {:08x}: {}  {}", marker, addr, encoding, text));
+            lines.push(if addr == pc {
+                line.style(Style::new().add_modifier(Modifier::REVERSED))
+            } else if debugger.breakpoints.contains(&addr) {
+                line.fg(Color::Red)
+            } else {
+                line
+            });
+            addr = addr.wrapping_add(size);
+        }
+
+        Paragraph::new(lines).block(Block::bordered().title(" Disassembly "))
+    }
+
+    fn memory(&self, area: Rect) -> Paragraph<'_> {
+        let start = self.debugger.memory_view & !0xf;
+        let lines: Vec<Line> = (0..area.height.saturating_sub(2) as u32)
+            .map(|row| {
+                let addr = start.wrapping_add(16 * row);
+                let bytes: Vec<u8> = (0..16)
+                    .map(|i| self.debugger.read_byte(addr.wrapping_add(i)))
+                    .collect();
+                let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                let ascii: String = bytes
+                    .iter()
+                    .map(|byte| {
+                        if byte.is_ascii_graphic() {
+                            *byte as char
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect();
+                Line::from(format!("{:08x}  {}  {}", addr, hex.join(" "), ascii))
+            })
+            .collect();
+
+        Paragraph::new(lines).block(Block::bordered().title(" Memory "))
+    }
+
+    fn registers(&self) -> Paragraph<'_> {
+        let vm = &self.debugger.vm;
+        let previous = self.debugger.previous_registers();
+        let register = |number: u32| {
+            let value = vm.registers.read_reg(number);
+            let span = Span::from(format!(
+                "{:>4} {:08x}",
+                REGISTER_NAMES[number as usize], value
+            ));
+            // Highlight what the last step changed
+            if previous.is_some_and(|previous| previous.read_reg(number) != value) {
+                span.fg(Color::Yellow)
+            } else {
+                span
+            }
+        };
+
+        let mut lines: Vec<Line> = (0..16)
+            .map(|row| Line::from(vec![register(row), Span::from("   "), register(row + 16)]))
+            .collect();
+        lines.push(Line::from(format!(
+            "  pc {:08x}   instret {}",
+            vm.pc, vm.csrs.instret
+        )));
+
+        Paragraph::new(lines).block(Block::bordered().title(" Registers "))
+    }
+
+    fn call_stack(&self) -> Paragraph<'_> {
+        let debugger = &self.debugger;
+        let function = |addr: u32| {
+            debugger
+                .disassembler
+                .symbolize(addr)
+                .unwrap_or_else(|| format!("{:08x}", addr))
+        };
+        let mut lines = vec![Line::from(format!(
+            "#0 {:08x} in {}",
+            debugger.vm.pc,
+            function(debugger.vm.pc)
+        ))];
+        for (depth, frame) in debugger.call_stack.iter().rev().enumerate() {
+            lines.push(Line::from(format!(
+                "#{} {:08x} in {}",
+                depth + 1,
+                frame.return_addr,
+                function(frame.return_addr)
+            )));
+        }
+
+        Paragraph::new(lines).block(Block::bordered().title(" Call stack "))
+    }
+
+    fn output(&self, area: Rect) -> Paragraph<'_> {
+        let handler = self.debugger.vm.syscall_handler.as_ref() as &dyn Any;
+        let stdout = if let Some(handler) = handler.downcast_ref::<DefaultSyscallHandler>() {
+            Some(&handler.stdout)
+        } else if let Some(handler) = handler.downcast_ref::<LinuxSyscallHandler>() {
+            Some(&handler.stdout)
+        } else {
+            handler
+                .downcast_ref::<Sp1SyscallHandler>()
+                .map(|handler| &handler.stdout)
+        };
+        let text = stdout
+            .map(|stdout| String::from_utf8_lossy(stdout).into_owned())
+            .unwrap_or_default();
+        let lines: Vec<Line> = text
+            .lines()
+            .map(|line| Line::from(line.to_string()))
+            .collect();
+        let scroll = lines
+            .len()
+            .saturating_sub(area.height.saturating_sub(2) as usize);
+
+        Paragraph::new(lines)
+            .scroll((scroll as u16, 0))
+            .block(Block::bordered().title(" Output "))
+    }
+}
+
+/// Returns whether the user pressed Esc or Ctrl-C while the program runs
+fn interrupted() -> bool {
+    while event::poll(Duration::ZERO).unwrap_or(false) {
+        if let Ok(Event::Key(key)) = event::read() {
+            let ctrl_c =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if key.code == KeyCode::Esc || ctrl_c {
+                return true;
+            }
+        }
+    }
+    false
+}
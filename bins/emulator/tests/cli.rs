@@ -0,0 +1,68 @@
+//! Runs the bundled guests through the emulator binary, the way a user would.
+use std::process::{Command, Output};
+
+const GUESTS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../integration-testing");
+
+fn emulator(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_emulator"))
+        .args(args)
+        .output()
+        .expect("Failed to run the emulator")
+}
+
+#[test]
+fn test_sp1_programs_need_the_sp1_personality() {
+    let fibonacci = format!("{GUESTS}/rust-elfs/fibonacci");
+
+    // fibonacci halts through the SP1 HALT syscall, number 0 in t0
+    let output = emulator(&[&fibonacci]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("syscall_halt+0x2ec - error: InvalidSyscall"));
+
+    let output = emulator(&["--sp1", &fibonacci]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn test_sp1_programs_read_their_hints() {
+    let plonk_verify = format!("{GUESTS}/plonk_verify");
+
+    // Without a proof to read the verifier stops at its first read
+    let output = emulator(&["--sp1", &plonk_verify]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("in syscall_hint_read"));
+
+    // A placeholder proof is read and rejected, the real one is not bundled
+    let dir = std::env::temp_dir().join(format!("emulator-hints-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut vkey_hash = 2u64.to_le_bytes().to_vec();
+    vkey_hash.extend_from_slice(b"00");
+    let hints = [vec![0; 4], Vec::new(), vkey_hash];
+    let mut args = vec!["--sp1".to_string()];
+    for (i, hint) in hints.iter().enumerate() {
+        let path = dir.join(i.to_string());
+        std::fs::write(&path, hint).unwrap();
+        args.push(format!("--hint={}", path.display()));
+    }
+    args.push(plonk_verify);
+    let output = emulator(&args.iter().map(String::as_str).collect::<Vec<_>>());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("PlonkVkeyHashMismatch"));
+}
+
+#[test]
+fn test_linux_programs_run_under_the_linux_personality() {
+    let linux = format!("{GUESTS}/rust-elfs/linux");
+
+    let output = emulator(&["--linux", "--env", "HOME=/root", &linux, "--", "world"]);
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    assert_eq!(output.stdout, b"hello, world\nHOME=/root");
+
+    // Without it the first ecall, the writev of the greeting, is not a syscall the VM knows
+    let output = emulator(&[&linux]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("InvalidSyscall(66)"));
+}
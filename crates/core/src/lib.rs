@@ -1,4 +1,5 @@
 use interfaces::MemoryInterface;
+use std::{collections::BTreeMap, sync::Arc};
 pub mod interfaces;
 
 /// This is the size of a word in bytes for this vm
@@ -9,117 +10,111 @@ pub const MAXIMUM_MEMORY_SIZE: u32 = u32::MAX;
 pub const HALF_WORD: usize = 2;
 /// This is the size of a byte in the VM
 pub const BYTE: usize = 1;
+/// This is the size of a memory page in bytes
+pub const PAGE_SIZE: u32 = 4096;
+
+/// A fixed-size page of memory
+pub type Page = [u8; PAGE_SIZE as usize];
 
 /// This defines the different chuck of memory that can be read or written to
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MemoryChuckSize {
     BYTE,
     HalfWord,
     WordSize,
 }
 
+/// This defines what can be done with a region of memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+/// This is a contiguous region of memory with the same permissions, e.g. an ELF segment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub start: u32,
+    pub size: u32,
+    pub permissions: Permissions,
+}
+
+/// Sparse, paged and byte-addressable memory covering the whole 32-bit address space.
+/// Like RISC-V, half words and words are stored little-endian.
+/// Pages are allocated on the first write to them, reading an untouched page returns zeros.
+/// Pages are shared between clones and only copied when one of the clones writes to them.
 #[derive(Debug, Clone)]
 pub struct Memory {
-    pub memory: Vec<u32>,
+    /// The allocated pages, indexed by page number (address / PAGE_SIZE)
+    pub pages: BTreeMap<u32, Arc<Page>>,
+    /// The regions with restricted permissions, addresses outside of them are fully accessible
+    pub regions: Vec<MemoryRegion>,
+    /// When false the regions are tracked but not enforced
+    pub enforce_permissions: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Registers {
     data: [u32; 32],
 }
 
 impl MemoryInterface for Memory {
     fn read_mem(&self, addr: u32, size: MemoryChuckSize) -> Option<u32> {
-        // Calculate vector index for the word
-        let word_addr = addr >> 2;
-
-        // Get the offset within the word (0-3)
-        let byte_offset = addr & 0x3;
+        if !self.is_allowed(addr, chunk_len(&size), |p| p.read) {
+            return None;
+        }
 
-        // Read the word from memory
-        let word = self.memory.get(word_addr as usize).copied()?;
+        // Get the page holding the address and the offset within it
+        let (page, offset) = self.page(addr);
 
         match size {
-            MemoryChuckSize::BYTE => {
-                // For big-endian, we need to read from the opposite end of the word
-                let shift = (3 - byte_offset) * 8;
-                Some((word >> shift) & 0xFF)
-            }
+            MemoryChuckSize::BYTE => Some(page.map(|p| p[offset]).unwrap_or(0) as u32),
             MemoryChuckSize::HalfWord => {
-                if byte_offset % 2 != 0 {
+                if !addr.is_multiple_of(2) {
                     panic!("Half-word reads must be aligned to half-word boundaries");
                 }
-                let shift = (2 - byte_offset) * 8;
-                Some((word >> shift) & 0xFFFF)
+                // The least significant byte is stored at the lowest address
+                let bytes = page.map(|p| [p[offset], p[offset + 1]]).unwrap_or([0; 2]);
+                Some(u16::from_le_bytes(bytes) as u32)
             }
             MemoryChuckSize::WordSize => {
-                if byte_offset != 0 {
+                if !addr.is_multiple_of(4) {
                     panic!("Word reads must be aligned to word boundaries");
                 }
-                Some(word)
+                let bytes = page
+                    .map(|p| [p[offset], p[offset + 1], p[offset + 2], p[offset + 3]])
+                    .unwrap_or([0; 4]);
+                Some(u32::from_le_bytes(bytes))
             }
         }
     }
 
     fn write_mem(&mut self, addr: u32, size: MemoryChuckSize, value: u32) -> bool {
-        // Calculate vector index data to update is contained in
-        let word_addr = addr >> 2;
-
-        // Get the byte offset within the word
-        let byte_offset = addr & 0x3;
-
-        // Check if the address is within bounds
-        if word_addr >= self.memory.len() as u32 {
+        if !self.is_allowed(addr, chunk_len(&size), |p| p.write) {
             return false;
         }
 
         match size {
-            MemoryChuckSize::BYTE => {
-                // Calculate shift based on big-endian byte order
-                let shift = (3 - byte_offset) * 8;
-
-                // Create a mask to clear the target byte
-                let clear_mask = !(0xFF << shift);
-
-                // Read the current word value
-                let current = self.memory[word_addr as usize];
-
-                // Clear the target byte and set the new value
-                let new_value = (current & clear_mask) | ((value & 0xFF) << shift);
-
-                // Write the updated word back to memory
-                self.memory[word_addr as usize] = new_value;
-            }
+            MemoryChuckSize::BYTE => {}
             MemoryChuckSize::HalfWord => {
-                if byte_offset % 2 != 0 {
+                if !addr.is_multiple_of(2) {
                     panic!("Half-word writes must be aligned to half-word boundaries");
                 }
-
-                // Calculate shift based on big-endian half-word order
-                let shift = (2 - byte_offset) * 8;
-
-                // Create a mask to clear the target half-word
-                let clear_mask = !(0xFFFF << shift);
-
-                // Read the current word value
-                let current = self.memory[word_addr as usize];
-
-                // Clear the target half-word and set the new value
-                let new_value = (current & clear_mask) | ((value & 0xFFFF) << shift);
-
-                // Write the updated word back to memory
-                self.memory[word_addr as usize] = new_value;
             }
             MemoryChuckSize::WordSize => {
-                if byte_offset != 0 {
+                if !addr.is_multiple_of(4) {
                     panic!("Word writes must be aligned to word boundaries");
                 }
-
-                // Write the full word directly
-                self.memory[word_addr as usize] = value;
             }
         }
 
+        // Aligned accesses never cross a page, so the bytes can be written in one go
+        let (page, offset) = self.page_mut(addr);
+        let bytes = value.to_le_bytes();
+        let len = chunk_len(&size) as usize;
+        page[offset..offset + len].copy_from_slice(&bytes[..len]);
+
         true
     }
 }
@@ -140,25 +135,121 @@ impl Registers {
 
         self.data[reg as usize] = value;
     }
+
+    /// Returns a pointer to the 32 registers, x0 first, for code generated at runtime.
+    /// Writes through it must leave x0 at zero.
+    pub fn as_mut_ptr(&mut self) -> *mut u32 {
+        self.data.as_mut_ptr()
+    }
+}
+
+impl Permissions {
+    /// Read, write and execute
+    pub const ALL: Permissions = Permissions {
+        read: true,
+        write: true,
+        execute: true,
+    };
+}
+
+impl MemoryRegion {
+    pub fn contains(&self, addr: u32) -> bool {
+        addr.wrapping_sub(self.start) < self.size
+    }
 }
 
 impl Memory {
     pub fn new() -> Self {
         Memory {
-            memory: vec![0; MAXIMUM_MEMORY_SIZE as usize],
+            pages: BTreeMap::new(),
+            regions: Vec::new(),
+            enforce_permissions: true,
+        }
+    }
+
+    /// Returns the number of pages that have been allocated
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Returns the page holding `addr`, if it has been allocated, and the offset of `addr` in it
+    fn page(&self, addr: u32) -> (Option<&Page>, usize) {
+        let page = self.pages.get(&(addr / PAGE_SIZE)).map(|page| &**page);
+
+        (page, (addr % PAGE_SIZE) as usize)
+    }
+
+    /// Returns the page holding `addr` for writing and the offset of `addr` in it,
+    /// allocating the page or copying it if it is shared with a clone
+    fn page_mut(&mut self, addr: u32) -> (&mut Page, usize) {
+        let page = self
+            .pages
+            .entry(addr / PAGE_SIZE)
+            .or_insert_with(|| Arc::new([0; PAGE_SIZE as usize]));
+
+        (Arc::make_mut(page), (addr % PAGE_SIZE) as usize)
+    }
+
+    /// Restrict the permissions of a region of memory.
+    pub fn add_region(&mut self, region: MemoryRegion) {
+        self.regions.push(region);
+    }
+
+    /// Returns the permissions of the memory at `addr`
+    pub fn permissions(&self, addr: u32) -> Permissions {
+        self.regions
+            .iter()
+            .find(|region| region.contains(addr))
+            .map(|region| region.permissions)
+            .unwrap_or(Permissions::ALL)
+    }
+
+    /// Returns true if instructions can be fetched from `addr`
+    pub fn is_executable(&self, addr: u32) -> bool {
+        self.is_allowed(addr, 1, |p| p.execute)
+    }
+
+    /// Returns true if `addr` can be written to
+    pub fn is_writable(&self, addr: u32) -> bool {
+        self.is_allowed(addr, 1, |p| p.write)
+    }
+
+    /// Returns true if `check` allows every one of the `len` bytes at `addr`.
+    fn is_allowed(&self, addr: u32, len: u32, check: impl Fn(&Permissions) -> bool) -> bool {
+        if !self.enforce_permissions {
+            return true;
+        }
+        let last = addr.wrapping_add(len - 1);
+        match self.regions.iter().find(|region| region.contains(addr)) {
+            Some(region) if region.contains(last) => check(&region.permissions),
+            // The access crosses the boundary of a region
+            _ => (0..len).all(|i| check(&self.permissions(addr.wrapping_add(i)))),
+        }
+    }
+
+    /// Load `data` starting at `addr`, ignoring permissions.
+    pub fn load_bytes(&mut self, addr: u32, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            let (page, offset) = self.page_mut(addr.wrapping_add(i as u32));
+            page[offset] = *byte;
         }
     }
 
-    pub fn load_program(&mut self, program: &Vec<u32>, base_addr: u32) {
-        let mut addr = (base_addr >> 2) as usize;
+    /// Load a memory image made of `(address, word)` pairs, ignoring permissions.
+    /// Words are stored little-endian, as the ELF parser assembled them.
+    pub fn load_image(&mut self, image: impl IntoIterator<Item = (u32, u32)>) {
+        for (addr, word) in image {
+            self.load_bytes(addr, &word.to_le_bytes());
+        }
+    }
 
-        for word in program {
-            self.memory[addr] = *word;
-            addr += 1;
+    pub fn load_program(&mut self, program: &[u32], base_addr: u32) {
+        for (i, word) in program.iter().enumerate() {
+            self.load_bytes(base_addr + (i * WORD_SIZE) as u32, &word.to_le_bytes());
         }
     }
 
-    pub fn new_with_load_program(program: &Vec<u32>, base_addr: u32) -> Self {
+    pub fn new_with_load_program(program: &[u32], base_addr: u32) -> Self {
         let mut memory = Memory::new();
         memory.load_program(program, base_addr);
 
@@ -166,6 +257,21 @@ impl Memory {
     }
 }
 
+/// Returns the number of bytes of an access of `size`.
+fn chunk_len(size: &MemoryChuckSize) -> u32 {
+    match size {
+        MemoryChuckSize::BYTE => BYTE as u32,
+        MemoryChuckSize::HalfWord => HALF_WORD as u32,
+        MemoryChuckSize::WordSize => WORD_SIZE as u32,
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub fn sign_extend_u32(x: u32) -> i64 {
     (x as i32) as i64
 }
@@ -178,31 +284,30 @@ mod tests {
     fn test_memory_read() {
         // Create a memory instance with our test values
         let mut memory = Memory::new();
-        memory.memory[0] = 147;
-        memory.memory[1] = 59772819;
+        memory.load_image([(0, 147), (4, 59772819)]);
 
-        // Test byte-by-byte reading from first word
-        assert_eq!(memory.read_mem(0, MemoryChuckSize::BYTE), Some(0));
+        // Test byte-by-byte reading from first word, least significant byte first
+        assert_eq!(memory.read_mem(0, MemoryChuckSize::BYTE), Some(147));
         assert_eq!(memory.read_mem(1, MemoryChuckSize::BYTE), Some(0));
         assert_eq!(memory.read_mem(2, MemoryChuckSize::BYTE), Some(0));
-        assert_eq!(memory.read_mem(3, MemoryChuckSize::BYTE), Some(147));
+        assert_eq!(memory.read_mem(3, MemoryChuckSize::BYTE), Some(0));
 
-        // Test byte-by-byte reading from second word
-        assert_eq!(memory.read_mem(4, MemoryChuckSize::BYTE), Some(3));
-        assert_eq!(memory.read_mem(5, MemoryChuckSize::BYTE), Some(144));
-        assert_eq!(memory.read_mem(6, MemoryChuckSize::BYTE), Some(15));
-        assert_eq!(memory.read_mem(7, MemoryChuckSize::BYTE), Some(147));
+        // Test byte-by-byte reading from second word (0x03900f93)
+        assert_eq!(memory.read_mem(4, MemoryChuckSize::BYTE), Some(147));
+        assert_eq!(memory.read_mem(5, MemoryChuckSize::BYTE), Some(15));
+        assert_eq!(memory.read_mem(6, MemoryChuckSize::BYTE), Some(144));
+        assert_eq!(memory.read_mem(7, MemoryChuckSize::BYTE), Some(3));
 
-        // Test half-word reading - update expected values
-        assert_eq!(memory.read_mem(0, MemoryChuckSize::HalfWord), Some(0));
-        assert_eq!(memory.read_mem(2, MemoryChuckSize::HalfWord), Some(147));
+        // Test half-word reading
+        assert_eq!(memory.read_mem(0, MemoryChuckSize::HalfWord), Some(147));
+        assert_eq!(memory.read_mem(2, MemoryChuckSize::HalfWord), Some(0));
         assert_eq!(
             memory.read_mem(4, MemoryChuckSize::HalfWord),
-            Some(3 * 256 + 144)
+            Some(15 * 256 + 147)
         );
         assert_eq!(
             memory.read_mem(6, MemoryChuckSize::HalfWord),
-            Some(15 * 256 + 147)
+            Some(3 * 256 + 144)
         );
 
         // Test word reading - update expected value for second word
@@ -254,17 +359,101 @@ mod tests {
 
         assert_eq!(memory.read_mem(4, MemoryChuckSize::HalfWord), Some(0x1234));
         assert_eq!(memory.read_mem(6, MemoryChuckSize::HalfWord), Some(0x5678));
+        assert_eq!(
+            memory.read_mem(4, MemoryChuckSize::WordSize),
+            Some(0x56781234)
+        );
+        assert_eq!(memory.read_mem(5, MemoryChuckSize::BYTE), Some(0x12));
+        assert_eq!(
+            memory.read_mem(0, MemoryChuckSize::WordSize),
+            Some(0xDDCCBBAA)
+        );
 
         // Test word writing and reading
         assert!(memory.write_mem(8, MemoryChuckSize::WordSize, 0x87654321));
 
-        assert_eq!(memory.read_mem(8, MemoryChuckSize::BYTE), Some(0x87));
-        assert_eq!(memory.read_mem(9, MemoryChuckSize::BYTE), Some(0x65));
-        assert_eq!(memory.read_mem(10, MemoryChuckSize::BYTE), Some(0x43));
-        assert_eq!(memory.read_mem(11, MemoryChuckSize::BYTE), Some(0x21));
+        assert_eq!(memory.read_mem(8, MemoryChuckSize::BYTE), Some(0x21));
+        assert_eq!(memory.read_mem(9, MemoryChuckSize::BYTE), Some(0x43));
+        assert_eq!(memory.read_mem(10, MemoryChuckSize::BYTE), Some(0x65));
+        assert_eq!(memory.read_mem(11, MemoryChuckSize::BYTE), Some(0x87));
         assert_eq!(
             memory.read_mem(8, MemoryChuckSize::WordSize),
             Some(0x87654321)
         );
     }
+
+    #[test]
+    fn test_region_permissions() {
+        let mut memory = Memory::new();
+        memory.add_region(MemoryRegion {
+            start: 0x1000,
+            size: 0x100,
+            permissions: Permissions {
+                read: true,
+                write: false,
+                execute: true,
+            },
+        });
+
+        assert!(!memory.write_mem(0x1000, MemoryChuckSize::WordSize, 1));
+        assert!(!memory.write_mem(0x10ff, MemoryChuckSize::BYTE, 1));
+        assert!(memory.write_mem(0x1100, MemoryChuckSize::WordSize, 1));
+        assert!(memory.is_executable(0x1000));
+        // A word is only writable when every one of its bytes is
+        memory.add_region(MemoryRegion {
+            start: 0x2002,
+            size: 0x10,
+            permissions: Permissions {
+                read: false,
+                write: false,
+                execute: false,
+            },
+        });
+        assert!(!memory.write_mem(0x2000, MemoryChuckSize::WordSize, 1));
+        assert_eq!(memory.read_mem(0x2000, MemoryChuckSize::WordSize), None);
+        assert_eq!(memory.read_mem(0x2000, MemoryChuckSize::HalfWord), Some(0));
+        assert!(!memory.write_mem(0x2010, MemoryChuckSize::WordSize, 1));
+        assert!(memory.write_mem(0x2012, MemoryChuckSize::HalfWord, 1));
+
+        memory.enforce_permissions = false;
+        assert!(memory.write_mem(0x1000, MemoryChuckSize::WordSize, 1));
+        assert_eq!(memory.read_mem(0x1000, MemoryChuckSize::WordSize), Some(1));
+    }
+
+    #[test]
+    fn test_pages_are_allocated_lazily() {
+        let mut memory = Memory::new();
+        assert_eq!(
+            memory.read_mem(0x8000_0000, MemoryChuckSize::WordSize),
+            Some(0)
+        );
+        assert_eq!(memory.page_count(), 0);
+
+        assert!(memory.write_mem(0x8000_0000, MemoryChuckSize::WordSize, 1));
+        assert!(memory.write_mem(0x8000_0ffc, MemoryChuckSize::WordSize, 2));
+        assert_eq!(memory.page_count(), 1);
+
+        assert!(memory.write_mem(0xffff_fffc, MemoryChuckSize::WordSize, 3));
+        assert_eq!(memory.page_count(), 2);
+        assert_eq!(
+            memory.read_mem(0xffff_fffc, MemoryChuckSize::WordSize),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn test_clone_is_copy_on_write() {
+        let mut memory = Memory::new();
+        memory.write_mem(0x1000, MemoryChuckSize::WordSize, 1);
+        memory.write_mem(0x2000, MemoryChuckSize::WordSize, 2);
+
+        let mut clone = memory.clone();
+        assert!(Arc::ptr_eq(&memory.pages[&1], &clone.pages[&1]));
+
+        clone.write_mem(0x1000, MemoryChuckSize::WordSize, 10);
+        assert_eq!(memory.read_mem(0x1000, MemoryChuckSize::WordSize), Some(1));
+        assert_eq!(clone.read_mem(0x1000, MemoryChuckSize::WordSize), Some(10));
+        assert!(!Arc::ptr_eq(&memory.pages[&1], &clone.pages[&1]));
+        assert!(Arc::ptr_eq(&memory.pages[&2], &clone.pages[&2]));
+    }
 }
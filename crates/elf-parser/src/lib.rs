@@ -3,12 +3,24 @@
 //! return the binary instructions and the memory image. The memory image is useful for global constant
 //! this code was copied from SP1 codebase's implementation of the ELF parser.
 //! see: [code](https://github.com/succinctlabs/sp1/blob/dev/crates/core/executor/src/disassembler/elf.rs)
+//! Besides the memory image it reads the symbol tables, the section headers and the DWARF line
+//! tables, which are what turn a bare pc into `fibonacci::main+0x1c (src/main.rs:27)`.
+mod lines;
+mod symbols;
 
-use core::{MAXIMUM_MEMORY_SIZE, WORD_SIZE};
+pub use lines::{LineRow, LineTable};
+pub use symbols::{demangle, Section, Symbol};
+
+use core::{MemoryRegion, Permissions, HALF_WORD, MAXIMUM_MEMORY_SIZE, WORD_SIZE};
 use elf::{
-    abi::{EM_RISCV, ET_EXEC, PF_X, PT_LOAD},
+    abi::{
+        EM_RISCV, ET_EXEC, PF_R, PF_W, PF_X, PT_LOAD, PT_PHDR, SHN_ABS, STT_FUNC, STT_NOTYPE,
+        STT_OBJECT,
+    },
     endian::LittleEndian,
     file::Class,
+    string_table::StringTable,
+    symbol::SymbolTable,
     ElfBytes,
 };
 use hashbrown::HashMap;
@@ -21,6 +33,8 @@ use std::cmp::min;
 ///
 /// - Base Integer Instruction Set (I)
 /// - Integer Multiplication and Division (M)
+/// - Atomic Instructions (A)
+/// - Compressed Instructions (C)
 ///
 /// This format is commonly used in embedded systems and is supported by many compilers.
 #[derive(Debug, Clone)]
@@ -33,6 +47,26 @@ pub struct Elf {
     pub pc_base: u32,
     /// The initial memory image, useful for global constants.
     pub memory_image: HashMap<u32, u32>,
+    /// The loadable segments of the program and their permissions.
+    pub segments: Vec<MemoryRegion>,
+    /// The named functions, objects and labels of the symbol tables, sorted by address.
+    pub symbols: Vec<Symbol>,
+    /// The sections of the file, in the order of the section headers.
+    pub sections: Vec<Section>,
+    /// The source lines of the instructions, if the program was built with debug information.
+    pub line_table: Option<LineTable>,
+    /// Where the program headers are in memory, if a segment loads them.
+    pub program_headers: Option<ProgramHeaders>,
+}
+
+/// The program headers of an ELF file loaded in memory, Linux passes them to the program in its
+/// auxiliary vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeaders {
+    pub addr: u32,
+    pub count: u32,
+    /// The size of a program header in bytes
+    pub entry_size: u32,
 }
 
 impl Elf {
@@ -42,12 +76,70 @@ impl Elf {
         pc_start: u32,
         pc_base: u32,
         memory_image: HashMap<u32, u32>,
+        segments: Vec<MemoryRegion>,
+        symbols: Vec<Symbol>,
     ) -> Self {
         Self {
             instructions,
             pc_start,
             pc_base,
             memory_image,
+            segments,
+            symbols,
+            sections: Vec::new(),
+            line_table: None,
+            program_headers: None,
+        }
+    }
+
+    /// Returns the symbol named `name`.
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    /// Returns the section named `name`.
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+
+    /// Returns the function or object `addr` falls in and the offset of `addr` in it.
+    /// Functions win over other symbols, and symbols of unknown size cover everything up to the
+    /// next symbol.
+    pub fn symbol_at(&self, addr: u32) -> Option<(&Symbol, u32)> {
+        let end = self.symbols.partition_point(|symbol| symbol.addr <= addr);
+        let candidates = &self.symbols[..end];
+        let symbol = candidates
+            .iter()
+            .rev()
+            .filter(|symbol| symbol.contains(addr))
+            .max_by_key(|symbol| (symbol.is_function, symbol.addr))
+            .or_else(|| candidates.last().filter(|symbol| symbol.size == 0))?;
+
+        Some((symbol, addr - symbol.addr))
+    }
+
+    /// Returns the source file and line the instruction at `addr` comes from.
+    pub fn line_at(&self, addr: u32) -> Option<(&str, u32)> {
+        self.line_table.as_ref()?.find(addr)
+    }
+
+    /// Describe `addr` with the demangled symbol it falls in and its source line, as in
+    /// `fibonacci::main+0x1c (src/main.rs:27)`. Returns `None` when nothing is known about it.
+    pub fn symbolize(&self, addr: u32) -> Option<String> {
+        let symbol = self.symbol_at(addr).map(|(symbol, offset)| {
+            if offset == 0 {
+                symbol.demangled()
+            } else {
+                format!("{}+0x{:x}", symbol.demangled(), offset)
+            }
+        });
+        let line = self.line_at(addr);
+
+        match (symbol, line) {
+            (Some(symbol), Some((file, line))) => Some(format!("{} ({}:{})", symbol, file, line)),
+            (Some(symbol), None) => Some(symbol),
+            (None, Some((file, line))) => Some(format!("{}:{}", file, line)),
+            (None, None) => None,
         }
     }
 
@@ -75,8 +167,8 @@ impl Elf {
         // Get the entrypoint of the ELF file as an u32.
         let entry: u32 = elf.ehdr.e_entry.try_into()?;
 
-        // Make sure the entrypoint is valid.
-        if entry == MAXIMUM_MEMORY_SIZE || entry % WORD_SIZE as u32 != 0 {
+        // Make sure the entrypoint is valid, compressed instructions only need it aligned to 2 bytes.
+        if entry == MAXIMUM_MEMORY_SIZE || !entry.is_multiple_of(HALF_WORD as u32) {
             anyhow::bail!("invalid entrypoint");
         }
 
@@ -88,7 +180,33 @@ impl Elf {
             anyhow::bail!("too many program headers");
         }
 
+        // The program headers are in memory when a segment loads the part of the file holding them
+        let phoff: u32 = elf.ehdr.e_phoff.try_into()?;
+        let program_headers = segments
+            .iter()
+            .find(|segment| segment.p_type == PT_PHDR)
+            .map(|segment| segment.p_vaddr)
+            .or_else(|| {
+                segments
+                    .iter()
+                    .filter(|segment| segment.p_type == PT_LOAD)
+                    .find(|segment| {
+                        (segment.p_offset..segment.p_offset + segment.p_filesz)
+                            .contains(&(phoff as u64))
+                    })
+                    .map(|segment| segment.p_vaddr + phoff as u64 - segment.p_offset)
+            })
+            .map(|addr| -> anyhow::Result<_> {
+                Ok(ProgramHeaders {
+                    addr: addr.try_into()?,
+                    count: elf.ehdr.e_phnum as u32,
+                    entry_size: elf.ehdr.e_phentsize as u32,
+                })
+            })
+            .transpose()?;
+
         let mut instructions: Vec<u32> = Vec::new();
+        let mut regions: Vec<MemoryRegion> = Vec::new();
         let mut base_address = u32::MAX;
 
         // Only read segments that are executable instructions that are also PT_LOAD.
@@ -107,7 +225,7 @@ impl Elf {
 
             // Get the virtual address of the segment as an u32.
             let vaddr: u32 = segment.p_vaddr.try_into()?;
-            if vaddr % WORD_SIZE as u32 != 0 {
+            if !vaddr.is_multiple_of(WORD_SIZE as u32) {
                 anyhow::bail!("vaddr {vaddr:08x} is unaligned");
             }
 
@@ -117,6 +235,16 @@ impl Elf {
                 base_address = vaddr;
             }
 
+            regions.push(MemoryRegion {
+                start: vaddr,
+                size: mem_size,
+                permissions: Permissions {
+                    read: (segment.p_flags & PF_R) != 0,
+                    write: (segment.p_flags & PF_W) != 0,
+                    execute: (segment.p_flags & PF_X) != 0,
+                },
+            });
+
             // Get the offset to the segment.
             let offset: u32 = segment.p_offset.try_into()?;
 
@@ -154,6 +282,76 @@ impl Elf {
             }
         }
 
-        Ok(Elf::new(instructions, entry, base_address, image))
+        // Read the section headers, they are optional in executables.
+        let mut sections = Vec::new();
+        if let (Some(headers), Some(names)) = elf.section_headers_with_strtab()? {
+            for header in headers.iter() {
+                sections.push(Section {
+                    name: names.get(header.sh_name as usize)?.to_string(),
+                    kind: header.sh_type,
+                    flags: header.sh_flags.try_into()?,
+                    addr: header.sh_addr.try_into()?,
+                    offset: header.sh_offset.try_into()?,
+                    size: header.sh_size.try_into()?,
+                });
+            }
+        }
+
+        // Read the symbol tables, stripped binaries have none.
+        let mut symbols = Vec::new();
+        if let Some((table, strings)) = elf.symbol_table()? {
+            read_symbols(&table, &strings, &sections, &mut symbols)?;
+        }
+        if let Some((table, strings)) = elf.dynamic_symbol_table()? {
+            read_symbols(&table, &strings, &sections, &mut symbols)?;
+        }
+        symbols.sort_by(|a, b| (a.addr, &a.name).cmp(&(b.addr, &b.name)));
+        symbols.dedup();
+
+        // Line information only helps debugging, a program with DWARF we can not read still loads
+        let line_table = LineTable::parse(&elf).ok().flatten();
+
+        let mut elf = Elf::new(instructions, entry, base_address, image, regions, symbols);
+        elf.sections = sections;
+        elf.line_table = line_table;
+        elf.program_headers = program_headers;
+
+        Ok(elf)
+    }
+}
+
+/// Add the named functions, objects and labels of a symbol table to `symbols`.
+fn read_symbols(
+    table: &SymbolTable<LittleEndian>,
+    strings: &StringTable,
+    sections: &[Section],
+    symbols: &mut Vec<Symbol>,
+) -> anyhow::Result<()> {
+    for symbol in table.iter() {
+        let symtype = symbol.st_symtype();
+        if symbol.is_undefined()
+            || symbol.st_shndx == SHN_ABS
+            || !matches!(symtype, STT_FUNC | STT_OBJECT | STT_NOTYPE)
+        {
+            continue;
+        }
+        // Labels of the debug sections are not addresses of the program
+        let section = sections.get(symbol.st_shndx as usize);
+        if section.is_some_and(|section| !section.is_alloc()) {
+            continue;
+        }
+        let name = strings.get(symbol.st_name as usize)?;
+        // Skip the mapping symbols ($x, $d) marking code and data, and the assembler's local labels
+        if name.is_empty() || name.starts_with('$') || name.starts_with(".L") {
+            continue;
+        }
+        symbols.push(Symbol {
+            name: name.to_string(),
+            addr: symbol.st_value.try_into()?,
+            size: symbol.st_size.try_into()?,
+            is_function: symtype == STT_FUNC,
+        });
     }
+
+    Ok(())
 }
@@ -0,0 +1,136 @@
+//! This mod holds the source line information of an ELF file, read from the DWARF line tables of
+//! the `.debug_line` section. Binaries built without debug information have none.
+use elf::{endian::LittleEndian, ElfBytes};
+use gimli::{AttributeValue, EndianSlice, Reader, RunTimeEndian, SectionId};
+
+/// A row of a line table: the instructions from `addr` up to the next row come from `line`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRow {
+    pub addr: u32,
+    /// The index of the source file in [LineTable::files]
+    pub file: usize,
+    /// The line in the source file, 0 when the instructions have no source line
+    pub line: u32,
+}
+
+/// The line tables of all the compilation units of a program, merged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineTable {
+    /// The paths of the source files, relative to the directory they were compiled in when they are
+    /// in it
+    pub files: Vec<String>,
+    /// The rows, sorted by address
+    pub rows: Vec<LineRow>,
+}
+
+impl LineTable {
+    /// Returns the source file and line the instruction at `addr` comes from.
+    pub fn find(&self, addr: u32) -> Option<(&str, u32)> {
+        let index = self.rows.partition_point(|row| row.addr <= addr);
+        let row = self.rows.get(index.checked_sub(1)?)?;
+        if row.line == 0 {
+            return None;
+        }
+
+        Some((self.files[row.file].as_str(), row.line))
+    }
+
+    /// Read the line tables of an ELF file, returns `None` when it has no `.debug_line` section.
+    pub(crate) fn parse(elf: &ElfBytes<LittleEndian>) -> anyhow::Result<Option<Self>> {
+        if elf.section_header_by_name(".debug_line")?.is_none() {
+            return Ok(None);
+        }
+        let dwarf = gimli::Dwarf::load(|id: SectionId| -> anyhow::Result<_> {
+            let data = match elf.section_header_by_name(id.name())? {
+                Some(header) => match elf.section_data(&header)? {
+                    (data, None) => data,
+                    (_, Some(_)) => anyhow::bail!("compressed {} is not supported", id.name()),
+                },
+                None => &[],
+            };
+            Ok(EndianSlice::new(data, RunTimeEndian::Little))
+        })?;
+
+        let mut table = LineTable::default();
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let Some(program) = unit.line_program.clone() else {
+                continue;
+            };
+
+            let mut rows = program.rows();
+            let mut files = Vec::new();
+            while let Some((header, row)) = rows.next_row()? {
+                let addr: u32 = row.address().try_into()?;
+                if row.end_sequence() {
+                    table.rows.push(LineRow {
+                        addr,
+                        file: 0,
+                        line: 0,
+                    });
+                    continue;
+                }
+
+                // Intern the path of the file, the first time the unit uses it
+                let index = row.file_index() as usize;
+                if files.len() <= index {
+                    files.resize(index + 1, None);
+                }
+                let file = match files[index] {
+                    Some(file) => file,
+                    None => {
+                        let path = match row.file(header) {
+                            Some(entry) => {
+                                let name = attr_string(&dwarf, &unit, entry.path_name())?;
+                                match entry.directory(header) {
+                                    // Directory 0 is the one the unit was compiled in
+                                    Some(dir) if entry.directory_index() != 0 => {
+                                        let dir = attr_string(&dwarf, &unit, dir)?;
+                                        if name.starts_with('/') {
+                                            name
+                                        } else {
+                                            format!("{}/{}", dir.trim_end_matches('/'), name)
+                                        }
+                                    }
+                                    _ => name,
+                                }
+                            }
+                            None => String::from("??"),
+                        };
+                        let file = match table.files.iter().position(|file| *file == path) {
+                            Some(file) => file,
+                            None => {
+                                table.files.push(path);
+                                table.files.len() - 1
+                            }
+                        };
+                        files[index] = Some(file);
+                        file
+                    }
+                };
+
+                table.rows.push(LineRow {
+                    addr,
+                    file,
+                    line: row.line().map_or(0, |line| line.get() as u32),
+                });
+            }
+        }
+        // A sequence may start where another one ends, the start must win
+        table.rows.sort_by_key(|row| (row.addr, row.line != 0));
+
+        Ok(Some(table))
+    }
+}
+
+fn attr_string<R: Reader>(
+    dwarf: &gimli::Dwarf<R>,
+    unit: &gimli::Unit<R>,
+    value: AttributeValue<R>,
+) -> anyhow::Result<String> {
+    Ok(dwarf
+        .attr_string(unit, value)?
+        .to_string_lossy()?
+        .into_owned())
+}
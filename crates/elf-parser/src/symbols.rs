@@ -0,0 +1,164 @@
+//! This mod holds the symbols and the sections of an ELF file.
+use elf::abi::{SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE};
+
+/// A symbol of the ELF symbol tables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    /// The name of the symbol, as it appears in the symbol table.
+    pub name: String,
+    /// The address of the symbol.
+    pub addr: u32,
+    /// The size of the function or object in bytes, 0 if unknown.
+    pub size: u32,
+    /// Whether the symbol is a function.
+    pub is_function: bool,
+}
+
+impl Symbol {
+    /// Returns the demangled name of the symbol, see [demangle].
+    pub fn demangled(&self) -> String {
+        demangle(&self.name)
+    }
+
+    /// Returns whether `addr` falls in the symbol, symbols of size 0 only contain their address.
+    pub fn contains(&self, addr: u32) -> bool {
+        addr == self.addr || addr.wrapping_sub(self.addr) < self.size
+    }
+}
+
+/// A section of the ELF file, from the section headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    /// The type of the section, one of the `SHT_` constants of the ELF specification.
+    pub kind: u32,
+    /// The `SHF_` flags of the section.
+    pub flags: u32,
+    /// The address of the section in memory, 0 if it is not loaded.
+    pub addr: u32,
+    /// The offset of the section in the file.
+    pub offset: u32,
+    pub size: u32,
+}
+
+impl Section {
+    /// Returns whether the section is loaded in memory when the program runs.
+    pub fn is_alloc(&self) -> bool {
+        self.flags & SHF_ALLOC != 0
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.flags & SHF_WRITE != 0
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.flags & SHF_EXECINSTR != 0
+    }
+}
+
+/// Demangle a Rust symbol name in the legacy mangling scheme, which rustc uses by default.
+/// `_ZN9fibonacci4main17h0123456789abcdefE` becomes `fibonacci::main`, names in any other scheme
+/// are returned as they are.
+pub fn demangle(name: &str) -> String {
+    demangle_legacy(name).unwrap_or_else(|| name.to_string())
+}
+
+fn demangle_legacy(name: &str) -> Option<String> {
+    // LLVM may add a suffix to the names of local symbols it duplicated
+    let name = name.split(".llvm.").next()?;
+    let mut rest = name.strip_prefix("_ZN")?.strip_suffix('E')?;
+
+    let mut components = Vec::new();
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let len: usize = rest[..digits].parse().ok()?;
+        let component = rest.get(digits..digits + len)?;
+        components.push(component);
+        rest = &rest[digits + len..];
+    }
+    // The last component is the hash of the crate and of the signature
+    if let Some(hash) = components.last().and_then(|last| last.strip_prefix('h')) {
+        if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            components.pop();
+        }
+    }
+    if components.is_empty() {
+        return None;
+    }
+
+    components
+        .iter()
+        .map(|component| unescape(component))
+        .collect::<Option<Vec<_>>>()
+        .map(|components| components.join("::"))
+}
+
+/// Undo the escaping of the characters that are not allowed in symbol names
+fn unescape(component: &str) -> Option<String> {
+    let mut rest = component
+        .strip_prefix("_$")
+        .map_or(component, |_| &component[1..]);
+    let mut unescaped = String::new();
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('$') {
+            let end = after.find('$')?;
+            let escape = &after[..end];
+            let c = match escape {
+                "SP" => '@',
+                "BP" => '*',
+                "RF" => '&',
+                "LT" => '<',
+                "GT" => '>',
+                "LP" => '(',
+                "RP" => ')',
+                "C" => ',',
+                _ => {
+                    let code = u32::from_str_radix(escape.strip_prefix('u')?, 16).ok()?;
+                    char::from_u32(code)?
+                }
+            };
+            unescaped.push(c);
+            rest = &after[end + 1..];
+        } else if let Some(after) = rest.strip_prefix("..") {
+            unescaped.push_str("::");
+            rest = after;
+        } else {
+            let c = rest.chars().next()?;
+            unescaped.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    Some(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_demangle() {
+        assert_eq!(
+            demangle("_ZN9fibonacci4main17h0123456789abcdefE"),
+            "fibonacci::main"
+        );
+        assert_eq!(
+            demangle("_ZN4core3fmt5write17h5f4f3e2d1c0b0a09E.llvm.1234"),
+            "core::fmt::write"
+        );
+        assert_eq!(
+            demangle("_ZN60_$LT$alloc..string..String$u20$as$u20$core..fmt..Display$GT$3fmt17h0123456789abcdefE"),
+            "<alloc::string::String as core::fmt::Display>::fmt"
+        );
+        assert_eq!(
+            demangle("_ZN4core3ptr13drop_in_place17h0123456789abcdefE"),
+            "core::ptr::drop_in_place"
+        );
+        assert_eq!(demangle("_ZN3fooE"), "foo");
+        // Not in the legacy scheme, or not valid
+        assert_eq!(demangle("main"), "main");
+        assert_eq!(demangle("_ZN3fo"), "_ZN3fo");
+        assert_eq!(demangle("_ZN9fooE"), "_ZN9fooE");
+    }
+}
@@ -0,0 +1,119 @@
+//! Compares the basic-block interpreter against single stepping on the bundled guests, and the JIT
+//! when built with it.
+//! Run with `cargo bench -p emulator-sdk`, or `cargo bench -p emulator-sdk --features jit`.
+//!
+//! `bench` sieves primes and exits through the default syscall handler, `fibonacci` is the SP1
+//! example and runs to its halt under the SP1 handler. Both execute the whole program.
+//! `plonk_verify` can not verify a proof here: the proof is not bundled and the verification needs
+//! the bn254 precompiles, which the SP1 handler does not implement. It is given a placeholder proof
+//! instead and runs for [PLONK_VERIFY_BUDGET] instructions of the start of the verification,
+//! hashing the verifying key, before it would reject the proof.
+use elf_parser::Elf;
+use emulator_sdk::vm::{RunConfig, RunOutcome, Vm};
+use std::time::{Duration, Instant};
+
+const GUESTS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../integration-testing");
+
+const ITERATIONS: u32 = 10;
+
+/// `plonk_verify` rejects the placeholder proof after about 2.07M instructions
+const PLONK_VERIFY_BUDGET: u64 = 2_000_000;
+
+/// A guest to benchmark and how to set it up
+struct Workload {
+    path: &'static str,
+    vm: fn(&Elf) -> Vm,
+    /// Stop after this many instructions instead of running the guest to its exit
+    budget: Option<u64>,
+}
+
+const WORKLOADS: [Workload; 3] = [
+    Workload {
+        path: "rust-elfs/bench",
+        vm: Vm::from_elf,
+        budget: None,
+    },
+    Workload {
+        path: "rust-elfs/fibonacci",
+        vm: |elf| Vm::from_sp1_elf(elf, []),
+        budget: None,
+    },
+    Workload {
+        path: "plonk_verify",
+        vm: |elf| {
+            // The proof (whose first 4 bytes are the hash of the verifying key), the public
+            // values and the bincode encoded hash of the program verifying key
+            let mut vkey_hash = 2u64.to_le_bytes().to_vec();
+            vkey_hash.extend_from_slice(b"00");
+            Vm::from_sp1_elf(elf, [vec![0; 4], Vec::new(), vkey_hash])
+        },
+        budget: Some(PLONK_VERIFY_BUDGET),
+    },
+];
+
+/// Returns the fastest of `ITERATIONS` runs and the number of instructions retired
+fn bench(vm: &Vm, budget: Option<u64>, config: RunConfig) -> (Duration, u64) {
+    let mut best = Duration::MAX;
+    let mut instructions = 0;
+    for _ in 0..ITERATIONS {
+        let mut vm = vm.clone();
+        let start = Instant::now();
+        let outcome = vm.run(RunConfig {
+            max_instructions: budget,
+            ..config.clone()
+        });
+        match budget {
+            Some(_) => assert_eq!(outcome, RunOutcome::BudgetExhausted),
+            None => assert!(
+                matches!(outcome, RunOutcome::Exited(_)),
+                "The guest did not exit: {outcome:?}"
+            ),
+        }
+        best = best.min(start.elapsed());
+        instructions = vm.csrs.instret;
+    }
+
+    (best, instructions)
+}
+
+fn main() {
+    for workload in WORKLOADS {
+        let path = format!("{GUESTS}/{}", workload.path);
+        let elf = Elf::decode(&std::fs::read(&path).expect("Failed to read the ELF"))
+            .expect("Failed to decode the ELF");
+        let vm = (workload.vm)(&elf);
+        let (step, instructions) = bench(
+            &vm,
+            workload.budget,
+            RunConfig {
+                cache_blocks: false,
+                ..RunConfig::default()
+            },
+        );
+        let mips = |time: Duration| instructions as f64 / time.as_secs_f64() / 1e6;
+        let report = |engine: &str, time: Duration| {
+            println!(
+                "    {engine:<7} {:>12?} {:>8.1} MIPS ({:.1}x)",
+                time,
+                mips(time),
+                step.as_secs_f64() / time.as_secs_f64()
+            )
+        };
+
+        println!("{}: {instructions} instructions", workload.path);
+        report("step", step);
+        let (blocks, _) = bench(&vm, workload.budget, RunConfig::default());
+        report("blocks", blocks);
+        if cfg!(feature = "jit") {
+            let (jit, _) = bench(
+                &vm,
+                workload.budget,
+                RunConfig {
+                    jit: true,
+                    ..RunConfig::default()
+                },
+            );
+            report("jit", jit);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Encoders of the RV32 instructions the tests assemble their programs from. The operands are in
+//! the order of the instruction fields, so `sw(rs1, rs2, imm)` is `sw rs2, imm(rs1)` and
+//! `lui(rd, imm)` takes the upper 20 bits like the `lui` of an assembler.
+pub const ECALL: u32 = 0x0000_0073;
+pub const EBREAK: u32 = 0x0010_0073;
+pub const MRET: u32 = 0x3020_0073;
+pub const FENCE_I: u32 = 0x0000_100f;
+/// `jalr x0, 0(ra)`
+pub const RET: u32 = 0x0000_8067;
+
+pub fn r_type(funct7: u32, funct3: u32, rd: u32, rs1: u32, rs2: u32) -> u32 {
+    (funct7 << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | 0b0110011
+}
+
+pub fn i_type(opcode: u32, funct3: u32, rd: u32, rs1: u32, imm: i32) -> u32 {
+    ((imm as u32) << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+pub fn s_type(funct3: u32, rs1: u32, rs2: u32, imm: i32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 5) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (funct3 << 12)
+        | ((imm & 0x1f) << 7)
+        | 0b0100011
+}
+
+pub fn b_type(funct3: u32, rs1: u32, rs2: u32, offset: i32) -> u32 {
+    let imm = offset as u32;
+    ((imm >> 12 & 1) << 31)
+        | ((imm >> 5 & 0x3f) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (funct3 << 12)
+        | ((imm >> 1 & 0xf) << 8)
+        | ((imm >> 11 & 1) << 7)
+        | 0b1100011
+}
+
+pub fn addi(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(0b0010011, 0b000, rd, rs1, imm)
+}
+
+pub fn lui(rd: u32, imm: u32) -> u32 {
+    (imm << 12) | (rd << 7) | 0b0110111
+}
+
+pub fn lw(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(0b0000011, 0b010, rd, rs1, imm)
+}
+
+pub fn sw(rs1: u32, rs2: u32, imm: i32) -> u32 {
+    s_type(0b010, rs1, rs2, imm)
+}
+
+pub fn bne(rs1: u32, rs2: u32, offset: i32) -> u32 {
+    b_type(0b001, rs1, rs2, offset)
+}
+
+pub fn jal(rd: u32, offset: i32) -> u32 {
+    let imm = offset as u32;
+    ((imm >> 20 & 1) << 31)
+        | ((imm >> 1 & 0x3ff) << 21)
+        | ((imm >> 11 & 1) << 20)
+        | ((imm >> 12 & 0xff) << 12)
+        | (rd << 7)
+        | 0b1101111
+}
+
+pub fn jalr(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(0b1100111, 0b000, rd, rs1, imm)
+}
+
+/// A Zicsr instruction, `funct3` picks between csrrw, csrrs, csrrc and their immediate forms
+pub fn csr(funct3: u32, rd: u32, rs1: u32, csr: u32) -> u32 {
+    (csr << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | 0b1110011
+}
+
+/// An RV32A word instruction, LR.W, SC.W or an AMO picked by `funct5`
+pub fn amo(funct5: u32, rd: u32, rs1: u32, rs2: u32) -> u32 {
+    (funct5 << 27) | (rs2 << 20) | (rs1 << 15) | (0b010 << 12) | (rd << 7) | 0b0101111
+}
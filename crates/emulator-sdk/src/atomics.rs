@@ -0,0 +1,245 @@
+//! This mod holds the RV32A (atomic) extension.
+//! The VM has a single hart, so every AMO is trivially atomic, it is a load, an operation and a store
+//! executed as one instruction. LR.W reserves the word it loads and SC.W only succeeds while that
+//! reservation holds, any store to the reserved word (or any SC.W) clears it.
+use crate::{
+    instructions::RType,
+    vm::{VMErrors, Vm},
+};
+use core::MemoryChuckSize;
+
+pub const AMO_ADD: u32 = 0b00000;
+pub const AMO_SWAP: u32 = 0b00001;
+pub const LR: u32 = 0b00010;
+pub const SC: u32 = 0b00011;
+pub const AMO_XOR: u32 = 0b00100;
+pub const AMO_OR: u32 = 0b01000;
+pub const AMO_AND: u32 = 0b01100;
+pub const AMO_MIN: u32 = 0b10000;
+pub const AMO_MAX: u32 = 0b10100;
+pub const AMO_MINU: u32 = 0b11000;
+pub const AMO_MAXU: u32 = 0b11100;
+
+/// Execute LR.W, SC.W or an AMO*.W instruction.
+/// The aq and rl bits are ignored since there is no other hart to order accesses against.
+pub fn process_atomic(vm: &mut Vm, decoded_instruction: &RType) -> Result<(), VMErrors> {
+    if decoded_instruction.funct3 != 0b010 {
+        return Err(VMErrors::InvalidFunct3(decoded_instruction.funct3));
+    }
+
+    let funct5 = decoded_instruction.funct7 >> 2;
+    let addr = vm.read_reg(decoded_instruction.rs1 as u32);
+
+    match funct5 {
+        LR => {
+            if decoded_instruction.rs2 != 0 {
+                return Err(VMErrors::InvalidInstruction);
+            }
+            if addr & 0x3 != 0 {
+                return Err(VMErrors::LoadAddressMisaligned(addr));
+            }
+
+            let value = vm
+                .read_mem(addr, MemoryChuckSize::WordSize)
+                .ok_or(VMErrors::LoadAccessFault(addr))?;
+            vm.reservation = Some(addr);
+            vm.write_reg(decoded_instruction.rd as u32, value);
+        }
+        SC => {
+            let value = vm.read_reg(decoded_instruction.rs2 as u32);
+            if addr & 0x3 != 0 {
+                return Err(VMErrors::StoreAddressMisaligned(addr));
+            }
+
+            let reserved = vm.reservation.take() == Some(addr);
+            if reserved && !vm.write_mem(addr, MemoryChuckSize::WordSize, value) {
+                return Err(VMErrors::StoreAccessFault(addr));
+            }
+            vm.write_reg(decoded_instruction.rd as u32, if reserved { 0 } else { 1 });
+        }
+        _ => {
+            let operation: fn(u32, u32) -> u32 = match funct5 {
+                AMO_SWAP => |_, src| src,
+                AMO_ADD => |old, src| old.wrapping_add(src),
+                AMO_XOR => |old, src| old ^ src,
+                AMO_AND => |old, src| old & src,
+                AMO_OR => |old, src| old | src,
+                AMO_MIN => |old, src| (old as i32).min(src as i32) as u32,
+                AMO_MAX => |old, src| (old as i32).max(src as i32) as u32,
+                AMO_MINU => |old, src| old.min(src),
+                AMO_MAXU => |old, src| old.max(src),
+                _ => return Err(VMErrors::InvalidFunct7(decoded_instruction.funct7)),
+            };
+
+            let src = vm.read_reg(decoded_instruction.rs2 as u32);
+            if addr & 0x3 != 0 {
+                return Err(VMErrors::StoreAddressMisaligned(addr));
+            }
+
+            let old = vm
+                .read_mem(addr, MemoryChuckSize::WordSize)
+                .ok_or(VMErrors::StoreAccessFault(addr))?;
+            if !vm.write_mem(addr, MemoryChuckSize::WordSize, operation(old, src)) {
+                return Err(VMErrors::StoreAccessFault(addr));
+            }
+            vm.write_reg(decoded_instruction.rd as u32, old);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{amo, sw, EBREAK};
+    use crate::vm::RunConfig;
+    use core::interfaces::MemoryInterface;
+
+    const ADDR: u32 = 0x100;
+
+    /// Run `program` with x1 = `ADDR`, x2 = `src` and `old` stored at `ADDR`
+    fn run(program: Vec<u32>, old: u32, src: u32) -> Vm {
+        let mut vm = Vm::from_bin(program).unwrap();
+        vm.registers.write_reg(1, ADDR);
+        vm.registers.write_reg(2, src);
+        vm.memory.write_mem(ADDR, MemoryChuckSize::WordSize, old);
+        vm.run(RunConfig::default());
+        vm
+    }
+
+    /// Check that the AMO stores `expected` and returns the old value in rd
+    fn check_amo(funct5: u32, old: u32, src: u32, expected: u32) {
+        let vm = run(vec![amo(funct5, 3, 1, 2), EBREAK], old, src);
+
+        assert_eq!(vm.registers.read_reg(3), old);
+        assert_eq!(
+            vm.memory.read_mem(ADDR, MemoryChuckSize::WordSize),
+            Some(expected)
+        );
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_encoding_matches_reference() {
+        // as encoded by llvm-mc
+        assert_eq!(amo(AMO_ADD, 10, 12, 11), 0x00b6_252f); // amoadd.w a0, a1, (a2)
+        assert_eq!(amo(LR, 5, 10, 0), 0x1005_22af); // lr.w t0, (a0)
+        assert_eq!(amo(AMO_MAXU, 3, 5, 4), 0xe042_a1af); // amomaxu.w x3, x4, (x5)
+    }
+
+    #[test]
+    fn test_amoswap() {
+        check_amo(AMO_SWAP, 7, 9, 9);
+    }
+
+    #[test]
+    fn test_amoadd() {
+        check_amo(AMO_ADD, 7, 9, 16);
+        check_amo(AMO_ADD, u32::MAX, 2, 1);
+    }
+
+    #[test]
+    fn test_amoxor() {
+        check_amo(AMO_XOR, 0b1100, 0b1010, 0b0110);
+    }
+
+    #[test]
+    fn test_amoand() {
+        check_amo(AMO_AND, 0b1100, 0b1010, 0b1000);
+    }
+
+    #[test]
+    fn test_amoor() {
+        check_amo(AMO_OR, 0b1100, 0b1010, 0b1110);
+    }
+
+    #[test]
+    fn test_amomin() {
+        check_amo(AMO_MIN, -5i32 as u32, 3, -5i32 as u32);
+        check_amo(AMO_MIN, 3, -5i32 as u32, -5i32 as u32);
+    }
+
+    #[test]
+    fn test_amomax() {
+        check_amo(AMO_MAX, -5i32 as u32, 3, 3);
+        check_amo(AMO_MAX, 3, -5i32 as u32, 3);
+    }
+
+    #[test]
+    fn test_amominu() {
+        check_amo(AMO_MINU, -5i32 as u32, 3, 3);
+        check_amo(AMO_MINU, 3, 5, 3);
+    }
+
+    #[test]
+    fn test_amomaxu() {
+        check_amo(AMO_MAXU, -5i32 as u32, 3, -5i32 as u32);
+        check_amo(AMO_MAXU, 3, 5, 5);
+    }
+
+    #[test]
+    fn test_lr_sc_succeeds_with_reservation() {
+        let vm = run(vec![amo(LR, 3, 1, 0), amo(SC, 4, 1, 2), EBREAK], 7, 9);
+
+        assert_eq!(vm.registers.read_reg(3), 7);
+        assert_eq!(vm.registers.read_reg(4), 0);
+        assert_eq!(vm.memory.read_mem(ADDR, MemoryChuckSize::WordSize), Some(9));
+        assert_eq!(vm.reservation, None);
+    }
+
+    #[test]
+    fn test_sc_fails_without_reservation() {
+        let vm = run(vec![amo(SC, 4, 1, 2), EBREAK], 7, 9);
+
+        assert_eq!(vm.registers.read_reg(4), 1);
+        assert_eq!(vm.memory.read_mem(ADDR, MemoryChuckSize::WordSize), Some(7));
+    }
+
+    #[test]
+    fn test_sc_fails_after_store_to_reserved_word() {
+        let vm = run(
+            vec![amo(LR, 3, 1, 0), sw(1, 0, 0), amo(SC, 4, 1, 2), EBREAK],
+            7,
+            9,
+        );
+
+        assert_eq!(vm.registers.read_reg(4), 1);
+        assert_eq!(vm.memory.read_mem(ADDR, MemoryChuckSize::WordSize), Some(0));
+    }
+
+    #[test]
+    fn test_second_sc_fails() {
+        let vm = run(
+            vec![amo(LR, 3, 1, 0), amo(SC, 4, 1, 2), amo(SC, 5, 1, 0), EBREAK],
+            7,
+            9,
+        );
+
+        assert_eq!(vm.registers.read_reg(4), 0);
+        assert_eq!(vm.registers.read_reg(5), 1);
+        assert_eq!(vm.memory.read_mem(ADDR, MemoryChuckSize::WordSize), Some(9));
+    }
+
+    #[test]
+    fn test_misaligned_accesses_fault() {
+        let mut vm = Vm::from_bin(vec![amo(LR, 3, 1, 0)]).unwrap();
+        vm.registers.write_reg(1, ADDR + 2);
+        assert!(matches!(
+            vm.step(),
+            Err(VMErrors::LoadAddressMisaligned(0x102))
+        ));
+
+        for funct5 in [SC, AMO_SWAP, AMO_ADD, AMO_MAXU] {
+            let mut vm = Vm::from_bin(vec![amo(funct5, 3, 1, 2)]).unwrap();
+            vm.registers.write_reg(1, ADDR + 1);
+            vm.registers.write_reg(3, 42);
+            assert!(matches!(
+                vm.step(),
+                Err(VMErrors::StoreAddressMisaligned(0x101))
+            ));
+            assert_eq!(vm.registers.read_reg(3), 42);
+            assert_eq!(vm.pc, 0);
+        }
+    }
+}
@@ -0,0 +1,687 @@
+//! This mod holds the pre-decoded instruction cache and the basic-block interpreter.
+//! Instructions are decoded once into a compact [Op] and cached by pc in basic blocks, a run of
+//! instructions ending at the first control transfer. [Vm::run] executes whole blocks in a tight
+//! loop and only falls back to [Vm::step] for what a block can not hold: system instructions,
+//! atomics, fences, instructions that raise an exception and any access that is not the common case.
+//! A store to a page instructions were decoded from drops the blocks holding them. The whole cache
+//! is dropped on FENCE.I and at the start of every run, since the host may have changed the memory
+//! in between.
+use crate::{
+    instructions::{
+        DecodedInstruction, InstructionDecoder, IMMEDIATE_CLASS, IMMEDIATE_LOAD_CLASS, JALR_CLASS,
+        REGISTER_CLASS, UPPER_IMMEDIATE_CLASS,
+    },
+    trace::chunk_bytes,
+    vm::Vm,
+};
+use core::{interfaces::MemoryInterface, sign_extend_u32, MemoryChuckSize};
+use hashbrown::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Blocks are cut after this many instructions
+pub const MAX_BLOCK_LEN: usize = 64;
+
+/// Instructions are tracked at this granularity to find stores to code
+const CODE_PAGE_SHIFT: u32 = 12;
+
+/// These are the register-register and register-immediate operations of RV32IM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AluOp {
+    Add,
+    Sub,
+    Sll,
+    Slt,
+    Sltu,
+    Xor,
+    Srl,
+    Sra,
+    Or,
+    And,
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+}
+
+impl AluOp {
+    /// Apply the operation, `b` is rs2 or the immediate (the shift amount for shifts).
+    pub fn apply(self, a: u32, b: u32) -> u32 {
+        match self {
+            AluOp::Add => a.wrapping_add(b),
+            AluOp::Sub => a.wrapping_sub(b),
+            AluOp::Sll => a.wrapping_shl(b),
+            AluOp::Slt => ((a as i32) < (b as i32)) as u32,
+            AluOp::Sltu => (a < b) as u32,
+            AluOp::Xor => a ^ b,
+            AluOp::Srl => a.wrapping_shr(b),
+            AluOp::Sra => (a as i32).wrapping_shr(b) as u32,
+            AluOp::Or => a | b,
+            AluOp::And => a & b,
+            AluOp::Mul => a.wrapping_mul(b),
+            AluOp::Mulh => (sign_extend_u32(a).wrapping_mul(sign_extend_u32(b)) >> 32) as u32,
+            AluOp::Mulhsu => (sign_extend_u32(a).wrapping_mul(b as i64) >> 32) as u32,
+            AluOp::Mulhu => ((a as u64).wrapping_mul(b as u64) >> 32) as u32,
+            AluOp::Div => match b {
+                0 => u32::MAX,
+                _ => (a as i32).wrapping_div(b as i32) as u32,
+            },
+            AluOp::Divu => a.checked_div(b).unwrap_or(u32::MAX),
+            AluOp::Rem => match b {
+                0 => a,
+                _ => (a as i32).wrapping_rem(b as i32) as u32,
+            },
+            AluOp::Remu => a.checked_rem(b).unwrap_or(a),
+        }
+    }
+}
+
+/// These are the conditions of the branch instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BranchCond {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Ltu,
+    Geu,
+}
+
+impl BranchCond {
+    pub fn holds(self, a: u32, b: u32) -> bool {
+        match self {
+            BranchCond::Eq => a == b,
+            BranchCond::Ne => a != b,
+            BranchCond::Lt => (a as i32) < (b as i32),
+            BranchCond::Ge => (a as i32) >= (b as i32),
+            BranchCond::Ltu => a < b,
+            BranchCond::Geu => a >= b,
+        }
+    }
+}
+
+/// This is a pre-decoded instruction.
+/// Everything that only depends on the instruction and its pc, like jump targets and the value of
+/// `auipc`, is computed when decoding.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Alu {
+        op: AluOp,
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+    },
+    AluImm {
+        op: AluOp,
+        rd: u8,
+        rs1: u8,
+        imm: u32,
+    },
+    /// lui and auipc
+    LoadImm {
+        rd: u8,
+        value: u32,
+    },
+    Load {
+        size: MemoryChuckSize,
+        signed: bool,
+        rd: u8,
+        rs1: u8,
+        offset: u32,
+    },
+    Store {
+        size: MemoryChuckSize,
+        rs1: u8,
+        rs2: u8,
+        offset: u32,
+    },
+    Branch {
+        cond: BranchCond,
+        rs1: u8,
+        rs2: u8,
+        target: u32,
+    },
+    Jal {
+        rd: u8,
+        target: u32,
+    },
+    Jalr {
+        rd: u8,
+        rs1: u8,
+        offset: u32,
+    },
+}
+
+impl Op {
+    /// Lower the instruction at `pc`.
+    /// Returns `None` for the instructions that are left to [Vm::step].
+    pub fn lower(decoded: &InstructionDecoder, pc: u32) -> Option<Self> {
+        match &decoded.decoded_instruction {
+            DecodedInstruction::RType(r) if decoded.opcode == REGISTER_CLASS => {
+                let op = match (r.funct3, r.funct7) {
+                    (0b000, 0b0000000) => AluOp::Add,
+                    (0b000, 0b0100000) => AluOp::Sub,
+                    (0b001, 0b0000000) => AluOp::Sll,
+                    (0b010, 0b0000000) => AluOp::Slt,
+                    (0b011, 0b0000000) => AluOp::Sltu,
+                    (0b100, 0b0000000) => AluOp::Xor,
+                    (0b101, 0b0000000) => AluOp::Srl,
+                    (0b101, 0b0100000) => AluOp::Sra,
+                    (0b110, 0b0000000) => AluOp::Or,
+                    (0b111, 0b0000000) => AluOp::And,
+                    (0b000, 0b0000001) => AluOp::Mul,
+                    (0b001, 0b0000001) => AluOp::Mulh,
+                    (0b010, 0b0000001) => AluOp::Mulhsu,
+                    (0b011, 0b0000001) => AluOp::Mulhu,
+                    (0b100, 0b0000001) => AluOp::Div,
+                    (0b101, 0b0000001) => AluOp::Divu,
+                    (0b110, 0b0000001) => AluOp::Rem,
+                    (0b111, 0b0000001) => AluOp::Remu,
+                    _ => return None,
+                };
+                Some(Op::Alu {
+                    op,
+                    rd: r.rd as u8,
+                    rs1: r.rs1 as u8,
+                    rs2: r.rs2 as u8,
+                })
+            }
+            DecodedInstruction::IType(i) if decoded.opcode == IMMEDIATE_CLASS => {
+                let shamt = i.metadata.imm_shift_amt;
+                let (op, imm) = match (i.funct3, i.metadata.funct7) {
+                    (0b000, _) => (AluOp::Add, i.imm as u32),
+                    (0b001, 0b0000000) => (AluOp::Sll, shamt),
+                    (0b010, _) => (AluOp::Slt, i.imm as u32),
+                    (0b011, _) => (AluOp::Sltu, i.imm as u32),
+                    (0b100, _) => (AluOp::Xor, i.imm as u32),
+                    (0b101, 0b0000000) => (AluOp::Srl, shamt),
+                    (0b101, 0b0100000) => (AluOp::Sra, shamt),
+                    (0b110, _) => (AluOp::Or, i.imm as u32),
+                    (0b111, _) => (AluOp::And, i.imm as u32),
+                    _ => return None,
+                };
+                Some(Op::AluImm {
+                    op,
+                    rd: i.rd as u8,
+                    rs1: i.rs1 as u8,
+                    imm,
+                })
+            }
+            DecodedInstruction::IType(i) if decoded.opcode == IMMEDIATE_LOAD_CLASS => {
+                let (size, signed) = match i.funct3 {
+                    0b000 => (MemoryChuckSize::BYTE, true),
+                    0b001 => (MemoryChuckSize::HalfWord, true),
+                    0b010 => (MemoryChuckSize::WordSize, false),
+                    0b100 => (MemoryChuckSize::BYTE, false),
+                    0b101 => (MemoryChuckSize::HalfWord, false),
+                    _ => return None,
+                };
+                Some(Op::Load {
+                    size,
+                    signed,
+                    rd: i.rd as u8,
+                    rs1: i.rs1 as u8,
+                    offset: i.imm as u32,
+                })
+            }
+            DecodedInstruction::IType(i) if decoded.opcode == JALR_CLASS && i.funct3 == 0 => {
+                Some(Op::Jalr {
+                    rd: i.rd as u8,
+                    rs1: i.rs1 as u8,
+                    offset: i.imm as u32,
+                })
+            }
+            DecodedInstruction::SType(s) => {
+                let size = match s.funct3 {
+                    0b000 => MemoryChuckSize::BYTE,
+                    0b001 => MemoryChuckSize::HalfWord,
+                    0b010 => MemoryChuckSize::WordSize,
+                    _ => return None,
+                };
+                Some(Op::Store {
+                    size,
+                    rs1: s.rs1 as u8,
+                    rs2: s.rs2 as u8,
+                    offset: s.imm as u32,
+                })
+            }
+            DecodedInstruction::BType(b) => {
+                let cond = match b.funct3 {
+                    0b000 => BranchCond::Eq,
+                    0b001 => BranchCond::Ne,
+                    0b100 => BranchCond::Lt,
+                    0b101 => BranchCond::Ge,
+                    0b110 => BranchCond::Ltu,
+                    0b111 => BranchCond::Geu,
+                    _ => return None,
+                };
+                Some(Op::Branch {
+                    cond,
+                    rs1: b.rs1 as u8,
+                    rs2: b.rs2 as u8,
+                    target: pc.wrapping_add(b.imm as u32),
+                })
+            }
+            DecodedInstruction::UType(u) => {
+                let value = match decoded.opcode {
+                    UPPER_IMMEDIATE_CLASS => u.imm as u32,
+                    _ => pc.wrapping_add(u.imm as u32),
+                };
+                Some(Op::LoadImm {
+                    rd: u.rd as u8,
+                    value,
+                })
+            }
+            DecodedInstruction::JType(j) => Some(Op::Jal {
+                rd: j.rd as u8,
+                target: pc.wrapping_add(j.imm as u32),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Whether the op ends a basic block
+    pub fn is_control_transfer(&self) -> bool {
+        matches!(self, Op::Branch { .. } | Op::Jal { .. } | Op::Jalr { .. })
+    }
+}
+
+/// This is a cached instruction, `next_pc` is the pc of the instruction after it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedOp {
+    pub op: Op,
+    pub pc: u32,
+    pub next_pc: u32,
+}
+
+/// This is a basic block, it is empty when its first instruction is left to [Vm::step].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Block {
+    pub ops: Vec<CachedOp>,
+}
+
+/// This is the cache of decoded basic blocks, keyed by the pc of their first instruction.
+#[derive(Debug, Clone, Default)]
+pub struct InstructionCache {
+    blocks: HashMap<u32, Arc<Block>>,
+    /// The pcs of the blocks holding instructions decoded from each page
+    code_pages: HashMap<u32, HashSet<u32>>,
+    /// The compiled blocks, they are dropped with the decoded ones
+    #[cfg(feature = "jit")]
+    pub jit: crate::jit::Jit,
+}
+
+impl InstructionCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the block starting at `pc`, if it has been decoded.
+    pub fn get(&self, pc: u32) -> Option<&Arc<Block>> {
+        self.blocks.get(&pc)
+    }
+
+    pub fn insert(&mut self, pc: u32, block: Block) -> Arc<Block> {
+        for op in &block.ops {
+            for page in [op.pc, op.next_pc.wrapping_sub(1)] {
+                self.code_pages
+                    .entry(page >> CODE_PAGE_SHIFT)
+                    .or_default()
+                    .insert(pc);
+            }
+        }
+
+        let block = Arc::new(block);
+        self.blocks.insert(pc, block.clone());
+        block
+    }
+
+    /// Drop the blocks decoded from the pages holding the `len` bytes at `addr`, as they may be
+    /// stale after a store there. Returns whether any block was dropped.
+    pub fn invalidate(&mut self, addr: u32, len: u32) -> bool {
+        if self.code_pages.is_empty() {
+            return false;
+        }
+
+        let first = addr >> CODE_PAGE_SHIFT;
+        let last = addr.wrapping_add(len.max(1) - 1) >> CODE_PAGE_SHIFT;
+        let mut invalidated = false;
+        for page in [first, last] {
+            let Some(pcs) = self.code_pages.remove(&page) else {
+                continue;
+            };
+            for pc in pcs {
+                // A block spanning two pages may still be listed under the other one
+                if self.blocks.remove(&pc).is_some() {
+                    invalidated = true;
+                }
+                #[cfg(feature = "jit")]
+                self.jit.invalidate(pc);
+            }
+        }
+        invalidated
+    }
+
+    /// Drop every decoded block.
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.code_pages.clear();
+        #[cfg(feature = "jit")]
+        self.jit.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+/// This is what executing a cached op did.
+enum Flow {
+    /// Continue with the next op of the block
+    Next,
+    /// The op executed and the block ends here
+    End,
+    /// The op did not execute, it is left to [Vm::step]
+    Bail,
+}
+
+impl Vm {
+    /// Decode the basic block starting at `pc`.
+    fn decode_block(&self, mut pc: u32) -> Block {
+        let mut block = Block::default();
+
+        while block.ops.len() < MAX_BLOCK_LEN {
+            let Ok(decoded) = self
+                .fetch(pc)
+                .and_then(|instruction| InstructionDecoder::decode(&instruction))
+            else {
+                break;
+            };
+            let Some(op) = Op::lower(&decoded, pc) else {
+                break;
+            };
+
+            let next_pc = pc.wrapping_add(decoded.size);
+            let control_transfer = op.is_control_transfer();
+            block.ops.push(CachedOp { op, pc, next_pc });
+            if control_transfer {
+                break;
+            }
+            pc = next_pc;
+        }
+
+        block
+    }
+
+    /// Execute up to `max_instructions` instructions of the basic block at the current pc, running
+    /// its compiled version when `jit` is set and the block fits in the budget.
+    /// Returns the number of instructions executed, 0 when the instruction at the pc is left to
+    /// [Vm::step]. Must only be called when no trace is recorded and no observer is attached.
+    #[cfg_attr(not(feature = "jit"), allow(unused_variables))]
+    pub(crate) fn execute_block(&mut self, max_instructions: u64, jit: bool) -> u64 {
+        let block = match self.instruction_cache.get(self.pc) {
+            Some(block) => block.clone(),
+            None => {
+                let block = self.decode_block(self.pc);
+                if let (Some(first), Some(last)) = (block.ops.first(), block.ops.last()) {
+                    self.touch(first.pc, last.next_pc.wrapping_sub(first.pc));
+                }
+                self.instruction_cache.insert(self.pc, block)
+            }
+        };
+
+        #[cfg(feature = "jit")]
+        if jit && !block.ops.is_empty() && block.ops.len() as u64 <= max_instructions {
+            if let Some(executed) = self.execute_compiled_block(&block) {
+                return executed;
+            }
+        }
+
+        let mut executed = 0;
+        for cached in block.ops.iter() {
+            if executed == max_instructions {
+                break;
+            }
+
+            let flow = self.execute_op(cached);
+            if let Flow::Bail = flow {
+                break;
+            }
+            self.csrs.retire();
+            executed += 1;
+            if let Flow::End = flow {
+                break;
+            }
+        }
+
+        executed
+    }
+
+    /// Execute a cached op, bailing out on anything but the common case.
+    #[inline(always)]
+    fn execute_op(&mut self, cached: &CachedOp) -> Flow {
+        match cached.op {
+            Op::Alu { op, rd, rs1, rs2 } => {
+                let value = op.apply(
+                    self.registers.read_reg(rs1 as u32),
+                    self.registers.read_reg(rs2 as u32),
+                );
+                self.registers.write_reg(rd as u32, value);
+            }
+            Op::AluImm { op, rd, rs1, imm } => {
+                let value = op.apply(self.registers.read_reg(rs1 as u32), imm);
+                self.registers.write_reg(rd as u32, value);
+            }
+            Op::LoadImm { rd, value } => self.registers.write_reg(rd as u32, value),
+            Op::Load {
+                ref size,
+                signed,
+                rd,
+                rs1,
+                offset,
+            } => {
+                let addr = self.registers.read_reg(rs1 as u32).wrapping_add(offset);
+                if addr & align_mask(size) != 0 {
+                    return Flow::Bail;
+                }
+                let Some(value) = self.memory.read_mem(addr, size.clone()) else {
+                    return Flow::Bail;
+                };
+                self.touch(addr, chunk_bytes(size) as u32);
+                let value = match (size, signed) {
+                    (MemoryChuckSize::BYTE, true) => value as i8 as u32,
+                    (MemoryChuckSize::HalfWord, true) => value as i16 as u32,
+                    _ => value,
+                };
+                self.registers.write_reg(rd as u32, value);
+            }
+            Op::Store {
+                ref size,
+                rs1,
+                rs2,
+                offset,
+            } => {
+                let addr = self.registers.read_reg(rs1 as u32).wrapping_add(offset);
+                if addr & align_mask(size) != 0 {
+                    return Flow::Bail;
+                }
+                // Commands to the host are left to the interpreter
+                if self
+                    .htif
+                    .as_ref()
+                    .is_some_and(|htif| htif.watches(addr, chunk_bytes(size) as u32))
+                {
+                    return Flow::Bail;
+                }
+                let value = self.registers.read_reg(rs2 as u32);
+                if !self.memory.write_mem(addr, size.clone(), value) {
+                    return Flow::Bail;
+                }
+                self.touch(addr, chunk_bytes(size) as u32);
+                if self.reservation == Some(addr & !0x3) {
+                    self.reservation = None;
+                }
+                // The rest of the block may just have been overwritten
+                if self
+                    .instruction_cache
+                    .invalidate(addr, chunk_bytes(size) as u32)
+                {
+                    self.pc = cached.next_pc;
+                    return Flow::End;
+                }
+            }
+            Op::Branch {
+                cond,
+                rs1,
+                rs2,
+                target,
+            } => {
+                let taken = cond.holds(
+                    self.registers.read_reg(rs1 as u32),
+                    self.registers.read_reg(rs2 as u32),
+                );
+                self.pc = if taken { target } else { cached.next_pc };
+                return Flow::End;
+            }
+            Op::Jal { rd, target } => {
+                self.registers.write_reg(rd as u32, cached.next_pc);
+                self.pc = target;
+                return Flow::End;
+            }
+            Op::Jalr { rd, rs1, offset } => {
+                let target = self.registers.read_reg(rs1 as u32).wrapping_add(offset) & !1;
+                self.registers.write_reg(rd as u32, cached.next_pc);
+                self.pc = target;
+                return Flow::End;
+            }
+        }
+
+        self.pc = cached.next_pc;
+        Flow::Next
+    }
+}
+
+fn align_mask(size: &MemoryChuckSize) -> u32 {
+    match size {
+        MemoryChuckSize::BYTE => 0x0,
+        MemoryChuckSize::HalfWord => 0x1,
+        MemoryChuckSize::WordSize => 0x3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{addi, jalr, lui, sw, EBREAK, FENCE_I};
+    use crate::vm::{RunConfig, RunOutcome};
+
+    #[test]
+    fn test_alu_edge_cases() {
+        assert_eq!(
+            AluOp::Div.apply(i32::MIN as u32, -1i32 as u32),
+            i32::MIN as u32
+        );
+        assert_eq!(AluOp::Div.apply(7, 0), u32::MAX);
+        assert_eq!(AluOp::Divu.apply(7, 0), u32::MAX);
+        assert_eq!(AluOp::Rem.apply(i32::MIN as u32, -1i32 as u32), 0);
+        assert_eq!(AluOp::Rem.apply(-7i32 as u32, 0), -7i32 as u32);
+        assert_eq!(AluOp::Remu.apply(7, 0), 7);
+        assert_eq!(AluOp::Mulh.apply(-1i32 as u32, -1i32 as u32), 0);
+        assert_eq!(AluOp::Mulhsu.apply(-1i32 as u32, u32::MAX), u32::MAX);
+        assert_eq!(AluOp::Mulhu.apply(u32::MAX, u32::MAX), u32::MAX - 1);
+        assert_eq!(AluOp::Sra.apply(0x8000_0000, 33), 0xc000_0000);
+        assert_eq!(AluOp::Slt.apply(-1i32 as u32, 0), 1);
+        assert_eq!(AluOp::Sltu.apply(-1i32 as u32, 0), 0);
+    }
+
+    #[test]
+    fn test_blocks_end_at_control_transfers() {
+        let vm = Vm::from_bin(vec![
+            addi(1, 0, 1),
+            addi(2, 0, 2),
+            (1 << 20) | (0b001 << 12) | 0b1100011, // bne x0, x1, 0
+            addi(3, 0, 3),
+        ])
+        .unwrap();
+        let block = vm.decode_block(0);
+
+        assert_eq!(block.ops.len(), 3);
+        assert_eq!(
+            block.ops[2],
+            CachedOp {
+                op: Op::Branch {
+                    cond: BranchCond::Ne,
+                    rs1: 0,
+                    rs2: 1,
+                    target: 8
+                },
+                pc: 8,
+                next_pc: 12
+            }
+        );
+        // System instructions are left to step
+        assert!(vm.decode_block(16).ops.is_empty());
+    }
+
+    #[test]
+    fn test_budget_stops_inside_a_block() {
+        let mut vm = Vm::from_bin(vec![addi(1, 1, 1); 8]).unwrap();
+        let outcome = vm.run(RunConfig {
+            max_instructions: Some(3),
+            ..RunConfig::default()
+        });
+
+        assert_eq!(outcome, RunOutcome::BudgetExhausted);
+        assert_eq!(vm.registers.read_reg(1), 3);
+        assert_eq!(vm.pc, 12);
+        assert_eq!(vm.csrs.instret, 3);
+    }
+
+    #[test]
+    fn test_store_to_code_invalidates_the_block() {
+        // Overwrite the instruction at 16, in the block being executed, with addi x2, x0, 7
+        let patch = addi(2, 0, 7);
+        let mut vm = Vm::from_bin(vec![
+            lui(1, patch.wrapping_add(0x800) >> 12),
+            addi(1, 1, ((patch & 0xfff) as i32) << 20 >> 20),
+            sw(0, 1, 16),
+            addi(3, 0, 3),
+            addi(2, 0, 1),
+            EBREAK,
+        ])
+        .unwrap();
+
+        assert_eq!(vm.run(RunConfig::default()), RunOutcome::Breakpoint(20));
+        assert_eq!(vm.registers.read_reg(2), 7);
+        assert_eq!(vm.registers.read_reg(3), 3);
+    }
+
+    #[test]
+    fn test_store_to_code_keeps_the_blocks_of_other_pages() {
+        // Jump to the next page, which overwrites the lui at 0 and stops
+        let mut program = vec![lui(5, 1), jalr(0, 5, 0)];
+        program.resize(0x400, 0);
+        program.extend([sw(0, 0, 0), EBREAK]);
+        let mut vm = Vm::from_bin(program).unwrap();
+
+        assert_eq!(vm.run(RunConfig::default()), RunOutcome::Breakpoint(0x1004));
+        assert!(vm.instruction_cache.get(0).is_none());
+        assert!(vm.instruction_cache.get(0x1000).is_some());
+    }
+
+    #[test]
+    fn test_fence_i_clears_the_cache() {
+        let mut vm = Vm::from_bin(vec![addi(1, 0, 1), FENCE_I, EBREAK]).unwrap();
+        vm.run(RunConfig::default());
+
+        assert!(vm.instruction_cache.get(0).is_none());
+        assert!(vm.instruction_cache.get(8).is_some());
+    }
+}
@@ -0,0 +1,332 @@
+//! This mod holds the RV32C (compressed) extension.
+//! Every 16-bit compressed instruction has an equivalent 32-bit instruction, so instead of executing
+//! them separately they are expanded and go through the regular decoder.
+use crate::vm::VMErrors;
+
+/// Returns true if the low half word of `insn` is a compressed instruction,
+/// 32-bit instructions always have their two lowest bits set.
+pub fn is_compressed(insn: u32) -> bool {
+    insn & 0b11 != 0b11
+}
+
+fn r_type(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    (funct7 << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+fn i_type(imm: i32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    (((imm as u32) & 0xfff) << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+fn s_type(imm: i32, rs2: u32, rs1: u32, funct3: u32, opcode: u32) -> u32 {
+    let imm = imm as u32;
+    (((imm >> 5) & 0x7f) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (funct3 << 12)
+        | ((imm & 0x1f) << 7)
+        | opcode
+}
+
+fn b_type(imm: i32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    let imm = imm as u32;
+    (((imm >> 12) & 0x1) << 31)
+        | (((imm >> 5) & 0x3f) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (funct3 << 12)
+        | (((imm >> 1) & 0xf) << 8)
+        | (((imm >> 11) & 0x1) << 7)
+        | 0b1100011
+}
+
+fn j_type(imm: i32, rd: u32) -> u32 {
+    let imm = imm as u32;
+    (((imm >> 20) & 0x1) << 31)
+        | (((imm >> 1) & 0x3ff) << 21)
+        | (((imm >> 11) & 0x1) << 20)
+        | (((imm >> 12) & 0xff) << 12)
+        | (rd << 7)
+        | 0b1101111
+}
+
+/// Returns bit `n` of `insn` moved to position `to`
+fn bit(insn: u32, n: u32, to: u32) -> u32 {
+    ((insn >> n) & 1) << to
+}
+
+/// Sign extends the lowest `bits` bits of `value`
+fn sign_extend(value: u32, bits: u32) -> i32 {
+    ((value << (32 - bits)) as i32) >> (32 - bits)
+}
+
+/// Returns one of the 8 registers (x8-x15) addressable by the 3-bit register fields
+fn creg(insn: u32, lsb: u32) -> u32 {
+    ((insn >> lsb) & 0x7) + 8
+}
+
+/// 6-bit signed immediate of C.ADDI, C.LI and C.ANDI: imm[5] = bit 12, imm[4:0] = bits 6:2
+fn ci_imm(insn: u32) -> i32 {
+    sign_extend(bit(insn, 12, 5) | ((insn >> 2) & 0x1f), 6)
+}
+
+/// Word offset of C.LW and C.SW: offset[5:3] = bits 12:10, offset[2] = bit 6, offset[6] = bit 5
+fn cl_offset(insn: u32) -> i32 {
+    (((insn >> 7) & 0x38) | bit(insn, 6, 2) | bit(insn, 5, 6)) as i32
+}
+
+/// Offset of C.J and C.JAL: offset[11|4|9:8|10|6|7|3:1|5] = bits 12:2
+fn cj_offset(insn: u32) -> i32 {
+    let offset = bit(insn, 12, 11)
+        | bit(insn, 11, 4)
+        | bit(insn, 10, 9)
+        | bit(insn, 9, 8)
+        | bit(insn, 8, 10)
+        | bit(insn, 7, 6)
+        | bit(insn, 6, 7)
+        | ((insn >> 2) & 0xe)
+        | bit(insn, 2, 5);
+    sign_extend(offset, 12)
+}
+
+/// Offset of C.BEQZ and C.BNEZ: offset[8|4:3] = bits 12:10, offset[7:6|2:1|5] = bits 6:2
+fn cb_offset(insn: u32) -> i32 {
+    let offset = bit(insn, 12, 8)
+        | ((insn >> 7) & 0x18)
+        | ((insn << 1) & 0xc0)
+        | ((insn >> 2) & 0x6)
+        | bit(insn, 2, 5);
+    sign_extend(offset, 9)
+}
+
+/// Expand a 16-bit compressed instruction into its 32-bit equivalent.
+///
+/// # Errors
+///
+/// Returns `InvalidInstruction` for reserved encodings and for the floating point loads and stores,
+/// since the F and D extensions are not supported.
+pub fn expand(insn: u16) -> Result<u32, VMErrors> {
+    let insn = insn as u32;
+    let funct3 = (insn >> 13) & 0x7;
+    let rd = (insn >> 7) & 0x1f;
+    let rs2 = (insn >> 2) & 0x1f;
+
+    match (insn & 0b11, funct3) {
+        (0b00, 0b000) => {
+            // C.ADDI4SPN: addi rd', x2, nzuimm
+            let imm =
+                ((insn >> 7) & 0x30) | ((insn >> 1) & 0x3c0) | bit(insn, 6, 2) | bit(insn, 5, 3);
+            if imm == 0 {
+                return Err(VMErrors::InvalidInstruction);
+            }
+            Ok(i_type(imm as i32, 2, 0b000, creg(insn, 2), 0b0010011))
+        }
+        (0b00, 0b010) => {
+            // C.LW: lw rd', offset(rs1')
+            Ok(i_type(
+                cl_offset(insn),
+                creg(insn, 7),
+                0b010,
+                creg(insn, 2),
+                0b0000011,
+            ))
+        }
+        (0b00, 0b110) => {
+            // C.SW: sw rs2', offset(rs1')
+            Ok(s_type(
+                cl_offset(insn),
+                creg(insn, 2),
+                creg(insn, 7),
+                0b010,
+                0b0100011,
+            ))
+        }
+        (0b01, 0b000) => {
+            // C.ADDI (C.NOP when rd is x0): addi rd, rd, imm
+            Ok(i_type(ci_imm(insn), rd, 0b000, rd, 0b0010011))
+        }
+        (0b01, 0b001) => {
+            // C.JAL: jal x1, offset
+            Ok(j_type(cj_offset(insn), 1))
+        }
+        (0b01, 0b010) => {
+            // C.LI: addi rd, x0, imm
+            Ok(i_type(ci_imm(insn), 0, 0b000, rd, 0b0010011))
+        }
+        (0b01, 0b011) if rd == 2 => {
+            // C.ADDI16SP: addi x2, x2, nzimm[9:4]
+            let imm = bit(insn, 12, 9)
+                | bit(insn, 6, 4)
+                | bit(insn, 5, 6)
+                | ((insn << 4) & 0x180)
+                | bit(insn, 2, 5);
+            if imm == 0 {
+                return Err(VMErrors::InvalidInstruction);
+            }
+            Ok(i_type(sign_extend(imm, 10), 2, 0b000, 2, 0b0010011))
+        }
+        (0b01, 0b011) => {
+            // C.LUI: lui rd, nzimm[17:12]
+            let imm = ci_imm(insn);
+            if imm == 0 {
+                return Err(VMErrors::InvalidInstruction);
+            }
+            Ok(((imm as u32) << 12) | (rd << 7) | 0b0110111)
+        }
+        (0b01, 0b100) => {
+            let rd = creg(insn, 7);
+            match (insn >> 10) & 0x3 {
+                0b00 | 0b01 if bit(insn, 12, 0) != 0 => Err(VMErrors::InvalidInstruction),
+                // C.SRLI: srli rd', rd', shamt
+                0b00 => Ok(i_type(rs2 as i32, rd, 0b101, rd, 0b0010011)),
+                // C.SRAI: srai rd', rd', shamt
+                0b01 => Ok(i_type((0x400 | rs2) as i32, rd, 0b101, rd, 0b0010011)),
+                // C.ANDI: andi rd', rd', imm
+                0b10 => Ok(i_type(ci_imm(insn), rd, 0b111, rd, 0b0010011)),
+                _ => {
+                    if bit(insn, 12, 0) != 0 {
+                        // C.SUBW and C.ADDW only exist on RV64
+                        return Err(VMErrors::InvalidInstruction);
+                    }
+                    let rs2 = creg(insn, 2);
+                    let (funct7, funct3) = match (insn >> 5) & 0x3 {
+                        0b00 => (0b0100000, 0b000), // C.SUB
+                        0b01 => (0b0000000, 0b100), // C.XOR
+                        0b10 => (0b0000000, 0b110), // C.OR
+                        _ => (0b0000000, 0b111),    // C.AND
+                    };
+                    Ok(r_type(funct7, rs2, rd, funct3, rd, 0b0110011))
+                }
+            }
+        }
+        (0b01, 0b101) => {
+            // C.J: jal x0, offset
+            Ok(j_type(cj_offset(insn), 0))
+        }
+        (0b01, 0b110) => {
+            // C.BEQZ: beq rs1', x0, offset
+            Ok(b_type(cb_offset(insn), 0, creg(insn, 7), 0b000))
+        }
+        (0b01, 0b111) => {
+            // C.BNEZ: bne rs1', x0, offset
+            Ok(b_type(cb_offset(insn), 0, creg(insn, 7), 0b001))
+        }
+        (0b10, 0b000) => {
+            // C.SLLI: slli rd, rd, shamt
+            if bit(insn, 12, 0) != 0 {
+                return Err(VMErrors::InvalidInstruction);
+            }
+            Ok(i_type(rs2 as i32, rd, 0b001, rd, 0b0010011))
+        }
+        (0b10, 0b010) => {
+            // C.LWSP: lw rd, offset(x2)
+            if rd == 0 {
+                return Err(VMErrors::InvalidInstruction);
+            }
+            let offset = bit(insn, 12, 5) | ((insn >> 2) & 0x1c) | ((insn << 4) & 0xc0);
+            Ok(i_type(offset as i32, 2, 0b010, rd, 0b0000011))
+        }
+        (0b10, 0b100) => match (bit(insn, 12, 0), rd, rs2) {
+            // C.JR: jalr x0, 0(rs1)
+            (0, 0, 0) => Err(VMErrors::InvalidInstruction),
+            (0, rs1, 0) => Ok(i_type(0, rs1, 0b000, 0, 0b1100111)),
+            // C.MV: add rd, x0, rs2
+            (0, rd, rs2) => Ok(r_type(0, rs2, 0, 0b000, rd, 0b0110011)),
+            // C.EBREAK
+            (_, 0, 0) => Ok(0x0010_0073),
+            // C.JALR: jalr x1, 0(rs1)
+            (_, rs1, 0) => Ok(i_type(0, rs1, 0b000, 1, 0b1100111)),
+            // C.ADD: add rd, rd, rs2
+            (_, rd, rs2) => Ok(r_type(0, rs2, rd, 0b000, rd, 0b0110011)),
+        },
+        (0b10, 0b110) => {
+            // C.SWSP: sw rs2, offset(x2)
+            let offset = ((insn >> 7) & 0x3c) | ((insn >> 1) & 0xc0);
+            Ok(s_type(offset as i32, rs2, 2, 0b010, 0b0100011))
+        }
+        _ => Err(VMErrors::InvalidInstruction),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{RunConfig, Vm};
+
+    #[test]
+    fn test_expand_matches_reference_encodings() {
+        // (compressed, expanded) pairs, as encoded by llvm-mc
+        let cases = [
+            (0x0808, 0x0101_0513), // c.addi4spn a0, sp, 16
+            (0x424c, 0x0046_2583), // c.lw a1, 4(a2)
+            (0xdf74, 0x06d7_2e23), // c.sw a3, 124(a4)
+            (0x1575, 0xffd5_0513), // c.addi a0, -3
+            (0x3001, 0x801f_f0ef), // c.jal -2048
+            (0x42fd, 0x01f0_0293), // c.li t0, 31
+            (0x7101, 0xe001_0113), // c.addi16sp sp, -512
+            (0x7785, 0xfffe_17b7), // c.lui a5, 0xfffe1
+            (0x800d, 0x0034_5413), // c.srli s0, 3
+            (0x84fd, 0x41f4_d493), // c.srai s1, 31
+            (0x997d, 0xfff5_7513), // c.andi a0, -1
+            (0x8d0d, 0x40b5_0533), // c.sub a0, a1
+            (0x8d2d, 0x00b5_4533), // c.xor a0, a1
+            (0x8c45, 0x0094_6433), // c.or s0, s1
+            (0x8f7d, 0x00f7_7733), // c.and a4, a5
+            (0xa101, 0x4000_006f), // c.j 1024
+            (0xd101, 0xf005_00e3), // c.beqz a0, -256
+            (0xecfd, 0x0e04_9f63), // c.bnez s1, 254
+            (0x0316, 0x0053_1313), // c.slli t1, 5
+            (0x50fe, 0x0fc1_2083), // c.lwsp ra, 252(sp)
+            (0x8082, 0x0000_8067), // c.jr ra
+            (0x851e, 0x0070_0533), // c.mv a0, t2
+            (0x9002, 0x0010_0073), // c.ebreak
+            (0x9282, 0x0002_80e7), // c.jalr t0
+            (0x9526, 0x0095_0533), // c.add a0, s1
+            (0xc0a2, 0x0481_2023), // c.swsp s0, 64(sp)
+        ];
+
+        for (compressed, expanded) in cases {
+            assert_eq!(
+                expand(compressed).unwrap(),
+                expanded,
+                "expanding {compressed:04x}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_reserved_encodings_are_rejected() {
+        for insn in [
+            0x0000, // all zeros
+            0x2000, // c.fld
+            0x6000, // c.flw
+            0x4002, // c.lwsp with rd = x0
+            0x8002, // c.jr with rs1 = x0
+            0x6101, // c.addi16sp with a zero immediate
+            0x1002, // c.slli with shamt[5] set
+            0x9d01, // c.subw
+        ] {
+            assert!(expand(insn).is_err(), "expanding {insn:04x}");
+        }
+    }
+
+    #[test]
+    fn test_mixed_width_execution() {
+        let program: [u16; 8] = [
+            0x4515, // c.li a0, 5
+            0x0593, 0x0070, // addi a1, x0, 7 (2-byte aligned)
+            0x952e, // c.add a0, a1
+            0x2011, // c.jal +4
+            0x9002, // c.ebreak
+            0x0506, // c.slli a0, 1
+            0x8082, // c.jr ra
+        ];
+        let bytes: Vec<u8> = program.iter().flat_map(|h| h.to_le_bytes()).collect();
+        let mut vm = Vm::from_bin(vec![]).unwrap();
+        vm.memory.load_bytes(0, &bytes);
+        vm.run(RunConfig::default());
+
+        assert_eq!(vm.registers.read_reg(10), 24);
+        assert_eq!(vm.registers.read_reg(1), 10);
+        assert_eq!(vm.pc, 10);
+    }
+}
@@ -0,0 +1,320 @@
+//! This mod holds the Zicsr extension and the machine-mode CSR file.
+//! The VM only implements machine mode, so every CSR the file knows about is accessible and the only
+//! permission check left is that read-only CSRs (address bits 11:10 set) are never written.
+//! Accessing a CSR the file does not implement is an illegal instruction.
+use crate::{
+    instructions::IType,
+    vm::{VMErrors, Vm},
+};
+use serde::{Deserialize, Serialize};
+
+pub const MSTATUS: u32 = 0x300;
+pub const MISA: u32 = 0x301;
+pub const MIE: u32 = 0x304;
+pub const MTVEC: u32 = 0x305;
+pub const MSCRATCH: u32 = 0x340;
+pub const MEPC: u32 = 0x341;
+pub const MCAUSE: u32 = 0x342;
+pub const MTVAL: u32 = 0x343;
+pub const MIP: u32 = 0x344;
+pub const MCYCLE: u32 = 0xb00;
+pub const MINSTRET: u32 = 0xb02;
+pub const MCYCLEH: u32 = 0xb80;
+pub const MINSTRETH: u32 = 0xb82;
+pub const CYCLE: u32 = 0xc00;
+pub const TIME: u32 = 0xc01;
+pub const INSTRET: u32 = 0xc02;
+pub const CYCLEH: u32 = 0xc80;
+pub const TIMEH: u32 = 0xc81;
+pub const INSTRETH: u32 = 0xc82;
+pub const MVENDORID: u32 = 0xf11;
+pub const MARCHID: u32 = 0xf12;
+pub const MIMPID: u32 = 0xf13;
+pub const MHARTID: u32 = 0xf14;
+
+/// mstatus.MIE, machine interrupts enabled
+pub const MSTATUS_MIE: u32 = 1 << 3;
+/// mstatus.MPIE, the value of MIE before the last trap
+pub const MSTATUS_MPIE: u32 = 1 << 7;
+/// mstatus.MPP, the privilege mode before the last trap, always machine mode
+pub const MSTATUS_MPP: u32 = 0b11 << 11;
+
+/// misa of an RV32IMAC hart: MXL = 1 (32-bit) and the A, C, I and M extension bits
+pub const MISA_VALUE: u32 = (1 << 30) | (1 << 0) | (1 << 2) | (1 << 8) | (1 << 12);
+
+/// The bits of mie backing the machine software, timer and external interrupts
+const MIE_MASK: u32 = (1 << 3) | (1 << 7) | (1 << 11);
+
+/// This is the machine-mode CSR file.
+/// Every instruction takes a single cycle and `time` ticks with `cycle`, which keeps the execution
+/// deterministic.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CsrFile {
+    pub mstatus: u32,
+    pub mtvec: u32,
+    pub mepc: u32,
+    pub mcause: u32,
+    pub mtval: u32,
+    pub mscratch: u32,
+    pub mie: u32,
+    pub mip: u32,
+    pub mhartid: u32,
+    pub cycle: u64,
+    pub instret: u64,
+}
+
+impl Default for CsrFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CsrFile {
+    pub fn new() -> Self {
+        Self {
+            mstatus: MSTATUS_MPP,
+            mtvec: 0,
+            mepc: 0,
+            mcause: 0,
+            mtval: 0,
+            mscratch: 0,
+            mie: 0,
+            mip: 0,
+            mhartid: 0,
+            cycle: 0,
+            instret: 0,
+        }
+    }
+
+    /// Returns true if the CSR at `addr` is read-only
+    pub fn is_read_only(addr: u32) -> bool {
+        (addr >> 10) & 0b11 == 0b11
+    }
+
+    /// Read the CSR at `addr`.
+    pub fn read(&self, addr: u32) -> Result<u32, VMErrors> {
+        let value = match addr {
+            MSTATUS => self.mstatus,
+            MISA => MISA_VALUE,
+            MIE => self.mie,
+            MTVEC => self.mtvec,
+            MSCRATCH => self.mscratch,
+            MEPC => self.mepc,
+            MCAUSE => self.mcause,
+            MTVAL => self.mtval,
+            MIP => self.mip,
+            MCYCLE | CYCLE | TIME => self.cycle as u32,
+            MCYCLEH | CYCLEH | TIMEH => (self.cycle >> 32) as u32,
+            MINSTRET | INSTRET => self.instret as u32,
+            MINSTRETH | INSTRETH => (self.instret >> 32) as u32,
+            MVENDORID | MARCHID | MIMPID => 0,
+            MHARTID => self.mhartid,
+            _ => return Err(VMErrors::InvalidCsr(addr)),
+        };
+
+        Ok(value)
+    }
+
+    /// Write the CSR at `addr`.
+    /// Fields are WARL, bits that cannot hold the written value keep a legal one.
+    pub fn write(&mut self, addr: u32, value: u32) -> Result<(), VMErrors> {
+        if Self::is_read_only(addr) {
+            return Err(VMErrors::InvalidCsr(addr));
+        }
+
+        match addr {
+            MSTATUS => self.mstatus = (value & (MSTATUS_MIE | MSTATUS_MPIE)) | MSTATUS_MPP,
+            // misa is not writable, the extensions can not be turned off
+            MISA => {}
+            MIE => self.mie = value & MIE_MASK,
+            // Only the direct (0) and vectored (1) modes exist
+            MTVEC => {
+                self.mtvec = if value & 0b11 > 1 {
+                    value & !0b11
+                } else {
+                    value
+                }
+            }
+            MSCRATCH => self.mscratch = value,
+            MEPC => self.mepc = value & !0b1,
+            MCAUSE => self.mcause = value,
+            MTVAL => self.mtval = value,
+            // There are no interrupt sources yet, so nothing is ever pending
+            MIP => {}
+            MCYCLE => self.cycle = (self.cycle & !0xffff_ffff) | value as u64,
+            MCYCLEH => self.cycle = (self.cycle & 0xffff_ffff) | ((value as u64) << 32),
+            MINSTRET => self.instret = (self.instret & !0xffff_ffff) | value as u64,
+            MINSTRETH => self.instret = (self.instret & 0xffff_ffff) | ((value as u64) << 32),
+            _ => return Err(VMErrors::InvalidCsr(addr)),
+        }
+
+        Ok(())
+    }
+
+    /// Count a retired instruction
+    pub fn retire(&mut self) {
+        self.cycle = self.cycle.wrapping_add(1);
+        self.instret = self.instret.wrapping_add(1);
+    }
+}
+
+/// Execute CSRRW, CSRRS, CSRRC or one of their immediate variants.
+/// CSRRW does not read the CSR when rd is x0, CSRRS and CSRRC do not write it when rs1 (or the
+/// immediate) is 0, so reading a read-only CSR with them is legal.
+pub fn process_csr(vm: &mut Vm, decoded_instruction: &IType) -> Result<(), VMErrors> {
+    let addr = (decoded_instruction.imm as u32) & 0xfff;
+    let rd = decoded_instruction.rd as u32;
+    let rs1 = decoded_instruction.rs1 as u32;
+
+    // The immediate variants use the rs1 field as a 5-bit unsigned immediate
+    let src = if decoded_instruction.funct3 & 0b100 != 0 {
+        rs1
+    } else {
+        vm.read_reg(rs1)
+    };
+
+    let (old, new) = match decoded_instruction.funct3 & 0b011 {
+        0b01 => {
+            // csrrw, csrrwi
+            let old = if rd != 0 { vm.read_csr(addr)? } else { 0 };
+            (old, Some(src))
+        }
+        0b10 => {
+            // csrrs, csrrsi
+            let old = vm.read_csr(addr)?;
+            (old, (rs1 != 0).then_some(old | src))
+        }
+        0b11 => {
+            // csrrc, csrrci
+            let old = vm.read_csr(addr)?;
+            (old, (rs1 != 0).then_some(old & !src))
+        }
+        _ => return Err(VMErrors::InvalidFunct3(decoded_instruction.funct3)),
+    };
+
+    if let Some(new) = new {
+        vm.write_csr(addr, new)?;
+    }
+    vm.write_reg(rd, old);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{addi, csr, EBREAK};
+    use crate::vm::RunConfig;
+
+    fn run(program: Vec<u32>) -> Vm {
+        let mut vm = Vm::from_bin(program).unwrap();
+        vm.run(RunConfig::default());
+        vm
+    }
+
+    #[test]
+    fn test_encoding_matches_reference() {
+        // as encoded by llvm-mc
+        assert_eq!(csr(0b001, 10, 11, MSCRATCH), 0x3405_9573); // csrrw a0, mscratch, a1
+        assert_eq!(csr(0b110, 0, 8, MSTATUS), 0x3004_6073); // csrsi mstatus, 8
+        assert_eq!(csr(0b010, 10, 0, MHARTID), 0xf140_2573); // csrr a0, mhartid
+    }
+
+    #[test]
+    fn test_csrrw_swaps() {
+        let vm = run(vec![
+            addi(1, 0, 0x55),
+            csr(0b001, 0, 1, MSCRATCH),
+            addi(1, 0, 0x66),
+            csr(0b001, 2, 1, MSCRATCH),
+            EBREAK,
+        ]);
+
+        assert_eq!(vm.registers.read_reg(2), 0x55);
+        assert_eq!(vm.csrs.mscratch, 0x66);
+    }
+
+    #[test]
+    fn test_csrrs_and_csrrc() {
+        let vm = run(vec![
+            addi(1, 0, 0b1100),
+            csr(0b010, 2, 1, MSCRATCH), // csrrs
+            addi(1, 0, 0b0110),
+            csr(0b011, 3, 1, MSCRATCH), // csrrc
+            EBREAK,
+        ]);
+
+        assert_eq!(vm.registers.read_reg(2), 0);
+        assert_eq!(vm.registers.read_reg(3), 0b1100);
+        assert_eq!(vm.csrs.mscratch, 0b1000);
+    }
+
+    #[test]
+    fn test_immediate_variants() {
+        let vm = run(vec![
+            csr(0b101, 0, 0x1f, MTVAL),    // csrrwi
+            csr(0b111, 1, 0b00011, MTVAL), // csrrci
+            csr(0b110, 2, 0b10000, MEPC),  // csrrsi
+            csr(0b110, 3, 0, MTVAL),       // csrrsi with a zero immediate only reads
+            EBREAK,
+        ]);
+
+        assert_eq!(vm.registers.read_reg(1), 0x1f);
+        assert_eq!(vm.registers.read_reg(2), 0);
+        assert_eq!(vm.registers.read_reg(3), 0x1c);
+        assert_eq!(vm.csrs.mepc, 0x10);
+    }
+
+    #[test]
+    fn test_read_only_csrs() {
+        let vm = run(vec![
+            csr(0b010, 1, 0, MHARTID),
+            csr(0b010, 2, 0, MISA),
+            csr(0b010, 3, 0, CYCLE),
+            csr(0b010, 4, 0, INSTRET),
+            csr(0b010, 5, 0, TIME),
+            EBREAK,
+        ]);
+
+        assert_eq!(vm.registers.read_reg(1), 0);
+        assert_eq!(vm.registers.read_reg(2), MISA_VALUE);
+        assert_eq!(vm.registers.read_reg(3), 2);
+        assert_eq!(vm.registers.read_reg(4), 3);
+        assert_eq!(vm.registers.read_reg(5), 4);
+        assert_eq!(vm.csrs.instret, 5);
+
+        let mut vm = Vm::from_bin(vec![csr(0b001, 0, 1, CYCLE)]).unwrap();
+        assert!(matches!(vm.step(), Err(VMErrors::InvalidCsr(CYCLE))));
+
+        let mut vm = Vm::from_bin(vec![csr(0b110, 0, 1, MHARTID)]).unwrap();
+        assert!(matches!(vm.step(), Err(VMErrors::InvalidCsr(MHARTID))));
+    }
+
+    #[test]
+    fn test_unknown_csr_is_an_error() {
+        // satp, the VM has no supervisor mode
+        let mut vm = Vm::from_bin(vec![csr(0b010, 1, 0, 0x180)]).unwrap();
+        vm.registers.write_reg(1, 7);
+
+        assert!(matches!(vm.step(), Err(VMErrors::InvalidCsr(0x180))));
+        assert_eq!(vm.registers.read_reg(1), 7);
+        assert_eq!(vm.pc, 0);
+    }
+
+    #[test]
+    fn test_warl_fields() {
+        let mut csrs = CsrFile::new();
+        csrs.write(MSTATUS, u32::MAX).unwrap();
+        csrs.write(MISA, 0).unwrap();
+        csrs.write(MTVEC, 0x8000_0003).unwrap();
+        csrs.write(MEPC, 0x8000_0003).unwrap();
+        csrs.write(MCYCLEH, 1).unwrap();
+
+        assert_eq!(csrs.mstatus, MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP);
+        assert_eq!(csrs.read(MISA).unwrap(), MISA_VALUE);
+        assert_eq!(csrs.mtvec, 0x8000_0000);
+        assert_eq!(csrs.mepc, 0x8000_0002);
+        assert_eq!(csrs.read(CYCLEH).unwrap(), 1);
+        assert_eq!(csrs.cycle, 1 << 32);
+    }
+}
@@ -0,0 +1,546 @@
+//! This mod holds the disassembler.
+//! It turns instructions back into assembly the way `objdump` prints it: canonical mnemonics, ABI
+//! register names, the usual pseudo-instructions (`li`, `mv`, `ret`, `j`, `nop`, ..) and jump and
+//! branch targets resolved to the nearest symbol of the ELF, as in `jal 0x2008f0 <main+0x1c>`.
+//! Compressed instructions are shown as the instruction they expand to.
+use crate::{
+    csr::{
+        CYCLE, CYCLEH, INSTRET, INSTRETH, MARCHID, MCAUSE, MCYCLE, MCYCLEH, MEPC, MHARTID, MIE,
+        MIMPID, MINSTRET, MINSTRETH, MIP, MISA, MSCRATCH, MSTATUS, MTVAL, MTVEC, MVENDORID, TIME,
+        TIMEH,
+    },
+    instructions::{
+        DecodedInstruction, InstructionDecoder, MiscMemOp, ATOMIC_CLASS, BRANCH_CLASS,
+        ENVIRONMENT_CLASS, IMMEDIATE_CLASS, IMMEDIATE_LOAD_CLASS, JALR_CLASS, JAL_CLASS,
+        MISC_MEM_CLASS, UPPER_IMMEDIATE_CLASS,
+    },
+    vm::VMErrors,
+};
+use elf_parser::Elf;
+use std::{collections::BTreeMap, fmt::Write};
+
+/// The ABI names of the registers
+pub const REGISTER_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// The encoding of `unimp`, a write of the read-only cycle CSR
+const UNIMP: u32 = 0xc000_1073;
+
+/// The names of the CSRs the VM implements
+const CSR_NAMES: [(u32, &str); 23] = [
+    (MSTATUS, "mstatus"),
+    (MISA, "misa"),
+    (MIE, "mie"),
+    (MTVEC, "mtvec"),
+    (MSCRATCH, "mscratch"),
+    (MEPC, "mepc"),
+    (MCAUSE, "mcause"),
+    (MTVAL, "mtval"),
+    (MIP, "mip"),
+    (MCYCLE, "mcycle"),
+    (MINSTRET, "minstret"),
+    (MCYCLEH, "mcycleh"),
+    (MINSTRETH, "minstreth"),
+    (CYCLE, "cycle"),
+    (TIME, "time"),
+    (INSTRET, "instret"),
+    (CYCLEH, "cycleh"),
+    (TIMEH, "timeh"),
+    (INSTRETH, "instreth"),
+    (MVENDORID, "mvendorid"),
+    (MARCHID, "marchid"),
+    (MIMPID, "mimpid"),
+    (MHARTID, "mhartid"),
+];
+
+fn reg(number: usize) -> &'static str {
+    REGISTER_NAMES[number]
+}
+
+fn csr_name(csr: u32) -> String {
+    CSR_NAMES
+        .iter()
+        .find(|(number, _)| *number == csr)
+        .map_or_else(|| format!("0x{:x}", csr), |(_, name)| name.to_string())
+}
+
+/// The `iorw` set of a fence predecessor or successor
+fn fence_set(bits: u32) -> String {
+    let set: String = "iorw"
+        .chars()
+        .enumerate()
+        .filter(|(i, _)| bits & (0b1000 >> i) != 0)
+        .map(|(_, c)| c)
+        .collect();
+    if set.is_empty() {
+        String::from("0")
+    } else {
+        set
+    }
+}
+
+/// This is a symbol known to the disassembler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Label {
+    name: String,
+    size: u32,
+}
+
+/// This disassembles instructions, resolving addresses to the symbols it was given.
+#[derive(Debug, Clone, Default)]
+pub struct Disassembler {
+    symbols: BTreeMap<u32, Label>,
+}
+
+impl Disassembler {
+    /// Create a disassembler without symbols, addresses are shown as they are.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a disassembler resolving addresses to the demangled symbols of an ELF file.
+    /// When several symbols share an address the functions win.
+    pub fn from_elf(elf: &Elf) -> Self {
+        let mut disassembler = Self::new();
+        let (functions, others): (Vec<_>, Vec<_>) =
+            elf.symbols.iter().partition(|symbol| symbol.is_function);
+        for symbol in functions.into_iter().chain(others) {
+            disassembler.add_symbol(symbol.addr, symbol.size, &symbol.demangled());
+        }
+        disassembler
+    }
+
+    /// Add a symbol of `size` bytes at `addr`, a size of 0 covers everything up to the next symbol.
+    /// The first symbol added at an address is the one shown.
+    pub fn add_symbol(&mut self, addr: u32, size: u32, name: &str) {
+        self.symbols.entry(addr).or_insert_with(|| Label {
+            name: name.to_string(),
+            size,
+        });
+    }
+
+    /// Returns the name of the symbol starting at `addr`.
+    pub fn symbol_at(&self, addr: u32) -> Option<&str> {
+        self.symbols.get(&addr).map(|label| label.name.as_str())
+    }
+
+    /// Returns the address of the symbol `addr` falls in.
+    pub fn symbol_start(&self, addr: u32) -> Option<u32> {
+        let (start, label) = self.symbols.range(..=addr).next_back()?;
+        (label.size == 0 || addr - start < label.size).then_some(*start)
+    }
+
+    /// Returns `addr` relative to the symbol it falls in, as in `main+0x1c`.
+    pub fn symbolize(&self, addr: u32) -> Option<String> {
+        let (start, label) = self.symbols.range(..=addr).next_back()?;
+        let offset = addr - start;
+        if label.size != 0 && offset >= label.size {
+            return None;
+        }
+        if offset == 0 {
+            Some(label.name.clone())
+        } else {
+            Some(format!("{}+0x{:x}", label.name, offset))
+        }
+    }
+
+    /// Returns the target of a jump or a branch, followed by its symbol if there is one.
+    fn target(&self, addr: u32) -> String {
+        match self.symbolize(addr) {
+            Some(symbol) => format!("0x{:x} <{}>", addr, symbol),
+            None => format!("0x{:x}", addr),
+        }
+    }
+
+    /// Disassemble the instruction at `pc`, compressed instructions are in the lower 16 bits.
+    /// # Errors
+    /// This function returns an error if the instruction is not valid.
+    pub fn disassemble(&self, pc: u32, instruction: u32) -> Result<String, VMErrors> {
+        let decoded = InstructionDecoder::decode(&instruction)?;
+
+        match (&decoded.decoded_instruction, decoded.opcode) {
+            (DecodedInstruction::RType(r), ATOMIC_CLASS) => {
+                let name = match r.funct7 >> 2 {
+                    0b00010 if r.rs2 == 0 => "lr.w",
+                    0b00011 => "sc.w",
+                    0b00001 => "amoswap.w",
+                    0b00000 => "amoadd.w",
+                    0b00100 => "amoxor.w",
+                    0b01100 => "amoand.w",
+                    0b01000 => "amoor.w",
+                    0b10000 => "amomin.w",
+                    0b10100 => "amomax.w",
+                    0b11000 => "amominu.w",
+                    0b11100 => "amomaxu.w",
+                    _ => return Err(VMErrors::InvalidFunct7(r.funct7)),
+                };
+                if r.funct3 != 0b010 {
+                    return Err(VMErrors::InvalidFunct3(r.funct3));
+                }
+                let ordering = match r.funct7 & 0b11 {
+                    0b10 => ".aq",
+                    0b01 => ".rl",
+                    0b11 => ".aqrl",
+                    _ => "",
+                };
+                Ok(if name == "lr.w" {
+                    format!("{}{} {}, ({})", name, ordering, reg(r.rd), reg(r.rs1))
+                } else {
+                    format!(
+                        "{}{} {}, {}, ({})",
+                        name,
+                        ordering,
+                        reg(r.rd),
+                        reg(r.rs2),
+                        reg(r.rs1)
+                    )
+                })
+            }
+            (DecodedInstruction::RType(r), _) => {
+                let name = match (r.funct7, r.funct3) {
+                    (0x00, 0b000) => "add",
+                    (0x20, 0b000) => "sub",
+                    (0x00, 0b001) => "sll",
+                    (0x00, 0b010) => "slt",
+                    (0x00, 0b011) => "sltu",
+                    (0x00, 0b100) => "xor",
+                    (0x00, 0b101) => "srl",
+                    (0x20, 0b101) => "sra",
+                    (0x00, 0b110) => "or",
+                    (0x00, 0b111) => "and",
+                    (0x01, 0b000) => "mul",
+                    (0x01, 0b001) => "mulh",
+                    (0x01, 0b010) => "mulhsu",
+                    (0x01, 0b011) => "mulhu",
+                    (0x01, 0b100) => "div",
+                    (0x01, 0b101) => "divu",
+                    (0x01, 0b110) => "rem",
+                    (0x01, 0b111) => "remu",
+                    (0x00 | 0x01 | 0x20, _) => return Err(VMErrors::InvalidFunct3(r.funct3)),
+                    _ => return Err(VMErrors::InvalidFunct7(r.funct7)),
+                };
+                let (rd, rs1, rs2) = (reg(r.rd), reg(r.rs1), reg(r.rs2));
+                Ok(match (name, r.rs1, r.rs2) {
+                    ("add", 0, _) => format!("mv {}, {}", rd, rs2),
+                    ("sub", 0, _) => format!("neg {}, {}", rd, rs2),
+                    ("sltu", 0, _) => format!("snez {}, {}", rd, rs2),
+                    ("slt", _, 0) => format!("sltz {}, {}", rd, rs1),
+                    ("slt", 0, _) => format!("sgtz {}, {}", rd, rs2),
+                    _ => format!("{} {}, {}, {}", name, rd, rs1, rs2),
+                })
+            }
+            (DecodedInstruction::IType(i), IMMEDIATE_CLASS) => {
+                let (rd, rs1) = (reg(i.rd), reg(i.rs1));
+                let shamt = i.metadata.imm_shift_amt;
+                Ok(match (i.funct3, i.metadata.funct7) {
+                    (0b000, _) if i.rd == 0 && i.rs1 == 0 && i.imm == 0 => String::from("nop"),
+                    (0b000, _) if i.rs1 == 0 => format!("li {}, {}", rd, i.imm),
+                    (0b000, _) if i.imm == 0 => format!("mv {}, {}", rd, rs1),
+                    (0b000, _) => format!("addi {}, {}, {}", rd, rs1, i.imm),
+                    (0b010, _) => format!("slti {}, {}, {}", rd, rs1, i.imm),
+                    (0b011, _) if i.imm == 1 => format!("seqz {}, {}", rd, rs1),
+                    (0b011, _) => format!("sltiu {}, {}, {}", rd, rs1, i.imm),
+                    (0b100, _) if i.imm == -1 => format!("not {}, {}", rd, rs1),
+                    (0b100, _) => format!("xori {}, {}, {}", rd, rs1, i.imm),
+                    (0b110, _) => format!("ori {}, {}, {}", rd, rs1, i.imm),
+                    (0b111, _) => format!("andi {}, {}, {}", rd, rs1, i.imm),
+                    (0b001, 0x00) => format!("slli {}, {}, {}", rd, rs1, shamt),
+                    (0b101, 0x00) => format!("srli {}, {}, {}", rd, rs1, shamt),
+                    (0b101, 0x20) => format!("srai {}, {}, {}", rd, rs1, shamt),
+                    (_, funct7) => return Err(VMErrors::InvalidFunct7(funct7)),
+                })
+            }
+            (DecodedInstruction::IType(i), IMMEDIATE_LOAD_CLASS) => {
+                let name = match i.funct3 {
+                    0b000 => "lb",
+                    0b001 => "lh",
+                    0b010 => "lw",
+                    0b100 => "lbu",
+                    0b101 => "lhu",
+                    _ => return Err(VMErrors::InvalidFunct3(i.funct3)),
+                };
+                Ok(format!("{} {}, {}({})", name, reg(i.rd), i.imm, reg(i.rs1)))
+            }
+            (DecodedInstruction::IType(i), JALR_CLASS) => Ok(match (i.rd, i.rs1, i.imm) {
+                (0, 1, 0) => String::from("ret"),
+                (0, _, 0) => format!("jr {}", reg(i.rs1)),
+                (0, _, _) => format!("jr {}({})", i.imm, reg(i.rs1)),
+                (1, _, 0) => format!("jalr {}", reg(i.rs1)),
+                (1, _, _) => format!("jalr {}({})", i.imm, reg(i.rs1)),
+                _ => format!("jalr {}, {}({})", reg(i.rd), i.imm, reg(i.rs1)),
+            }),
+            (DecodedInstruction::IType(i), MISC_MEM_CLASS) => Ok(match MiscMemOp::new(i)? {
+                MiscMemOp::Fence {
+                    pred: 0b1111,
+                    succ: 0b1111,
+                } => String::from("fence"),
+                MiscMemOp::Fence { pred, succ } => {
+                    format!("fence {}, {}", fence_set(pred), fence_set(succ))
+                }
+                MiscMemOp::FenceTso => String::from("fence.tso"),
+                MiscMemOp::Pause => String::from("pause"),
+                MiscMemOp::FenceI => String::from("fence.i"),
+            }),
+            (DecodedInstruction::IType(i), ENVIRONMENT_CLASS) => {
+                let csr = i.imm as u32 & 0xfff;
+                let (rd, rs1) = (reg(i.rd), reg(i.rs1));
+                Ok(match i.funct3 {
+                    0b000 => match (csr, i.rd, i.rs1) {
+                        (0x000, 0, 0) => String::from("ecall"),
+                        (0x001, 0, 0) => String::from("ebreak"),
+                        (0x302, 0, 0) => String::from("mret"),
+                        (0x105, 0, 0) => String::from("wfi"),
+                        _ => return Err(VMErrors::InvalidOpcode(decoded.opcode)),
+                    },
+                    0b001 if instruction == UNIMP => String::from("unimp"),
+                    0b001 if i.rd == 0 => format!("csrw {}, {}", csr_name(csr), rs1),
+                    0b010 if i.rs1 == 0 => format!("csrr {}, {}", rd, csr_name(csr)),
+                    0b010 if i.rd == 0 => format!("csrs {}, {}", csr_name(csr), rs1),
+                    0b011 if i.rd == 0 => format!("csrc {}, {}", csr_name(csr), rs1),
+                    0b101 if i.rd == 0 => format!("csrwi {}, {}", csr_name(csr), i.rs1),
+                    0b110 if i.rd == 0 => format!("csrsi {}, {}", csr_name(csr), i.rs1),
+                    0b111 if i.rd == 0 => format!("csrci {}, {}", csr_name(csr), i.rs1),
+                    0b001..=0b011 => format!(
+                        "{} {}, {}, {}",
+                        ["csrrw", "csrrs", "csrrc"][i.funct3 as usize - 1],
+                        rd,
+                        csr_name(csr),
+                        rs1
+                    ),
+                    0b101..=0b111 => format!(
+                        "{} {}, {}, {}",
+                        ["csrrwi", "csrrsi", "csrrci"][i.funct3 as usize - 5],
+                        rd,
+                        csr_name(csr),
+                        i.rs1
+                    ),
+                    _ => return Err(VMErrors::InvalidFunct3(i.funct3)),
+                })
+            }
+            (DecodedInstruction::IType(_), opcode) => Err(VMErrors::InvalidOpcode(opcode)),
+            (DecodedInstruction::SType(s), _) => {
+                let name = match s.funct3 {
+                    0b000 => "sb",
+                    0b001 => "sh",
+                    0b010 => "sw",
+                    _ => return Err(VMErrors::InvalidFunct3(s.funct3)),
+                };
+                Ok(format!(
+                    "{} {}, {}({})",
+                    name,
+                    reg(s.rs2),
+                    s.imm,
+                    reg(s.rs1)
+                ))
+            }
+            (DecodedInstruction::BType(b), BRANCH_CLASS) => {
+                let name = match b.funct3 {
+                    0b000 => "beq",
+                    0b001 => "bne",
+                    0b100 => "blt",
+                    0b101 => "bge",
+                    0b110 => "bltu",
+                    0b111 => "bgeu",
+                    _ => return Err(VMErrors::InvalidFunct3(b.funct3)),
+                };
+                let target = self.target(pc.wrapping_add(b.imm as u32));
+                let (rs1, rs2) = (reg(b.rs1), reg(b.rs2));
+                Ok(match (name, b.rs1, b.rs2) {
+                    ("beq", _, 0) => format!("beqz {}, {}", rs1, target),
+                    ("bne", _, 0) => format!("bnez {}, {}", rs1, target),
+                    ("bge", 0, _) => format!("blez {}, {}", rs2, target),
+                    ("bge", _, 0) => format!("bgez {}, {}", rs1, target),
+                    ("blt", _, 0) => format!("bltz {}, {}", rs1, target),
+                    ("blt", 0, _) => format!("bgtz {}, {}", rs2, target),
+                    _ => format!("{} {}, {}, {}", name, rs1, rs2, target),
+                })
+            }
+            (DecodedInstruction::BType(_), opcode) => Err(VMErrors::InvalidOpcode(opcode)),
+            (DecodedInstruction::UType(u), opcode) => {
+                let name = if opcode == UPPER_IMMEDIATE_CLASS {
+                    "lui"
+                } else {
+                    "auipc"
+                };
+                Ok(format!(
+                    "{} {}, 0x{:x}",
+                    name,
+                    reg(u.rd),
+                    (u.imm as u32) >> 12
+                ))
+            }
+            (DecodedInstruction::JType(j), JAL_CLASS) => {
+                let target = self.target(pc.wrapping_add(j.imm as u32));
+                Ok(match j.rd {
+                    0 => format!("j {}", target),
+                    1 => format!("jal {}", target),
+                    _ => format!("jal {}, {}", reg(j.rd), target),
+                })
+            }
+            (DecodedInstruction::JType(_), opcode) => Err(VMErrors::InvalidOpcode(opcode)),
+        }
+    }
+
+    /// Disassemble the executable segments of an ELF file into an `objdump`-like listing, with a
+    /// label before every symbol.
+    pub fn disassemble_elf(&self, elf: &Elf) -> String {
+        let byte = |addr: u32| {
+            let word = elf.memory_image.get(&(addr & !3)).copied().unwrap_or(0);
+            (word >> (8 * (addr & 3))) as u8
+        };
+        let mut listing = String::new();
+        let mut segments: Vec<_> = elf
+            .segments
+            .iter()
+            .filter(|segment| segment.permissions.execute)
+            .collect();
+        segments.sort_by_key(|segment| segment.start);
+
+        for segment in segments {
+            let end = segment.start.saturating_add(segment.size);
+            let mut addr = segment.start;
+            while addr < end {
+                if let Some(name) = self.symbol_at(addr) {
+                    let _ = write!(listing, "\n{:08x} <{}>:\n", addr, name);
+                }
+                let half = u16::from_le_bytes([byte(addr), byte(addr + 1)]);
+                let (instruction, size) = if half & 0b11 != 0b11 || end - addr < 4 {
+                    (half as u32, 2)
+                } else {
+                    let upper = u16::from_le_bytes([byte(addr + 2), byte(addr + 3)]);
+                    (half as u32 | (upper as u32) << 16, 4)
+                };
+                let encoding = if size == 2 {
+                    format!("{:04x}    ", instruction)
+                } else {
+                    format!("{:08x}", instruction)
+                };
+                let text = self.disassemble(addr, instruction).unwrap_or_else(|_| {
+                    format!(".{} 0x{:x}", ["half", "word"][size / 4], instruction)
+                });
+                let _ = writeln!(listing, "{:8x}:  {}  {}", addr, encoding, text);
+                addr = addr.saturating_add(size as u32);
+            }
+        }
+
+        listing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disassemble(pc: u32, instruction: u32) -> String {
+        let mut disassembler = Disassembler::new();
+        disassembler.add_symbol(0x100, 0x40, "main");
+        disassembler.add_symbol(0x200, 0, "loop");
+        disassembler.disassemble(pc, instruction).unwrap()
+    }
+
+    #[test]
+    fn test_canonical_mnemonics() {
+        // addi a0, a1, 5
+        assert_eq!(disassemble(0, 0x0055_8513), "addi a0, a1, 5");
+        // add s0, s1, t6
+        assert_eq!(disassemble(0, 0x01f4_8433), "add s0, s1, t6");
+        // sub a0, a1, a2
+        assert_eq!(disassemble(0, 0x40c5_8533), "sub a0, a1, a2");
+        // mulhu t0, t1, t2
+        assert_eq!(disassemble(0, 0x0273_32b3), "mulhu t0, t1, t2");
+        // srai a0, a0, 3
+        assert_eq!(disassemble(0, 0x4035_5513), "srai a0, a0, 3");
+        assert_eq!(disassemble(0, 0xff81_2503), "lw a0, -8(sp)");
+        assert_eq!(disassemble(0, 0x00a1_2423), "sw a0, 8(sp)");
+        assert_eq!(disassemble(0, 0x0001_2537), "lui a0, 0x12");
+        assert_eq!(disassemble(0, 0x0000_0517), "auipc a0, 0x0");
+        assert_eq!(disassemble(0, 0x0000_0073), "ecall");
+        assert_eq!(disassemble(0, 0x0010_0073), "ebreak");
+        assert_eq!(disassemble(0, 0x3020_0073), "mret");
+        // csrrw a0, mtvec, a1
+        assert_eq!(disassemble(0, 0x3055_9573), "csrrw a0, mtvec, a1");
+        assert_eq!(disassemble(0, 0x3420_2573), "csrr a0, mcause");
+        assert_eq!(disassemble(0, 0x3050_d073), "csrwi mtvec, 1");
+        assert_eq!(disassemble(0, 0xc000_1073), "unimp");
+        assert_eq!(disassemble(0, 0x1005_a52f), "lr.w a0, (a1)");
+        assert_eq!(disassemble(0, 0x1ec5_a52f), "sc.w.aqrl a0, a2, (a1)");
+        assert_eq!(disassemble(0, 0x04c5_a52f), "amoadd.w.aq a0, a2, (a1)");
+        assert_eq!(disassemble(0, 0x0ff0_000f), "fence");
+        assert_eq!(disassemble(0, 0x0230_000f), "fence r, rw");
+        assert_eq!(disassemble(0, 0x8330_000f), "fence.tso");
+        assert_eq!(disassemble(0, 0x0100_000f), "pause");
+        assert_eq!(disassemble(0, 0x0000_100f), "fence.i");
+    }
+
+    #[test]
+    fn test_pseudo_instructions() {
+        assert_eq!(disassemble(0, 0x0000_0013), "nop");
+        assert_eq!(disassemble(0, 0x0050_0513), "li a0, 5");
+        assert_eq!(disassemble(0, 0xfff0_0513), "li a0, -1");
+        assert_eq!(disassemble(0, 0x0005_8513), "mv a0, a1");
+        assert_eq!(disassemble(0, 0xfff5_c513), "not a0, a1");
+        assert_eq!(disassemble(0, 0x40b0_0533), "neg a0, a1");
+        assert_eq!(disassemble(0, 0x0015_b513), "seqz a0, a1");
+        assert_eq!(disassemble(0, 0x00b0_3533), "snez a0, a1");
+        assert_eq!(disassemble(0, 0x0000_8067), "ret");
+        assert_eq!(disassemble(0, 0x0005_0067), "jr a0");
+        assert_eq!(disassemble(0, 0x0005_00e7), "jalr a0");
+        assert_eq!(disassemble(0, 0x0045_00e7), "jalr 4(a0)");
+        assert_eq!(disassemble(0, 0x0045_02e7), "jalr t0, 4(a0)");
+        assert_eq!(disassemble(0, 0x3000_2573), "csrr a0, mstatus");
+        assert_eq!(disassemble(0, 0x3005_9073), "csrw mstatus, a1");
+        // Compressed instructions are shown expanded, c.li a0, 5 and c.jr ra
+        assert_eq!(disassemble(0, 0x4515), "li a0, 5");
+        assert_eq!(disassemble(0, 0x8082), "ret");
+        // c.mv a1, a3 expands to add a1, zero, a3
+        assert_eq!(disassemble(0, 0x85b6), "mv a1, a3");
+    }
+
+    #[test]
+    fn test_targets_are_symbolized() {
+        // j +0x1c from 0x100
+        assert_eq!(disassemble(0x100, 0x01c0_006f), "j 0x11c <main+0x1c>");
+        // jal +0x100 from 0x100
+        assert_eq!(disassemble(0x100, 0x1000_00ef), "jal 0x200 <loop>");
+        // jal t0, -0x100 from 0x200, before any symbol
+        assert_eq!(disassemble(0x200, 0xf01f_f2ef), "jal t0, 0x100 <main>");
+        // bne a0, a1, -8 from 0x208, 0x200 has no size so it covers everything after it
+        assert_eq!(disassemble(0x208, 0xfeb5_1ce3), "bne a0, a1, 0x200 <loop>");
+        // beq a0, zero, +8 from 0x138, just past the end of main
+        assert_eq!(disassemble(0x138, 0x0005_0463), "beqz a0, 0x140");
+        // blt zero, a0, +8 and bge zero, a0, +8 from 0
+        assert_eq!(disassemble(0, 0x00a0_4463), "bgtz a0, 0x8");
+        assert_eq!(disassemble(0, 0x00a0_5463), "blez a0, 0x8");
+
+        let disassembler = Disassembler::new();
+        assert_eq!(disassembler.symbolize(0x100), None);
+        assert_eq!(
+            disassembler.disassemble(0, 0xffff_ffff),
+            Err(VMErrors::InvalidOpcode(0x7f))
+        );
+    }
+
+    #[test]
+    fn test_disassemble_elf() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../../fibonacci");
+        let elf = Elf::decode(&std::fs::read(path).unwrap()).unwrap();
+        let disassembler = Disassembler::from_elf(&elf);
+        let listing = disassembler.disassemble_elf(&elf);
+
+        assert!(listing.contains("\n0020085c <__rust_alloc>:\n"));
+        assert!(listing.contains("  200878:  00008067  ret\n"));
+        assert_eq!(
+            disassembler.symbolize(0x20087c + 0x1c).as_deref(),
+            Some("__rust_realloc+0x1c")
+        );
+        let lines = listing
+            .lines()
+            .filter(|line| line.starts_with("  "))
+            .count();
+        assert!(lines > 1000);
+        assert!(!listing.contains(".word"));
+    }
+}
@@ -0,0 +1,794 @@
+//! This mod holds a GDB Remote Serial Protocol stub.
+//! [GdbStub] lets `gdb` (or lldb) debug the guest running in a [Vm]: reading and writing registers
+//! and memory, single stepping, continuing, software breakpoints and watchpoints.
+//! The stub steps the VM one instruction at a time so that breakpoints, watchpoints and interrupts
+//! from the debugger are noticed right away.
+use crate::{
+    compressed::is_compressed,
+    observer::ExecutionObserver,
+    trace::{AccessKind, MemoryAccess},
+    vm::{VMErrors, Vm},
+};
+use core::PAGE_SIZE;
+use std::{
+    collections::BTreeSet,
+    io::{self, ErrorKind, Read, Write},
+    net::TcpStream,
+};
+
+/// The register names of the target description, in GDB's register order
+const REGISTER_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+/// GDB's register number of the pc
+const PC_REGISTER: usize = 32;
+
+/// The largest packet the stub accepts, advertised to the debugger
+const PACKET_SIZE: usize = 0x4000;
+/// The number of instructions run between two checks for an interrupt from the debugger
+const INTERRUPT_CHECK_INTERVAL: u64 = 0x1000;
+
+/// Signals reported to the debugger
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGABRT: u8 = 6;
+const SIGBUS: u8 = 10;
+const SIGSEGV: u8 = 11;
+
+/// This is the connection to the debugger.
+pub trait GdbConnection: Read + Write {
+    /// Returns whether the debugger sent an interrupt (Ctrl-C), without blocking.
+    fn interrupted(&mut self) -> io::Result<bool>;
+}
+
+impl GdbConnection for TcpStream {
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = match self.read(&mut byte) {
+            Ok(1) => Ok(byte[0] == 0x03),
+            Ok(_) => Ok(false),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        };
+        self.set_nonblocking(false)?;
+        result
+    }
+}
+
+/// This defines which accesses trigger a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+/// This is a watched range of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: u32,
+    pub len: u32,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    fn matches(&self, access: &MemoryAccess) -> bool {
+        let kind = matches!(
+            (self.kind, access.kind),
+            (WatchKind::Access, _)
+                | (WatchKind::Read, AccessKind::Read)
+                | (WatchKind::Write, AccessKind::Write)
+        );
+        let overlaps = access.addr < self.addr.wrapping_add(self.len)
+            && self.addr < access.addr.wrapping_add(access.size as u32);
+
+        kind && overlaps
+    }
+}
+
+/// This observer checks the memory accesses of the guest against the watchpoints.
+#[derive(Debug, Clone, Default)]
+pub struct WatchpointObserver {
+    pub watchpoints: Vec<Watchpoint>,
+    /// The watchpoint hit by the last instruction and the address of the access
+    pub hit: Option<(Watchpoint, u32)>,
+}
+
+impl ExecutionObserver for WatchpointObserver {
+    fn on_memory_access(&mut self, access: &MemoryAccess) {
+        if self.hit.is_some() {
+            return;
+        }
+        self.hit = self
+            .watchpoints
+            .iter()
+            .find(|watchpoint| watchpoint.matches(access))
+            .map(|watchpoint| (*watchpoint, access.addr));
+    }
+
+    fn box_clone(&self) -> Box<dyn ExecutionObserver> {
+        Box::new(self.clone())
+    }
+}
+
+/// This is what the stub does after handling a packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// Send this reply
+    Reply(String),
+    /// Send this reply and end the session
+    Close(String),
+}
+
+/// This is a GDB stub debugging a [Vm].
+#[derive(Debug, Clone)]
+pub struct GdbStub {
+    pub vm: Vm,
+    pub breakpoints: BTreeSet<u32>,
+    /// The reply to `?`, why the VM last stopped
+    stop_reason: String,
+    /// The address of the `ebreak` the VM stopped on, which resuming steps over
+    ebreak: Option<u32>,
+}
+
+impl GdbStub {
+    /// Create a stub for `vm`, stopped before its first instruction.
+    pub fn new(vm: Vm) -> Self {
+        Self {
+            vm,
+            breakpoints: BTreeSet::new(),
+            stop_reason: format!("S{:02x}", SIGTRAP),
+            ebreak: None,
+        }
+    }
+
+    /// Serve the debugger on `connection` until it detaches, kills the VM or disconnects.
+    pub fn serve(&mut self, connection: &mut impl GdbConnection) -> io::Result<()> {
+        let mut last_reply = String::new();
+
+        loop {
+            let packet = match read_packet(connection)? {
+                Some(Packet::Data(packet)) => packet,
+                Some(Packet::Nack) => {
+                    write_packet(connection, &last_reply)?;
+                    continue;
+                }
+                Some(Packet::Interrupt) | Some(Packet::Invalid) => continue,
+                None => return Ok(()),
+            };
+
+            let response = self.handle(&packet, &mut || connection.interrupted().unwrap_or(true));
+            match response {
+                Response::Reply(reply) => {
+                    write_packet(connection, &reply)?;
+                    last_reply = reply;
+                }
+                Response::Close(reply) => {
+                    write_packet(connection, &reply)?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Handle a packet from the debugger, `interrupted` is polled while the VM runs.
+    pub fn handle(&mut self, packet: &str, interrupted: &mut dyn FnMut() -> bool) -> Response {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => self.stop_reason.clone(),
+            Some(b'g') => self.read_registers(),
+            Some(b'G') => self.write_registers(&packet[1..]),
+            Some(b'p') => self.read_register(&packet[1..]),
+            Some(b'P') => self.write_register(&packet[1..]),
+            Some(b'm') => self.read_memory(&packet[1..]),
+            Some(b'M') => self.write_memory(&packet[1..]),
+            Some(b'c') => self.resume(false, interrupted),
+            Some(b's') => self.resume(true, interrupted),
+            Some(b'Z') => self.set_breakpoint(&packet[1..], true),
+            Some(b'z') => self.set_breakpoint(&packet[1..], false),
+            Some(b'H') | Some(b'T') => String::from("OK"),
+            Some(b'k') => return Response::Close(String::from("OK")),
+            Some(b'D') => return Response::Close(String::from("OK")),
+            Some(b'q') => self.query(&packet[1..]),
+            Some(b'v') => self.v_packet(&packet[1..], interrupted),
+            _ => String::new(),
+        };
+
+        Response::Reply(reply)
+    }
+
+    fn query(&self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            format!("PacketSize={:x};qXfer:features:read+;swbreak+", PACKET_SIZE)
+        } else if let Some(annex) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            parse_pair(annex, ',')
+                .map(|(offset, len)| {
+                    let xml = target_xml();
+                    let start = (offset as usize).min(xml.len());
+                    let end = (start + len as usize).min(xml.len());
+                    let prefix = if end == xml.len() { 'l' } else { 'm' };
+                    format!("{}{}", prefix, &xml[start..end])
+                })
+                .unwrap_or_else(|| String::from("E01"))
+        } else if query == "Attached" {
+            String::from("1")
+        } else if query == "C" {
+            String::from("QC1")
+        } else if query == "fThreadInfo" {
+            String::from("m1")
+        } else if query == "sThreadInfo" {
+            String::from("l")
+        } else {
+            String::new()
+        }
+    }
+
+    fn v_packet(&mut self, packet: &str, interrupted: &mut dyn FnMut() -> bool) -> String {
+        if packet == "Cont?" {
+            String::from("vCont;c;C;s;S")
+        } else if let Some(actions) = packet.strip_prefix("Cont;") {
+            // There is a single thread, the first action applies to it
+            match actions.as_bytes().first() {
+                Some(b'c') | Some(b'C') => self.resume(false, interrupted),
+                Some(b's') | Some(b'S') => self.resume(true, interrupted),
+                _ => String::from("E01"),
+            }
+        } else {
+            String::new()
+        }
+    }
+
+    fn register(&self, number: usize) -> u32 {
+        if number == PC_REGISTER {
+            self.vm.pc
+        } else {
+            self.vm.registers.read_reg(number as u32)
+        }
+    }
+
+    fn set_register(&mut self, number: usize, value: u32) {
+        if number == PC_REGISTER {
+            self.vm.pc = value;
+        } else {
+            self.vm.registers.write_reg(number as u32, value);
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        (0..=PC_REGISTER)
+            .map(|number| format!("{:08x}", self.register(number).swap_bytes()))
+            .collect()
+    }
+
+    fn write_registers(&mut self, data: &str) -> String {
+        let Some(bytes) = decode_hex(data) else {
+            return String::from("E01");
+        };
+        for (number, value) in bytes.chunks_exact(4).take(PC_REGISTER + 1).enumerate() {
+            self.set_register(number, u32::from_le_bytes(value.try_into().unwrap()));
+        }
+        String::from("OK")
+    }
+
+    fn read_register(&self, number: &str) -> String {
+        match u32::from_str_radix(number, 16) {
+            Ok(number) if number as usize <= PC_REGISTER => {
+                format!("{:08x}", self.register(number as usize).swap_bytes())
+            }
+            _ => String::from("E01"),
+        }
+    }
+
+    fn write_register(&mut self, assignment: &str) -> String {
+        let Some((number, value)) = assignment.split_once('=') else {
+            return String::from("E01");
+        };
+        match (u32::from_str_radix(number, 16), decode_hex(value)) {
+            (Ok(number), Some(value)) if number as usize <= PC_REGISTER && value.len() == 4 => {
+                self.set_register(
+                    number as usize,
+                    u32::from_le_bytes(value.try_into().unwrap()),
+                );
+                String::from("OK")
+            }
+            _ => String::from("E01"),
+        }
+    }
+
+    fn read_memory(&self, range: &str) -> String {
+        let Some((addr, len)) = parse_pair(range, ',') else {
+            return String::from("E01");
+        };
+
+        // The debugger sees memory regardless of the permissions, untouched pages read as zeros
+        (0..len.min(PACKET_SIZE as u32 / 2))
+            .map(|i| {
+                let addr = addr.wrapping_add(i);
+                let byte = self
+                    .vm
+                    .memory
+                    .pages
+                    .get(&(addr / PAGE_SIZE))
+                    .map_or(0, |page| page[(addr % PAGE_SIZE) as usize]);
+                format!("{:02x}", byte)
+            })
+            .collect()
+    }
+
+    fn write_memory(&mut self, packet: &str) -> String {
+        let Some((range, data)) = packet.split_once(':') else {
+            return String::from("E01");
+        };
+        match (parse_pair(range, ','), decode_hex(data)) {
+            (Some((addr, len)), Some(data)) if data.len() == len as usize => {
+                self.vm.memory.load_bytes(addr, &data);
+                // The debugger may have patched code
+                self.vm.instruction_cache.clear();
+                String::from("OK")
+            }
+            _ => String::from("E01"),
+        }
+    }
+
+    fn set_breakpoint(&mut self, packet: &str, insert: bool) -> String {
+        let mut fields = packet.split(',');
+        let (Some(kind), Some(addr), Some(len)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return String::from("E01");
+        };
+        let (Ok(addr), Ok(len)) = (u32::from_str_radix(addr, 16), u32::from_str_radix(len, 16))
+        else {
+            return String::from("E01");
+        };
+
+        let kind = match kind {
+            // Software and hardware breakpoints are the same to the VM
+            "0" | "1" => {
+                if insert {
+                    self.breakpoints.insert(addr);
+                } else {
+                    self.breakpoints.remove(&addr);
+                }
+                return String::from("OK");
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return String::new(),
+        };
+
+        let watchpoint = Watchpoint { addr, len, kind };
+        if self.vm.observer::<WatchpointObserver>().is_none() {
+            self.vm.add_observer(WatchpointObserver::default());
+        }
+        let observer = self.vm.observer_mut::<WatchpointObserver>().unwrap();
+        if insert {
+            observer.watchpoints.push(watchpoint);
+        } else {
+            observer.watchpoints.retain(|w| *w != watchpoint);
+        }
+        String::from("OK")
+    }
+
+    /// Run the VM until it stops, or for a single instruction when `step` is set, returning the
+    /// stop reply.
+    /// A program that has exited stays exited. Resuming from an `ebreak` in the program moves the
+    /// pc past it instead of executing it again, which is all a single step does then.
+    fn resume(&mut self, step: bool, interrupted: &mut dyn FnMut() -> bool) -> String {
+        if self.stop_reason.starts_with('W') {
+            return self.stop_reason.clone();
+        }
+        if self.ebreak.take() == Some(self.vm.pc) {
+            let instruction = self.vm.fetch(self.vm.pc).unwrap_or(0);
+            let size = if is_compressed(instruction) { 2 } else { 4 };
+            self.vm.pc = self.vm.pc.wrapping_add(size);
+            if step {
+                self.stop_reason = format!("S{:02x}", SIGTRAP);
+                return self.stop_reason.clone();
+            }
+        }
+
+        let mut executed = 0u64;
+        let reason = loop {
+            // Like `Vm::run`, a breakpoint at the pc execution resumes from is not hit again
+            if executed > 0 && self.breakpoints.contains(&self.vm.pc) {
+                break format!("T{:02x}swbreak:;", SIGTRAP);
+            }
+            if executed > 0 && executed.is_multiple_of(INTERRUPT_CHECK_INTERVAL) && interrupted() {
+                break format!("S{:02x}", SIGINT);
+            }
+
+            let pc = self.vm.pc;
+            let result = self.vm.step();
+            executed += 1;
+            if let Some((watchpoint, addr)) = self
+                .vm
+                .observer_mut::<WatchpointObserver>()
+                .and_then(|observer| observer.hit.take())
+            {
+                let kind = match watchpoint.kind {
+                    WatchKind::Write => "watch",
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Access => "awatch",
+                };
+                break format!("T{:02x}{}:{:x};", SIGTRAP, kind, addr);
+            }
+
+            match result {
+                Ok(true) if step => break format!("S{:02x}", SIGTRAP),
+                Ok(true) => {}
+                // Only ebreak halts without moving the pc
+                Ok(false) if self.vm.pc == pc => {
+                    self.ebreak = Some(pc);
+                    break format!("S{:02x}", SIGTRAP);
+                }
+                Ok(false) => break format!("W{:02x}", self.vm.exit_code as u8),
+                Err(error) => break format!("S{:02x}", signal(&error)),
+            }
+        };
+
+        self.stop_reason.clone_from(&reason);
+        reason
+    }
+}
+
+/// Returns the signal reported for an error that stopped the VM
+fn signal(error: &VMErrors) -> u8 {
+    match error {
+        VMErrors::InvalidInstruction
+        | VMErrors::InvalidOpcode(_)
+        | VMErrors::InvalidFunct3(_)
+        | VMErrors::InvalidFunct7(_)
+        | VMErrors::InvalidCsr(_) => SIGILL,
+        VMErrors::InstructionAddressMisaligned(_)
+        | VMErrors::LoadAddressMisaligned(_)
+        | VMErrors::StoreAddressMisaligned(_) => SIGBUS,
+        VMErrors::InstructionAccessFault(_)
+        | VMErrors::LoadAccessFault(_)
+        | VMErrors::StoreAccessFault(_)
+        | VMErrors::InvalidMemoryAccess
+        | VMErrors::MemoryError
+        | VMErrors::MemoryLoadError
+        | VMErrors::MemoryStoreError => SIGSEGV,
+        VMErrors::Breakpoint(_) => SIGTRAP,
+        _ => SIGABRT,
+    }
+}
+
+/// Returns the target description, telling the debugger the VM is an RV32 hart
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\"><target version=\"1.0\">\
+         <architecture>riscv:rv32</architecture><feature name=\"org.gnu.gdb.riscv.cpu\">",
+    );
+    for (number, name) in REGISTER_NAMES.iter().enumerate() {
+        let kind = match *name {
+            "ra" => "code_ptr",
+            "sp" | "gp" | "tp" | "fp" => "data_ptr",
+            _ => "int",
+        };
+        xml.push_str(&format!(
+            "<reg name=\"{}\" bitsize=\"32\" type=\"{}\" regnum=\"{}\"/>",
+            name, kind, number
+        ));
+    }
+    xml.push_str(&format!(
+        "<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\" regnum=\"{}\"/></feature></target>",
+        PC_REGISTER
+    ));
+    xml
+}
+
+/// Parse two hex numbers separated by `separator`
+fn parse_pair(data: &str, separator: char) -> Option<(u32, u32)> {
+    let (first, second) = data.split_once(separator)?;
+    Some((
+        u32::from_str_radix(first, 16).ok()?,
+        u32::from_str_radix(second, 16).ok()?,
+    ))
+}
+
+fn decode_hex(data: &str) -> Option<Vec<u8>> {
+    if !data.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..data.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(data.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// This is what the debugger sent
+enum Packet {
+    Data(String),
+    /// The debugger did not receive the last reply correctly
+    Nack,
+    /// Ctrl-C while the VM is stopped
+    Interrupt,
+    /// A packet with a bad checksum, the debugger is asked to send it again
+    Invalid,
+}
+
+fn read_byte(connection: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match connection.read(&mut byte) {
+        Ok(0) => Ok(None),
+        Ok(_) => Ok(Some(byte[0])),
+        Err(e) if e.kind() == ErrorKind::Interrupted => read_byte(connection),
+        Err(e) => Err(e),
+    }
+}
+
+/// Read the next packet, acknowledging it. Returns `None` once the debugger disconnected.
+fn read_packet(connection: &mut (impl Read + Write)) -> io::Result<Option<Packet>> {
+    loop {
+        match read_byte(connection)? {
+            None => return Ok(None),
+            Some(b'$') => break,
+            Some(b'-') => return Ok(Some(Packet::Nack)),
+            Some(0x03) => return Ok(Some(Packet::Interrupt)),
+            // Acks and noise between packets
+            Some(_) => {}
+        }
+    }
+
+    let mut data = Vec::new();
+    let mut checksum = 0u8;
+    loop {
+        match read_byte(connection)? {
+            None => return Ok(None),
+            Some(b'#') => break,
+            Some(byte) => {
+                checksum = checksum.wrapping_add(byte);
+                data.push(byte);
+            }
+        }
+    }
+    let mut expected = [0; 2];
+    for byte in &mut expected {
+        let Some(value) = read_byte(connection)? else {
+            return Ok(None);
+        };
+        *byte = value;
+    }
+
+    let valid = std::str::from_utf8(&expected)
+        .ok()
+        .and_then(|expected| u8::from_str_radix(expected, 16).ok())
+        == Some(checksum);
+    if !valid {
+        connection.write_all(b"-")?;
+        return Ok(Some(Packet::Invalid));
+    }
+    connection.write_all(b"+")?;
+
+    // Run-length encoding and escapes only show up in binary data, which is not supported
+    Ok(Some(Packet::Data(
+        String::from_utf8_lossy(&data).into_owned(),
+    )))
+}
+
+fn write_packet(connection: &mut impl Write, data: &str) -> io::Result<()> {
+    let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+    write!(connection, "${}#{:02x}", data, checksum)?;
+    connection.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{addi, lw, sw, EBREAK, ECALL};
+    use crate::syscalls::SYS_EXIT;
+    use std::io::Cursor;
+
+    fn stub(program: Vec<u32>) -> GdbStub {
+        GdbStub::new(Vm::from_bin(program).unwrap())
+    }
+
+    fn reply(stub: &mut GdbStub, packet: &str) -> String {
+        match stub.handle(packet, &mut || false) {
+            Response::Reply(reply) => reply,
+            Response::Close(reply) => reply,
+        }
+    }
+
+    /// A debugger that sent everything in `input` and got `output` back
+    struct Connection {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Connection {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Connection {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl GdbConnection for Connection {
+        fn interrupted(&mut self) -> io::Result<bool> {
+            Ok(false)
+        }
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut stub = stub(vec![addi(1, 0, 0x123), EBREAK]);
+        assert_eq!(reply(&mut stub, "s"), "S05");
+
+        let registers = reply(&mut stub, "g");
+        assert_eq!(registers.len(), 33 * 8);
+        assert_eq!(&registers[8..16], "23010000");
+        assert_eq!(&registers[32 * 8..], "04000000");
+
+        assert_eq!(reply(&mut stub, "P2=78563412"), "OK");
+        assert_eq!(stub.vm.registers.read_reg(2), 0x1234_5678);
+        assert_eq!(reply(&mut stub, "p2"), "78563412");
+        assert_eq!(reply(&mut stub, "P20=00010000"), "OK");
+        assert_eq!(stub.vm.pc, 0x100);
+        assert_eq!(reply(&mut stub, "p21"), "E01");
+
+        let mut registers = reply(&mut stub, "g");
+        registers.replace_range(24..32, "efbeadde");
+        assert_eq!(reply(&mut stub, &format!("G{}", registers)), "OK");
+        assert_eq!(stub.vm.registers.read_reg(3), 0xdead_beef);
+        assert_eq!(stub.vm.registers.read_reg(2), 0x1234_5678);
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut stub = stub(vec![addi(1, 0, 0x123)]);
+
+        assert_eq!(reply(&mut stub, "m0,4"), "93003012");
+        assert_eq!(reply(&mut stub, "m10000,2"), "0000");
+        assert_eq!(reply(&mut stub, "M10001,3:aabbcc"), "OK");
+        assert_eq!(reply(&mut stub, "m10000,5"), "00aabbcc00");
+        assert_eq!(reply(&mut stub, "M10001,3:aabb"), "E01");
+    }
+
+    #[test]
+    fn test_breakpoints_and_continue() {
+        let mut stub = stub(vec![
+            addi(1, 0, 1),
+            addi(1, 1, 1),
+            addi(1, 1, 1),
+            addi(10, 1, 0),
+            addi(17, 0, SYS_EXIT as i32),
+            ECALL,
+        ]);
+
+        assert_eq!(reply(&mut stub, "Z0,8,4"), "OK");
+        assert_eq!(reply(&mut stub, "c"), "T05swbreak:;");
+        assert_eq!(stub.vm.pc, 8);
+        assert_eq!(reply(&mut stub, "?"), "T05swbreak:;");
+        // Continuing from a breakpoint does not hit it again
+        assert_eq!(reply(&mut stub, "z0,8,4"), "OK");
+        assert_eq!(reply(&mut stub, "Z0,c,4"), "OK");
+        assert_eq!(reply(&mut stub, "vCont;c"), "T05swbreak:;");
+        assert_eq!(stub.vm.pc, 12);
+        assert_eq!(reply(&mut stub, "vCont;s:1"), "S05");
+        assert_eq!(stub.vm.pc, 16);
+        assert_eq!(reply(&mut stub, "c"), "W03");
+        // The program has exited, resuming does not run it
+        assert_eq!(reply(&mut stub, "c"), "W03");
+        assert_eq!(reply(&mut stub, "s"), "W03");
+        assert_eq!(stub.vm.pc, 24);
+    }
+
+    #[test]
+    fn test_resume_steps_over_ebreak() {
+        let mut stub = stub(vec![
+            EBREAK,
+            addi(10, 0, 1),
+            EBREAK,
+            addi(10, 10, 1),
+            addi(17, 0, SYS_EXIT as i32),
+            ECALL,
+        ]);
+
+        assert_eq!(reply(&mut stub, "c"), "S05");
+        assert_eq!(stub.vm.pc, 0);
+        assert_eq!(reply(&mut stub, "s"), "S05");
+        assert_eq!(stub.vm.pc, 4);
+        assert_eq!(reply(&mut stub, "c"), "S05");
+        assert_eq!(stub.vm.pc, 8);
+        assert_eq!(reply(&mut stub, "c"), "W02");
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut stub = stub(vec![
+            addi(1, 0, 0x200),
+            addi(2, 0, 7),
+            lw(3, 1, 0),
+            sw(1, 2, 4),
+            lw(3, 1, 4),
+            EBREAK,
+        ]);
+
+        assert_eq!(reply(&mut stub, "Z2,204,4"), "OK");
+        assert_eq!(reply(&mut stub, "Z3,204,4"), "OK");
+        assert_eq!(reply(&mut stub, "c"), "T05watch:204;");
+        assert_eq!(stub.vm.pc, 16);
+        assert_eq!(reply(&mut stub, "c"), "T05rwatch:204;");
+        assert_eq!(stub.vm.registers.read_reg(3), 7);
+
+        assert_eq!(reply(&mut stub, "z2,204,4"), "OK");
+        assert_eq!(reply(&mut stub, "z3,204,4"), "OK");
+        assert_eq!(reply(&mut stub, "Z4,200,1"), "OK");
+        assert_eq!(reply(&mut stub, "P20=00000000"), "OK");
+        assert_eq!(reply(&mut stub, "c"), "T05awatch:200;");
+        assert_eq!(stub.vm.pc, 12);
+    }
+
+    #[test]
+    fn test_faults_and_interrupts() {
+        let mut stub = stub(vec![0xffff_ffff]);
+        assert_eq!(reply(&mut stub, "c"), "S04");
+
+        // jal x0, 0
+        let mut stub = self::stub(vec![0b1101111]);
+        let mut polls = 0;
+        let response = stub.handle("c", &mut || {
+            polls += 1;
+            polls == 3
+        });
+        assert_eq!(response, Response::Reply(String::from("S02")));
+        assert_eq!(stub.vm.csrs.instret, 3 * INTERRUPT_CHECK_INTERVAL);
+    }
+
+    #[test]
+    fn test_target_description() {
+        let mut stub = stub(vec![]);
+
+        assert!(
+            reply(&mut stub, "qSupported:multiprocess+;swbreak+").contains("qXfer:features:read+")
+        );
+        let first = reply(&mut stub, "qXfer:features:read:target.xml:0,40");
+        assert_eq!(first.len(), 0x41);
+        assert!(first.starts_with("m<?xml"));
+        let rest = reply(&mut stub, "qXfer:features:read:target.xml:40,4000");
+        assert!(rest.starts_with('l'));
+        assert!(rest.ends_with("</target>"));
+        assert_eq!(first[1..].to_string() + &rest[1..], target_xml());
+    }
+
+    #[test]
+    fn test_session() {
+        let mut input = Vec::new();
+        for packet in ["qAttached", "m0,4", "s"] {
+            let mut data = Vec::new();
+            write_packet(&mut data, packet).unwrap();
+            input.push(b'+');
+            input.extend(data);
+        }
+        // A corrupted packet, then a nack asking for the last reply again
+        input.extend(b"$g#00-");
+        input.extend(b"+$k#6b");
+        let mut connection = Connection {
+            input: Cursor::new(input),
+            output: Vec::new(),
+        };
+
+        let mut stub = stub(vec![addi(1, 0, 0x123)]);
+        stub.serve(&mut connection).unwrap();
+
+        assert_eq!(
+            String::from_utf8(connection.output).unwrap(),
+            "+$1#31+$93003012#92+$S05#b8-$S05#b8+$OK#9a"
+        );
+        assert_eq!(stub.vm.pc, 4);
+    }
+}
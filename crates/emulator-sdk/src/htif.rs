@@ -0,0 +1,259 @@
+//! This mod holds the host-target interface (HTIF) bare-metal programs, like the riscv-tests, use to
+//! talk to the host. The program writes 64-bit commands to the `tohost` symbol and the host answers
+//! through `fromhost`. A command is the device in its top byte, the command in the next one and a
+//! 48-bit payload:
+//! - device 0, command 0 with the lowest bit of the payload set exits with code `payload >> 1`. The
+//!   riscv-tests pass with code 0 and otherwise exit with the number of the test that failed
+//! - device 1, command 1 writes the lowest byte of the payload to the console
+//!
+//! Other commands, like the syscalls riscv-pk proxies to the host, are dropped.
+//! RV32 programs write the low word of `tohost` first, so the command is taken when the high word is
+//! written. The host then clears `tohost` and, for console writes, acknowledges the command in
+//! `fromhost`, with stores the VM traces like the program's own.
+use core::{interfaces::MemoryInterface, Memory, MemoryChuckSize};
+use elf_parser::Elf;
+use serde::{Deserialize, Serialize};
+
+pub const DEVICE_SYSCALL: u8 = 0;
+pub const DEVICE_CONSOLE: u8 = 1;
+pub const COMMAND_EXIT: u8 = 0;
+pub const COMMAND_PUTCHAR: u8 = 1;
+
+/// This is a command written to `tohost`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HtifCommand {
+    pub device: u8,
+    pub command: u8,
+    pub payload: u64,
+}
+
+impl HtifCommand {
+    pub fn decode(value: u64) -> Self {
+        Self {
+            device: (value >> 56) as u8,
+            command: (value >> 48) as u8,
+            payload: value & 0xffff_ffff_ffff,
+        }
+    }
+
+    pub fn encode(&self) -> u64 {
+        ((self.device as u64) << 56) | ((self.command as u64) << 48) | self.payload
+    }
+}
+
+/// This is the host side of the interface, watching the writes to `tohost`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Htif {
+    /// The address of the `tohost` doubleword
+    pub tohost: u32,
+    /// The address of the `fromhost` doubleword, responses are dropped without it
+    pub fromhost: Option<u32>,
+    /// The bytes written to the console
+    pub console: Vec<u8>,
+    /// The exit code of the exit command, once the program sent it
+    pub exit_code: Option<u32>,
+    /// Whether the VM still has to halt on the exit command
+    exit_pending: bool,
+}
+
+impl Htif {
+    pub fn new(tohost: u32, fromhost: Option<u32>) -> Self {
+        Self {
+            tohost,
+            fromhost,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the interface of a program defining the `tohost` symbol.
+    pub fn from_elf(elf: &Elf) -> Option<Self> {
+        let tohost = elf.symbol("tohost")?.addr;
+        let fromhost = elf.symbol("fromhost").map(|symbol| symbol.addr);
+
+        Some(Self::new(tohost, fromhost))
+    }
+
+    /// Returns whether a write of `size` bytes at `addr` touches `tohost`.
+    #[inline(always)]
+    pub fn watches(&self, addr: u32, size: u32) -> bool {
+        addr < self.tohost.wrapping_add(8) && self.tohost < addr.wrapping_add(size)
+    }
+
+    /// Returns the result of a riscv-tests program once it exited, `Err` holds the number of the
+    /// test that failed.
+    pub fn test_result(&self) -> Option<Result<(), u32>> {
+        self.exit_code
+            .map(|code| if code == 0 { Ok(()) } else { Err(code) })
+    }
+
+    /// Take the command in `tohost` after a write of `size` bytes at `addr`, if it completes one.
+    /// Returns the words the host stores in response as (address, value): `tohost` cleared, then
+    /// the answer in `fromhost`.
+    pub(crate) fn write(&mut self, memory: &Memory, addr: u32, size: u32) -> Vec<(u32, u32)> {
+        let high = self.tohost.wrapping_add(4);
+        if addr >= high.wrapping_add(4) || addr.wrapping_add(size) <= high {
+            return Vec::new();
+        }
+        let low_word = memory.read_mem(self.tohost, MemoryChuckSize::WordSize);
+        let high_word = memory.read_mem(high, MemoryChuckSize::WordSize);
+        let (Some(low_word), Some(high_word)) = (low_word, high_word) else {
+            return Vec::new();
+        };
+        let value = ((high_word as u64) << 32) | low_word as u64;
+        if value == 0 {
+            return Vec::new();
+        }
+        let mut writes = vec![(self.tohost, 0), (high, 0)];
+
+        let command = HtifCommand::decode(value);
+        match (command.device, command.command) {
+            (DEVICE_SYSCALL, COMMAND_EXIT) if command.payload & 1 == 1 => {
+                self.exit_code = Some((command.payload >> 1) as u32);
+                self.exit_pending = true;
+            }
+            (DEVICE_CONSOLE, COMMAND_PUTCHAR) => {
+                self.console.push(command.payload as u8);
+                writes.extend(self.respond(HtifCommand {
+                    payload: 1,
+                    ..command
+                }));
+            }
+            _ => {}
+        }
+        writes
+    }
+
+    /// Returns the words storing `response` to `fromhost`, none without it.
+    fn respond(&self, response: HtifCommand) -> Vec<(u32, u32)> {
+        let Some(fromhost) = self.fromhost else {
+            return Vec::new();
+        };
+        let value = response.encode();
+        vec![
+            (fromhost, value as u32),
+            (fromhost.wrapping_add(4), (value >> 32) as u32),
+        ]
+    }
+
+    /// Returns the exit code of an exit command the VM has not halted on yet.
+    pub(crate) fn take_exit(&mut self) -> Option<u32> {
+        if !std::mem::take(&mut self.exit_pending) {
+            return None;
+        }
+        self.exit_code
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{addi, lui, sw, EBREAK, ECALL};
+    use crate::vm::{RunConfig, RunOutcome, Vm};
+
+    const TOHOST: u32 = 0x1000;
+    const FROMHOST: u32 = 0x1040;
+
+    /// A program writing `high:low` to tohost, then exiting with `exit`
+    fn program(low: i32, high: u32, exit: i32) -> Vec<u32> {
+        vec![
+            lui(5, TOHOST >> 12),
+            addi(6, 0, low),
+            lui(7, high >> 12),
+            sw(5, 6, 0),
+            sw(5, 7, 4),
+            addi(6, 0, exit),
+            sw(5, 6, 0),
+            sw(5, 0, 4),
+            EBREAK,
+        ]
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(
+            HtifCommand::decode(0x0101_0000_0000_0068),
+            HtifCommand {
+                device: DEVICE_CONSOLE,
+                command: COMMAND_PUTCHAR,
+                payload: 0x68
+            }
+        );
+        assert_eq!(HtifCommand::decode(11).payload, 11);
+        assert_eq!(
+            HtifCommand::decode(0x0101_0000_0000_0068).encode(),
+            0x0101_0000_0000_0068
+        );
+    }
+
+    #[test]
+    fn test_exit_and_console() {
+        for (cache_blocks, exit, result) in [(true, 1, Ok(())), (false, (5 << 1) | 1, Err(5))] {
+            let mut vm = Vm::from_bin(program(0x68, 0x0101_0000, exit)).unwrap();
+            vm.htif = Some(Htif::new(TOHOST, Some(FROMHOST)));
+            let outcome = vm.run(RunConfig {
+                cache_blocks,
+                ..RunConfig::default()
+            });
+
+            // The store completing the exit command does not retire
+            assert_eq!(outcome, RunOutcome::Exited(exit as u32 >> 1));
+            assert_eq!(vm.pc, 8 * 4);
+            assert_eq!(vm.csrs.instret, 7);
+            let htif = vm.htif.as_ref().unwrap();
+            assert_eq!(htif.test_result(), Some(result));
+            assert_eq!(htif.console, b"h");
+            assert_eq!(
+                vm.memory.read_mem(TOHOST, MemoryChuckSize::WordSize),
+                Some(0)
+            );
+            assert_eq!(
+                vm.memory.read_mem(FROMHOST, MemoryChuckSize::WordSize),
+                Some(1)
+            );
+            assert_eq!(
+                vm.memory.read_mem(FROMHOST + 4, MemoryChuckSize::WordSize),
+                Some(0x0101_0000)
+            );
+        }
+    }
+
+    #[test]
+    fn test_host_stores_are_traced() {
+        let mut vm = Vm::from_bin(program(0x68, 0x0101_0000, 1)).unwrap();
+        vm.htif = Some(Htif::new(TOHOST, Some(FROMHOST)));
+        vm.enable_trace();
+        vm.run(RunConfig::default());
+
+        // The store completing the console command, then the host clearing tohost and answering
+        let row = &vm.take_trace().unwrap().rows[4];
+        let writes: Vec<_> = row
+            .memory_accesses
+            .iter()
+            .map(|access| (access.addr, access.new_value))
+            .collect();
+        assert_eq!(
+            writes,
+            [
+                (TOHOST + 4, 0x0101_0000),
+                (TOHOST, 0),
+                (TOHOST + 4, 0),
+                (FROMHOST, 1),
+                (FROMHOST + 4, 0x0101_0000)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ecall_traps() {
+        // Without a trap handler the ecall of a bare-metal program stops the VM
+        let mut vm = Vm::from_bin(vec![ECALL]).unwrap();
+        vm.htif = Some(Htif::new(TOHOST, None));
+        assert_eq!(
+            vm.run(RunConfig::default()),
+            RunOutcome::Faulted {
+                pc: 0,
+                error: crate::vm::VMErrors::EnvironmentError
+            }
+        );
+    }
+}
@@ -1,4 +1,7 @@
-use crate::vm::VMErrors;
+use crate::{
+    compressed::{expand, is_compressed},
+    vm::VMErrors,
+};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct RType {
@@ -165,6 +168,39 @@ impl JType {
     }
 }
 
+/// These are the instructions of the MISC-MEM class, decoded from its I-type fields.
+/// Following the specification, the reserved fields are ignored: FENCE with an unknown fence mode is
+/// a normal FENCE and FENCE.I ignores its immediate, rs1 and rd.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MiscMemOp {
+    /// Orders the accesses in the `pred` set before the ones in the `succ` set, each set is the
+    /// device input, device output, memory read and memory write bits from high to low
+    Fence { pred: u32, succ: u32 },
+    /// Total store ordering, FENCE RW,RW with the TSO fence mode
+    FenceTso,
+    /// Hints that the hart is spinning, encoded as FENCE W,0
+    Pause,
+    /// Makes the stores visible to the instruction fetches that follow it (Zifencei)
+    FenceI,
+}
+
+impl MiscMemOp {
+    pub fn new(itype: &IType) -> Result<MiscMemOp, VMErrors> {
+        let fields = itype.imm as u32 & 0xfff;
+        let (fm, pred, succ) = (fields >> 8, (fields >> 4) & 0xf, fields & 0xf);
+
+        match itype.funct3 {
+            0b000 if fm == 0b1000 && pred == 0b0011 && succ == 0b0011 => Ok(MiscMemOp::FenceTso),
+            0b000 if fm == 0 && pred == 0b0001 && succ == 0 && itype.rd == 0 && itype.rs1 == 0 => {
+                Ok(MiscMemOp::Pause)
+            }
+            0b000 => Ok(MiscMemOp::Fence { pred, succ }),
+            0b001 => Ok(MiscMemOp::FenceI),
+            _ => Err(VMErrors::InvalidFunct3(itype.funct3)),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum DecodedInstruction {
     RType(RType),
@@ -185,88 +221,160 @@ pub const JALR_CLASS: u32 = 0b1100111;
 pub const UPPER_IMMEDIATE_CLASS: u32 = 0b0110111;
 pub const ENVIRONMENT_CLASS: u32 = 0b1110011;
 pub const UPPER_IMMEDIATE_TO_PC_CLASS: u32 = 0b0010111;
+pub const ATOMIC_CLASS: u32 = 0b0101111;
+pub const MISC_MEM_CLASS: u32 = 0b0001111;
 
 #[derive(Debug, Clone)]
 pub struct InstructionDecoder {
     pub decoded_instruction: DecodedInstruction,
     pub opcode: u32,
+    /// The size of the encoded instruction in bytes, 2 for compressed instructions and 4 otherwise
+    pub size: u32,
 }
 
 impl InstructionDecoder {
     pub fn decode(instruction: &u32) -> Result<Self, VMErrors> {
+        if is_compressed(*instruction) {
+            // Compressed instructions execute as their 32-bit equivalent
+            let mut decoded = Self::decode(&expand(*instruction as u16)?)?;
+            decoded.size = 2;
+            return Ok(decoded);
+        }
+
         let opcode = instruction & 0x7f;
 
         match opcode {
-            REGISTER_CLASS => {
+            REGISTER_CLASS | ATOMIC_CLASS => {
                 let decoded_instruction = DecodedInstruction::RType(RType::new(*instruction));
-                return Ok(Self {
+                Ok(Self {
                     decoded_instruction,
                     opcode,
-                });
+                    size: 4,
+                })
             }
-            IMMEDIATE_CLASS | IMMEDIATE_LOAD_CLASS | JALR_CLASS | ENVIRONMENT_CLASS => {
+            IMMEDIATE_CLASS | IMMEDIATE_LOAD_CLASS | JALR_CLASS | ENVIRONMENT_CLASS
+            | MISC_MEM_CLASS => {
                 let decoded_instruction = DecodedInstruction::IType(IType::new(*instruction));
-                return Ok(Self {
+                Ok(Self {
                     decoded_instruction,
                     opcode,
-                });
+                    size: 4,
+                })
             }
             STORE_CLASS => {
                 let decoded_instruction = DecodedInstruction::SType(SType::new(*instruction));
-                return Ok(Self {
+                Ok(Self {
                     decoded_instruction,
                     opcode,
-                });
+                    size: 4,
+                })
             }
             BRANCH_CLASS => {
                 let decoded_instruction = DecodedInstruction::BType(BType::new(*instruction));
-                return Ok(Self {
+                Ok(Self {
                     decoded_instruction,
                     opcode,
-                });
+                    size: 4,
+                })
             }
             JAL_CLASS => {
                 let decoded_instruction = DecodedInstruction::JType(JType::new(*instruction));
-                return Ok(Self {
+                Ok(Self {
                     decoded_instruction,
                     opcode,
-                });
+                    size: 4,
+                })
             }
             UPPER_IMMEDIATE_CLASS | UPPER_IMMEDIATE_TO_PC_CLASS => {
                 let decoded_instruction = DecodedInstruction::UType(UType::new(*instruction));
-                return Ok(Self {
+                Ok(Self {
                     decoded_instruction,
                     opcode,
-                });
+                    size: 4,
+                })
             }
             _ => Err(VMErrors::InvalidOpcode(opcode)),
         }
     }
+}
 
-    pub fn to_string(&self) -> String {
+impl std::fmt::Display for InstructionDecoder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self.decoded_instruction {
-            DecodedInstruction::RType(r) => format!(
+            DecodedInstruction::RType(r) => write!(
+                f,
                 "RType: funct7: {}, rs2: {}, rs1: {}, funct3: {}, rd: {}",
                 r.funct7, r.rs2, r.rs1, r.funct3, r.rd
             ),
-            DecodedInstruction::IType(i) => format!(
+            DecodedInstruction::IType(i) => write!(
+                f,
                 "IType: imm: {}, rs1: {}, funct3: {}, rd: {}",
                 i.imm, i.rs1, i.funct3, i.rd
             ),
-            DecodedInstruction::SType(s) => format!(
+            DecodedInstruction::SType(s) => write!(
+                f,
                 "SType: imm: {}, rs2: {}, rs1: {}, funct3: {}",
                 s.imm, s.rs2, s.rs1, s.funct3
             ),
-            DecodedInstruction::BType(b) => format!(
+            DecodedInstruction::BType(b) => write!(
+                f,
                 "BType: imm: {}, rs2: {}, rs1: {}, funct3: {}",
                 b.imm, b.rs2, b.rs1, b.funct3
             ),
             DecodedInstruction::UType(u) => {
-                format!("UType: imm: {}, rd: {}", u.imm, u.rd)
+                write!(f, "UType: imm: {}, rd: {}", u.imm, u.rd)
             }
             DecodedInstruction::JType(j) => {
-                format!("JType: imm: {}, rd: {}", j.imm, j.rd)
+                write!(f, "JType: imm: {}, rd: {}", j.imm, j.rd)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn misc_mem(instruction: u32) -> Result<MiscMemOp, VMErrors> {
+        MiscMemOp::new(&IType::new(instruction))
+    }
+
+    #[test]
+    fn test_misc_mem_ops() {
+        assert_eq!(
+            misc_mem(0x0ff0_000f),
+            Ok(MiscMemOp::Fence {
+                pred: 0b1111,
+                succ: 0b1111
+            })
+        );
+        // fence r, rw
+        assert_eq!(
+            misc_mem(0x0230_000f),
+            Ok(MiscMemOp::Fence {
+                pred: 0b0010,
+                succ: 0b0011
+            })
+        );
+        assert_eq!(misc_mem(0x8330_000f), Ok(MiscMemOp::FenceTso));
+        assert_eq!(misc_mem(0x0100_000f), Ok(MiscMemOp::Pause));
+        // A fence w,0 writing a register is not a pause, and the TSO mode only applies to rw,rw
+        assert_eq!(
+            misc_mem(0x0100_008f),
+            Ok(MiscMemOp::Fence {
+                pred: 0b0001,
+                succ: 0
+            })
+        );
+        assert_eq!(
+            misc_mem(0x8ff0_000f),
+            Ok(MiscMemOp::Fence {
+                pred: 0b1111,
+                succ: 0b1111
+            })
+        );
+        assert_eq!(misc_mem(0x0000_100f), Ok(MiscMemOp::FenceI));
+        assert_eq!(misc_mem(0xfff1_9f8f), Ok(MiscMemOp::FenceI));
+        assert_eq!(misc_mem(0x0000_200f), Err(VMErrors::InvalidFunct3(0b010)));
+    }
+}
@@ -0,0 +1,730 @@
+//! This mod holds the JIT execution engine, built with the `jit` feature.
+//! Basic blocks that ran [HOT_THRESHOLD] times are compiled to host code with Cranelift. Compiled
+//! blocks work directly on the VM's [Registers](core::Registers) and go through the same memory
+//! accesses as the block interpreter, so the architectural state is exact whenever a block returns.
+//! Like the block interpreter, a compiled block hands anything but the common case back: it returns
+//! before a load or store that would fault or is misaligned, which [Vm::step] then executes.
+use crate::{
+    block::{AluOp, Block, BranchCond, Op},
+    segment::touch_pages,
+    vm::Vm,
+};
+use core::{interfaces::MemoryInterface, MemoryChuckSize};
+use cranelift_codegen::{
+    ir::{condcodes::IntCC, types, AbiParam, FuncRef, InstBuilder, MemFlags, Signature, Value},
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+use hashbrown::HashMap;
+use std::{
+    fmt::{self, Debug},
+    ptr::addr_of_mut,
+};
+
+/// Blocks are compiled once they ran this many times
+pub const HOT_THRESHOLD: u32 = 32;
+
+/// The code of invalidated blocks is freed, with every other compiled block, once there are this
+/// many of them
+pub const MAX_STALE_BLOCKS: usize = 1024;
+
+/// Returned by [jit_load] when the load is left to the interpreter
+const LOAD_BAIL: u64 = 1 << 32;
+
+/// Returned by [jit_store]
+const STORE_OK: u32 = 0;
+const STORE_BAIL: u32 = 1;
+/// The store wrote to code, the block must return right after it
+const STORE_INVALIDATED: u32 = 2;
+
+/// This is a compiled block, it takes the registers and the VM and returns the pc to continue at in
+/// the high 32 bits and the number of instructions it retired in the low 32 bits.
+type CompiledBlock = unsafe extern "C" fn(*mut u32, *mut Vm) -> u64;
+
+enum Entry {
+    /// The number of times the block ran
+    Cold(u32),
+    Compiled(CompiledBlock),
+    /// Cranelift rejected the block, it stays interpreted
+    Failed,
+}
+
+/// This is the JIT engine of a VM, holding the compiled blocks by pc.
+/// A block invalidated by a store keeps its code, so dropping its entry is safe even while it runs.
+/// The code is freed by [Jit::clear], which only runs between blocks.
+pub struct Jit {
+    module: Option<JITModule>,
+    entries: HashMap<u32, Entry>,
+    load: Option<cranelift_module::FuncId>,
+    store: Option<cranelift_module::FuncId>,
+    /// The number of invalidated blocks whose code is still allocated
+    stale: usize,
+}
+
+impl Default for Jit {
+    fn default() -> Self {
+        Self {
+            module: None,
+            entries: HashMap::new(),
+            load: None,
+            store: None,
+            stale: 0,
+        }
+    }
+}
+
+/// Compiled code is not shared, clones start out cold.
+impl Clone for Jit {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl Debug for Jit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let compiled = self
+            .entries
+            .values()
+            .filter(|entry| matches!(entry, Entry::Compiled(_)))
+            .count();
+        f.debug_struct("Jit").field("compiled", &compiled).finish()
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl Jit {
+    /// Drop every compiled block and free their code, the module is created again on the next
+    /// compilation. Must not be called while a compiled block runs.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.load = None;
+        self.store = None;
+        self.stale = 0;
+        if let Some(module) = self.module.take() {
+            // SAFETY: no compiled block is running, and their entries are gone
+            unsafe { module.free_memory() };
+        }
+    }
+
+    /// Drop the compiled block at `pc`, if any. Its code is kept until the next [Jit::clear], as
+    /// the block may be the one running.
+    pub fn invalidate(&mut self, pc: u32) {
+        if let Some(Entry::Compiled(_)) = self.entries.remove(&pc) {
+            self.stale += 1;
+        }
+    }
+
+    /// Returns the number of compiled blocks
+    pub fn compiled(&self) -> usize {
+        self.entries
+            .values()
+            .filter(|entry| matches!(entry, Entry::Compiled(_)))
+            .count()
+    }
+
+    /// Returns the compiled block at `pc`, compiling `block` once it is hot.
+    fn lookup(&mut self, pc: u32, block: &Block) -> Option<CompiledBlock> {
+        // Code that keeps rewriting itself would otherwise grow the module without bound
+        if self.stale >= MAX_STALE_BLOCKS {
+            self.clear();
+        }
+
+        let entry = self.entries.entry(pc).or_insert(Entry::Cold(0));
+        match entry {
+            Entry::Compiled(compiled) => return Some(*compiled),
+            Entry::Failed => return None,
+            Entry::Cold(count) if *count + 1 < HOT_THRESHOLD => {
+                *count += 1;
+                return None;
+            }
+            Entry::Cold(_) => {}
+        }
+
+        let compiled = self.compile(block);
+        self.entries
+            .insert(pc, compiled.map(Entry::Compiled).unwrap_or(Entry::Failed));
+        compiled
+    }
+
+    fn module(&mut self) -> Option<&mut JITModule> {
+        if self.module.is_none() {
+            let mut flags = settings::builder();
+            flags.set("opt_level", "speed").ok()?;
+            let isa = cranelift_native::builder()
+                .ok()?
+                .finish(settings::Flags::new(flags))
+                .ok()?;
+
+            let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+            builder.symbol("jit_load", jit_load as *const u8);
+            builder.symbol("jit_store", jit_store as *const u8);
+            let mut module = JITModule::new(builder);
+
+            let ptr = module.target_config().pointer_type();
+            let mut load = module.make_signature();
+            load.params
+                .extend([ptr, types::I32, types::I32].map(AbiParam::new));
+            load.returns.push(AbiParam::new(types::I64));
+            let mut store = module.make_signature();
+            store
+                .params
+                .extend([ptr, types::I32, types::I32, types::I32].map(AbiParam::new));
+            store.returns.push(AbiParam::new(types::I32));
+
+            self.load = module
+                .declare_function("jit_load", Linkage::Import, &load)
+                .ok();
+            self.store = module
+                .declare_function("jit_store", Linkage::Import, &store)
+                .ok();
+            self.module = Some(module);
+        }
+
+        self.module.as_mut()
+    }
+
+    /// Compile `block` to host code.
+    fn compile(&mut self, block: &Block) -> Option<CompiledBlock> {
+        self.module()?;
+        let (load, store) = (self.load?, self.store?);
+        let module = self.module.as_mut()?;
+
+        let ptr = module.target_config().pointer_type();
+        let mut ctx = module.make_context();
+        ctx.func.signature = Signature {
+            params: vec![AbiParam::new(ptr), AbiParam::new(ptr)],
+            returns: vec![AbiParam::new(types::I64)],
+            call_conv: module.isa().default_call_conv(),
+        };
+        let load = module.declare_func_in_func(load, &mut ctx.func);
+        let store = module.declare_func_in_func(store, &mut ctx.func);
+
+        let mut builder_ctx = FunctionBuilderContext::new();
+        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+        let regs = builder.block_params(entry)[0];
+        let vm = builder.block_params(entry)[1];
+
+        let mut codegen = Codegen {
+            builder,
+            regs,
+            vm,
+            load,
+            store,
+        };
+        codegen.block(block);
+        codegen.builder.finalize();
+
+        let id = module
+            .declare_anonymous_function(&ctx.func.signature)
+            .ok()?;
+        module.define_function(id, &mut ctx).ok()?;
+        module.clear_context(&mut ctx);
+        module.finalize_definitions().ok()?;
+
+        // SAFETY: the function was compiled with the signature of `CompiledBlock`
+        Some(unsafe {
+            std::mem::transmute::<*const u8, CompiledBlock>(module.get_finalized_function(id))
+        })
+    }
+}
+
+/// This translates the ops of a block to Cranelift IR.
+struct Codegen<'a> {
+    builder: FunctionBuilder<'a>,
+    regs: Value,
+    vm: Value,
+    load: FuncRef,
+    store: FuncRef,
+}
+
+impl Codegen<'_> {
+    fn read_reg(&mut self, reg: u8) -> Value {
+        match reg {
+            0 => self.builder.ins().iconst(types::I32, 0),
+            _ => {
+                self.builder
+                    .ins()
+                    .load(types::I32, MemFlags::trusted(), self.regs, 4 * reg as i32)
+            }
+        }
+    }
+
+    fn write_reg(&mut self, reg: u8, value: Value) {
+        if reg != 0 {
+            self.builder
+                .ins()
+                .store(MemFlags::trusted(), value, self.regs, 4 * reg as i32);
+        }
+    }
+
+    fn constant(&mut self, value: u32) -> Value {
+        self.builder.ins().iconst(types::I32, value as i32 as i64)
+    }
+
+    /// Return to the VM, continuing at `pc` after `executed` instructions
+    fn exit(&mut self, pc: Value, executed: usize) {
+        let pc = self.builder.ins().uextend(types::I64, pc);
+        let pc = self.builder.ins().ishl_imm(pc, 32);
+        let result = self.builder.ins().bor_imm(pc, executed as i64);
+        self.builder.ins().return_(&[result]);
+    }
+
+    fn exit_at(&mut self, pc: u32, executed: usize) {
+        let pc = self.constant(pc);
+        self.exit(pc, executed);
+    }
+
+    /// Return to the VM when `cond` holds, continuing with the next op otherwise
+    fn exit_if(&mut self, cond: Value, pc: u32, executed: usize) {
+        let exit = self.builder.create_block();
+        let next = self.builder.create_block();
+        self.builder.ins().brif(cond, exit, &[], next, &[]);
+
+        self.builder.switch_to_block(exit);
+        self.builder.seal_block(exit);
+        self.exit_at(pc, executed);
+
+        self.builder.switch_to_block(next);
+        self.builder.seal_block(next);
+    }
+
+    fn block(&mut self, block: &Block) {
+        for (i, cached) in block.ops.iter().enumerate() {
+            match cached.op {
+                Op::Alu { op, rd, rs1, rs2 } => {
+                    let a = self.read_reg(rs1);
+                    let b = self.read_reg(rs2);
+                    let value = self.alu(op, a, b);
+                    self.write_reg(rd, value);
+                }
+                Op::AluImm { op, rd, rs1, imm } => {
+                    let a = self.read_reg(rs1);
+                    let b = self.constant(imm);
+                    let value = self.alu(op, a, b);
+                    self.write_reg(rd, value);
+                }
+                Op::LoadImm { rd, value } => {
+                    let value = self.constant(value);
+                    self.write_reg(rd, value);
+                }
+                Op::Load {
+                    ref size,
+                    signed,
+                    rd,
+                    rs1,
+                    offset,
+                } => {
+                    let base = self.read_reg(rs1);
+                    let addr = self.builder.ins().iadd_imm(base, offset as i32 as i64);
+                    let kind = self.constant(load_kind(size, signed));
+                    let call = self.builder.ins().call(self.load, &[self.vm, addr, kind]);
+                    let result = self.builder.inst_results(call)[0];
+
+                    let bail = self.builder.ins().ushr_imm(result, 32);
+                    self.exit_if(bail, cached.pc, i);
+                    let value = self.builder.ins().ireduce(types::I32, result);
+                    self.write_reg(rd, value);
+                }
+                Op::Store {
+                    ref size,
+                    rs1,
+                    rs2,
+                    offset,
+                } => {
+                    let base = self.read_reg(rs1);
+                    let addr = self.builder.ins().iadd_imm(base, offset as i32 as i64);
+                    let value = self.read_reg(rs2);
+                    let kind = self.constant(store_kind(size));
+                    let call = self
+                        .builder
+                        .ins()
+                        .call(self.store, &[self.vm, addr, value, kind]);
+                    let status = self.builder.inst_results(call)[0];
+
+                    let bail = self
+                        .builder
+                        .ins()
+                        .icmp_imm(IntCC::Equal, status, STORE_BAIL as i64);
+                    self.exit_if(bail, cached.pc, i);
+                    // The rest of the block may just have been overwritten
+                    let invalidated =
+                        self.builder
+                            .ins()
+                            .icmp_imm(IntCC::Equal, status, STORE_INVALIDATED as i64);
+                    self.exit_if(invalidated, cached.next_pc, i + 1);
+                }
+                Op::Branch {
+                    cond,
+                    rs1,
+                    rs2,
+                    target,
+                } => {
+                    let a = self.read_reg(rs1);
+                    let b = self.read_reg(rs2);
+                    let cc = match cond {
+                        BranchCond::Eq => IntCC::Equal,
+                        BranchCond::Ne => IntCC::NotEqual,
+                        BranchCond::Lt => IntCC::SignedLessThan,
+                        BranchCond::Ge => IntCC::SignedGreaterThanOrEqual,
+                        BranchCond::Ltu => IntCC::UnsignedLessThan,
+                        BranchCond::Geu => IntCC::UnsignedGreaterThanOrEqual,
+                    };
+                    let taken = self.builder.ins().icmp(cc, a, b);
+                    let target = self.constant(target);
+                    let next = self.constant(cached.next_pc);
+                    let pc = self.builder.ins().select(taken, target, next);
+                    self.exit(pc, i + 1);
+                    return;
+                }
+                Op::Jal { rd, target } => {
+                    let link = self.constant(cached.next_pc);
+                    self.write_reg(rd, link);
+                    self.exit_at(target, i + 1);
+                    return;
+                }
+                Op::Jalr { rd, rs1, offset } => {
+                    let base = self.read_reg(rs1);
+                    let target = self.builder.ins().iadd_imm(base, offset as i32 as i64);
+                    let target = self.builder.ins().band_imm(target, !1);
+                    let link = self.constant(cached.next_pc);
+                    self.write_reg(rd, link);
+                    self.exit(target, i + 1);
+                    return;
+                }
+            }
+        }
+
+        // The block was cut before an instruction it can not hold
+        let next_pc = block.ops.last().map(|cached| cached.next_pc).unwrap_or(0);
+        self.exit_at(next_pc, block.ops.len());
+    }
+
+    fn alu(&mut self, op: AluOp, a: Value, b: Value) -> Value {
+        let ins = self.builder.ins();
+        match op {
+            AluOp::Add => ins.iadd(a, b),
+            AluOp::Sub => ins.isub(a, b),
+            // Shift amounts are taken modulo 32, as in RISC-V
+            AluOp::Sll => ins.ishl(a, b),
+            AluOp::Srl => ins.ushr(a, b),
+            AluOp::Sra => ins.sshr(a, b),
+            AluOp::Slt => {
+                let lt = ins.icmp(IntCC::SignedLessThan, a, b);
+                self.builder.ins().uextend(types::I32, lt)
+            }
+            AluOp::Sltu => {
+                let lt = ins.icmp(IntCC::UnsignedLessThan, a, b);
+                self.builder.ins().uextend(types::I32, lt)
+            }
+            AluOp::Xor => ins.bxor(a, b),
+            AluOp::Or => ins.bor(a, b),
+            AluOp::And => ins.band(a, b),
+            AluOp::Mul => ins.imul(a, b),
+            AluOp::Mulh => ins.smulhi(a, b),
+            AluOp::Mulhu => ins.umulhi(a, b),
+            AluOp::Mulhsu => {
+                let a = ins.sextend(types::I64, a);
+                let b = self.builder.ins().uextend(types::I64, b);
+                let product = self.builder.ins().imul(a, b);
+                let high = self.builder.ins().sshr_imm(product, 32);
+                self.builder.ins().ireduce(types::I32, high)
+            }
+            AluOp::Div | AluOp::Rem => {
+                // Division by zero and overflow trap on the host, they are given their RISC-V
+                // results instead
+                let zero = ins.icmp_imm(IntCC::Equal, b, 0);
+                let min = self
+                    .builder
+                    .ins()
+                    .icmp_imm(IntCC::Equal, a, i32::MIN as i64);
+                let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, b, -1);
+                let overflow = self.builder.ins().band(min, minus_one);
+                let unsafe_divisor = self.builder.ins().bor(zero, overflow);
+                let one = self.constant(1);
+                let divisor = self.builder.ins().select(unsafe_divisor, one, b);
+                if op == AluOp::Div {
+                    // a / 1 is already the overflow result
+                    let quotient = self.builder.ins().sdiv(a, divisor);
+                    let all_ones = self.constant(u32::MAX);
+                    self.builder.ins().select(zero, all_ones, quotient)
+                } else {
+                    // a % 1 is already the overflow result
+                    let remainder = self.builder.ins().srem(a, divisor);
+                    self.builder.ins().select(zero, a, remainder)
+                }
+            }
+            AluOp::Divu | AluOp::Remu => {
+                let zero = ins.icmp_imm(IntCC::Equal, b, 0);
+                let one = self.constant(1);
+                let divisor = self.builder.ins().select(zero, one, b);
+                if op == AluOp::Divu {
+                    let quotient = self.builder.ins().udiv(a, divisor);
+                    let all_ones = self.constant(u32::MAX);
+                    self.builder.ins().select(zero, all_ones, quotient)
+                } else {
+                    let remainder = self.builder.ins().urem(a, divisor);
+                    self.builder.ins().select(zero, a, remainder)
+                }
+            }
+        }
+    }
+}
+
+/// The funct3 of the load
+fn load_kind(size: &MemoryChuckSize, signed: bool) -> u32 {
+    match (size, signed) {
+        (MemoryChuckSize::BYTE, true) => 0b000,
+        (MemoryChuckSize::HalfWord, true) => 0b001,
+        (MemoryChuckSize::WordSize, _) => 0b010,
+        (MemoryChuckSize::BYTE, false) => 0b100,
+        (MemoryChuckSize::HalfWord, false) => 0b101,
+    }
+}
+
+/// The funct3 of the store
+fn store_kind(size: &MemoryChuckSize) -> u32 {
+    match size {
+        MemoryChuckSize::BYTE => 0b000,
+        MemoryChuckSize::HalfWord => 0b001,
+        MemoryChuckSize::WordSize => 0b010,
+    }
+}
+
+fn chunk(kind: u32) -> (MemoryChuckSize, u32) {
+    match kind & 0b11 {
+        0b00 => (MemoryChuckSize::BYTE, 0),
+        0b01 => (MemoryChuckSize::HalfWord, 1),
+        _ => (MemoryChuckSize::WordSize, 3),
+    }
+}
+
+/// Load for compiled blocks, returns the loaded value or [LOAD_BAIL].
+extern "C" fn jit_load(vm: *mut Vm, addr: u32, kind: u32) -> u64 {
+    // SAFETY: only the memory and the touched pages are borrowed, compiled blocks hold on to the
+    // registers alone
+    let (memory, touched_pages) = unsafe { (&(*vm).memory, &mut (*vm).touched_pages) };
+    let (size, align_mask) = chunk(kind);
+    if addr & align_mask != 0 {
+        return LOAD_BAIL;
+    }
+
+    let Some(value) = memory.read_mem(addr, size) else {
+        return LOAD_BAIL;
+    };
+    if let Some(pages) = touched_pages {
+        touch_pages(pages, addr, align_mask + 1);
+    }
+    (match kind {
+        0b000 => value as i8 as u32,
+        0b001 => value as i16 as u32,
+        _ => value,
+    }) as u64
+}
+
+/// Store for compiled blocks, with the same side effects as a store of the block interpreter.
+extern "C" fn jit_store(vm: *mut Vm, addr: u32, value: u32, kind: u32) -> u32 {
+    // SAFETY: the registers are not borrowed, see `jit_load`. Invalidating blocks drops the compiled
+    // blocks but not their code, which stays valid until the VM is dropped
+    let (memory, reservation, cache, touched_pages, htif) = unsafe {
+        (
+            &mut (*vm).memory,
+            &mut (*vm).reservation,
+            &mut (*vm).instruction_cache,
+            &mut (*vm).touched_pages,
+            &(*vm).htif,
+        )
+    };
+    let (size, align_mask) = chunk(kind);
+    // Commands to the host are left to the interpreter
+    if htif
+        .as_ref()
+        .is_some_and(|htif| htif.watches(addr, align_mask + 1))
+    {
+        return STORE_BAIL;
+    }
+    if addr & align_mask != 0 || !memory.write_mem(addr, size, value) {
+        return STORE_BAIL;
+    }
+    if let Some(pages) = touched_pages {
+        touch_pages(pages, addr, align_mask + 1);
+    }
+
+    if *reservation == Some(addr & !0x3) {
+        *reservation = None;
+    }
+    if cache.invalidate(addr, align_mask + 1) {
+        return STORE_INVALIDATED;
+    }
+    STORE_OK
+}
+
+impl Vm {
+    /// Run the compiled version of `block`, the block at the current pc, compiling it once hot.
+    /// Returns the number of instructions executed, `None` when the block is not compiled.
+    pub(crate) fn execute_compiled_block(&mut self, block: &Block) -> Option<u64> {
+        let compiled = self.instruction_cache.jit.lookup(self.pc, block)?;
+
+        let vm: *mut Vm = self;
+        // SAFETY: `regs` is derived from `vm`, so both pointers share its provenance. The block
+        // only touches the registers through `regs` and the rest of the VM through the helpers
+        // above
+        let result = unsafe {
+            let regs = (*addr_of_mut!((*vm).registers)).as_mut_ptr();
+            compiled(regs, vm)
+        };
+
+        let executed = result & 0xffff_ffff;
+        self.pc = (result >> 32) as u32;
+        self.csrs.cycle += executed;
+        self.csrs.instret += executed;
+        Some(executed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{addi, bne, r_type, EBREAK};
+    use crate::vm::{RunConfig, RunOutcome};
+
+    /// Run `program` with and without the JIT and check both end in the same state
+    fn check_same(program: Vec<u32>, registers: &[(u32, u32)]) -> Vm {
+        let [interpreted, compiled] = [false, true].map(|jit| {
+            let mut vm = Vm::from_bin(program.clone()).unwrap();
+            for (reg, value) in registers {
+                vm.registers.write_reg(*reg, *value);
+            }
+            let outcome = vm.run(RunConfig {
+                jit,
+                ..RunConfig::default()
+            });
+            assert_eq!(outcome, RunOutcome::Breakpoint(vm.pc));
+            vm
+        });
+
+        for reg in 0..32 {
+            assert_eq!(
+                interpreted.registers.read_reg(reg),
+                compiled.registers.read_reg(reg),
+                "x{reg}"
+            );
+        }
+        assert_eq!(interpreted.pc, compiled.pc);
+        assert_eq!(interpreted.csrs, compiled.csrs);
+        compiled
+    }
+
+    #[test]
+    fn test_hot_loop_is_compiled() {
+        // Sum 1..=100 in x2
+        let vm = check_same(
+            vec![
+                addi(1, 0, 100),
+                r_type(0, 0, 2, 2, 1),
+                addi(1, 1, -1),
+                bne(1, 0, -8),
+                EBREAK,
+            ],
+            &[],
+        );
+
+        assert_eq!(vm.registers.read_reg(2), 5050);
+        assert_eq!(vm.instruction_cache.jit.compiled(), 1);
+    }
+
+    #[test]
+    fn test_clear_frees_the_code() {
+        let program = vec![addi(1, 0, 100), addi(1, 1, -1), bne(1, 0, -4), EBREAK];
+        let mut vm = check_same(program, &[]);
+        assert!(vm.instruction_cache.jit.module.is_some());
+
+        vm.instruction_cache.clear();
+        assert!(vm.instruction_cache.jit.module.is_none());
+        assert_eq!(vm.instruction_cache.jit.compiled(), 0);
+
+        // The module is created again for the next run
+        vm.pc = 0;
+        let config = RunConfig {
+            jit: true,
+            ..RunConfig::default()
+        };
+        assert_eq!(vm.run(config), RunOutcome::Breakpoint(12));
+        assert_eq!(vm.instruction_cache.jit.compiled(), 1);
+    }
+
+    #[test]
+    fn test_stale_code_is_freed() {
+        let mut vm = check_same(
+            vec![addi(1, 0, 100), addi(1, 1, -1), bne(1, 0, -4), EBREAK],
+            &[],
+        );
+        let jit = &mut vm.instruction_cache.jit;
+        jit.stale = MAX_STALE_BLOCKS;
+        assert!(jit.lookup(4, &Block::default()).is_none());
+
+        assert_eq!(jit.stale, 0);
+        assert_eq!(jit.compiled(), 0);
+    }
+
+    #[test]
+    fn test_alu_ops_match_the_interpreter() {
+        let values = [
+            0,
+            1,
+            2,
+            31,
+            32,
+            0x7fff_ffff,
+            0x8000_0000,
+            u32::MAX,
+            0xdead_beef,
+        ];
+        let ops = [
+            (0b0000000, 0b000),
+            (0b0100000, 0b000),
+            (0b0000000, 0b001),
+            (0b0000000, 0b010),
+            (0b0000000, 0b011),
+            (0b0000000, 0b100),
+            (0b0000000, 0b101),
+            (0b0100000, 0b101),
+            (0b0000000, 0b110),
+            (0b0000000, 0b111),
+            (0b0000001, 0b000),
+            (0b0000001, 0b001),
+            (0b0000001, 0b010),
+            (0b0000001, 0b011),
+            (0b0000001, 0b100),
+            (0b0000001, 0b101),
+            (0b0000001, 0b110),
+            (0b0000001, 0b111),
+        ];
+
+        for a in values {
+            for b in values {
+                // Loop enough times for the block to be compiled, x3 = x1 op x2 for every op
+                let mut program = vec![addi(10, 0, HOT_THRESHOLD as i32 + 1)];
+                for (i, (funct7, funct3)) in ops.iter().enumerate() {
+                    program.push(r_type(*funct7, *funct3, 11 + i as u32, 1, 2));
+                }
+                program.push(addi(10, 10, -1));
+                program.push(bne(10, 0, -4 * (ops.len() as i32 + 1)));
+                program.push(EBREAK);
+
+                let vm = check_same(program, &[(1, a), (2, b)]);
+                assert_eq!(vm.instruction_cache.jit.compiled(), 1);
+            }
+        }
+    }
+}
@@ -1,3 +1,25 @@
+#[cfg(any(test, feature = "asm"))]
+pub mod asm;
+pub mod atomics;
+pub mod block;
+pub mod compressed;
+pub mod csr;
+pub mod disassembler;
+pub mod gdb;
+pub mod htif;
 pub mod instructions;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod linux;
+pub mod memory_checking;
+pub mod observer;
+pub mod segment;
+pub mod signature;
+pub mod snapshot;
+pub mod sp1;
+pub mod syscalls;
+pub mod trace;
+pub mod trap;
 pub mod utils;
+pub mod vfs;
 pub mod vm;
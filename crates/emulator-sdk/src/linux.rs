@@ -0,0 +1,1268 @@
+//! This mod holds the Linux personality of the VM, which runs statically linked RV32 Linux programs
+//! (built against musl or newlib) in user mode, like `qemu-riscv32` does.
+//! [Vm::from_linux_elf] lays out the process: the program, the initial stack holding the arguments,
+//! the environment and the auxiliary vector, and the [LinuxSyscallHandler] servicing the syscalls.
+//!
+//! The process is deterministic: the clocks start at a configured time and advance on every read,
+//! and `getrandom` draws from a seeded generator. Memory has no protection besides the permissions
+//! of the ELF segments, the `prot` of `mmap` is ignored and unmapped pages read as zeros.
+//! Files are opened in the [Vfs] of the handler, which only holds what the host put there.
+use crate::{
+    syscalls::{
+        SyscallContext, SyscallHandler, SyscallOutcome, EBADF, MAX_BUFFER_LEN, SYS_EXIT, SYS_READ,
+        SYS_WRITE,
+    },
+    vfs::{normalize, FileHandle, Node, Vfs},
+    vm::{VMErrors, Vm},
+};
+use bincode::Options;
+use core::{Memory, PAGE_SIZE};
+use elf_parser::Elf;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{stderr, stdout, Write},
+};
+
+pub const SYS_IOCTL: u32 = 29;
+pub const SYS_OPENAT: u32 = 56;
+pub const SYS_CLOSE: u32 = 57;
+/// Syscall number for `_llseek(fd, offset_high, offset_low, result, whence)`, RV32 has no `lseek`
+pub const SYS_LSEEK: u32 = 62;
+pub const SYS_READV: u32 = 65;
+pub const SYS_WRITEV: u32 = 66;
+pub const SYS_FSTAT: u32 = 80;
+pub const SYS_EXIT_GROUP: u32 = 94;
+pub const SYS_SET_TID_ADDRESS: u32 = 96;
+pub const SYS_RT_SIGACTION: u32 = 134;
+pub const SYS_RT_SIGPROCMASK: u32 = 135;
+pub const SYS_UNAME: u32 = 160;
+pub const SYS_GETPID: u32 = 172;
+pub const SYS_GETTID: u32 = 178;
+pub const SYS_BRK: u32 = 214;
+pub const SYS_MUNMAP: u32 = 215;
+pub const SYS_MMAP: u32 = 222;
+pub const SYS_MADVISE: u32 = 233;
+pub const SYS_GETRANDOM: u32 = 278;
+pub const SYS_STATX: u32 = 291;
+/// RV32 only has the clock syscalls with 64-bit times
+pub const SYS_CLOCK_GETTIME64: u32 = 403;
+
+pub const ENOENT: i32 = 2;
+pub const ENOMEM: i32 = 12;
+pub const EFAULT: i32 = 14;
+pub const EEXIST: i32 = 17;
+pub const ENODEV: i32 = 19;
+pub const ENOTDIR: i32 = 20;
+pub const EISDIR: i32 = 21;
+pub const EINVAL: i32 = 22;
+pub const ENOTTY: i32 = 25;
+pub const ESPIPE: i32 = 29;
+pub const EROFS: i32 = 30;
+pub const ENAMETOOLONG: i32 = 36;
+pub const ENOSYS: i32 = 38;
+
+/// The initial stack grows down from here
+pub const STACK_TOP: u32 = 0x8000_0000;
+/// The stack may grow this far, mappings stop below it
+pub const STACK_SIZE: u32 = 8 << 20;
+/// Anonymous mappings are placed from here up, the heap grows up to here
+pub const MMAP_BASE: u32 = 0x4000_0000;
+
+/// The thread id of the only thread, which is also the process id
+const TID: u32 = 1;
+/// How much the clocks advance on every read, in nanoseconds
+const CLOCK_TICK: u64 = 1000;
+
+const MAP_FIXED: u32 = 0x10;
+const MAP_ANONYMOUS: u32 = 0x20;
+const MADV_DONTNEED: u32 = 4;
+const SIGKILL: u32 = 9;
+const SIGSTOP: u32 = 19;
+/// The highest signal number, signals are 1 to 64
+const NSIG: u32 = 64;
+/// The size of a signal set, one bit per signal
+const SIGSET_SIZE: u32 = 8;
+const SIG_BLOCK: u32 = 0;
+const SIG_UNBLOCK: u32 = 1;
+const SIG_SETMASK: u32 = 2;
+/// The `flags` of `statx` telling it to look at `dirfd` itself when the path is empty
+const AT_EMPTY_PATH: u32 = 0x1000;
+/// The fields of the struct statx that are filled in
+const STATX_BASIC_STATS: u32 = 0x7ff;
+/// The most bytes a single getrandom call fills on Linux
+const GETRANDOM_MAX: u32 = 33_554_431;
+const S_IFCHR: u32 = 0o020000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+/// The `dirfd` of `openat` for paths relative to the working directory, which is `/`
+const AT_FDCWD: u32 = -100i32 as u32;
+/// The longest path `openat` takes, with its terminating zero
+const PATH_MAX: u32 = 4096;
+
+const AT_NULL: u32 = 0;
+const AT_PHDR: u32 = 3;
+const AT_PHENT: u32 = 4;
+const AT_PHNUM: u32 = 5;
+const AT_PAGESZ: u32 = 6;
+const AT_ENTRY: u32 = 9;
+const AT_UID: u32 = 11;
+const AT_EUID: u32 = 12;
+const AT_GID: u32 = 13;
+const AT_EGID: u32 = 14;
+const AT_HWCAP: u32 = 16;
+const AT_CLKTCK: u32 = 17;
+const AT_SECURE: u32 = 23;
+const AT_RANDOM: u32 = 25;
+const AT_EXECFN: u32 = 31;
+/// The extensions of the hart, one bit per letter: I, M, A and C
+const HWCAP: u32 = (1 << 8) | (1 << 12) | 1 | (1 << 2);
+
+/// This is what a file descriptor refers to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OpenFile {
+    Stdin,
+    Stdout,
+    Stderr,
+    /// A file of the [Vfs]
+    File(FileHandle),
+}
+
+/// The syscall handler of Linux programs, see the [module](self) documentation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinuxSyscallHandler {
+    /// Bytes served to the program when it reads from stdin
+    pub stdin: VecDeque<u8>,
+    /// Everything the program wrote to stdout
+    pub stdout: Vec<u8>,
+    /// Everything the program wrote to stderr
+    pub stderr: Vec<u8>,
+    /// When set, the output is also forwarded to the host's stdout/stderr.
+    /// This is up to the host and is not saved in snapshots
+    #[serde(skip)]
+    pub echo: bool,
+    /// The open file descriptors
+    pub fds: BTreeMap<u32, OpenFile>,
+    /// The files the program can open
+    pub vfs: Vfs,
+    /// The lowest program break, right after the segments of the program
+    pub brk_start: u32,
+    /// The current program break
+    pub brk: u32,
+    /// The end of the anonymous mappings
+    pub mmap_end: u32,
+    /// What `CLOCK_REALTIME` reads when the program starts, in nanoseconds since the Unix epoch
+    pub realtime_start: u64,
+    /// The time elapsed since the program started, in nanoseconds
+    pub elapsed: u64,
+    /// The state of the generator behind `getrandom`
+    pub random_state: u64,
+    /// The actions set with `rt_sigaction`, as the kernel struct sigaction by signal number.
+    /// Signals are never delivered, the actions are only given back
+    pub signal_actions: BTreeMap<u32, [u8; 16]>,
+    /// The signals blocked with `rt_sigprocmask`, bit n - 1 for signal n
+    pub signal_mask: u64,
+}
+
+impl Default for LinuxSyscallHandler {
+    fn default() -> Self {
+        Self {
+            stdin: VecDeque::new(),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            echo: false,
+            fds: BTreeMap::from([
+                (0, OpenFile::Stdin),
+                (1, OpenFile::Stdout),
+                (2, OpenFile::Stderr),
+            ]),
+            vfs: Vfs::new(),
+            brk_start: 0,
+            brk: 0,
+            mmap_end: MMAP_BASE,
+            realtime_start: 0,
+            elapsed: 0,
+            random_state: 0,
+            signal_actions: BTreeMap::new(),
+            signal_mask: 0,
+        }
+    }
+}
+
+impl LinuxSyscallHandler {
+    /// Create a new handler that forwards the output of the program to the host.
+    pub fn new() -> Self {
+        Self {
+            echo: true,
+            ..Default::default()
+        }
+    }
+
+    /// Create a new handler whose `getrandom` draws from a generator seeded with `seed`.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            random_state: seed,
+            ..Self::new()
+        }
+    }
+
+    /// Returns the next random number, the generator is SplitMix64.
+    fn next_random(&mut self) -> u64 {
+        self.random_state = self.random_state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.random_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn random_bytes(&mut self, len: usize) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(len + 8);
+        while bytes.len() < len {
+            bytes.extend_from_slice(&self.next_random().to_le_bytes());
+        }
+        bytes.truncate(len);
+        bytes
+    }
+
+    /// Returns the result of writing `data` to `fd`.
+    fn output(&mut self, fd: u32, data: &[u8]) -> u32 {
+        match self.fds.get_mut(&fd) {
+            Some(OpenFile::Stdout) => {
+                if self.echo {
+                    let _ = stdout().write_all(data);
+                }
+                self.stdout.extend_from_slice(data);
+            }
+            Some(OpenFile::Stderr) => {
+                if self.echo {
+                    let _ = stderr().write_all(data);
+                }
+                self.stderr.extend_from_slice(data);
+            }
+            Some(OpenFile::File(handle)) => {
+                return match self.vfs.write(handle, data) {
+                    Ok(written) => written,
+                    Err(errno) => -errno as u32,
+                }
+            }
+            _ => return -EBADF as u32,
+        }
+
+        data.len() as u32
+    }
+
+    /// Returns up to `len` bytes read from `fd`, or the error number.
+    fn input(&mut self, fd: u32, len: u32) -> Result<Vec<u8>, i32> {
+        match self.fds.get_mut(&fd) {
+            Some(OpenFile::Stdin) => {
+                let len = (len as usize).min(self.stdin.len());
+                Ok(self.stdin.drain(..len).collect())
+            }
+            Some(OpenFile::File(handle)) => self.vfs.read(handle, len),
+            _ => Err(EBADF),
+        }
+    }
+
+    /// Returns the buffers of the `count` iovecs at `iov`, as addresses and lengths.
+    fn iovecs(ctx: &mut SyscallContext, iov: u32, count: u32) -> Result<Vec<(u32, u32)>, VMErrors> {
+        let iovecs = ctx.read_bytes(iov, 8 * count)?;
+        Ok(iovecs
+            .chunks_exact(8)
+            .map(|iovec| {
+                (
+                    u32::from_le_bytes(iovec[..4].try_into().unwrap()),
+                    u32::from_le_bytes(iovec[4..].try_into().unwrap()),
+                )
+            })
+            .collect())
+    }
+
+    fn sys_write(&mut self, ctx: &mut SyscallContext) -> Result<u32, VMErrors> {
+        let fd = ctx.arg(0);
+        let buf = ctx.arg(1);
+        // A larger write is cut short, which the guest sees as a partial write
+        let count = ctx.arg(2).min(MAX_BUFFER_LEN);
+        // The buffer is only read for a descriptor that can be written, like Linux
+        if matches!(self.fds.get(&fd), None | Some(OpenFile::Stdin)) {
+            return Ok(-EBADF as u32);
+        }
+        let data = ctx.read_bytes(buf, count)?;
+
+        Ok(self.output(fd, &data))
+    }
+
+    fn sys_writev(&mut self, ctx: &mut SyscallContext) -> Result<u32, VMErrors> {
+        let fd = ctx.arg(0);
+        let iov = ctx.arg(1);
+        let count = ctx.arg(2);
+        if count > 1024 {
+            return Ok(-EINVAL as u32);
+        }
+        if matches!(self.fds.get(&fd), None | Some(OpenFile::Stdin)) {
+            return Ok(-EBADF as u32);
+        }
+
+        // Like write, the buffers are cut short at MAX_BUFFER_LEN bytes in total
+        let mut data = Vec::new();
+        for (base, len) in Self::iovecs(ctx, iov, count)? {
+            let len = len.min(MAX_BUFFER_LEN - data.len() as u32);
+            data.extend(ctx.read_bytes(base, len)?);
+        }
+
+        Ok(self.output(fd, &data))
+    }
+
+    fn sys_read(&mut self, ctx: &mut SyscallContext) -> Result<u32, VMErrors> {
+        let fd = ctx.arg(0);
+        let buf = ctx.arg(1);
+        let count = ctx.arg(2).min(MAX_BUFFER_LEN);
+        match self.input(fd, count) {
+            Ok(data) => {
+                ctx.write_bytes(buf, &data)?;
+                Ok(data.len() as u32)
+            }
+            Err(errno) => Ok(-errno as u32),
+        }
+    }
+
+    fn sys_readv(&mut self, ctx: &mut SyscallContext) -> Result<u32, VMErrors> {
+        let fd = ctx.arg(0);
+        let iov = ctx.arg(1);
+        let count = ctx.arg(2);
+        if count > 1024 {
+            return Ok(-EINVAL as u32);
+        }
+
+        // The buffers are filled in order, stopping at the first short read
+        let mut total = 0u32;
+        for (base, len) in Self::iovecs(ctx, iov, count)? {
+            let len = len.min(MAX_BUFFER_LEN - total);
+            let data = match self.input(fd, len) {
+                Ok(data) => data,
+                Err(errno) if total == 0 => return Ok(-errno as u32),
+                Err(_) => break,
+            };
+            ctx.write_bytes(base, &data)?;
+            total += data.len() as u32;
+            if data.len() < len as usize {
+                break;
+            }
+        }
+
+        Ok(total)
+    }
+
+    /// Returns the zero terminated path at `pathname`, or the error number.
+    fn read_path(ctx: &mut SyscallContext, pathname: u32) -> Result<Result<String, i32>, VMErrors> {
+        let mut path = Vec::new();
+        loop {
+            if path.len() as u32 == PATH_MAX {
+                return Ok(Err(ENAMETOOLONG));
+            }
+            let Some(addr) = pathname.checked_add(path.len() as u32) else {
+                return Ok(Err(EFAULT));
+            };
+            match ctx.read_bytes(addr, 1)?[0] {
+                0 => break,
+                byte => path.push(byte),
+            }
+        }
+
+        Ok(String::from_utf8(path).map_err(|_| ENOENT))
+    }
+
+    /// Returns the error number of resolving `path` against `dirfd`, if it can not be.
+    fn check_dirfd(&self, dirfd: u32, path: &str) -> Option<i32> {
+        if path.is_empty() {
+            return Some(ENOENT);
+        }
+        // There are no directory descriptors, relative paths are only relative to `/`
+        if !path.starts_with('/') && dirfd != AT_FDCWD {
+            return Some(match self.fds.contains_key(&dirfd) {
+                true => ENOTDIR,
+                false => EBADF,
+            });
+        }
+        None
+    }
+
+    fn sys_openat(&mut self, ctx: &mut SyscallContext) -> Result<u32, VMErrors> {
+        let dirfd = ctx.arg(0);
+        let pathname = ctx.arg(1);
+        let flags = ctx.arg(2);
+
+        let path = match Self::read_path(ctx, pathname)? {
+            Ok(path) => path,
+            Err(errno) => return Ok(-errno as u32),
+        };
+        if let Some(errno) = self.check_dirfd(dirfd, &path) {
+            return Ok(-errno as u32);
+        }
+
+        let handle = match self.vfs.open(&normalize(&path), flags) {
+            Ok(handle) => handle,
+            Err(errno) => return Ok(-errno as u32),
+        };
+        // Like Linux, the lowest free descriptor
+        let fd = (0..).find(|fd| !self.fds.contains_key(fd)).unwrap();
+        self.fds.insert(fd, OpenFile::File(handle));
+
+        Ok(fd)
+    }
+
+    /// Move the program break, returning the new break or the current one on failure.
+    fn sys_brk(&mut self, ctx: &mut SyscallContext) -> u32 {
+        let brk = ctx.arg(0);
+        if brk < self.brk_start || brk > MMAP_BASE {
+            return self.brk;
+        }
+
+        // Memory given back is zero filled if the program grows the heap again
+        if brk < self.brk {
+            ctx.vm.unmap(brk.next_multiple_of(PAGE_SIZE), self.brk);
+        }
+        self.brk = brk;
+
+        brk
+    }
+
+    fn sys_mmap(&mut self, ctx: &mut SyscallContext) -> u32 {
+        let addr = ctx.arg(0);
+        let len = ctx.arg(1);
+        let flags = ctx.arg(3);
+        if flags & MAP_ANONYMOUS == 0 {
+            return -ENODEV as u32;
+        }
+        let Some(len) = len
+            .checked_next_multiple_of(PAGE_SIZE)
+            .filter(|len| *len > 0)
+        else {
+            return -EINVAL as u32;
+        };
+
+        if flags & MAP_FIXED != 0 {
+            if !addr.is_multiple_of(PAGE_SIZE) || addr.checked_add(len).is_none() {
+                return -EINVAL as u32;
+            }
+            ctx.vm.unmap(addr, addr + len);
+            return addr;
+        }
+
+        // The address is only a hint, mappings are placed one after the other
+        let start = self.mmap_end;
+        match start.checked_add(len) {
+            Some(end) if end <= STACK_TOP - STACK_SIZE => {
+                self.mmap_end = end;
+                start
+            }
+            _ => -ENOMEM as u32,
+        }
+    }
+
+    fn sys_munmap(&mut self, ctx: &mut SyscallContext) -> u32 {
+        let addr = ctx.arg(0);
+        let Some(len) = ctx.arg(1).checked_next_multiple_of(PAGE_SIZE) else {
+            return -EINVAL as u32;
+        };
+        if !addr.is_multiple_of(PAGE_SIZE) || len == 0 || addr.checked_add(len).is_none() {
+            return -EINVAL as u32;
+        }
+
+        ctx.vm.unmap(addr, addr + len);
+        // The end of the last mapping can be mapped again
+        if addr + len == self.mmap_end && addr >= MMAP_BASE {
+            self.mmap_end = addr;
+        }
+        0
+    }
+
+    fn sys_clock_gettime(&mut self, ctx: &mut SyscallContext) -> Result<u32, VMErrors> {
+        let clock = ctx.arg(0);
+        let tp = ctx.arg(1);
+        let time = match clock {
+            // CLOCK_REALTIME, CLOCK_REALTIME_COARSE, CLOCK_REALTIME_ALARM and CLOCK_TAI
+            0 | 5 | 8 | 11 => self.realtime_start.wrapping_add(self.elapsed),
+            // The monotonic, boot time and cpu time clocks
+            1..=4 | 6 | 7 | 9 => self.elapsed,
+            _ => return Ok(-EINVAL as u32),
+        };
+        self.elapsed += CLOCK_TICK;
+
+        let mut timespec = Vec::with_capacity(16);
+        timespec.extend_from_slice(&(time / 1_000_000_000).to_le_bytes());
+        timespec.extend_from_slice(&(time % 1_000_000_000).to_le_bytes());
+        ctx.write_bytes(tp, &timespec)?;
+
+        Ok(0)
+    }
+
+    fn sys_getrandom(&mut self, ctx: &mut SyscallContext) -> Result<u32, VMErrors> {
+        let buf = ctx.arg(0);
+        // Like Linux, a single call fills at most GETRANDOM_MAX bytes
+        let len = ctx.arg(1).min(GETRANDOM_MAX);
+        let data = self.random_bytes(len as usize);
+        ctx.write_bytes(buf, &data)?;
+
+        Ok(len)
+    }
+
+    fn sys_uname(&mut self, ctx: &mut SyscallContext) -> Result<u32, VMErrors> {
+        let buf = ctx.arg(0);
+        // sysname, nodename, release, version, machine and domainname, 65 bytes each
+        let mut utsname = Vec::with_capacity(6 * 65);
+        for field in ["Linux", "riscv", "6.1.0", "#1", "riscv32", "(none)"] {
+            let mut bytes = field.as_bytes().to_vec();
+            bytes.resize(65, 0);
+            utsname.extend(bytes);
+        }
+        ctx.write_bytes(buf, &utsname)?;
+
+        Ok(0)
+    }
+
+    /// Returns the mode, the size and the block size of the file open at `fd`, or the error number.
+    /// The standard streams are terminals.
+    fn fd_stat(&self, fd: u32) -> Result<(u32, u64, u32), i32> {
+        match self.fds.get(&fd) {
+            Some(OpenFile::File(handle)) => {
+                let mode = if handle.writable { 0o644 } else { 0o444 };
+                Ok((S_IFREG | mode, self.vfs.size(handle), PAGE_SIZE))
+            }
+            Some(_) => Ok((S_IFCHR | 0o620, 0, 1024)),
+            None => Err(EBADF),
+        }
+    }
+
+    /// Returns the mode, the size and the block size of the file at `path`, or the error number.
+    /// The files of the preopened directories are read-only.
+    fn path_stat(&mut self, path: &str) -> Result<(u32, u64, u32), i32> {
+        match self.vfs.open(path, 0) {
+            Ok(handle) => {
+                let mode = match handle.node {
+                    Node::Memory(_) => 0o644,
+                    Node::Host(_) => 0o444,
+                };
+                Ok((S_IFREG | mode, self.vfs.size(&handle), PAGE_SIZE))
+            }
+            Err(EISDIR) => Ok((S_IFDIR | 0o755, 0, PAGE_SIZE)),
+            Err(errno) => Err(errno),
+        }
+    }
+
+    fn sys_fstat(&mut self, ctx: &mut SyscallContext) -> Result<u32, VMErrors> {
+        let fd = ctx.arg(0);
+        let statbuf = ctx.arg(1);
+        // In the layout of the generic struct stat64
+        let mut stat = [0u8; 104];
+        let (mode, size, blksize) = match self.fd_stat(fd) {
+            Ok(stat) => stat,
+            Err(errno) => return Ok(-errno as u32),
+        };
+        stat[16..20].copy_from_slice(&mode.to_le_bytes());
+        stat[20..24].copy_from_slice(&1u32.to_le_bytes());
+        stat[48..56].copy_from_slice(&size.to_le_bytes());
+        stat[56..60].copy_from_slice(&blksize.to_le_bytes());
+        stat[64..72].copy_from_slice(&size.div_ceil(512).to_le_bytes());
+        ctx.write_bytes(statbuf, &stat)?;
+
+        Ok(0)
+    }
+
+    fn sys_statx(&mut self, ctx: &mut SyscallContext) -> Result<u32, VMErrors> {
+        let dirfd = ctx.arg(0);
+        let pathname = ctx.arg(1);
+        let flags = ctx.arg(2);
+        let statxbuf = ctx.arg(4);
+
+        let path = match Self::read_path(ctx, pathname)? {
+            Ok(path) => path,
+            Err(errno) => return Ok(-errno as u32),
+        };
+        let stat = if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+            self.fd_stat(dirfd)
+        } else if let Some(errno) = self.check_dirfd(dirfd, &path) {
+            Err(errno)
+        } else {
+            self.path_stat(&normalize(&path))
+        };
+        let (mode, size, blksize) = match stat {
+            Ok(stat) => stat,
+            Err(errno) => return Ok(-errno as u32),
+        };
+
+        // The struct statx, the timestamps are all zero
+        let mut statx = [0u8; 256];
+        statx[0..4].copy_from_slice(&STATX_BASIC_STATS.to_le_bytes());
+        statx[4..8].copy_from_slice(&blksize.to_le_bytes());
+        statx[16..20].copy_from_slice(&1u32.to_le_bytes());
+        statx[28..30].copy_from_slice(&(mode as u16).to_le_bytes());
+        statx[40..48].copy_from_slice(&size.to_le_bytes());
+        statx[48..56].copy_from_slice(&size.div_ceil(512).to_le_bytes());
+        ctx.write_bytes(statxbuf, &statx)?;
+
+        Ok(0)
+    }
+
+    fn sys_close(&mut self, ctx: &mut SyscallContext) -> u32 {
+        match self.fds.remove(&ctx.arg(0)) {
+            Some(_) => 0,
+            None => -EBADF as u32,
+        }
+    }
+
+    fn sys_lseek(&mut self, ctx: &mut SyscallContext) -> Result<u32, VMErrors> {
+        let fd = ctx.arg(0);
+        let offset = ((ctx.arg(1) as u64) << 32 | ctx.arg(2) as u64) as i64;
+        let result = ctx.arg(3);
+        let whence = ctx.arg(4);
+        let handle = match self.fds.get_mut(&fd) {
+            Some(OpenFile::File(handle)) => handle,
+            Some(_) => return Ok(-ESPIPE as u32),
+            None => return Ok(-EBADF as u32),
+        };
+
+        match self.vfs.seek(handle, offset, whence) {
+            Ok(offset) => {
+                ctx.write_bytes(result, &offset.to_le_bytes())?;
+                Ok(0)
+            }
+            Err(errno) => Ok(-errno as u32),
+        }
+    }
+
+    fn sys_rt_sigaction(&mut self, ctx: &mut SyscallContext) -> Result<u32, VMErrors> {
+        let signal = ctx.arg(0);
+        let act = ctx.arg(1);
+        let oldact = ctx.arg(2);
+        let sigsetsize = ctx.arg(3);
+        if sigsetsize != SIGSET_SIZE
+            || !(1..=NSIG).contains(&signal)
+            || (act != 0 && (signal == SIGKILL || signal == SIGSTOP))
+        {
+            return Ok(-EINVAL as u32);
+        }
+
+        let old = self
+            .signal_actions
+            .get(&signal)
+            .copied()
+            .unwrap_or_default();
+        if act != 0 {
+            let action = ctx.read_bytes(act, 16)?;
+            self.signal_actions
+                .insert(signal, action.try_into().expect("read 16 bytes"));
+        }
+        if oldact != 0 {
+            ctx.write_bytes(oldact, &old)?;
+        }
+
+        Ok(0)
+    }
+
+    fn sys_rt_sigprocmask(&mut self, ctx: &mut SyscallContext) -> Result<u32, VMErrors> {
+        let how = ctx.arg(0);
+        let set = ctx.arg(1);
+        let oldset = ctx.arg(2);
+        let sigsetsize = ctx.arg(3);
+        if sigsetsize != SIGSET_SIZE {
+            return Ok(-EINVAL as u32);
+        }
+
+        let old = self.signal_mask;
+        if set != 0 {
+            let set = u64::from_le_bytes(ctx.read_bytes(set, 8)?.try_into().unwrap());
+            let mask = match how {
+                SIG_BLOCK => old | set,
+                SIG_UNBLOCK => old & !set,
+                SIG_SETMASK => set,
+                _ => return Ok(-EINVAL as u32),
+            };
+            // SIGKILL and SIGSTOP can not be blocked
+            self.signal_mask = mask & !(1 << (SIGKILL - 1) | 1 << (SIGSTOP - 1));
+        }
+        if oldset != 0 {
+            ctx.write_bytes(oldset, &old.to_le_bytes())?;
+        }
+
+        Ok(0)
+    }
+
+    /// Only `MADV_DONTNEED` does something: the anonymous pages in the range read as zeros again.
+    /// Pages of the program are left as they are, the other advice is ignored.
+    fn sys_madvise(&mut self, ctx: &mut SyscallContext) -> u32 {
+        let addr = ctx.arg(0);
+        let advice = ctx.arg(2);
+        let Some(end) = ctx
+            .arg(1)
+            .checked_next_multiple_of(PAGE_SIZE)
+            .and_then(|len| addr.checked_add(len))
+        else {
+            return -EINVAL as u32;
+        };
+        if !addr.is_multiple_of(PAGE_SIZE) {
+            return -EINVAL as u32;
+        }
+
+        if advice == MADV_DONTNEED {
+            // The heap, the mappings and the stack, past the page the program ends in
+            let start = addr.max(self.brk_start.next_multiple_of(PAGE_SIZE));
+            let end = end.min(STACK_TOP);
+            if start < end {
+                ctx.vm.unmap(start, end);
+            }
+        }
+        0
+    }
+
+    fn sys_ioctl(&mut self, ctx: &mut SyscallContext) -> u32 {
+        // No terminal settings, which also tells the C libraries not to line buffer stdout
+        match self.fds.get(&ctx.arg(0)) {
+            Some(_) => -ENOTTY as u32,
+            None => -EBADF as u32,
+        }
+    }
+}
+
+impl SyscallHandler for LinuxSyscallHandler {
+    fn handle(
+        &mut self,
+        number: u32,
+        ctx: &mut SyscallContext,
+    ) -> Result<SyscallOutcome, VMErrors> {
+        let result = match number {
+            // The exit status is the low byte of the code. The program has a single thread, so
+            // ending it with exit ends the process like exit_group
+            SYS_EXIT | SYS_EXIT_GROUP => return Ok(SyscallOutcome::Exit(ctx.arg(0) & 0xff)),
+            SYS_WRITE => self.sys_write(ctx)?,
+            SYS_WRITEV => self.sys_writev(ctx)?,
+            SYS_READ => self.sys_read(ctx)?,
+            SYS_READV => self.sys_readv(ctx)?,
+            SYS_BRK => self.sys_brk(ctx),
+            SYS_MMAP => self.sys_mmap(ctx),
+            SYS_MUNMAP => self.sys_munmap(ctx),
+            SYS_CLOCK_GETTIME64 => self.sys_clock_gettime(ctx)?,
+            SYS_GETRANDOM => self.sys_getrandom(ctx)?,
+            SYS_UNAME => self.sys_uname(ctx)?,
+            SYS_SET_TID_ADDRESS | SYS_GETPID | SYS_GETTID => TID,
+            SYS_RT_SIGACTION => self.sys_rt_sigaction(ctx)?,
+            SYS_RT_SIGPROCMASK => self.sys_rt_sigprocmask(ctx)?,
+            SYS_MADVISE => self.sys_madvise(ctx),
+            SYS_STATX => self.sys_statx(ctx)?,
+            SYS_OPENAT => self.sys_openat(ctx)?,
+            SYS_CLOSE => self.sys_close(ctx),
+            SYS_FSTAT => self.sys_fstat(ctx)?,
+            SYS_LSEEK => self.sys_lseek(ctx)?,
+            SYS_IOCTL => self.sys_ioctl(ctx),
+            // Like Linux, the program is told the syscall does not exist
+            _ => -ENOSYS as u32,
+        };
+        ctx.set_return(result);
+
+        Ok(SyscallOutcome::Continue)
+    }
+
+    fn box_clone(&self) -> Box<dyn SyscallHandler> {
+        Box::new(self.clone())
+    }
+
+    fn save_state(&self) -> Result<Vec<u8>, VMErrors> {
+        bincode::DefaultOptions::new()
+            .serialize(self)
+            .map_err(|_| VMErrors::SerializationError)
+    }
+
+    fn restore_state(&mut self, state: &[u8]) -> Result<(), VMErrors> {
+        let echo = self.echo;
+        *self = bincode::DefaultOptions::new()
+            .deserialize(state)
+            .map_err(|_| VMErrors::SerializationError)?;
+        self.echo = echo;
+
+        Ok(())
+    }
+}
+
+impl Vm {
+    /// Create a new Vm running a statically linked Linux program with the arguments `args`, the
+    /// first being the name of the program, and the environment `env` (`NAME=value` strings).
+    /// `handler` services the syscalls, its heap is placed after the segments of the program.
+    pub fn from_linux_elf(
+        elf: &Elf,
+        args: &[String],
+        env: &[String],
+        mut handler: LinuxSyscallHandler,
+    ) -> Self {
+        let mut vm = Self::from_elf(elf);
+
+        let end = elf
+            .segments
+            .iter()
+            .map(|segment| segment.start.saturating_add(segment.size))
+            .max()
+            .unwrap_or(0);
+        handler.brk_start = end.next_multiple_of(PAGE_SIZE);
+        handler.brk = handler.brk_start;
+        handler.mmap_end = MMAP_BASE;
+
+        let sp = initial_stack(&mut vm.memory, elf, args, env, &mut handler);
+        vm.registers.write_reg(2, sp);
+        vm.set_syscall_handler(handler);
+
+        vm
+    }
+}
+
+/// Lay out the initial stack of the process and return the stack pointer, which points to argc.
+/// Above it are the pointers to the arguments, to the environment and the auxiliary vector, then
+/// the strings and the random bytes they point to.
+fn initial_stack(
+    memory: &mut Memory,
+    elf: &Elf,
+    args: &[String],
+    env: &[String],
+    handler: &mut LinuxSyscallHandler,
+) -> u32 {
+    let mut sp = STACK_TOP;
+    let mut push = |memory: &mut Memory, bytes: &[u8]| {
+        sp -= bytes.len() as u32;
+        memory.load_bytes(sp, bytes);
+        sp
+    };
+
+    let random = push(memory, &handler.random_bytes(16));
+    let mut push_strings = |memory: &mut Memory, strings: &[String]| -> Vec<u32> {
+        strings
+            .iter()
+            .map(|string| push(memory, &[string.as_bytes(), &[0]].concat()))
+            .collect()
+    };
+    let envp = push_strings(memory, env);
+    let argv = push_strings(memory, args);
+    let execfn = argv.first().copied().unwrap_or(0);
+
+    let mut auxv = vec![
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_ENTRY, elf.pc_start),
+        (AT_UID, 0),
+        (AT_EUID, 0),
+        (AT_GID, 0),
+        (AT_EGID, 0),
+        (AT_HWCAP, HWCAP),
+        (AT_CLKTCK, 100),
+        (AT_SECURE, 0),
+        (AT_RANDOM, random),
+        (AT_EXECFN, execfn),
+    ];
+    if let Some(headers) = &elf.program_headers {
+        auxv.extend([
+            (AT_PHDR, headers.addr),
+            (AT_PHENT, headers.entry_size),
+            (AT_PHNUM, headers.count),
+        ]);
+    }
+    auxv.push((AT_NULL, 0));
+
+    let mut words = vec![argv.len() as u32];
+    words.extend(argv);
+    words.push(0);
+    words.extend(envp);
+    words.push(0);
+    words.extend(auxv.iter().flat_map(|(key, value)| [*key, *value]));
+
+    // The ABI wants the stack pointer 16 byte aligned
+    let sp = (sp - 4 * words.len() as u32) & !0xf;
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    memory.load_bytes(sp, &bytes);
+
+    sp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{addi, jal, lui, EBREAK, ECALL, RET};
+    use crate::vm::{RunConfig, RunOutcome};
+    use core::{interfaces::MemoryInterface, MemoryChuckSize};
+
+    /// A program making the syscall `number` with `args`, keeping the result in `s1`
+    fn syscall(number: u32, args: &[u32]) -> Vec<u32> {
+        let mut program = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            let rd = 10 + i as u32;
+            // lui + addi, the addi immediate is sign extended
+            let upper = arg.wrapping_add(0x800) >> 12;
+            program.push(lui(rd, upper & 0xfffff));
+            program.push(addi(rd, rd, (*arg as i32) << 20 >> 20));
+        }
+        program.extend([addi(17, 0, number as i32), ECALL, addi(9, 10, 0)]);
+        program
+    }
+
+    fn run(program: Vec<u32>, handler: LinuxSyscallHandler) -> Vm {
+        let mut vm = Vm::from_bin(program).unwrap();
+        vm.set_syscall_handler(handler);
+        vm.run(RunConfig::default());
+        vm
+    }
+
+    fn state(vm: &Vm) -> &LinuxSyscallHandler {
+        (vm.syscall_handler.as_ref() as &dyn std::any::Any)
+            .downcast_ref()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_writev_and_exit_group() {
+        let mut program = syscall(SYS_WRITEV, &[1, 0x1000, 2]);
+        program.extend(syscall(SYS_EXIT_GROUP, &[0x1ff]));
+        let mut vm = Vm::from_bin(program).unwrap();
+        vm.set_syscall_handler(LinuxSyscallHandler::default());
+        vm.memory.load_bytes(0x2000, b"hello, world\n");
+        for (i, word) in [0x2000, 7, 0x2007, 6].iter().enumerate() {
+            vm.memory
+                .write_mem(0x1000 + 4 * i as u32, MemoryChuckSize::WordSize, *word);
+        }
+
+        assert_eq!(vm.run(RunConfig::default()), RunOutcome::Exited(0xff));
+        assert_eq!(state(&vm).stdout, b"hello, world\n");
+    }
+
+    #[test]
+    fn test_brk_and_mmap() {
+        let handler = LinuxSyscallHandler {
+            brk_start: 0x3000,
+            brk: 0x3000,
+            ..Default::default()
+        };
+        let mut program = syscall(SYS_BRK, &[0]);
+        program.extend(syscall(SYS_BRK, &[0x5800]));
+        program.push(addi(18, 9, 0));
+        program.extend(syscall(SYS_BRK, &[MMAP_BASE + 1]));
+        program.push(addi(19, 9, 0));
+        // mmap(NULL, 5000, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0)
+        program.extend(syscall(SYS_MMAP, &[0, 5000, 3, 0x22, u32::MAX, 0]));
+        program.push(addi(20, 9, 0));
+        program.extend(syscall(SYS_MMAP, &[0, 4096, 3, 0x22, u32::MAX, 0]));
+        program.push(addi(21, 9, 0));
+        program.extend(syscall(SYS_MUNMAP, &[MMAP_BASE + 0x2000, 4096]));
+        program.push(addi(22, 9, 0));
+        program.extend(syscall(SYS_MMAP, &[0, 4096, 3, 0x02, 3, 0]));
+        program.push(EBREAK);
+
+        let vm = run(program, handler);
+        assert_eq!(vm.registers.read_reg(18), 0x5800);
+        // Past the heap limit the break does not move
+        assert_eq!(vm.registers.read_reg(19), 0x5800);
+        assert_eq!(state(&vm).brk, 0x5800);
+        assert_eq!(vm.registers.read_reg(20), MMAP_BASE);
+        assert_eq!(vm.registers.read_reg(21), MMAP_BASE + 0x2000);
+        assert_eq!(vm.registers.read_reg(22), 0);
+        assert_eq!(state(&vm).mmap_end, MMAP_BASE + 0x2000);
+        // Only anonymous mappings are supported
+        assert_eq!(vm.registers.read_reg(9), -ENODEV as u32);
+    }
+
+    #[test]
+    fn test_unmapped_code_is_not_run() {
+        // Call the function at 0x1000, unmap it and call it again
+        let mut program = vec![jal(1, 0x1000)];
+        program.extend(syscall(SYS_MUNMAP, &[0x1000, 4096]));
+        program.push(jal(1, 0x1000 - 4 * program.len() as i32));
+        program.resize(0x400, 0);
+        program.extend([addi(18, 18, 1), RET]);
+        let mut vm = Vm::from_bin(program).unwrap();
+        vm.set_syscall_handler(LinuxSyscallHandler::default());
+        vm.touched_pages = Some(Default::default());
+
+        let outcome = vm.run(RunConfig::default());
+        assert!(
+            matches!(outcome, RunOutcome::Faulted { pc: 0x1000, .. }),
+            "{outcome:?}"
+        );
+        assert_eq!(vm.registers.read_reg(18), 1);
+        assert!(vm.touched_pages.unwrap().contains(&1));
+    }
+
+    #[test]
+    fn test_munmap_length_overflow() {
+        // The length rounded up to a page overflows
+        let mut program = syscall(SYS_MUNMAP, &[0x1000, u32::MAX]);
+        program.push(EBREAK);
+
+        let vm = run(program, LinuxSyscallHandler::default());
+        assert_eq!(vm.registers.read_reg(9), -EINVAL as u32);
+    }
+
+    #[test]
+    fn test_clock_and_random_are_deterministic() {
+        let mut program = syscall(SYS_CLOCK_GETTIME64, &[1, 0x1000]);
+        program.extend(syscall(SYS_CLOCK_GETTIME64, &[0, 0x1010]));
+        program.extend(syscall(SYS_GETRANDOM, &[0x1020, 12, 0]));
+        program.extend(syscall(SYS_CLOCK_GETTIME64, &[42, 0x1030]));
+        program.push(EBREAK);
+        let mut handler = LinuxSyscallHandler::with_seed(7);
+        handler.echo = false;
+        handler.realtime_start = 1_700_000_000_500_000_000;
+
+        let vm = run(program.clone(), handler.clone());
+        let word = |addr| vm.memory.read_mem(addr, MemoryChuckSize::WordSize).unwrap();
+        assert_eq!((word(0x1000), word(0x1008)), (0, 0));
+        assert_eq!((word(0x1010), word(0x1018)), (1_700_000_000, 500_001_000));
+        assert_eq!(vm.registers.read_reg(9), -EINVAL as u32);
+
+        let random: Vec<u32> = (0..3).map(|i| word(0x1020 + 4 * i)).collect();
+        assert_ne!(random, [0, 0, 0]);
+        let again = run(program, handler);
+        let random_again: Vec<u32> = (0..3)
+            .map(|i| {
+                again
+                    .memory
+                    .read_mem(0x1020 + 4 * i, MemoryChuckSize::WordSize)
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(random, random_again);
+    }
+
+    #[test]
+    fn test_files_and_unknown_syscalls() {
+        let mut program = syscall(SYS_FSTAT, &[1, 0x1000]);
+        program.push(addi(18, 9, 0));
+        program.extend(syscall(SYS_CLOSE, &[0]));
+        program.push(addi(19, 9, 0));
+        program.extend(syscall(SYS_READ, &[0, 0x1100, 4]));
+        program.push(addi(20, 9, 0));
+        program.extend(syscall(SYS_LSEEK, &[1, 0, 0, 0x1200, 0]));
+        program.push(addi(21, 9, 0));
+        program.extend(syscall(SYS_OPENAT, &[u32::MAX - 99, 0x1300, 0, 0]));
+        program.push(addi(22, 9, 0));
+        program.extend(syscall(1234, &[]));
+        program.push(EBREAK);
+
+        let vm = run(program, LinuxSyscallHandler::default());
+        assert_eq!(vm.registers.read_reg(18), 0);
+        assert_eq!(
+            vm.memory.read_mem(0x1010, MemoryChuckSize::WordSize),
+            Some(S_IFCHR | 0o620)
+        );
+        assert_eq!(vm.registers.read_reg(19), 0);
+        assert_eq!(vm.registers.read_reg(20), -EBADF as u32);
+        assert_eq!(vm.registers.read_reg(21), -ESPIPE as u32);
+        assert_eq!(vm.registers.read_reg(22), -ENOENT as u32);
+        assert_eq!(vm.registers.read_reg(9), -ENOSYS as u32);
+    }
+
+    #[test]
+    fn test_vfs_files() {
+        let mut program = syscall(SYS_CLOSE, &[0]);
+        program.extend(syscall(SYS_OPENAT, &[AT_FDCWD, 0x1300, 0, 0]));
+        program.push(addi(18, 9, 0));
+        program.extend(syscall(SYS_LSEEK, &[0, 0, 7, 0x1400, 0]));
+        program.push(addi(19, 9, 0));
+        program.extend(syscall(SYS_READV, &[0, 0x1000, 2]));
+        program.push(addi(20, 9, 0));
+        program.extend(syscall(SYS_FSTAT, &[0, 0x1500]));
+        program.push(addi(21, 9, 0));
+        program.extend(syscall(SYS_OPENAT, &[AT_FDCWD, 0x1320, 0o101, 0]));
+        program.push(addi(22, 9, 0));
+        program.extend(syscall(SYS_WRITE, &[3, 0x2000, 2]));
+        program.push(addi(23, 9, 0));
+        program.extend(syscall(SYS_OPENAT, &[5, 0x1321, 0, 0]));
+        program.push(addi(24, 9, 0));
+        program.extend(syscall(SYS_OPENAT, &[AT_FDCWD, 0x1300, 0o302, 0]));
+        program.push(EBREAK);
+
+        let mut handler = LinuxSyscallHandler::default();
+        handler.vfs.add_file("/data/in.txt", "hello, file");
+        let mut vm = Vm::from_bin(program).unwrap();
+        vm.set_syscall_handler(handler);
+        vm.memory.load_bytes(0x1300, b"data/../data/in.txt\0");
+        vm.memory.load_bytes(0x1320, b"/out.txt\0");
+        for (i, word) in [0x2000, 2, 0x2010, 10].iter().enumerate() {
+            vm.memory
+                .write_mem(0x1000 + 4 * i as u32, MemoryChuckSize::WordSize, *word);
+        }
+        vm.run(RunConfig::default());
+
+        // The lowest free descriptor is reused
+        assert_eq!(vm.registers.read_reg(18), 0);
+        assert_eq!(vm.registers.read_reg(19), 0);
+        assert_eq!(
+            vm.memory.read_mem(0x1400, MemoryChuckSize::WordSize),
+            Some(7)
+        );
+        assert_eq!(vm.registers.read_reg(20), 4);
+        assert_eq!(
+            vm.memory.read_mem(0x2000, MemoryChuckSize::HalfWord),
+            Some(0x6966)
+        );
+        assert_eq!(
+            vm.memory.read_mem(0x2010, MemoryChuckSize::HalfWord),
+            Some(0x656c)
+        );
+        assert_eq!(vm.registers.read_reg(21), 0);
+        assert_eq!(
+            vm.memory.read_mem(0x1510, MemoryChuckSize::WordSize),
+            Some(S_IFREG | 0o444)
+        );
+        assert_eq!(
+            vm.memory.read_mem(0x1530, MemoryChuckSize::WordSize),
+            Some(11)
+        );
+        assert_eq!(vm.registers.read_reg(22), 3);
+        assert_eq!(vm.registers.read_reg(23), 2);
+        assert_eq!(state(&vm).vfs.file("/out.txt"), Some(&b"fi"[..]));
+        assert_eq!(vm.registers.read_reg(24), -EBADF as u32);
+        assert_eq!(vm.registers.read_reg(9), -EEXIST as u32);
+    }
+
+    #[test]
+    fn test_statx() {
+        // statx(AT_FDCWD, path, 0, STATX_BASIC_STATS, buf) and statx(1, "", AT_EMPTY_PATH, ...)
+        let mut program = syscall(SYS_STATX, &[AT_FDCWD, 0x1300, 0, 0x7ff, 0x1400]);
+        program.push(addi(18, 9, 0));
+        program.extend(syscall(
+            SYS_STATX,
+            &[1, 0x1320, AT_EMPTY_PATH, 0x7ff, 0x1500],
+        ));
+        program.push(addi(19, 9, 0));
+        program.extend(syscall(SYS_STATX, &[AT_FDCWD, 0x1330, 0, 0x7ff, 0x1600]));
+        program.push(addi(20, 9, 0));
+        program.extend(syscall(SYS_STATX, &[1, 0x1320, 0, 0x7ff, 0x1600]));
+        program.push(addi(21, 9, 0));
+        program.extend(syscall(
+            SYS_STATX,
+            &[7, 0x1320, AT_EMPTY_PATH, 0x7ff, 0x1600],
+        ));
+        program.push(EBREAK);
+
+        let mut handler = LinuxSyscallHandler::default();
+        handler.vfs.add_file("/data/in.txt", "hello, file");
+        let mut vm = Vm::from_bin(program).unwrap();
+        vm.set_syscall_handler(handler);
+        vm.memory.load_bytes(0x1300, b"/data/in.txt\0");
+        vm.memory.load_bytes(0x1320, b"\0");
+        vm.memory.load_bytes(0x1330, b"/data\0");
+        vm.run(RunConfig::default());
+
+        assert_eq!(vm.registers.read_reg(18), 0);
+        assert_eq!(
+            vm.memory.read_mem(0x1400, MemoryChuckSize::WordSize),
+            Some(STATX_BASIC_STATS)
+        );
+        assert_eq!(
+            vm.memory.read_mem(0x141c, MemoryChuckSize::HalfWord),
+            Some(S_IFREG | 0o644)
+        );
+        assert_eq!(
+            vm.memory.read_mem(0x1428, MemoryChuckSize::WordSize),
+            Some(11)
+        );
+        assert_eq!(vm.registers.read_reg(19), 0);
+        assert_eq!(
+            vm.memory.read_mem(0x151c, MemoryChuckSize::HalfWord),
+            Some(S_IFCHR | 0o620)
+        );
+        // Directories exist but can not be opened
+        assert_eq!(vm.registers.read_reg(20), 0);
+        assert_eq!(
+            vm.memory.read_mem(0x161c, MemoryChuckSize::HalfWord),
+            Some(S_IFDIR | 0o755)
+        );
+        // An empty path needs AT_EMPTY_PATH, and then an open descriptor
+        assert_eq!(vm.registers.read_reg(21), -ENOENT as u32);
+        assert_eq!(vm.registers.read_reg(9), -EBADF as u32);
+    }
+
+    #[test]
+    fn test_signals_and_ids() {
+        // Set an action for SIGINT, then set it again reading back the first one
+        let mut program = syscall(SYS_RT_SIGACTION, &[2, 0x1000, 0, 8]);
+        program.extend(syscall(SYS_RT_SIGACTION, &[2, 0x1010, 0x1020, 8]));
+        program.extend(syscall(SYS_RT_SIGACTION, &[SIGKILL, 0x1000, 0, 8]));
+        program.push(addi(18, 9, 0));
+        // Block everything, then unblock SIGINT and read the mask back
+        program.extend(syscall(SYS_RT_SIGPROCMASK, &[SIG_SETMASK, 0x1100, 0, 8]));
+        program.extend(syscall(
+            SYS_RT_SIGPROCMASK,
+            &[SIG_UNBLOCK, 0x1108, 0x1110, 8],
+        ));
+        program.extend(syscall(SYS_RT_SIGPROCMASK, &[SIG_BLOCK, 0x1100, 0, 4]));
+        program.push(addi(19, 9, 0));
+        program.extend(syscall(SYS_GETPID, &[]));
+        program.push(addi(20, 9, 0));
+        program.extend(syscall(SYS_GETTID, &[]));
+        program.push(EBREAK);
+
+        let mut vm = Vm::from_bin(program).unwrap();
+        vm.set_syscall_handler(LinuxSyscallHandler::default());
+        vm.memory.load_bytes(0x1000, &[1; 16]);
+        vm.memory.load_bytes(0x1010, &[2; 16]);
+        vm.memory.load_bytes(0x1100, &[0xff; 8]);
+        vm.memory.load_bytes(0x1108, &2u64.to_le_bytes());
+        vm.run(RunConfig::default());
+
+        assert_eq!(
+            vm.memory.read_mem(0x1020, MemoryChuckSize::WordSize),
+            Some(0x0101_0101)
+        );
+        assert_eq!(state(&vm).signal_actions[&2], [2; 16]);
+        assert_eq!(vm.registers.read_reg(18), -EINVAL as u32);
+        // SIGKILL and SIGSTOP stay unblocked
+        let all = !(1u64 << 8 | 1 << 18);
+        assert_eq!(
+            vm.memory.read_mem(0x1110, MemoryChuckSize::WordSize),
+            Some(all as u32)
+        );
+        assert_eq!(state(&vm).signal_mask, all & !2);
+        assert_eq!(vm.registers.read_reg(19), -EINVAL as u32);
+        assert_eq!(vm.registers.read_reg(20), TID);
+        assert_eq!(vm.registers.read_reg(9), TID);
+    }
+
+    #[test]
+    fn test_madvise() {
+        let handler = LinuxSyscallHandler {
+            brk_start: 0x3000,
+            brk: 0x5000,
+            ..Default::default()
+        };
+        let mut program = syscall(SYS_MADVISE, &[0x2000, 0x3000, MADV_DONTNEED]);
+        program.push(addi(18, 9, 0));
+        program.extend(syscall(SYS_MADVISE, &[0x2004, 4, MADV_DONTNEED]));
+        program.push(EBREAK);
+        let mut vm = Vm::from_bin(program).unwrap();
+        vm.set_syscall_handler(handler);
+        vm.memory.load_bytes(0x2000, &[1; 0x3000]);
+
+        vm.run(RunConfig::default());
+        assert_eq!(vm.registers.read_reg(18), 0);
+        // The page below the heap is left alone, the heap is zero filled
+        assert_eq!(
+            vm.memory.read_mem(0x2ffc, MemoryChuckSize::WordSize),
+            Some(0x0101_0101)
+        );
+        assert_eq!(
+            vm.memory.read_mem(0x3000, MemoryChuckSize::WordSize),
+            Some(0)
+        );
+        assert_eq!(
+            vm.memory.read_mem(0x4ffc, MemoryChuckSize::WordSize),
+            Some(0)
+        );
+        assert_eq!(vm.registers.read_reg(9), -EINVAL as u32);
+    }
+
+    #[test]
+    fn test_writes_check_the_descriptor_first() {
+        // The buffer is unmapped, a bad descriptor is reported before it is read
+        let mut program = syscall(SYS_WRITE, &[0, u32::MAX - 3, 8]);
+        program.push(addi(18, 9, 0));
+        program.extend(syscall(SYS_WRITEV, &[5, u32::MAX - 3, 1]));
+        program.push(EBREAK);
+
+        let vm = run(program, LinuxSyscallHandler::default());
+        assert_eq!(vm.registers.read_reg(18), -EBADF as u32);
+        assert_eq!(vm.registers.read_reg(9), -EBADF as u32);
+    }
+}
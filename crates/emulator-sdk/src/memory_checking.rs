@@ -0,0 +1,272 @@
+//! This mod derives the tables of an offline memory checking argument from an execution trace.
+//! Every access to memory and to the register file gets a timestamp from a global clock, the accesses
+//! are then sorted by (address, timestamp) so that consecutive accesses to the same location can be
+//! checked against each other, reads must return the last value written (or the initial value).
+//! Memory is checked at byte granularity since accesses can be 1, 2 or 4 bytes wide.
+use crate::{
+    trace::{AccessKind, Trace},
+    vm::{RunConfig, RunOutcome, VMErrors, Vm},
+};
+use core::{interfaces::MemoryInterface, Memory, MemoryChuckSize, Registers};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// This is a single access to a location, `value` is the value read or written.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessRecord {
+    pub addr: u32,
+    pub timestamp: u64,
+    pub kind: AccessKind,
+    pub value: u32,
+}
+
+/// This is the memory checking table of one address space (memory or registers).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsistencyTable {
+    /// The accesses sorted by (address, timestamp)
+    pub accesses: Vec<AccessRecord>,
+    /// The value of every accessed location before its first access
+    pub initial_image: BTreeMap<u32, u32>,
+    /// The value of every accessed location after its last access
+    pub final_image: BTreeMap<u32, u32>,
+}
+
+/// These are the tables for both the memory and the register file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryCheckingTables {
+    /// Byte addressed memory accesses, values are single bytes
+    pub memory: ConsistencyTable,
+    /// Register accesses, addresses are register indices
+    pub registers: ConsistencyTable,
+}
+
+impl ConsistencyTable {
+    fn record(&mut self, addr: u32, timestamp: u64, kind: AccessKind, old: u32, new: u32) {
+        self.initial_image.entry(addr).or_insert(old);
+        self.final_image.insert(addr, new);
+        self.accesses.push(AccessRecord {
+            addr,
+            timestamp,
+            kind,
+            value: new,
+        });
+    }
+
+    fn sort(&mut self) {
+        self.accesses.sort_by_key(|a| (a.addr, a.timestamp));
+    }
+
+    /// Replay the sorted accesses, checking that every read returns the last value written to its
+    /// location and that every location ends with its final value.
+    pub fn verify(&self) -> Result<(), VMErrors> {
+        let mut current: Option<(u32, u64, u32)> = None;
+
+        for access in &self.accesses {
+            let error = VMErrors::ConsistencyError {
+                addr: access.addr,
+                timestamp: access.timestamp,
+            };
+
+            let value = match current {
+                Some((addr, timestamp, value)) if addr == access.addr => {
+                    if access.timestamp <= timestamp {
+                        return Err(error);
+                    }
+                    value
+                }
+                _ => {
+                    if let Some((addr, _, value)) = current {
+                        if self.final_image.get(&addr) != Some(&value) {
+                            return Err(error);
+                        }
+                    }
+                    *self.initial_image.get(&access.addr).ok_or(error.clone())?
+                }
+            };
+
+            if access.kind == AccessKind::Read && access.value != value {
+                return Err(error);
+            }
+            current = Some((access.addr, access.timestamp, access.value));
+        }
+
+        if let Some((addr, timestamp, value)) = current {
+            if self.final_image.get(&addr) != Some(&value) {
+                return Err(VMErrors::ConsistencyError { addr, timestamp });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl MemoryCheckingTables {
+    /// Derive the tables from an execution trace.
+    /// Within an instruction the register accesses are clocked before the memory accesses.
+    pub fn from_trace(trace: &Trace) -> Self {
+        let mut tables = Self::default();
+        let mut timestamp = 0;
+
+        for row in &trace.rows {
+            for access in &row.register_accesses {
+                tables.registers.record(
+                    access.register as u32,
+                    timestamp,
+                    access.kind,
+                    access.old_value,
+                    access.new_value,
+                );
+                timestamp += 1;
+            }
+
+            for access in &row.memory_accesses {
+                for i in 0..access.size as u32 {
+                    tables.memory.record(
+                        access.addr.wrapping_add(i),
+                        timestamp,
+                        access.kind,
+                        (access.old_value >> (8 * i)) & 0xff,
+                        (access.new_value >> (8 * i)) & 0xff,
+                    );
+                }
+                timestamp += 1;
+            }
+        }
+
+        tables.memory.sort();
+        tables.registers.sort();
+        tables
+    }
+
+    /// Run `vm` until it halts with tracing enabled and derive its tables.
+    /// The initial and final images are checked against the state of the VM before and after the run.
+    /// # Errors
+    /// This function returns the error the guest faulted on, [VMErrors::Unfinished] if it stopped
+    /// without exiting, or a consistency error if the images do not match the VM.
+    pub fn from_execution(vm: &mut Vm) -> Result<Self, VMErrors> {
+        let initial_memory = vm.memory.clone();
+        let initial_registers = vm.registers.clone();
+
+        vm.enable_trace();
+        let outcome = vm.run(RunConfig::default());
+        let trace = vm.take_trace().unwrap_or_default();
+        match outcome {
+            RunOutcome::Exited(_) => {}
+            RunOutcome::Faulted { error, .. } => return Err(error),
+            _ => return Err(VMErrors::Unfinished(vm.pc)),
+        }
+        let tables = Self::from_trace(&trace);
+
+        check_memory_image(&tables.memory.initial_image, &initial_memory)?;
+        check_memory_image(&tables.memory.final_image, &vm.memory)?;
+        check_register_image(&tables.registers.initial_image, &initial_registers)?;
+        check_register_image(&tables.registers.final_image, &vm.registers)?;
+
+        Ok(tables)
+    }
+
+    /// Verify both tables, see [ConsistencyTable::verify].
+    pub fn verify(&self) -> Result<(), VMErrors> {
+        self.registers.verify()?;
+        self.memory.verify()
+    }
+}
+
+fn check_memory_image(image: &BTreeMap<u32, u32>, memory: &Memory) -> Result<(), VMErrors> {
+    let mut memory = memory.clone();
+    memory.enforce_permissions = false;
+
+    for (addr, value) in image {
+        if memory.read_mem(*addr, MemoryChuckSize::BYTE) != Some(*value) {
+            return Err(VMErrors::ConsistencyError {
+                addr: *addr,
+                timestamp: 0,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn check_register_image(image: &BTreeMap<u32, u32>, registers: &Registers) -> Result<(), VMErrors> {
+    for (register, value) in image {
+        if registers.read_reg(*register) != *value {
+            return Err(VMErrors::ConsistencyError {
+                addr: *register,
+                timestamp: 0,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program() -> Vm {
+        Vm::from_bin(vec![
+            0x00a0_0093, // addi x1, x0, 10
+            0x1010_2023, // sw x1, 0x100(x0)
+            0x1000_2103, // lw x2, 0x100(x0)
+            0xfff1_0113, // addi x2, x2, -1
+            0x1020_0023, // sb x2, 0x100(x0)
+            0xfe01_1ae3, // bne x2, x0, -12
+            0x1000_4183, // lbu x3, 0x100(x0)
+            0x05d0_0893, // addi x17, x0, 93
+            0x0000_0073, // ecall (exit)
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_tables_from_execution_are_consistent() {
+        let mut vm = program();
+        let tables = MemoryCheckingTables::from_execution(&mut vm).unwrap();
+        tables.verify().unwrap();
+
+        assert!(tables
+            .memory
+            .accesses
+            .windows(2)
+            .all(|w| (w[0].addr, w[0].timestamp) < (w[1].addr, w[1].timestamp)));
+        assert_eq!(tables.memory.initial_image[&0x100], 0);
+        assert_eq!(tables.memory.final_image[&0x100], 0);
+        assert_eq!(tables.registers.final_image[&1], 10);
+    }
+
+    #[test]
+    fn test_unfinished_execution_is_rejected() {
+        let mut vm = Vm::from_bin(vec![0x00a0_0093, 0x0010_0073]).unwrap();
+        assert_eq!(
+            MemoryCheckingTables::from_execution(&mut vm),
+            Err(VMErrors::Unfinished(4))
+        );
+
+        // Running into the zeros after the program, there is no trap handler
+        let mut vm = Vm::from_bin(vec![0x00a0_0093]).unwrap();
+        assert_eq!(
+            MemoryCheckingTables::from_execution(&mut vm),
+            Err(VMErrors::InvalidInstruction)
+        );
+    }
+
+    #[test]
+    fn test_tampered_read_is_rejected() {
+        let mut vm = program();
+        let mut tables = MemoryCheckingTables::from_execution(&mut vm).unwrap();
+        let read = tables
+            .memory
+            .accesses
+            .iter_mut()
+            .find(|a| a.kind == AccessKind::Read)
+            .unwrap();
+        read.value ^= 1;
+
+        assert!(matches!(
+            tables.verify(),
+            Err(VMErrors::ConsistencyError { .. })
+        ));
+    }
+}
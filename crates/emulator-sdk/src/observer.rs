@@ -0,0 +1,340 @@
+//! This mod holds the execution observer API of the VM.
+//! Observers attached to a [Vm](crate::vm::Vm) are called before and after every instruction and on
+//! every memory access, register write and trap, without being able to change the execution.
+//! A VM without observers only pays for checking that there are none.
+use crate::{
+    disassembler::Disassembler,
+    instructions::InstructionDecoder,
+    trace::{AccessKind, MemoryAccess},
+    trap::Trap,
+};
+use std::{
+    any::Any,
+    collections::{BTreeMap, VecDeque},
+    fmt::Debug,
+};
+
+/// This trait is implemented by anything that wants to watch the execution of the VM.
+/// Every hook does nothing by default.
+pub trait ExecutionObserver: Any + Debug {
+    /// Called once the instruction at `pc` has been fetched and decoded, before it is executed.
+    fn before_instruction(&mut self, _pc: u32, _instruction: u32, _decoded: &InstructionDecoder) {}
+
+    /// Called after the instruction at `pc` executed without raising an exception.
+    fn after_instruction(&mut self, _pc: u32, _next_pc: u32) {}
+
+    /// Called on every data memory access made by an instruction, instruction fetches excluded.
+    fn on_memory_access(&mut self, _access: &MemoryAccess) {}
+
+    /// Called on every write to a register other than x0.
+    fn on_register_write(&mut self, _register: u32, _old_value: u32, _new_value: u32) {}
+
+    /// Called when the exception raised at `pc` is delivered to the guest's trap handler.
+    fn on_trap(&mut self, _pc: u32, _trap: &Trap) {}
+
+    /// Clone this observer into a new box, this is what makes the [Vm](crate::vm::Vm) cloneable.
+    fn box_clone(&self) -> Box<dyn ExecutionObserver>;
+}
+
+impl Clone for Box<dyn ExecutionObserver> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// This observer logs every executed instruction to stderr, optionally with the accesses it made.
+#[derive(Debug, Clone, Default)]
+pub struct LoggingObserver {
+    /// Also log register writes
+    pub registers: bool,
+    /// Also log memory accesses
+    pub memory: bool,
+    /// Disassembles the logged instructions, with the symbols of the program if it has them
+    pub disassembler: Disassembler,
+}
+
+impl ExecutionObserver for LoggingObserver {
+    fn before_instruction(&mut self, pc: u32, instruction: u32, decoded: &InstructionDecoder) {
+        if let Some(name) = self.disassembler.symbol_at(pc) {
+            eprintln!("{}:", name);
+        }
+        let text = self
+            .disassembler
+            .disassemble(pc, instruction)
+            .unwrap_or_else(|_| decoded.to_string());
+        eprintln!("{:08x}: {:08x} {}", pc, instruction, text);
+    }
+
+    fn on_memory_access(&mut self, access: &MemoryAccess) {
+        if !self.memory {
+            return;
+        }
+        match access.kind {
+            AccessKind::Read => eprintln!(
+                "    mem[{:08x}; {}] -> {:08x}",
+                access.addr, access.size, access.new_value
+            ),
+            AccessKind::Write => eprintln!(
+                "    mem[{:08x}; {}] <- {:08x} (was {:08x})",
+                access.addr, access.size, access.new_value, access.old_value
+            ),
+        }
+    }
+
+    fn on_register_write(&mut self, register: u32, old_value: u32, new_value: u32) {
+        if self.registers {
+            eprintln!(
+                "    x{} <- {:08x} (was {:08x})",
+                register, new_value, old_value
+            );
+        }
+    }
+
+    fn on_trap(&mut self, pc: u32, trap: &Trap) {
+        eprintln!(
+            "{:08x}: trap {:?}, tval {:08x}",
+            pc, trap.exception, trap.tval
+        );
+    }
+
+    fn box_clone(&self) -> Box<dyn ExecutionObserver> {
+        Box::new(self.clone())
+    }
+}
+
+/// This is an instruction recorded by the [TracingObserver].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TracedInstruction {
+    pub pc: u32,
+    pub instruction: u32,
+}
+
+/// This observer records the instructions the VM executed, in order.
+/// With a capacity only the most recent instructions are kept, which is what is needed to find out
+/// how a program got to a fault.
+#[derive(Debug, Clone, Default)]
+pub struct TracingObserver {
+    pub instructions: VecDeque<TracedInstruction>,
+    /// The number of instructions kept, unbounded when `None`
+    pub capacity: Option<usize>,
+}
+
+impl TracingObserver {
+    /// Create a tracing observer keeping every instruction.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a tracing observer keeping only the last `capacity` instructions.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            instructions: VecDeque::with_capacity(capacity),
+            capacity: Some(capacity),
+        }
+    }
+}
+
+impl ExecutionObserver for TracingObserver {
+    fn before_instruction(&mut self, pc: u32, instruction: u32, _decoded: &InstructionDecoder) {
+        if self.capacity == Some(0) {
+            return;
+        }
+        if Some(self.instructions.len()) == self.capacity {
+            self.instructions.pop_front();
+        }
+        self.instructions
+            .push_back(TracedInstruction { pc, instruction });
+    }
+
+    fn box_clone(&self) -> Box<dyn ExecutionObserver> {
+        Box::new(self.clone())
+    }
+}
+
+/// This observer counts what the VM did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CountingObserver {
+    /// Instructions that executed without raising an exception
+    pub instructions: u64,
+    /// Instructions fetched and decoded per opcode, including the ones that raised an exception
+    pub opcodes: BTreeMap<u32, u64>,
+    pub memory_reads: u64,
+    pub memory_writes: u64,
+    pub register_writes: u64,
+    pub traps: u64,
+}
+
+impl ExecutionObserver for CountingObserver {
+    fn before_instruction(&mut self, _pc: u32, _instruction: u32, decoded: &InstructionDecoder) {
+        *self.opcodes.entry(decoded.opcode).or_default() += 1;
+    }
+
+    fn after_instruction(&mut self, _pc: u32, _next_pc: u32) {
+        self.instructions += 1;
+    }
+
+    fn on_memory_access(&mut self, access: &MemoryAccess) {
+        match access.kind {
+            AccessKind::Read => self.memory_reads += 1,
+            AccessKind::Write => self.memory_writes += 1,
+        }
+    }
+
+    fn on_register_write(&mut self, _register: u32, _old_value: u32, _new_value: u32) {
+        self.register_writes += 1;
+    }
+
+    fn on_trap(&mut self, _pc: u32, _trap: &Trap) {
+        self.traps += 1;
+    }
+
+    fn box_clone(&self) -> Box<dyn ExecutionObserver> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{addi, csr, lw, sw, EBREAK, MRET};
+    use crate::{
+        csr::{MEPC, MTVEC},
+        trap::Exception,
+        vm::{RunConfig, Vm},
+    };
+
+    /// Records every register write
+    #[derive(Debug, Clone, Default)]
+    struct RegisterWrites(Vec<(u32, u32, u32)>);
+
+    impl ExecutionObserver for RegisterWrites {
+        fn on_register_write(&mut self, register: u32, old_value: u32, new_value: u32) {
+            self.0.push((register, old_value, new_value));
+        }
+
+        fn box_clone(&self) -> Box<dyn ExecutionObserver> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn test_counting_observer() {
+        let mut vm = Vm::from_bin(vec![
+            addi(1, 0, 0x100),
+            csr(0b001, 0, 1, MTVEC), // csrw mtvec, x1
+            addi(2, 0, 0x200),
+            sw(2, 1, 0),
+            lw(3, 2, 0),
+            0xffff_ffff,
+            EBREAK,
+        ])
+        .unwrap();
+        // Skip the illegal instruction
+        vm.memory.load_program(
+            &[
+                csr(0b010, 4, 0, MEPC), // csrr x4, mepc
+                addi(4, 4, 4),
+                csr(0b001, 0, 4, MEPC), // csrw mepc, x4
+                MRET,
+            ],
+            0x100,
+        );
+        vm.add_observer(CountingObserver::default());
+        vm.run(RunConfig::default());
+
+        let counts = vm.observer::<CountingObserver>().unwrap();
+        assert_eq!(counts.instructions, 10);
+        assert_eq!(counts.opcodes[&0b0010011], 3);
+        // The illegal instruction does not decode
+        assert_eq!(counts.opcodes[&0b1110011], 5);
+        assert_eq!(counts.memory_reads, 1);
+        assert_eq!(counts.memory_writes, 1);
+        // x1, x2, x3 and x4 twice, csrw mtvec and mepc write x0
+        assert_eq!(counts.register_writes, 5);
+        assert_eq!(counts.traps, 1);
+    }
+
+    #[test]
+    fn test_tracing_observer_keeps_the_last_instructions() {
+        let program = vec![addi(1, 0, 1), addi(2, 0, 2), addi(3, 0, 3), EBREAK];
+        let mut vm = Vm::from_bin(program.clone()).unwrap();
+        vm.add_observer(TracingObserver::with_capacity(2));
+        vm.add_observer(TracingObserver::new());
+        vm.run(RunConfig::default());
+
+        let last = &vm.observer::<TracingObserver>().unwrap().instructions;
+        assert_eq!(
+            last.iter().copied().collect::<Vec<_>>(),
+            [
+                TracedInstruction {
+                    pc: 8,
+                    instruction: program[2]
+                },
+                TracedInstruction {
+                    pc: 12,
+                    instruction: EBREAK
+                }
+            ]
+        );
+
+        let observers = vm.take_observers();
+        let all = (observers[1].as_ref() as &dyn Any)
+            .downcast_ref::<TracingObserver>()
+            .unwrap();
+        assert_eq!(all.instructions.len(), 4);
+        assert!(vm.observers.is_empty());
+    }
+
+    #[test]
+    fn test_register_writes_are_observed() {
+        let mut vm =
+            Vm::from_bin(vec![addi(1, 0, 5), addi(1, 1, 2), addi(0, 1, 1), EBREAK]).unwrap();
+        vm.add_observer(RegisterWrites::default());
+        let mut clone = vm.clone();
+        vm.run(RunConfig::default());
+
+        assert_eq!(
+            vm.observer::<RegisterWrites>().unwrap().0,
+            [(1, 0, 5), (1, 5, 7)]
+        );
+        // Clones get their own copy of the observers
+        assert!(clone.observer_mut::<RegisterWrites>().unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn test_trap_is_observed() {
+        #[derive(Debug, Clone, Default)]
+        struct Traps(Vec<(u32, Trap)>);
+
+        impl ExecutionObserver for Traps {
+            fn on_trap(&mut self, pc: u32, trap: &Trap) {
+                self.0.push((pc, *trap));
+            }
+
+            fn box_clone(&self) -> Box<dyn ExecutionObserver> {
+                Box::new(self.clone())
+            }
+        }
+
+        let mut vm = Vm::from_bin(vec![
+            addi(1, 0, 0x100),
+            csr(0b001, 0, 1, MTVEC), // csrw mtvec, x1
+            0xffff_ffff,
+        ])
+        .unwrap();
+        vm.memory.load_program(&[EBREAK], 0x100);
+        vm.add_observer(Traps::default());
+        vm.run(RunConfig::default());
+
+        assert_eq!(
+            vm.observer::<Traps>().unwrap().0,
+            [(
+                8,
+                Trap {
+                    exception: Exception::IllegalInstruction,
+                    tval: 0xffff_ffff
+                }
+            )]
+        );
+    }
+}
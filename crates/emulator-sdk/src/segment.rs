@@ -0,0 +1,218 @@
+//! This mod holds execution segments, also called continuations.
+//! zkVMs prove long executions in fixed-size segments. [Vm::run_segment] runs for a number of
+//! cycles and returns the boundary the segment ended on: the commitment to the state it started
+//! from, the pages it touched and the state it ended in. A VM restored from that state, see
+//! [Vm::restore], resumes exactly where the segment stopped, and [Segments] iterates the segments
+//! of a whole execution.
+//!
+//! A page is touched when an instruction is fetched from it, when a load or a store accesses it
+//! or when a syscall handler accesses it through the [SyscallContext](crate::syscalls::SyscallContext).
+//! Cached blocks touch the pages of all their instructions when they are decoded, even if the
+//! budget cuts them short, so the touched pages may include a few more code pages than needed.
+use crate::{
+    snapshot::Snapshot,
+    vm::{RunConfig, RunOutcome, VMErrors, Vm},
+};
+use core::PAGE_SIZE;
+use hashbrown::HashSet;
+
+/// Mark the pages of the `size` bytes at `addr` as touched.
+#[inline(always)]
+pub(crate) fn touch_pages(pages: &mut HashSet<u32>, addr: u32, size: u32) {
+    if size == 0 {
+        return;
+    }
+    let first = addr / PAGE_SIZE;
+    let last = addr.saturating_add(size - 1) / PAGE_SIZE;
+    for page in first..=last {
+        pages.insert(page);
+    }
+}
+
+/// This is a segment of an execution, as returned by [Vm::run_segment].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    /// The commitment to the state the segment started from, see [Snapshot::commitment]
+    pub start_commitment: [u8; 32],
+    /// The page numbers of the pages the segment touched, sorted
+    pub touched_pages: Vec<u32>,
+    /// The state the segment ended in, the next segment starts from it
+    pub end: Snapshot,
+    /// The number of cycles the segment ran for
+    pub cycles: u64,
+    /// Why the segment ended, [RunOutcome::BudgetExhausted] unless the execution is over
+    pub outcome: RunOutcome,
+}
+
+impl Segment {
+    /// Returns whether the execution continues after this segment.
+    pub fn is_last(&self) -> bool {
+        self.outcome != RunOutcome::BudgetExhausted
+    }
+}
+
+impl Vm {
+    /// Run for at most `max_cycles` cycles and return the boundary of the segment.
+    /// # Errors
+    /// This function returns an error if the state of the VM can not be snapshotted.
+    pub fn run_segment(&mut self, max_cycles: u64) -> Result<Segment, VMErrors> {
+        let start_commitment = self.snapshot()?.commitment()?;
+        let start_cycle = self.csrs.cycle;
+
+        self.touched_pages = Some(HashSet::new());
+        let outcome = self.run(RunConfig {
+            max_cycles: Some(max_cycles),
+            ..RunConfig::default()
+        });
+        let mut touched_pages: Vec<u32> = self
+            .touched_pages
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        touched_pages.sort_unstable();
+
+        Ok(Segment {
+            start_commitment,
+            touched_pages,
+            end: self.snapshot()?,
+            cycles: self.csrs.cycle - start_cycle,
+            outcome,
+        })
+    }
+
+    /// Returns an iterator over the segments of the rest of the execution, see [Segments].
+    pub fn segments(self, segment_cycles: u64) -> Segments {
+        Segments::new(self, segment_cycles)
+    }
+}
+
+/// This iterates the segments of an execution, each of them at most `segment_cycles` long.
+/// The iteration stops after the segment the execution ends in, or after an error.
+#[derive(Debug, Clone)]
+pub struct Segments {
+    vm: Vm,
+    segment_cycles: u64,
+    done: bool,
+}
+
+impl Segments {
+    /// Iterate the segments of the execution of `vm`.
+    /// # Panics
+    /// This function panics if `segment_cycles` is 0.
+    pub fn new(vm: Vm, segment_cycles: u64) -> Self {
+        assert!(segment_cycles > 0, "segments must be at least a cycle long");
+
+        Self {
+            vm,
+            segment_cycles,
+            done: false,
+        }
+    }
+
+    /// Iterate the segments of the execution of an ELF file.
+    /// # Errors
+    /// This function may return an error if the ELF is not valid.
+    pub fn from_elf(path: String, segment_cycles: u64) -> Result<Self, anyhow::Error> {
+        Ok(Self::new(Vm::from_bin_elf(path)?, segment_cycles))
+    }
+
+    /// Returns the VM, in the state the last segment ended in.
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    /// Returns the VM, in the state the last segment ended in.
+    pub fn into_vm(self) -> Vm {
+        self.vm
+    }
+}
+
+impl Iterator for Segments {
+    type Item = Result<Segment, VMErrors>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let segment = self.vm.run_segment(self.segment_cycles);
+        self.done = segment.as_ref().map_or(true, Segment::is_last);
+        Some(segment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{addi, bne, lui, lw, sw, ECALL};
+    use crate::syscalls::SYS_EXIT;
+
+    /// Adds up the words at 0x5000, 0x5004, .. 0x5000 + 4 * 99 into 0x9000 and exits with the sum,
+    /// the loop is hot enough to be compiled
+    fn program() -> Vec<u32> {
+        let mut program = vec![
+            lui(1, 0x5),
+            addi(2, 0, 100),
+            addi(3, 0, 0),
+            // loop:
+            lw(4, 1, 0),
+            (4 << 20) | (3 << 15) | (3 << 7) | 0b0110011, // add x3, x3, x4
+            addi(1, 1, 4),
+            addi(2, 2, -1),
+            bne(2, 0, -16),
+            lui(5, 0x9),
+            sw(5, 3, 0),
+            addi(10, 3, 0),
+            addi(17, 0, SYS_EXIT as i32),
+            ECALL,
+        ];
+        // The loop straddles the first and the second page
+        program.splice(0..0, vec![addi(0, 0, 0); 1019]);
+        program
+    }
+
+    fn vm() -> Vm {
+        let mut vm = Vm::from_bin(program()).unwrap();
+        for i in 0..100 {
+            vm.memory.load_program(&[i], 0x5000 + 4 * i);
+        }
+        vm
+    }
+
+    #[test]
+    fn test_touched_pages() {
+        let segment = vm().run_segment(100_000).unwrap();
+
+        assert_eq!(segment.outcome, RunOutcome::Exited(4950));
+        assert_eq!(segment.touched_pages, [0, 1, 5, 9]);
+        // The exiting ecall does not retire
+        assert_eq!(segment.cycles, 1019 + 3 + 500 + 4);
+        assert_eq!(
+            segment.start_commitment,
+            vm().snapshot().unwrap().commitment().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_segments_resume_at_their_boundary() {
+        let mut expected = vm();
+        expected.run(RunConfig::default());
+
+        let segments = vm().segments(100).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(segments.len(), 16);
+        assert!(segments[..15].iter().all(|segment| segment.cycles == 100));
+        assert_eq!(segments[15].outcome, RunOutcome::Exited(4950));
+        assert_eq!(&segments[15].end, &expected.snapshot().unwrap());
+        // The first segments only run the nops of the first page
+        assert_eq!(segments[0].touched_pages, [0]);
+        assert_eq!(segments[10].touched_pages, [0, 1, 5]);
+
+        for pair in segments.windows(2) {
+            assert_eq!(pair[1].start_commitment, pair[0].end.commitment().unwrap());
+
+            let mut vm = Vm::from_snapshot(&pair[0].end).unwrap();
+            assert_eq!(vm.run_segment(100).unwrap(), pair[1]);
+        }
+    }
+}
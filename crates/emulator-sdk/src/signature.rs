@@ -0,0 +1,96 @@
+//! This mod holds the signature dump of the RISC-V architectural tests (riscv-arch-test).
+//! A test writes its results between the `begin_signature` and `end_signature` symbols, and once it
+//! halts RISCOF compares them, dumped one 32-bit word per line as 8 lowercase hex digits, with the
+//! signature of a reference model.
+use crate::vm::{VMErrors, Vm};
+use core::{interfaces::MemoryInterface, MemoryChuckSize};
+use elf_parser::Elf;
+
+/// This is the memory range holding the signature of a test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignatureRange {
+    pub begin: u32,
+    /// The end of the signature, exclusive
+    pub end: u32,
+}
+
+impl SignatureRange {
+    /// Returns the range between the `begin_signature` and `end_signature` symbols of a program.
+    pub fn from_elf(elf: &Elf) -> Option<Self> {
+        let begin = elf.symbol("begin_signature")?.addr;
+        let end = elf.symbol("end_signature")?.addr;
+        if end < begin {
+            return None;
+        }
+
+        Some(Self { begin, end })
+    }
+}
+
+impl Vm {
+    /// Read the words of the signature, a range not ending on a word boundary is rounded up.
+    /// # Errors
+    /// This function returns an error if the range is not word aligned or not in memory.
+    pub fn signature(&self, range: SignatureRange) -> Result<Vec<u32>, VMErrors> {
+        if range.begin & 0x3 != 0 {
+            return Err(VMErrors::LoadAddressMisaligned(range.begin));
+        }
+
+        (range.begin..range.end)
+            .step_by(4)
+            .map(|addr| {
+                self.memory
+                    .read_mem(addr, MemoryChuckSize::WordSize)
+                    .ok_or(VMErrors::LoadAccessFault(addr))
+            })
+            .collect()
+    }
+
+    /// Dump the signature in the format of RISCOF signature files.
+    /// # Errors
+    /// This function returns an error if the signature can not be read, see [Vm::signature].
+    pub fn dump_signature(&self, range: SignatureRange) -> Result<String, VMErrors> {
+        Ok(self
+            .signature(range)?
+            .iter()
+            .map(|word| format!("{:08x}\n", word))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump_signature() {
+        let mut vm = Vm::from_bin(vec![0; 8]).unwrap();
+        vm.memory
+            .write_mem(0x10, MemoryChuckSize::WordSize, 0xdeadbeef);
+        vm.memory.write_mem(0x14, MemoryChuckSize::BYTE, 0x2a);
+
+        let range = SignatureRange {
+            begin: 0x10,
+            end: 0x1a,
+        };
+        assert_eq!(vm.signature(range), Ok(vec![0xdeadbeef, 0x2a, 0]));
+        assert_eq!(
+            vm.dump_signature(range).unwrap(),
+            "deadbeef\n0000002a\n00000000\n"
+        );
+        assert_eq!(
+            vm.dump_signature(SignatureRange {
+                begin: 0x10,
+                end: 0x10
+            }),
+            Ok(String::new())
+        );
+        assert_eq!(
+            vm.signature(SignatureRange {
+                begin: 0x12,
+                end: 0x20
+            }),
+            Err(VMErrors::LoadAddressMisaligned(0x12))
+        );
+    }
+}
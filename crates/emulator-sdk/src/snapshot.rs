@@ -0,0 +1,339 @@
+//! This mod holds VM snapshots.
+//! A [Snapshot] is the architectural state of a [Vm]: the registers, the pc, the CSRs, the allocated
+//! memory pages, the state of the syscall handler and of the HTIF host, and whether ebreak halts.
+//! Restoring it into a VM resumes the execution exactly where the snapshot was taken, which is what
+//! checkpointing and continuations are built on.
+//! The execution trace, the observers and the instruction cache are not part of a snapshot.
+//!
+//! On disk a snapshot is the [SNAPSHOT_MAGIC] bytes, the [SNAPSHOT_VERSION] as a little-endian u32
+//! and the snapshot itself in the compact binary format (bincode with variable length integers).
+use crate::{
+    csr::CsrFile,
+    htif::Htif,
+    vm::{VMErrors, Vm},
+};
+use bincode::Options;
+use core::{MemoryRegion, Permissions, Registers};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// The bytes every snapshot file starts with
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"RVSS";
+/// The version of the snapshot format, bumped on every incompatible change
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// This is a memory region of the snapshotted VM, see [MemoryRegion].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegionSnapshot {
+    pub start: u32,
+    pub size: u32,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+/// This is the state of a [Vm] at one point of its execution.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub pc: u32,
+    pub registers: [u32; 32],
+    pub csrs: CsrFile,
+    pub exit_code: u32,
+    pub reservation: Option<u32>,
+    /// The allocated pages, as (page number, content) sorted by page number
+    pub pages: Vec<(u32, Vec<u8>)>,
+    pub regions: Vec<RegionSnapshot>,
+    pub enforce_permissions: bool,
+    /// The state of the syscall handler, as returned by [SyscallHandler::save_state](crate::syscalls::SyscallHandler::save_state)
+    pub syscall_handler: Vec<u8>,
+    /// The `tohost` and `fromhost` addresses, the console and a pending exit of the HTIF host
+    pub htif: Option<Htif>,
+    pub halt_on_ebreak: bool,
+}
+
+impl From<&MemoryRegion> for RegionSnapshot {
+    fn from(region: &MemoryRegion) -> Self {
+        Self {
+            start: region.start,
+            size: region.size,
+            read: region.permissions.read,
+            write: region.permissions.write,
+            execute: region.permissions.execute,
+        }
+    }
+}
+
+impl From<&RegionSnapshot> for MemoryRegion {
+    fn from(region: &RegionSnapshot) -> Self {
+        Self {
+            start: region.start,
+            size: region.size,
+            permissions: Permissions {
+                read: region.read,
+                write: region.write,
+                execute: region.execute,
+            },
+        }
+    }
+}
+
+impl Snapshot {
+    /// Serialize the snapshot to the versioned on-disk format
+    pub fn to_bytes(&self) -> Result<Vec<u8>, VMErrors> {
+        let mut bytes = SNAPSHOT_MAGIC.to_vec();
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        bincode::DefaultOptions::new()
+            .serialize_into(&mut bytes, self)
+            .map_err(|_| VMErrors::SerializationError)?;
+
+        Ok(bytes)
+    }
+
+    /// Returns the commitment to the state, the SHA-256 of the on-disk format
+    pub fn commitment(&self) -> Result<[u8; 32], VMErrors> {
+        Ok(Sha256::digest(self.to_bytes()?).into())
+    }
+
+    /// Deserialize a snapshot from the versioned on-disk format
+    /// # Errors
+    /// Snapshots written by another version of the format are rejected with
+    /// [VMErrors::UnsupportedSnapshotVersion].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VMErrors> {
+        if bytes.len() < 8 || bytes[..4] != SNAPSHOT_MAGIC {
+            return Err(VMErrors::SerializationError);
+        }
+        let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if version != SNAPSHOT_VERSION {
+            return Err(VMErrors::UnsupportedSnapshotVersion(version));
+        }
+
+        bincode::DefaultOptions::new()
+            .deserialize(&bytes[8..])
+            .map_err(|_| VMErrors::SerializationError)
+    }
+}
+
+impl Vm {
+    /// Take a snapshot of the current state of the VM.
+    /// # Errors
+    /// This function returns an error if the syscall handler state can not be saved.
+    pub fn snapshot(&self) -> Result<Snapshot, VMErrors> {
+        Ok(Snapshot {
+            pc: self.pc,
+            registers: std::array::from_fn(|i| self.registers.read_reg(i as u32)),
+            csrs: self.csrs.clone(),
+            exit_code: self.exit_code,
+            reservation: self.reservation,
+            pages: self
+                .memory
+                .pages
+                .iter()
+                .map(|(number, page)| (*number, page.to_vec()))
+                .collect(),
+            regions: self.memory.regions.iter().map(Into::into).collect(),
+            enforce_permissions: self.memory.enforce_permissions,
+            syscall_handler: self.syscall_handler.save_state()?,
+            htif: self.htif.clone(),
+            halt_on_ebreak: self.halt_on_ebreak,
+        })
+    }
+
+    /// Restore the state saved in `snapshot`, the VM resumes where the snapshot was taken.
+    /// The syscall handler must be of the same kind as the one the snapshot was taken with.
+    /// The trace and the observers are kept.
+    /// # Errors
+    /// This function returns an error if a page has the wrong size or the syscall handler rejects
+    /// its state, the VM is left untouched in that case.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), VMErrors> {
+        let pages = snapshot
+            .pages
+            .iter()
+            .map(|(number, data)| {
+                let page = data
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| VMErrors::SerializationError)?;
+                Ok((*number, Arc::new(page)))
+            })
+            .collect::<Result<_, VMErrors>>()?;
+        let mut syscall_handler = self.syscall_handler.clone();
+        syscall_handler.restore_state(&snapshot.syscall_handler)?;
+
+        let mut registers = Registers::new();
+        for (i, value) in snapshot.registers.iter().enumerate() {
+            registers.write_reg(i as u32, *value);
+        }
+        self.registers = registers;
+        self.pc = snapshot.pc;
+        self.csrs = snapshot.csrs.clone();
+        self.exit_code = snapshot.exit_code;
+        self.reservation = snapshot.reservation;
+        self.memory.pages = pages;
+        self.memory.regions = snapshot.regions.iter().map(Into::into).collect();
+        self.memory.enforce_permissions = snapshot.enforce_permissions;
+        self.syscall_handler = syscall_handler;
+        self.htif = snapshot.htif.clone();
+        self.halt_on_ebreak = snapshot.halt_on_ebreak;
+        self.running = false;
+        self.instruction_cache.clear();
+
+        Ok(())
+    }
+
+    /// Create a new Vm with the default syscall handler from a snapshot.
+    pub fn from_snapshot(snapshot: &Snapshot) -> Result<Self, VMErrors> {
+        let mut vm = Self::new();
+        vm.restore(snapshot)?;
+
+        Ok(vm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{addi, bne, sw, EBREAK, ECALL};
+    use crate::{
+        syscalls::{DefaultSyscallHandler, SyscallHandler, SYS_READ, SYS_WRITE},
+        vm::{RunConfig, RunOutcome},
+    };
+
+    /// Stores x1 = 10, 9, .. 1 to 0x1000, 0x1004, .. then reads a byte from stdin to 0x2000 and
+    /// echoes it to stdout
+    fn program() -> Vec<u32> {
+        vec![
+            addi(1, 0, 10),
+            addi(2, 0, 0x400),
+            addi(2, 2, 0x400),
+            addi(2, 2, 0x400),
+            addi(2, 2, 0x400),
+            // loop:
+            sw(2, 1, 0),
+            addi(2, 2, 4),
+            addi(1, 1, -1),
+            bne(1, 0, -12),
+            // read(0, 0x2000, 1)
+            addi(10, 0, 0),
+            addi(11, 2, 0x7d8),
+            addi(12, 0, 1),
+            addi(17, 0, SYS_READ as i32),
+            ECALL,
+            // write(1, 0x2000, 1)
+            addi(10, 0, 1),
+            addi(17, 0, SYS_WRITE as i32),
+            ECALL,
+            EBREAK,
+        ]
+    }
+
+    fn vm() -> Vm {
+        let mut vm = Vm::from_bin(program()).unwrap();
+        let mut handler = DefaultSyscallHandler::with_stdin(b"x");
+        handler.echo = false;
+        vm.set_syscall_handler(handler);
+        vm
+    }
+
+    fn stdout(vm: &Vm) -> Vec<u8> {
+        let state = vm.syscall_handler.save_state().unwrap();
+        let mut handler = DefaultSyscallHandler::default();
+        handler.restore_state(&state).unwrap();
+        handler.stdout
+    }
+
+    #[test]
+    fn test_resume_from_snapshot() {
+        let mut expected = vm();
+        assert_eq!(
+            expected.run(RunConfig::default()),
+            RunOutcome::Breakpoint(68)
+        );
+
+        for budget in [1, 7, 20, 42] {
+            let mut first = vm();
+            let outcome = first.run(RunConfig {
+                max_instructions: Some(budget),
+                ..RunConfig::default()
+            });
+            assert_eq!(outcome, RunOutcome::BudgetExhausted);
+
+            let bytes = first.snapshot().unwrap().to_bytes().unwrap();
+            let mut resumed = vm();
+            resumed
+                .restore(&Snapshot::from_bytes(&bytes).unwrap())
+                .unwrap();
+            assert_eq!(
+                resumed.run(RunConfig::default()),
+                RunOutcome::Breakpoint(68)
+            );
+
+            assert_eq!(resumed.snapshot(), expected.snapshot());
+            assert_eq!(stdout(&resumed), b"x");
+        }
+    }
+
+    #[test]
+    fn test_restore_rewinds_the_vm() {
+        let mut vm = vm();
+        vm.run(RunConfig {
+            max_instructions: Some(5),
+            ..RunConfig::default()
+        });
+        let snapshot = vm.snapshot().unwrap();
+        assert_eq!(
+            Vm::from_snapshot(&snapshot).unwrap().snapshot().unwrap(),
+            snapshot
+        );
+        vm.run(RunConfig::default());
+        assert_eq!(stdout(&vm), b"x");
+
+        vm.restore(&snapshot).unwrap();
+        assert_eq!(vm.snapshot().unwrap(), snapshot);
+        assert!(stdout(&vm).is_empty());
+        assert_eq!(vm.memory.page_count(), 1);
+        // The same input is served again
+        vm.run(RunConfig::default());
+        assert_eq!(stdout(&vm), b"x");
+    }
+
+    #[test]
+    fn test_htif_and_ebreak_are_restored() {
+        let mut vm = vm();
+        let mut htif = Htif::new(0x1000, Some(0x1040));
+        htif.console = b"hi".to_vec();
+        vm.htif = Some(htif);
+        vm.halt_on_ebreak = false;
+
+        let bytes = vm.snapshot().unwrap().to_bytes().unwrap();
+        let restored = Vm::from_snapshot(&Snapshot::from_bytes(&bytes).unwrap()).unwrap();
+        assert_eq!(restored.htif, vm.htif);
+        assert!(!restored.halt_on_ebreak);
+    }
+
+    #[test]
+    fn test_bad_snapshots_are_rejected() {
+        let bytes = vm().snapshot().unwrap().to_bytes().unwrap();
+
+        let mut version = bytes.clone();
+        version[4..8].copy_from_slice(&1u32.to_le_bytes());
+        assert_eq!(
+            Snapshot::from_bytes(&version),
+            Err(VMErrors::UnsupportedSnapshotVersion(1))
+        );
+        assert_eq!(
+            Snapshot::from_bytes(b"ELF\0\x01\0\0\0"),
+            Err(VMErrors::SerializationError)
+        );
+        assert_eq!(
+            Snapshot::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(VMErrors::SerializationError)
+        );
+
+        let mut snapshot = vm().snapshot().unwrap();
+        snapshot.pages[0].1.pop();
+        let mut vm = vm();
+        assert_eq!(vm.restore(&snapshot), Err(VMErrors::SerializationError));
+        assert_eq!(vm.memory.pages[&0].len(), 4096);
+    }
+}
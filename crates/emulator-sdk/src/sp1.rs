@@ -0,0 +1,369 @@
+//! This mod holds the SP1 personality of the VM, which runs programs built for the SP1 zkVM like
+//! the bundled `fibonacci` and `plonk_verify` guests.
+//! SP1 guests put the syscall number in `t0` rather than `a7`, pass the arguments in `a0`..`a2` and
+//! get the return value back in `t0`. Their input is a queue of byte vectors, the hints, which the
+//! host fills before the run and the guest pops with `hint_len` and `hint_read`.
+//!
+//! The precompiles (the hash, elliptic curve and field syscalls) are not implemented, a guest
+//! calling one stops with [VMErrors::InvalidSyscall].
+use crate::{
+    syscalls::{SyscallContext, SyscallHandler, SyscallOutcome},
+    vm::{VMErrors, Vm},
+};
+use bincode::Options;
+use core::{Page, Registers};
+use elf_parser::Elf;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{stderr, stdout, Write},
+    sync::Arc,
+};
+
+/// Register holding the syscall number and the return value
+pub const SP1_SYSCALL_REGISTER: u32 = 5;
+
+/// Syscall number for `halt(code)`
+pub const SP1_HALT: u32 = 0x00;
+/// Syscall number for `write(fd, buf, count)`
+pub const SP1_WRITE: u32 = 0x02;
+/// Syscall number for `enter_unconstrained()`, the start of a block whose effects are undone
+pub const SP1_ENTER_UNCONSTRAINED: u32 = 0x03;
+/// Syscall number for `exit_unconstrained()`
+pub const SP1_EXIT_UNCONSTRAINED: u32 = 0x04;
+/// Syscall number for `commit(index, word)`, one word of the digest of the public values
+pub const SP1_COMMIT: u32 = 0x10;
+/// Syscall number for `commit_deferred_proofs(index, word)`
+pub const SP1_COMMIT_DEFERRED_PROOFS: u32 = 0x1a;
+/// Syscall number for `verify_sp1_proof(vkey, pv_digest)`, the proofs are verified when proving
+pub const SP1_VERIFY_SP1_PROOF: u32 = 0x1b;
+/// Syscall number for `hint_len()`, the length of the next hint
+pub const SP1_HINT_LEN: u32 = 0xf0;
+/// Syscall number for `hint_read(buf, count)`, which pops the next hint into `buf`
+pub const SP1_HINT_READ: u32 = 0xf1;
+
+/// The file descriptor of the public values
+pub const FD_PUBLIC_VALUES: u32 = 3;
+/// Writing to this file descriptor appends a hint to the input
+pub const FD_HINT: u32 = 4;
+
+/// `hint_len` returns this when there are no hints left
+pub const NO_HINT: u32 = u32::MAX;
+
+/// The state an unconstrained block returns to
+#[derive(Debug, Clone)]
+struct Fork {
+    /// The pc of the `enter_unconstrained` ecall
+    pc: u32,
+    registers: Registers,
+    /// The pages are copied on write, keeping them is cheap
+    pages: BTreeMap<u32, Arc<Page>>,
+}
+
+/// The syscall handler of SP1 guests, see the [module](self) documentation.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Sp1SyscallHandler {
+    /// The hints the guest has not read yet
+    pub hints: VecDeque<Vec<u8>>,
+    /// Everything the guest wrote to fd 1
+    pub stdout: Vec<u8>,
+    /// Everything the guest wrote to fd 2
+    pub stderr: Vec<u8>,
+    /// Everything the guest wrote to [FD_PUBLIC_VALUES]
+    pub public_values: Vec<u8>,
+    /// When set, guest output is also forwarded to the host's stdout/stderr.
+    /// This is up to the host and is not saved in snapshots
+    #[serde(skip)]
+    pub echo: bool,
+    #[serde(skip)]
+    fork: Option<Fork>,
+}
+
+impl Sp1SyscallHandler {
+    /// Create a new handler that serves `hints` to the guest and forwards its output to the host.
+    pub fn with_hints(hints: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        Self {
+            hints: hints.into_iter().collect(),
+            echo: true,
+            ..Default::default()
+        }
+    }
+
+    fn sys_write(&mut self, ctx: &mut SyscallContext) -> Result<SyscallOutcome, VMErrors> {
+        let fd = ctx.arg(0);
+        let buf = ctx.arg(1);
+        let count = ctx.arg(2);
+        let data = ctx.read_bytes(buf, count)?;
+
+        match fd {
+            1 => {
+                if self.echo {
+                    let _ = stdout().write_all(&data);
+                }
+                self.stdout.extend_from_slice(&data);
+            }
+            2 => {
+                if self.echo {
+                    let _ = stderr().write_all(&data);
+                }
+                self.stderr.extend_from_slice(&data);
+            }
+            FD_PUBLIC_VALUES => self.public_values.extend_from_slice(&data),
+            FD_HINT => self.hints.push_back(data),
+            // SP1 runs host hooks on the other descriptors, without one the write is dropped
+            _ => {}
+        }
+
+        Ok(SyscallOutcome::Continue)
+    }
+
+    fn sys_enter_unconstrained(&mut self, ctx: &mut SyscallContext) -> SyscallOutcome {
+        if self.fork.is_none() {
+            self.fork = Some(Fork {
+                pc: ctx.vm.pc,
+                registers: ctx.vm.registers.clone(),
+                pages: ctx.vm.memory.pages.clone(),
+            });
+        }
+        ctx.vm.write_reg(SP1_SYSCALL_REGISTER, 1);
+        SyscallOutcome::Continue
+    }
+
+    /// Undo the registers and the memory the unconstrained block changed and resume after the
+    /// `enter_unconstrained` ecall, which now returns 0. The hints it wrote are kept.
+    fn sys_exit_unconstrained(&mut self, ctx: &mut SyscallContext) -> SyscallOutcome {
+        if let Some(fork) = self.fork.take() {
+            ctx.vm.pc = fork.pc;
+            ctx.vm.registers = fork.registers;
+            ctx.vm.memory.pages = fork.pages;
+            ctx.vm.reservation = None;
+            ctx.vm.instruction_cache.clear();
+        }
+        ctx.vm.write_reg(SP1_SYSCALL_REGISTER, 0);
+        SyscallOutcome::Continue
+    }
+
+    fn sys_hint_read(&mut self, ctx: &mut SyscallContext) -> Result<SyscallOutcome, VMErrors> {
+        let buf = ctx.arg(0);
+        let count = ctx.arg(1);
+        // The guest asks for exactly the length `hint_len` returned
+        if self.hints.front().map(Vec::len) != Some(count as usize) {
+            return Err(VMErrors::EnvironmentError);
+        }
+        let hint = self.hints.pop_front().expect("checked above");
+        ctx.write_bytes(buf, &hint)?;
+
+        Ok(SyscallOutcome::Continue)
+    }
+}
+
+impl SyscallHandler for Sp1SyscallHandler {
+    /// Handle the syscall in `t0`, `number` is the value of `a7` which SP1 does not use
+    fn handle(
+        &mut self,
+        _number: u32,
+        ctx: &mut SyscallContext,
+    ) -> Result<SyscallOutcome, VMErrors> {
+        match ctx.vm.read_reg(SP1_SYSCALL_REGISTER) {
+            SP1_HALT => Ok(SyscallOutcome::Exit(ctx.arg(0))),
+            SP1_WRITE => self.sys_write(ctx),
+            SP1_ENTER_UNCONSTRAINED => Ok(self.sys_enter_unconstrained(ctx)),
+            SP1_EXIT_UNCONSTRAINED => Ok(self.sys_exit_unconstrained(ctx)),
+            SP1_COMMIT | SP1_COMMIT_DEFERRED_PROOFS | SP1_VERIFY_SP1_PROOF => {
+                Ok(SyscallOutcome::Continue)
+            }
+            SP1_HINT_LEN => {
+                let len = self.hints.front().map_or(NO_HINT, |hint| hint.len() as u32);
+                ctx.vm.write_reg(SP1_SYSCALL_REGISTER, len);
+                Ok(SyscallOutcome::Continue)
+            }
+            SP1_HINT_READ => self.sys_hint_read(ctx),
+            number => Err(VMErrors::InvalidSyscall(number)),
+        }
+    }
+
+    fn box_clone(&self) -> Box<dyn SyscallHandler> {
+        Box::new(self.clone())
+    }
+
+    /// # Errors
+    /// The state inside an unconstrained block can not be saved, it holds a second copy of the VM.
+    fn save_state(&self) -> Result<Vec<u8>, VMErrors> {
+        if self.fork.is_some() {
+            return Err(VMErrors::SerializationError);
+        }
+        bincode::DefaultOptions::new()
+            .serialize(self)
+            .map_err(|_| VMErrors::SerializationError)
+    }
+
+    fn restore_state(&mut self, state: &[u8]) -> Result<(), VMErrors> {
+        let echo = self.echo;
+        *self = bincode::DefaultOptions::new()
+            .deserialize(state)
+            .map_err(|_| VMErrors::SerializationError)?;
+        self.echo = echo;
+
+        Ok(())
+    }
+}
+
+impl Vm {
+    /// Create a new Vm running the SP1 program `elf`, which reads `hints` as its input.
+    pub fn from_sp1_elf(elf: &Elf, hints: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        let mut vm = Self::from_elf(elf);
+        vm.set_syscall_handler(Sp1SyscallHandler::with_hints(hints));
+        vm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{addi, b_type, lw, sw, ECALL};
+    use crate::vm::{RunConfig, RunOutcome};
+
+    /// Runs `instructions` from 0 with the SP1 handler holding `hints`, echo off
+    fn run(instructions: Vec<u32>, hints: &[&[u8]]) -> (Vm, RunOutcome) {
+        let mut vm = Vm::from_bin(instructions).unwrap();
+        vm.set_syscall_handler(Sp1SyscallHandler {
+            hints: hints.iter().map(|hint| hint.to_vec()).collect(),
+            ..Default::default()
+        });
+        let outcome = vm.run(RunConfig::default());
+        (vm, outcome)
+    }
+
+    fn handler(vm: &Vm) -> &Sp1SyscallHandler {
+        (vm.syscall_handler.as_ref() as &dyn std::any::Any)
+            .downcast_ref()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_halt_and_write() {
+        let (vm, outcome) = run(
+            vec![
+                // Write the first 4 bytes of the program to the public values
+                addi(10, 0, FD_PUBLIC_VALUES as i32),
+                addi(11, 0, 0),
+                addi(12, 0, 4),
+                addi(5, 0, SP1_WRITE as i32),
+                ECALL,
+                addi(10, 0, 3),
+                addi(5, 0, SP1_HALT as i32),
+                ECALL,
+            ],
+            &[],
+        );
+
+        assert_eq!(outcome, RunOutcome::Exited(3));
+        assert_eq!(
+            handler(&vm).public_values,
+            addi(10, 0, 3).to_le_bytes().to_vec()
+        );
+    }
+
+    #[test]
+    fn test_hints_are_read_in_order() {
+        let (vm, outcome) = run(
+            vec![
+                addi(5, 0, SP1_HINT_LEN as i32),
+                ECALL,
+                addi(11, 5, 0),
+                addi(10, 0, 0x100),
+                addi(5, 0, SP1_HINT_READ as i32),
+                ECALL,
+                addi(5, 0, SP1_HINT_LEN as i32),
+                ECALL,
+                addi(20, 5, 0),
+                lw(10, 0, 0x100),
+                addi(5, 0, SP1_HALT as i32),
+                ECALL,
+            ],
+            &[&[1, 2, 3, 4], &[5]],
+        );
+
+        assert_eq!(outcome, RunOutcome::Exited(0x0403_0201));
+        assert_eq!(vm.registers.read_reg(20), 1);
+        assert_eq!(handler(&vm).hints, [vec![5]]);
+
+        let (_, outcome) = run(
+            vec![
+                addi(5, 0, SP1_HINT_LEN as i32),
+                ECALL,
+                addi(10, 5, 0),
+                addi(5, 0, SP1_HALT as i32),
+                ECALL,
+            ],
+            &[],
+        );
+        assert_eq!(outcome, RunOutcome::Exited(NO_HINT));
+    }
+
+    #[test]
+    fn test_a_hint_is_read_whole() {
+        let (_, outcome) = run(
+            vec![
+                addi(10, 0, 0x100),
+                addi(11, 0, 2),
+                addi(5, 0, SP1_HINT_READ as i32),
+                ECALL,
+            ],
+            &[&[1, 2, 3]],
+        );
+
+        assert!(matches!(
+            outcome,
+            RunOutcome::Faulted {
+                pc: 12,
+                error: VMErrors::EnvironmentError
+            }
+        ));
+    }
+
+    #[test]
+    fn test_unconstrained_blocks_are_undone() {
+        let (vm, outcome) = run(
+            vec![
+                addi(6, 0, 1),
+                addi(5, 0, SP1_ENTER_UNCONSTRAINED as i32),
+                ECALL,
+                // Skip the block when enter_unconstrained returns 0
+                b_type(0b000, 5, 0, 40),
+                addi(6, 0, 2),
+                sw(0, 6, 0x100),
+                addi(10, 0, FD_HINT as i32),
+                addi(11, 0, 0x100),
+                addi(12, 0, 1),
+                addi(5, 0, SP1_WRITE as i32),
+                ECALL,
+                addi(5, 0, SP1_EXIT_UNCONSTRAINED as i32),
+                ECALL,
+                lw(10, 0, 0x100),
+                addi(5, 0, SP1_HALT as i32),
+                ECALL,
+            ],
+            &[],
+        );
+
+        // The store and x6 are undone, the hint the block wrote is kept
+        assert_eq!(outcome, RunOutcome::Exited(0));
+        assert_eq!(vm.registers.read_reg(6), 1);
+        assert_eq!(handler(&vm).hints, [vec![2]]);
+    }
+
+    #[test]
+    fn test_precompiles_are_not_supported() {
+        // bn254_add
+        let (_, outcome) = run(vec![addi(5, 0, 0x10e), ECALL], &[]);
+
+        assert!(matches!(
+            outcome,
+            RunOutcome::Faulted {
+                pc: 4,
+                error: VMErrors::InvalidSyscall(0x10e)
+            }
+        ));
+    }
+}
@@ -0,0 +1,392 @@
+//! This mod holds the syscall interface of the VM.
+//! An `ecall` instruction hands control to the [SyscallHandler] registered on the [Vm](crate::vm::Vm),
+//! the syscall number is read from `a7` and the arguments from `a0`..`a5`, following the RISC-V Linux ABI.
+use crate::vm::{VMErrors, Vm};
+use bincode::Options;
+use core::MemoryChuckSize;
+use serde::{Deserialize, Serialize};
+use std::{
+    any::Any,
+    collections::VecDeque,
+    fmt::Debug,
+    io::{stderr, stdout, Write},
+};
+
+/// Register holding the syscall number
+pub const SYSCALL_NUMBER_REGISTER: u32 = 17;
+/// Register holding the first syscall argument and the return value
+pub const SYSCALL_ARG0_REGISTER: u32 = 10;
+
+/// Syscall number for `read(fd, buf, count)`
+pub const SYS_READ: u32 = 63;
+/// Syscall number for `write(fd, buf, count)`
+pub const SYS_WRITE: u32 = 64;
+/// Syscall number for `exit(code)`
+pub const SYS_EXIT: u32 = 93;
+
+/// Syscalls move at most this many bytes in or out of guest memory at once, so that a guest can not
+/// make the host allocate its whole address space
+pub const MAX_BUFFER_LEN: u32 = 1 << 26;
+
+/// Error number returned (negated) for an invalid file descriptor
+pub const EBADF: i32 = 9;
+
+/// This is what the VM should do after a syscall has been handled
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyscallOutcome {
+    /// Resume execution at the instruction after the `ecall`
+    Continue,
+    /// Halt the VM with the given exit code
+    Exit(u32),
+    /// Raise an environment call exception, leaving the `ecall` to the guest's trap handler
+    Trap,
+}
+
+/// This is the view of the machine state a syscall handler gets to work with.
+/// Accesses made through its methods go through the same paths as the guest's own loads and
+/// stores: they are recorded in the execution trace, reported to the observers, counted in the
+/// touched pages of the current segment (see [crate::segment]), break a pending LR/SC reservation
+/// and drop the decoded instructions they overwrite.
+/// The handler is detached from [SyscallContext::vm] while it runs.
+pub struct SyscallContext<'a> {
+    pub vm: &'a mut Vm,
+}
+
+impl SyscallContext<'_> {
+    /// Returns the nth syscall argument (`a0` + n)
+    pub fn arg(&mut self, n: u32) -> u32 {
+        self.vm.read_reg(SYSCALL_ARG0_REGISTER + n)
+    }
+
+    /// Writes the syscall return value to `a0`
+    pub fn set_return(&mut self, value: u32) {
+        self.vm.write_reg(SYSCALL_ARG0_REGISTER, value);
+    }
+
+    /// Reads `len` bytes of guest memory starting at `addr`
+    /// # Errors
+    /// This function returns an error if the range wraps around the address space, is longer than
+    /// [MAX_BUFFER_LEN] or is not readable.
+    pub fn read_bytes(&mut self, addr: u32, len: u32) -> Result<Vec<u8>, VMErrors> {
+        check_range(addr, len).ok_or(VMErrors::MemoryLoadError)?;
+
+        let mut data = Vec::with_capacity(len as usize);
+        for i in 0..len {
+            let byte = self
+                .vm
+                .read_mem(addr + i, MemoryChuckSize::BYTE)
+                .ok_or(VMErrors::MemoryLoadError)?;
+            data.push(byte as u8);
+        }
+
+        Ok(data)
+    }
+
+    /// Writes `data` to guest memory starting at `addr`
+    /// # Errors
+    /// This function returns an error if the range wraps around the address space or is not
+    /// writable.
+    pub fn write_bytes(&mut self, addr: u32, data: &[u8]) -> Result<(), VMErrors> {
+        let len = u32::try_from(data.len()).map_err(|_| VMErrors::MemoryStoreError)?;
+        check_range(addr, len).ok_or(VMErrors::MemoryStoreError)?;
+
+        for (i, byte) in data.iter().enumerate() {
+            if !self
+                .vm
+                .write_mem(addr + i as u32, MemoryChuckSize::BYTE, *byte as u32)
+            {
+                return Err(VMErrors::MemoryStoreError);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that the `len` bytes at `addr` neither wrap around nor exceed [MAX_BUFFER_LEN].
+fn check_range(addr: u32, len: u32) -> Option<()> {
+    (len <= MAX_BUFFER_LEN && addr.checked_add(len).is_some()).then_some(())
+}
+
+/// The handler standing in for the one that is running, see [SyscallContext].
+#[derive(Debug, Clone)]
+pub(crate) struct DetachedSyscallHandler;
+
+impl SyscallHandler for DetachedSyscallHandler {
+    fn handle(
+        &mut self,
+        number: u32,
+        _ctx: &mut SyscallContext,
+    ) -> Result<SyscallOutcome, VMErrors> {
+        Err(VMErrors::InvalidSyscall(number))
+    }
+
+    fn box_clone(&self) -> Box<dyn SyscallHandler> {
+        Box::new(self.clone())
+    }
+}
+
+/// This trait is implemented by anything that can service the guest's `ecall`s.
+/// Handlers can be downcast to their type through [Any].
+pub trait SyscallHandler: Any + Debug {
+    /// Handle the syscall identified by `number` (the value of `a7`).
+    fn handle(&mut self, number: u32, ctx: &mut SyscallContext)
+        -> Result<SyscallOutcome, VMErrors>;
+
+    /// Clone this handler into a new box, this is what makes the [Vm](crate::vm::Vm) cloneable.
+    fn box_clone(&self) -> Box<dyn SyscallHandler>;
+
+    /// Save the state of the handler for a [Snapshot](crate::snapshot::Snapshot).
+    /// Stateless handlers have nothing to save.
+    fn save_state(&self) -> Result<Vec<u8>, VMErrors> {
+        Ok(Vec::new())
+    }
+
+    /// Restore the state saved by [SyscallHandler::save_state].
+    fn restore_state(&mut self, _state: &[u8]) -> Result<(), VMErrors> {
+        Ok(())
+    }
+}
+
+impl Clone for Box<dyn SyscallHandler> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// The syscall handler every VM starts with.
+/// It supports `exit`, `write` to stdout/stderr and `read` from stdin.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DefaultSyscallHandler {
+    /// Bytes served to the guest when it reads from fd 0
+    pub stdin: VecDeque<u8>,
+    /// Everything the guest wrote to fd 1
+    pub stdout: Vec<u8>,
+    /// Everything the guest wrote to fd 2
+    pub stderr: Vec<u8>,
+    /// When set, guest output is also forwarded to the host's stdout/stderr.
+    /// This is up to the host and is not saved in snapshots
+    #[serde(skip)]
+    pub echo: bool,
+}
+
+impl DefaultSyscallHandler {
+    /// Create a new handler that forwards guest output to the host.
+    pub fn new() -> Self {
+        Self {
+            echo: true,
+            ..Default::default()
+        }
+    }
+
+    /// Create a new handler that serves `input` to the guest on fd 0.
+    pub fn with_stdin(input: &[u8]) -> Self {
+        Self {
+            stdin: input.iter().copied().collect(),
+            ..Self::new()
+        }
+    }
+
+    fn sys_write(&mut self, ctx: &mut SyscallContext) -> Result<SyscallOutcome, VMErrors> {
+        let fd = ctx.arg(0);
+        let buf = ctx.arg(1);
+        let count = ctx.arg(2);
+        // The buffer is only read for a valid fd, like Linux
+        if fd != 1 && fd != 2 {
+            ctx.set_return(-EBADF as u32);
+            return Ok(SyscallOutcome::Continue);
+        }
+        let data = ctx.read_bytes(buf, count)?;
+
+        if fd == 1 {
+            if self.echo {
+                let _ = stdout().write_all(&data);
+            }
+            self.stdout.extend_from_slice(&data);
+        } else {
+            if self.echo {
+                let _ = stderr().write_all(&data);
+            }
+            self.stderr.extend_from_slice(&data);
+        }
+
+        ctx.set_return(data.len() as u32);
+        Ok(SyscallOutcome::Continue)
+    }
+
+    fn sys_read(&mut self, ctx: &mut SyscallContext) -> Result<SyscallOutcome, VMErrors> {
+        if ctx.arg(0) != 0 {
+            ctx.set_return(-EBADF as u32);
+            return Ok(SyscallOutcome::Continue);
+        }
+
+        let buf = ctx.arg(1);
+        let count = (ctx.arg(2) as usize).min(self.stdin.len());
+        let data: Vec<u8> = self.stdin.drain(..count).collect();
+        ctx.write_bytes(buf, &data)?;
+
+        ctx.set_return(count as u32);
+        Ok(SyscallOutcome::Continue)
+    }
+}
+
+impl SyscallHandler for DefaultSyscallHandler {
+    fn handle(
+        &mut self,
+        number: u32,
+        ctx: &mut SyscallContext,
+    ) -> Result<SyscallOutcome, VMErrors> {
+        match number {
+            SYS_EXIT => Ok(SyscallOutcome::Exit(ctx.arg(0))),
+            SYS_WRITE => self.sys_write(ctx),
+            SYS_READ => self.sys_read(ctx),
+            _ => Err(VMErrors::InvalidSyscall(number)),
+        }
+    }
+
+    fn box_clone(&self) -> Box<dyn SyscallHandler> {
+        Box::new(self.clone())
+    }
+
+    fn save_state(&self) -> Result<Vec<u8>, VMErrors> {
+        bincode::DefaultOptions::new()
+            .serialize(self)
+            .map_err(|_| VMErrors::SerializationError)
+    }
+
+    fn restore_state(&mut self, state: &[u8]) -> Result<(), VMErrors> {
+        let echo = self.echo;
+        *self = bincode::DefaultOptions::new()
+            .deserialize(state)
+            .map_err(|_| VMErrors::SerializationError)?;
+        self.echo = echo;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{addi, EBREAK, ECALL};
+    use crate::{trace::AccessKind, vm::RunConfig};
+    use core::interfaces::MemoryInterface;
+
+    #[test]
+    fn test_exit_sets_exit_code() {
+        let mut vm =
+            Vm::from_bin(vec![addi(10, 0, 42), addi(17, 0, SYS_EXIT as i32), ECALL]).unwrap();
+        vm.run(RunConfig::default());
+
+        assert!(!vm.running);
+        assert_eq!(vm.exit_code, 42);
+        assert_eq!(vm.pc, 12);
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let mut vm = Vm::from_bin(vec![
+            // write(1, 0x100, 5)
+            addi(10, 0, 1),
+            addi(11, 0, 0x100),
+            addi(12, 0, 5),
+            addi(17, 0, SYS_WRITE as i32),
+            ECALL,
+            addi(9, 10, 0),
+            // read(0, 0x200, 8)
+            addi(10, 0, 0),
+            addi(11, 0, 0x200),
+            addi(12, 0, 8),
+            addi(17, 0, SYS_READ as i32),
+            ECALL,
+            EBREAK,
+        ])
+        .unwrap();
+        let mut handler = DefaultSyscallHandler::with_stdin(b"abc");
+        handler.echo = false;
+        vm.set_syscall_handler(handler);
+        for (i, b) in b"hello".iter().enumerate() {
+            vm.memory
+                .write_mem(0x100 + i as u32, MemoryChuckSize::BYTE, *b as u32);
+        }
+        vm.run(RunConfig::default());
+
+        assert_eq!(vm.registers.read_reg(9), 5);
+        assert_eq!(vm.registers.read_reg(10), 3);
+        assert_eq!(
+            vm.memory.read_mem(0x201, MemoryChuckSize::BYTE),
+            Some(b'b' as u32)
+        );
+        assert_eq!(vm.exit_code, 0);
+    }
+
+    #[test]
+    fn test_ebreak_halts_on_instruction() {
+        let mut vm = Vm::from_bin(vec![addi(1, 0, 1), EBREAK, addi(1, 0, 2)]).unwrap();
+        vm.run(RunConfig::default());
+
+        assert!(!vm.running);
+        assert_eq!(vm.pc, 4);
+        assert_eq!(vm.registers.read_reg(1), 1);
+    }
+
+    #[test]
+    fn test_accesses_go_through_the_vm() {
+        let mut vm = Vm::from_bin(vec![
+            // read(0, 0x200, 4)
+            addi(10, 0, 0),
+            addi(11, 0, 0x200),
+            addi(12, 0, 4),
+            addi(17, 0, SYS_READ as i32),
+            ECALL,
+            EBREAK,
+        ])
+        .unwrap();
+        vm.set_syscall_handler(DefaultSyscallHandler::with_stdin(b"abcd"));
+        vm.reservation = Some(0x200);
+        vm.enable_trace();
+        vm.run(RunConfig::default());
+
+        // Like a store, the write broke the reservation and was traced
+        assert_eq!(vm.reservation, None);
+        let trace = vm.take_trace().unwrap();
+        let writes = trace.rows[4]
+            .memory_accesses
+            .iter()
+            .filter(|access| access.kind == AccessKind::Write)
+            .count();
+        assert_eq!(writes, 4);
+    }
+
+    #[test]
+    fn test_buffers_are_checked_before_they_are_read() {
+        for (addr, len) in [(0xffff_fff0, 0x20), (0, u32::MAX)] {
+            let mut vm = Vm::new();
+            vm.registers.write_reg(10, 1);
+            vm.registers.write_reg(11, addr);
+            vm.registers.write_reg(12, len);
+            let mut ctx = SyscallContext { vm: &mut vm };
+
+            assert_eq!(
+                DefaultSyscallHandler::default().handle(SYS_WRITE, &mut ctx),
+                Err(VMErrors::MemoryLoadError)
+            );
+
+            // The fd is checked first
+            ctx.vm.registers.write_reg(10, 3);
+            assert_eq!(
+                DefaultSyscallHandler::default().handle(SYS_WRITE, &mut ctx),
+                Ok(SyscallOutcome::Continue)
+            );
+            assert_eq!(ctx.vm.registers.read_reg(10), -EBADF as u32);
+        }
+    }
+
+    #[test]
+    fn test_unknown_syscall_is_an_error() {
+        let mut vm = Vm::from_bin(vec![addi(17, 0, 1000), ECALL]).unwrap();
+        vm.step().unwrap();
+
+        assert!(matches!(vm.step(), Err(VMErrors::InvalidSyscall(1000))));
+    }
+}
@@ -0,0 +1,351 @@
+//! This mod holds the execution trace of the VM.
+//! When tracing is enabled on a [Vm](crate::vm::Vm) every executed instruction produces a
+//! [TraceRow] holding the instruction, its decoded fields and every register, memory and CSR access
+//! it made. Besides the retired instructions, this covers the ones halting the VM (ebreak and the
+//! exit syscalls) and the ones raising an exception that is delivered to the guest's trap handler:
+//! their row records the trap, the CSR writes delivering it and ends at the handler.
+//! An error the VM stops on changes nothing and leaves no row.
+//! This is the witness a zkVM prover needs to prove the execution.
+use crate::{
+    instructions::{DecodedInstruction, InstructionDecoder},
+    vm::VMErrors,
+};
+use bincode::Options;
+use core::MemoryChuckSize;
+use serde::{Deserialize, Serialize};
+
+/// This defines whether an access read or wrote its location
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// This is a single access to the register file.
+/// For reads `old_value` and `new_value` are the same.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegisterAccess {
+    pub kind: AccessKind,
+    pub register: u8,
+    pub old_value: u32,
+    pub new_value: u32,
+}
+
+/// This is a single access to memory, `size` is in bytes.
+/// For reads `old_value` and `new_value` are the same.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub addr: u32,
+    pub size: u8,
+    pub old_value: u32,
+    pub new_value: u32,
+}
+
+/// This is a single access to a CSR, by its 12-bit address.
+/// For reads `old_value` and `new_value` are the same.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CsrAccess {
+    pub kind: AccessKind,
+    pub csr: u16,
+    pub old_value: u32,
+    pub new_value: u32,
+}
+
+/// These are the fields of a decoded instruction, fields the format does not have are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecodedFields {
+    pub opcode: u32,
+    pub rd: Option<u8>,
+    pub rs1: Option<u8>,
+    pub rs2: Option<u8>,
+    pub funct3: Option<u32>,
+    pub funct7: Option<u32>,
+    pub imm: Option<i32>,
+}
+
+/// This is the record of one executed instruction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceRow {
+    /// Index of the instruction in the execution, starting at 0
+    pub clk: u64,
+    pub pc: u32,
+    pub next_pc: u32,
+    pub instruction: u32,
+    pub decoded: DecodedFields,
+    pub register_accesses: Vec<RegisterAccess>,
+    pub memory_accesses: Vec<MemoryAccess>,
+    pub csr_accesses: Vec<CsrAccess>,
+    /// The cause of the exception the instruction raised, `next_pc` is the trap handler then
+    pub trap: Option<u32>,
+}
+
+/// This is the trace of an execution, in the order the instructions were executed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trace {
+    pub rows: Vec<TraceRow>,
+}
+
+impl From<&InstructionDecoder> for DecodedFields {
+    fn from(decoder: &InstructionDecoder) -> Self {
+        let opcode = decoder.opcode;
+        match &decoder.decoded_instruction {
+            DecodedInstruction::RType(r) => DecodedFields {
+                opcode,
+                rd: Some(r.rd as u8),
+                rs1: Some(r.rs1 as u8),
+                rs2: Some(r.rs2 as u8),
+                funct3: Some(r.funct3),
+                funct7: Some(r.funct7),
+                imm: None,
+            },
+            DecodedInstruction::IType(i) => DecodedFields {
+                opcode,
+                rd: Some(i.rd as u8),
+                rs1: Some(i.rs1 as u8),
+                funct3: Some(i.funct3),
+                imm: Some(i.imm),
+                ..Default::default()
+            },
+            DecodedInstruction::SType(s) => DecodedFields {
+                opcode,
+                rs1: Some(s.rs1 as u8),
+                rs2: Some(s.rs2 as u8),
+                funct3: Some(s.funct3),
+                imm: Some(s.imm),
+                ..Default::default()
+            },
+            DecodedInstruction::BType(b) => DecodedFields {
+                opcode,
+                rs1: Some(b.rs1 as u8),
+                rs2: Some(b.rs2 as u8),
+                funct3: Some(b.funct3),
+                imm: Some(b.imm),
+                ..Default::default()
+            },
+            DecodedInstruction::UType(u) => DecodedFields {
+                opcode,
+                rd: Some(u.rd as u8),
+                imm: Some(u.imm),
+                ..Default::default()
+            },
+            DecodedInstruction::JType(j) => DecodedFields {
+                opcode,
+                rd: Some(j.rd as u8),
+                imm: Some(j.imm),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Returns the number of bytes accessed for a chunk size
+pub fn chunk_bytes(size: &MemoryChuckSize) -> u8 {
+    match size {
+        MemoryChuckSize::BYTE => 1,
+        MemoryChuckSize::HalfWord => 2,
+        MemoryChuckSize::WordSize => 4,
+    }
+}
+
+impl Trace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start the row of the instruction about to be executed
+    pub(crate) fn begin_row(&mut self, pc: u32, instruction: u32, decoded: DecodedFields) {
+        self.rows.push(TraceRow {
+            clk: self.rows.len() as u64,
+            pc,
+            next_pc: pc,
+            instruction,
+            decoded,
+            register_accesses: Vec::new(),
+            memory_accesses: Vec::new(),
+            csr_accesses: Vec::new(),
+            trap: None,
+        });
+    }
+
+    /// Complete the row of the instruction that was just retired
+    pub(crate) fn end_row(&mut self, next_pc: u32) {
+        if let Some(row) = self.rows.last_mut() {
+            row.next_pc = next_pc;
+        }
+    }
+
+    /// Complete the row of an instruction whose exception was delivered to the trap handler at
+    /// `handler`
+    pub(crate) fn trap_row(&mut self, cause: u32, handler: u32) {
+        if let Some(row) = self.rows.last_mut() {
+            row.trap = Some(cause);
+            row.next_pc = handler;
+        }
+    }
+
+    /// Drop the row of an instruction the VM stopped on
+    pub(crate) fn abort_row(&mut self) {
+        self.rows.pop();
+    }
+
+    pub(crate) fn record_register(&mut self, access: RegisterAccess) {
+        if let Some(row) = self.rows.last_mut() {
+            row.register_accesses.push(access);
+        }
+    }
+
+    pub(crate) fn record_memory(&mut self, access: MemoryAccess) {
+        if let Some(row) = self.rows.last_mut() {
+            row.memory_accesses.push(access);
+        }
+    }
+
+    pub(crate) fn record_csr(&mut self, access: CsrAccess) {
+        if let Some(row) = self.rows.last_mut() {
+            row.csr_accesses.push(access);
+        }
+    }
+
+    /// Serialize the trace to JSON
+    pub fn to_json(&self) -> Result<String, VMErrors> {
+        serde_json::to_string(self).map_err(|_| VMErrors::SerializationError)
+    }
+
+    /// Deserialize a trace from JSON
+    pub fn from_json(json: &str) -> Result<Self, VMErrors> {
+        serde_json::from_str(json).map_err(|_| VMErrors::SerializationError)
+    }
+
+    /// Serialize the trace to a compact binary format (bincode with variable length integers)
+    pub fn to_bytes(&self) -> Result<Vec<u8>, VMErrors> {
+        bincode::DefaultOptions::new()
+            .serialize(self)
+            .map_err(|_| VMErrors::SerializationError)
+    }
+
+    /// Deserialize a trace from the compact binary format
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VMErrors> {
+        bincode::DefaultOptions::new()
+            .deserialize(bytes)
+            .map_err(|_| VMErrors::SerializationError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{RunConfig, Vm};
+
+    #[test]
+    fn test_trace_records_accesses() {
+        let mut vm = Vm::from_bin(vec![
+            0x0050_0093, // addi x1, x0, 5
+            0x1010_2023, // sw x1, 0x100(x0)
+            0x1000_4103, // lbu x2, 0x100(x0)
+            0x0010_0073, // ebreak
+        ])
+        .unwrap();
+        vm.enable_trace();
+        vm.run(RunConfig::default());
+        let trace = vm.take_trace().unwrap();
+
+        assert_eq!(trace.rows.len(), 4);
+        assert_eq!(trace.rows[0].decoded.rd, Some(1));
+        assert_eq!(trace.rows[0].decoded.imm, Some(5));
+        assert_eq!(trace.rows[0].next_pc, 4);
+        assert_eq!(
+            trace.rows[0].register_accesses,
+            vec![
+                RegisterAccess {
+                    kind: AccessKind::Read,
+                    register: 0,
+                    old_value: 0,
+                    new_value: 0,
+                },
+                RegisterAccess {
+                    kind: AccessKind::Write,
+                    register: 1,
+                    old_value: 0,
+                    new_value: 5,
+                },
+            ]
+        );
+        assert_eq!(
+            trace.rows[1].memory_accesses,
+            vec![MemoryAccess {
+                kind: AccessKind::Write,
+                addr: 0x100,
+                size: 4,
+                old_value: 0,
+                new_value: 5,
+            }]
+        );
+        assert_eq!(
+            trace.rows[2].memory_accesses,
+            vec![MemoryAccess {
+                kind: AccessKind::Read,
+                addr: 0x100,
+                size: 1,
+                old_value: 5,
+                new_value: 5,
+            }]
+        );
+        assert_eq!(trace.rows[3].clk, 3);
+    }
+
+    #[test]
+    fn test_trace_records_traps_and_csr_accesses() {
+        let mut vm = Vm::from_bin(vec![
+            0x0100_0093, // addi x1, x0, 0x10
+            0x3050_9073, // csrrw x0, mtvec, x1
+            0xffff_ffff, // illegal
+            0x0000_0013, // nop
+            0x3420_2173, // csrrs x2, mcause, x0
+            0x0010_0073, // ebreak
+        ])
+        .unwrap();
+        vm.enable_trace();
+        vm.run(RunConfig::default());
+        let trace = vm.take_trace().unwrap();
+
+        assert_eq!(trace.rows.len(), 5);
+        assert_eq!(
+            trace.rows[1].csr_accesses,
+            vec![CsrAccess {
+                kind: AccessKind::Write,
+                csr: 0x305,
+                old_value: 0,
+                new_value: 0x10,
+            }]
+        );
+
+        // The illegal instruction traps to the handler at 0x10
+        let trap = &trace.rows[2];
+        assert_eq!((trap.pc, trap.next_pc, trap.trap), (8, 0x10, Some(2)));
+        let writes: Vec<_> = trap
+            .csr_accesses
+            .iter()
+            .map(|access| (access.csr, access.new_value))
+            .collect();
+        assert_eq!(&writes[..3], [(0x341, 8), (0x342, 2), (0x343, 0xffff_ffff)]);
+
+        assert_eq!(trace.rows[3].pc, 0x10);
+        assert_eq!(trace.rows[3].csr_accesses[0].old_value, 2);
+        assert_eq!(trace.rows[4].trap, None);
+    }
+
+    #[test]
+    fn test_trace_serialization_round_trip() {
+        let mut vm = Vm::from_bin(vec![0x0050_0093, 0x0010_0073]).unwrap();
+        vm.enable_trace();
+        vm.run(RunConfig::default());
+        let trace = vm.take_trace().unwrap();
+
+        let json = trace.to_json().unwrap();
+        let bytes = trace.to_bytes().unwrap();
+        assert_eq!(Trace::from_json(&json).unwrap(), trace);
+        assert_eq!(Trace::from_bytes(&bytes).unwrap(), trace);
+        assert!(bytes.len() < json.len());
+    }
+}
@@ -0,0 +1,283 @@
+//! This mod holds machine-mode trap delivery.
+//! Errors raised while executing an instruction that are architectural exceptions are delivered to
+//! the guest's trap handler: mepc gets the pc of the faulting instruction, mcause the exception code,
+//! mtval the faulting address (or the instruction for illegal instructions) and execution resumes at
+//! mtvec. Until the guest installs a trap handler (a non-zero mtvec) exceptions stop the VM instead.
+use crate::{
+    csr::{CsrFile, MSTATUS_MIE, MSTATUS_MPIE},
+    vm::VMErrors,
+};
+
+/// Set in mcause when the trap is an interrupt
+pub const INTERRUPT_BIT: u32 = 1 << 31;
+
+/// These are the synchronous exceptions a machine-mode hart can raise, the value is the mcause code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    InstructionAddressMisaligned = 0,
+    InstructionAccessFault = 1,
+    IllegalInstruction = 2,
+    Breakpoint = 3,
+    LoadAddressMisaligned = 4,
+    LoadAccessFault = 5,
+    StoreAddressMisaligned = 6,
+    StoreAccessFault = 7,
+    EnvironmentCallFromMMode = 11,
+}
+
+/// This is an exception ready to be delivered, `tval` is the value written to mtval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trap {
+    pub exception: Exception,
+    pub tval: u32,
+}
+
+impl Trap {
+    /// Returns the trap `error` raises, `instruction` is the faulting instruction.
+    /// Errors that are not architectural (syscall and host errors) return `None`.
+    pub fn from_error(error: &VMErrors, instruction: u32) -> Option<Self> {
+        let (exception, tval) = match error {
+            VMErrors::InstructionAddressMisaligned(addr) => {
+                (Exception::InstructionAddressMisaligned, *addr)
+            }
+            VMErrors::InstructionAccessFault(addr) => (Exception::InstructionAccessFault, *addr),
+            VMErrors::InvalidInstruction
+            | VMErrors::InvalidOpcode(_)
+            | VMErrors::InvalidFunct3(_)
+            | VMErrors::InvalidFunct7(_)
+            | VMErrors::InvalidCsr(_) => (Exception::IllegalInstruction, instruction),
+            VMErrors::LoadAddressMisaligned(addr) => (Exception::LoadAddressMisaligned, *addr),
+            VMErrors::LoadAccessFault(addr) => (Exception::LoadAccessFault, *addr),
+            VMErrors::StoreAddressMisaligned(addr) => (Exception::StoreAddressMisaligned, *addr),
+            VMErrors::StoreAccessFault(addr) => (Exception::StoreAccessFault, *addr),
+            VMErrors::Breakpoint(pc) => (Exception::Breakpoint, *pc),
+            VMErrors::EnvironmentError => (Exception::EnvironmentCallFromMMode, 0),
+            _ => return None,
+        };
+
+        Some(Self { exception, tval })
+    }
+}
+
+impl CsrFile {
+    /// Returns the pc a trap with the given mcause jumps to.
+    /// In vectored mode interrupts jump to BASE + 4 * cause, exceptions always jump to BASE.
+    pub fn trap_target(&self, cause: u32) -> u32 {
+        let base = self.mtvec & !0b11;
+
+        if self.mtvec & 0b11 == 1 && cause & INTERRUPT_BIT != 0 {
+            base.wrapping_add(4 * (cause & !INTERRUPT_BIT))
+        } else {
+            base
+        }
+    }
+
+    /// Enter a trap taken at `pc`, returning the pc of the trap handler.
+    pub fn enter_trap(&mut self, pc: u32, cause: u32, tval: u32) -> u32 {
+        self.mepc = pc & !0b1;
+        self.mcause = cause;
+        self.mtval = tval;
+
+        // MPIE <- MIE, MIE <- 0, MPP is always machine mode
+        let mie = self.mstatus & MSTATUS_MIE != 0;
+        self.mstatus &= !(MSTATUS_MIE | MSTATUS_MPIE);
+        if mie {
+            self.mstatus |= MSTATUS_MPIE;
+        }
+
+        self.trap_target(cause)
+    }
+
+    /// Return from a trap, returning the pc to resume at.
+    pub fn mret(&mut self) -> u32 {
+        // MIE <- MPIE, MPIE <- 1
+        let mpie = self.mstatus & MSTATUS_MPIE != 0;
+        self.mstatus &= !MSTATUS_MIE;
+        if mpie {
+            self.mstatus |= MSTATUS_MIE;
+        }
+        self.mstatus |= MSTATUS_MPIE;
+
+        self.mepc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{addi, csr, EBREAK, ECALL, MRET};
+    use crate::{
+        csr::{MCAUSE, MEPC, MSTATUS, MTVAL, MTVEC},
+        syscalls::{SyscallContext, SyscallHandler, SyscallOutcome},
+        vm::{RunConfig, Vm},
+    };
+    use core::{interfaces::MemoryInterface, MemoryChuckSize, MemoryRegion, Permissions};
+
+    const HANDLER: u32 = 0x100;
+
+    /// A trap handler saving mcause, mepc and mtval in x10-x12 and returning past the 4-byte
+    /// faulting instruction
+    fn handler() -> Vec<u32> {
+        vec![
+            csr(0b010, 10, 0, MCAUSE),
+            csr(0b010, 11, 0, MEPC),
+            csr(0b010, 12, 0, MTVAL),
+            addi(13, 11, 4),
+            csr(0b001, 0, 13, MEPC),
+            MRET,
+        ]
+    }
+
+    /// Load `program` at 0 with `handler()` at `HANDLER` installed as the trap handler
+    fn vm_with_handler(mut program: Vec<u32>) -> Vm {
+        program.splice(0..0, [addi(1, 0, HANDLER as i32), csr(0b001, 0, 1, MTVEC)]);
+        let mut vm = Vm::from_bin(program).unwrap();
+        vm.memory.load_program(&handler(), HANDLER);
+        vm
+    }
+
+    #[test]
+    fn test_illegal_instruction_traps() {
+        let mut vm = vm_with_handler(vec![0xffff_ffff, addi(5, 0, 1), EBREAK]);
+        vm.run(RunConfig::default());
+
+        assert_eq!(
+            vm.registers.read_reg(10),
+            Exception::IllegalInstruction as u32
+        );
+        assert_eq!(vm.registers.read_reg(11), 8);
+        assert_eq!(vm.registers.read_reg(12), 0xffff_ffff);
+        assert_eq!(vm.registers.read_reg(5), 1);
+        assert_eq!(vm.pc, 16);
+    }
+
+    #[test]
+    fn test_misaligned_load_traps() {
+        let mut vm = vm_with_handler(vec![
+            addi(2, 0, 0x202),
+            (0b00010 << 27) | (2 << 15) | (0b010 << 12) | (3 << 7) | 0b0101111, // lr.w x3, (x2)
+            EBREAK,
+        ]);
+        vm.registers.write_reg(3, 9);
+        vm.run(RunConfig::default());
+
+        assert_eq!(
+            vm.registers.read_reg(10),
+            Exception::LoadAddressMisaligned as u32
+        );
+        assert_eq!(vm.registers.read_reg(11), 12);
+        assert_eq!(vm.registers.read_reg(12), 0x202);
+        assert_eq!(vm.registers.read_reg(3), 9);
+    }
+
+    #[test]
+    fn test_store_access_fault_traps() {
+        let mut vm = vm_with_handler(vec![
+            addi(2, 0, 0x400),
+            (2 << 15) | (0b010 << 12) | 0b0100011, // sw x0, 0(x2)
+            EBREAK,
+        ]);
+        vm.memory.add_region(MemoryRegion {
+            start: 0x400,
+            size: 0x100,
+            permissions: Permissions {
+                read: true,
+                write: false,
+                execute: false,
+            },
+        });
+        vm.run(RunConfig::default());
+
+        assert_eq!(
+            vm.registers.read_reg(10),
+            Exception::StoreAccessFault as u32
+        );
+        assert_eq!(vm.registers.read_reg(12), 0x400);
+        assert_eq!(
+            vm.memory.read_mem(0x400, MemoryChuckSize::WordSize),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_ecall_traps_when_the_handler_asks() {
+        #[derive(Debug, Clone)]
+        struct TrapAll;
+
+        impl SyscallHandler for TrapAll {
+            fn handle(
+                &mut self,
+                _number: u32,
+                _ctx: &mut SyscallContext,
+            ) -> Result<SyscallOutcome, VMErrors> {
+                Ok(SyscallOutcome::Trap)
+            }
+
+            fn box_clone(&self) -> Box<dyn SyscallHandler> {
+                Box::new(self.clone())
+            }
+        }
+
+        let mut vm = vm_with_handler(vec![ECALL, EBREAK]);
+        vm.set_syscall_handler(TrapAll);
+        vm.run(RunConfig::default());
+
+        assert_eq!(
+            vm.registers.read_reg(10),
+            Exception::EnvironmentCallFromMMode as u32
+        );
+        assert_eq!(vm.registers.read_reg(11), 8);
+        assert_eq!(vm.pc, 12);
+    }
+
+    #[test]
+    fn test_mret_restores_interrupt_enable() {
+        let mut vm = vm_with_handler(vec![
+            addi(1, 0, MSTATUS_MIE as i32),
+            csr(0b010, 0, 1, MSTATUS), // csrs mstatus, x1
+            0xffff_ffff,
+            csr(0b010, 6, 0, MSTATUS),
+            EBREAK,
+        ]);
+        vm.memory.load_program(
+            &[
+                csr(0b010, 7, 0, MSTATUS),
+                csr(0b010, 13, 0, MEPC),
+                addi(13, 13, 4),
+                csr(0b001, 0, 13, MEPC),
+                MRET,
+            ],
+            HANDLER,
+        );
+        vm.run(RunConfig::default());
+
+        // MIE was saved to MPIE and cleared while handling the trap, MRET restores it
+        assert_eq!(
+            vm.registers.read_reg(7) & (MSTATUS_MIE | MSTATUS_MPIE),
+            MSTATUS_MPIE
+        );
+        assert_eq!(vm.registers.read_reg(6) & MSTATUS_MIE, MSTATUS_MIE);
+    }
+
+    #[test]
+    fn test_vectored_mode() {
+        let mut csrs = CsrFile::new();
+        csrs.write(MTVEC, 0x1000 | 1).unwrap();
+
+        assert_eq!(
+            csrs.enter_trap(0x20, Exception::IllegalInstruction as u32, 0),
+            0x1000
+        );
+        assert_eq!(csrs.enter_trap(0x20, INTERRUPT_BIT | 7, 0), 0x101c);
+        assert_eq!(csrs.mcause, INTERRUPT_BIT | 7);
+        assert_eq!(csrs.mepc, 0x20);
+    }
+
+    #[test]
+    fn test_faults_stop_the_vm_without_a_handler() {
+        let mut vm = Vm::from_bin(vec![0xffff_ffff]).unwrap();
+
+        assert!(matches!(vm.step(), Err(VMErrors::InvalidOpcode(_))));
+        assert_eq!(vm.pc, 0);
+    }
+}
@@ -1,5 +1,8 @@
-use crate::vm::{VMErrors, Vm};
-use core::{interfaces::MemoryInterface, MemoryChuckSize};
+use crate::{
+    trace::chunk_bytes,
+    vm::{VMErrors, Vm},
+};
+use core::MemoryChuckSize;
 
 pub fn process_load_to_reg(
     vm: &mut Vm,
@@ -8,7 +11,6 @@ pub fn process_load_to_reg(
     is_signed: bool,
 ) -> Result<(), VMErrors> {
     let addr = vm
-        .registers
         .read_reg(decoded_instruction.rs1 as u32)
         .wrapping_add(decoded_instruction.imm as u32);
 
@@ -18,14 +20,23 @@ pub fn process_load_to_reg(
         MemoryChuckSize::WordSize => 0x3,
     };
 
-    if (addr & align_mask) != 0x0 {
-        return Err(VMErrors::MemoryError);
-    }
-
-    let mut load_data = match vm.memory.read_mem(addr, mem_chuck_size.clone()) {
-        Some(d) => d,
-        None => {
-            return Err(VMErrors::MemoryLoadError);
+    let mut load_data = if (addr & align_mask) != 0x0 {
+        // Misaligned accesses are split into byte accesses
+        let mut data = 0;
+        for i in 0..chunk_bytes(&mem_chuck_size) as u32 {
+            let byte_addr = addr.wrapping_add(i);
+            let byte = vm
+                .read_mem(byte_addr, MemoryChuckSize::BYTE)
+                .ok_or(VMErrors::LoadAccessFault(byte_addr))?;
+            data |= byte << (8 * i);
+        }
+        data
+    } else {
+        match vm.read_mem(addr, mem_chuck_size.clone()) {
+            Some(d) => d,
+            None => {
+                return Err(VMErrors::LoadAccessFault(addr));
+            }
         }
     };
 
@@ -37,8 +48,7 @@ pub fn process_load_to_reg(
         }) as u32;
     }
 
-    vm.registers
-        .write_reg(decoded_instruction.rd as u32, load_data);
+    vm.write_reg(decoded_instruction.rd as u32, load_data);
 
     Ok(())
 }
@@ -49,10 +59,9 @@ pub fn process_store_to_memory(
     mem_chuck_size: MemoryChuckSize,
 ) -> Result<(), VMErrors> {
     let addr = vm
-        .registers
         .read_reg(decoded_instruction.rs1 as u32)
         .wrapping_add(decoded_instruction.imm as u32);
-    let data_to_store = vm.registers.read_reg(decoded_instruction.rs2 as u32);
+    let data_to_store = vm.read_reg(decoded_instruction.rs2 as u32);
 
     let align_mask = match mem_chuck_size {
         MemoryChuckSize::BYTE => 0x0,
@@ -61,14 +70,26 @@ pub fn process_store_to_memory(
     };
 
     if (addr & align_mask) != 0x0 {
-        return Err(VMErrors::MemoryError);
+        // Misaligned accesses are split into byte accesses, checking every byte first so that a
+        // faulting store leaves memory untouched
+        let size = chunk_bytes(&mem_chuck_size) as u32;
+        if let Some(byte_addr) = (0..size)
+            .map(|i| addr.wrapping_add(i))
+            .find(|byte_addr| !vm.memory.is_writable(*byte_addr))
+        {
+            return Err(VMErrors::StoreAccessFault(byte_addr));
+        }
+
+        for i in 0..size {
+            let byte = (data_to_store >> (8 * i)) & 0xff;
+            vm.write_mem(addr.wrapping_add(i), MemoryChuckSize::BYTE, byte);
+        }
+
+        return Ok(());
     }
 
-    if !vm
-        .memory
-        .write_mem(addr, mem_chuck_size.clone(), data_to_store)
-    {
-        return Err(VMErrors::MemoryStoreError);
+    if !vm.write_mem(addr, mem_chuck_size.clone(), data_to_store) {
+        return Err(VMErrors::StoreAccessFault(addr));
     }
 
     Ok(())
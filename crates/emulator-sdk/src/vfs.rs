@@ -0,0 +1,366 @@
+//! This mod holds the virtual filesystem the guest's file syscalls go through, see [crate::linux].
+//! The guest never sees the host filesystem. It sees in-memory files, which it can read, write and
+//! create, and host directories preopened read-only at guest paths, like WASI does. Each VM owns
+//! its filesystem, so what a guest writes is only visible to that VM (and to its clones, from the
+//! point they were cloned).
+//!
+//! Guest paths are absolute once normalized: `.` and `..` are resolved without leaving `/`, so
+//! relative paths start at `/`. A path under a preopened directory maps to the same relative path
+//! in the host directory, and symbolic links leading out of it are refused.
+use crate::{
+    linux::{EEXIST, EINVAL, EISDIR, ENOENT, EROFS},
+    syscalls::EBADF,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+pub const O_ACCMODE: u32 = 0o3;
+pub const O_RDONLY: u32 = 0o0;
+pub const O_WRONLY: u32 = 0o1;
+pub const O_RDWR: u32 = 0o2;
+pub const O_CREAT: u32 = 0o100;
+pub const O_EXCL: u32 = 0o200;
+pub const O_TRUNC: u32 = 0o1000;
+pub const O_APPEND: u32 = 0o2000;
+
+pub const SEEK_SET: u32 = 0;
+pub const SEEK_CUR: u32 = 1;
+pub const SEEK_END: u32 = 2;
+
+/// This is a host directory preopened at a guest path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mount {
+    /// The normalized guest path
+    pub guest: String,
+    /// The canonical host path
+    pub host: PathBuf,
+}
+
+/// This is what an open file refers to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Node {
+    /// An in-memory file, by its path
+    Memory(String),
+    /// A file of a preopened directory, read when it was opened
+    Host(Vec<u8>),
+}
+
+/// This is a file opened through the [Vfs], with its own offset.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileHandle {
+    pub node: Node,
+    pub offset: u64,
+    pub readable: bool,
+    pub writable: bool,
+    /// Whether every write goes to the end of the file
+    pub append: bool,
+}
+
+/// The filesystem of a VM, see the [module](self) documentation.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vfs {
+    /// The in-memory files, by path
+    files: BTreeMap<String, Vec<u8>>,
+    /// The preopened host directories
+    mounts: Vec<Mount>,
+}
+
+/// Returns the normalized absolute form of a guest path.
+pub fn normalize(path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+
+    format!("/{}", components.join("/"))
+}
+
+/// Returns the rest of `path` under the directory `dir`, both normalized, if it is under it.
+fn strip_dir<'a>(path: &'a str, dir: &str) -> Option<&'a str> {
+    if dir == "/" {
+        return Some(&path[1..]);
+    }
+    match path.strip_prefix(dir)? {
+        "" => Some(""),
+        rest => rest.strip_prefix('/'),
+    }
+}
+
+impl Vfs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an in-memory file at the guest path `path`, replacing any file there.
+    pub fn add_file(&mut self, path: &str, data: impl Into<Vec<u8>>) {
+        self.files.insert(normalize(path), data.into());
+    }
+
+    /// Returns the content of the in-memory file at `path`, which the guest may have written.
+    pub fn file(&self, path: &str) -> Option<&[u8]> {
+        self.files.get(&normalize(path)).map(Vec::as_slice)
+    }
+
+    /// Preopen the host directory `host` read-only at the guest path `guest`. A later directory
+    /// mapped at the same path replaces it.
+    /// # Errors
+    /// This function returns an error if `host` is not a directory.
+    pub fn map_dir(&mut self, host: impl AsRef<Path>, guest: &str) -> io::Result<()> {
+        let host = host.as_ref().canonicalize()?;
+        if !host.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a directory", host.display()),
+            ));
+        }
+        let guest = normalize(guest);
+        self.mounts.retain(|mount| mount.guest != guest);
+        self.mounts.push(Mount { guest, host });
+
+        Ok(())
+    }
+
+    /// Returns the mount the normalized `path` is under, the deepest one, and the rest of the path.
+    fn mount<'a>(&self, path: &'a str) -> Option<(&Mount, &'a str)> {
+        self.mounts
+            .iter()
+            .filter_map(|mount| Some((mount, strip_dir(path, &mount.guest)?)))
+            .max_by_key(|(mount, _)| mount.guest.len())
+    }
+
+    /// Returns whether the normalized `path` is a directory holding in-memory files.
+    fn is_memory_dir(&self, path: &str) -> bool {
+        self.files
+            .keys()
+            .any(|file| strip_dir(file, path).is_some_and(|rest| !rest.is_empty()))
+    }
+
+    /// Open the file at `path` with the `O_` flags of Linux, returning a Linux error number on
+    /// failure. Directories can not be opened.
+    pub fn open(&mut self, path: &str, flags: u32) -> Result<FileHandle, i32> {
+        let path = normalize(path);
+        let (readable, writable) = match flags & O_ACCMODE {
+            O_RDONLY => (true, false),
+            O_WRONLY => (false, true),
+            O_RDWR => (true, true),
+            _ => return Err(EINVAL),
+        };
+        let handle = |node| FileHandle {
+            node,
+            offset: 0,
+            readable,
+            writable,
+            append: flags & O_APPEND != 0,
+        };
+
+        // In-memory files shadow the preopened directories
+        if let Some(data) = self.files.get_mut(&path) {
+            if flags & O_CREAT != 0 && flags & O_EXCL != 0 {
+                return Err(EEXIST);
+            }
+            if writable && flags & O_TRUNC != 0 {
+                data.clear();
+            }
+            return Ok(handle(Node::Memory(path)));
+        }
+
+        if let Some((mount, rest)) = self.mount(&path) {
+            let host = mount.host.join(rest);
+            // Symbolic links may lead out of the directory
+            let host = match host.canonicalize() {
+                Ok(host) if host.starts_with(&mount.host) => host,
+                Ok(_) => return Err(ENOENT),
+                Err(_) if flags & O_CREAT != 0 => return Err(EROFS),
+                Err(_) => return Err(ENOENT),
+            };
+            if host.is_dir() {
+                return Err(EISDIR);
+            }
+            if writable || flags & (O_CREAT | O_TRUNC) != 0 {
+                return Err(EROFS);
+            }
+            let data = std::fs::read(host).map_err(|_| ENOENT)?;
+            return Ok(handle(Node::Host(data)));
+        }
+
+        if path == "/" || self.is_memory_dir(&path) {
+            return Err(EISDIR);
+        }
+        if flags & O_CREAT == 0 {
+            return Err(ENOENT);
+        }
+        self.files.insert(path.clone(), Vec::new());
+
+        Ok(handle(Node::Memory(path)))
+    }
+
+    fn data<'a>(&'a self, handle: &'a FileHandle) -> &'a [u8] {
+        match &handle.node {
+            Node::Memory(path) => self.files.get(path).map_or(&[], Vec::as_slice),
+            Node::Host(data) => data,
+        }
+    }
+
+    /// Returns the size of an open file.
+    pub fn size(&self, handle: &FileHandle) -> u64 {
+        self.data(handle).len() as u64
+    }
+
+    /// Read up to `len` bytes at the offset of `handle`, advancing it.
+    pub fn read(&self, handle: &mut FileHandle, len: u32) -> Result<Vec<u8>, i32> {
+        if !handle.readable {
+            return Err(EBADF);
+        }
+        let data = self.data(handle);
+        let start = (handle.offset as usize).min(data.len());
+        let end = start.saturating_add(len as usize).min(data.len());
+        let read = data[start..end].to_vec();
+        handle.offset += read.len() as u64;
+
+        Ok(read)
+    }
+
+    /// Write `data` at the offset of `handle`, advancing it and growing the file as needed.
+    pub fn write(&mut self, handle: &mut FileHandle, data: &[u8]) -> Result<u32, i32> {
+        let Node::Memory(path) = &handle.node else {
+            return Err(EBADF);
+        };
+        if !handle.writable {
+            return Err(EBADF);
+        }
+        let file = self.files.entry(path.clone()).or_default();
+        if handle.append {
+            handle.offset = file.len() as u64;
+        }
+
+        let start = usize::try_from(handle.offset).map_err(|_| EINVAL)?;
+        let end = start.checked_add(data.len()).ok_or(EINVAL)?;
+        if file.len() < end {
+            file.resize(end, 0);
+        }
+        file[start..end].copy_from_slice(data);
+        handle.offset = end as u64;
+
+        Ok(data.len() as u32)
+    }
+
+    /// Move the offset of `handle`, `whence` is one of the `SEEK_` constants.
+    pub fn seek(&self, handle: &mut FileHandle, offset: i64, whence: u32) -> Result<u64, i32> {
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => handle.offset,
+            SEEK_END => self.size(handle),
+            _ => return Err(EINVAL),
+        };
+        let offset = base.checked_add_signed(offset).ok_or(EINVAL)?;
+        if offset > i64::MAX as u64 {
+            return Err(EINVAL);
+        }
+        handle.offset = offset;
+
+        Ok(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("/data/./input.txt"), "/data/input.txt");
+        assert_eq!(normalize("data//input.txt/"), "/data/input.txt");
+        assert_eq!(normalize("/../../etc/passwd"), "/etc/passwd");
+        assert_eq!(normalize("/a/b/../c"), "/a/c");
+        assert_eq!(normalize(""), "/");
+    }
+
+    #[test]
+    fn test_memory_files() {
+        let mut vfs = Vfs::new();
+        vfs.add_file("/data/input.txt", "hello");
+
+        let mut file = vfs.open("data/../data/input.txt", O_RDONLY).unwrap();
+        assert_eq!(vfs.read(&mut file, 3), Ok(b"hel".to_vec()));
+        assert_eq!(vfs.read(&mut file, 10), Ok(b"lo".to_vec()));
+        assert_eq!(vfs.read(&mut file, 10), Ok(Vec::new()));
+        assert_eq!(vfs.write(&mut file, b"x"), Err(EBADF));
+        assert_eq!(vfs.seek(&mut file, -2, SEEK_END), Ok(3));
+        assert_eq!(vfs.seek(&mut file, -4, SEEK_CUR), Err(EINVAL));
+
+        // Writes past the end leave a zero filled hole
+        let mut file = vfs.open("/data/input.txt", O_WRONLY).unwrap();
+        assert_eq!(vfs.seek(&mut file, 7, SEEK_SET), Ok(7));
+        assert_eq!(vfs.write(&mut file, b"!"), Ok(1));
+        assert_eq!(vfs.file("/data/input.txt"), Some(&b"hello\0\0!"[..]));
+
+        let mut file = vfs.open("/out.log", O_WRONLY | O_CREAT | O_APPEND).unwrap();
+        vfs.write(&mut file, b"a").unwrap();
+        let mut other = vfs.open("/out.log", O_RDWR | O_APPEND).unwrap();
+        vfs.write(&mut other, b"b").unwrap();
+        vfs.write(&mut file, b"c").unwrap();
+        assert_eq!(vfs.file("/out.log"), Some(&b"abc"[..]));
+
+        assert_eq!(
+            vfs.open("/out.log", O_WRONLY | O_CREAT | O_EXCL),
+            Err(EEXIST)
+        );
+        vfs.open("/out.log", O_WRONLY | O_TRUNC).unwrap();
+        assert_eq!(vfs.file("/out.log"), Some(&b""[..]));
+        assert_eq!(vfs.open("/missing", O_RDONLY), Err(ENOENT));
+        assert_eq!(vfs.open("/data", O_RDONLY), Err(EISDIR));
+        assert_eq!(vfs.open("/", O_RDONLY), Err(EISDIR));
+    }
+
+    #[test]
+    fn test_mapped_dirs() {
+        let host = std::env::temp_dir().join(format!("vfs-test-{}", std::process::id()));
+        std::fs::create_dir_all(host.join("nested")).unwrap();
+        std::fs::write(host.join("nested/fixture.txt"), "fixture").unwrap();
+        let secret = host.with_extension("secret");
+        std::fs::write(&secret, "secret").unwrap();
+
+        let mut vfs = Vfs::new();
+        assert!(vfs.map_dir(host.join("nested/fixture.txt"), "/f").is_err());
+        vfs.map_dir(&host, "/fixtures").unwrap();
+
+        let mut file = vfs.open("/fixtures/nested/fixture.txt", O_RDONLY).unwrap();
+        assert_eq!(vfs.size(&file), 7);
+        assert_eq!(vfs.read(&mut file, 100), Ok(b"fixture".to_vec()));
+        assert_eq!(vfs.write(&mut file, b"x"), Err(EBADF));
+
+        // Read-only, and nothing outside of the directory
+        assert_eq!(vfs.open("/fixtures/nested/fixture.txt", O_RDWR), Err(EROFS));
+        assert_eq!(
+            vfs.open("/fixtures/new.txt", O_WRONLY | O_CREAT),
+            Err(EROFS)
+        );
+        assert_eq!(vfs.open("/fixtures/nested", O_RDONLY), Err(EISDIR));
+        assert_eq!(vfs.open("/fixtures/missing", O_RDONLY), Err(ENOENT));
+        let escape = format!("/fixtures/../{}", secret.display());
+        assert_eq!(vfs.open(&escape, O_RDONLY), Err(ENOENT));
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&secret, host.join("link")).unwrap();
+            assert_eq!(vfs.open("/fixtures/link", O_RDONLY), Err(ENOENT));
+        }
+
+        // In-memory files shadow the directory
+        vfs.add_file("/fixtures/nested/fixture.txt", "memory");
+        let mut file = vfs.open("/fixtures/nested/fixture.txt", O_RDONLY).unwrap();
+        assert_eq!(vfs.read(&mut file, 100), Ok(b"memory".to_vec()));
+
+        std::fs::remove_dir_all(&host).unwrap();
+        std::fs::remove_file(&secret).unwrap();
+    }
+}
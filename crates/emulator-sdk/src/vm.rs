@@ -1,16 +1,35 @@
 //! This mod holds all the necessary structs and functions to emulate a RISC-V CPU.
 use crate::{
-    instructions::InstructionDecoder,
+    atomics::process_atomic,
+    block::InstructionCache,
+    compressed::is_compressed,
+    csr::{process_csr, CsrFile, MCAUSE, MEPC, MSTATUS, MTVAL},
+    htif::Htif,
+    instructions::{DecodedInstruction, InstructionDecoder, MiscMemOp, ATOMIC_CLASS},
+    observer::ExecutionObserver,
+    segment::touch_pages,
+    syscalls::{
+        DefaultSyscallHandler, DetachedSyscallHandler, SyscallContext, SyscallHandler,
+        SyscallOutcome, SYSCALL_NUMBER_REGISTER,
+    },
+    trace::{
+        chunk_bytes, AccessKind, CsrAccess, DecodedFields, MemoryAccess, RegisterAccess, Trace,
+    },
+    trap::{Exception, Trap},
     utils::{process_load_to_reg, process_store_to_memory},
 };
-use core::{interfaces::MemoryInterface, sign_extend_u32, Memory, MemoryChuckSize, Registers};
+use core::{
+    interfaces::MemoryInterface, sign_extend_u32, Memory, MemoryChuckSize, Registers, PAGE_SIZE,
+};
 use elf_parser::Elf;
 use std::{
+    any::Any,
+    collections::HashSet,
     fs::File,
     io::{BufReader, Read},
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum VMErrors {
     InvalidInstruction,
     InvalidMemoryAccess,
@@ -21,6 +40,77 @@ pub enum VMErrors {
     MemoryStoreError,
     InvalidFunct7(u32),
     InvalidFunct3(u32),
+    InvalidSyscall(u32),
+    SerializationError,
+    ConsistencyError {
+        addr: u32,
+        timestamp: u64,
+    },
+    LoadAddressMisaligned(u32),
+    /// Raised by misaligned stores, SC.W and AMOs
+    StoreAddressMisaligned(u32),
+    /// Access to a CSR that does not exist, or a write to a read-only one
+    InvalidCsr(u32),
+    InstructionAddressMisaligned(u32),
+    InstructionAccessFault(u32),
+    LoadAccessFault(u32),
+    StoreAccessFault(u32),
+    /// An ebreak at this pc that does not halt the VM, see [RunConfig::halt_on_ebreak]
+    Breakpoint(u32),
+    /// A snapshot written by another version of the snapshot format
+    UnsupportedSnapshotVersion(u32),
+    /// The execution stopped at this pc before the guest exited
+    Unfinished(u32),
+}
+
+/// The conditions [Vm::run] halts on, besides the guest exiting or faulting.
+#[derive(Debug, Clone)]
+pub struct RunConfig {
+    /// Stop after retiring this many instructions
+    pub max_instructions: Option<u64>,
+    /// Stop after this many cycles, trapped instructions take a cycle without retiring
+    pub max_cycles: Option<u64>,
+    /// Stop before executing the instruction at any of these pcs
+    pub breakpoints: HashSet<u32>,
+    /// Stop on ebreak, otherwise it raises a breakpoint exception for the guest's trap handler
+    pub halt_on_ebreak: bool,
+    /// Stop on an instruction jumping to itself, which would otherwise spin forever
+    pub detect_infinite_loops: bool,
+    /// Execute cached basic blocks, see [crate::block]. Single stepping is used regardless while
+    /// tracing, with observers attached or with breakpoints set
+    pub cache_blocks: bool,
+    /// Compile hot blocks to host code, off by default. This has no effect unless built with the
+    /// `jit` feature
+    pub jit: bool,
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        Self {
+            max_instructions: None,
+            max_cycles: None,
+            breakpoints: HashSet::new(),
+            halt_on_ebreak: true,
+            detect_infinite_loops: false,
+            cache_blocks: true,
+            jit: false,
+        }
+    }
+}
+
+/// Why [Vm::run] returned.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunOutcome {
+    /// The guest exited with this code
+    Exited(u32),
+    /// Execution stopped on an error that was not delivered to a trap handler
+    Faulted { pc: u32, error: VMErrors },
+    /// The instruction or cycle budget ran out
+    BudgetExhausted,
+    /// Execution stopped on an ebreak or a breakpoint at this pc
+    Breakpoint(u32),
+    /// The instruction at this pc jumps to itself
+    InfiniteLoop(u32),
 }
 
 #[derive(Debug, Clone)]
@@ -30,6 +120,28 @@ pub struct Vm {
     pub pc: u32,
     pub running: bool,
     pub exit_code: u32,
+    pub csrs: CsrFile,
+    pub syscall_handler: Box<dyn SyscallHandler>,
+    /// The execution trace, only recorded when enabled
+    pub trace: Option<Trace>,
+    /// The word address reserved by the last LR.W, see [crate::atomics]
+    pub reservation: Option<u32>,
+    /// Whether ebreak halts the VM, see [RunConfig::halt_on_ebreak]
+    pub halt_on_ebreak: bool,
+    /// The observers watching the execution, see [crate::observer]
+    pub observers: Vec<Box<dyn ExecutionObserver>>,
+    /// The decoded basic blocks, see [crate::block]
+    pub instruction_cache: InstructionCache,
+    /// The pages accessed since tracking them started, see [crate::segment]
+    pub touched_pages: Option<hashbrown::HashSet<u32>>,
+    /// The host-target interface of bare-metal programs, see [crate::htif]
+    pub htif: Option<Htif>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Vm {
@@ -41,6 +153,15 @@ impl Vm {
             pc: 0,
             running: false,
             exit_code: 0,
+            csrs: CsrFile::new(),
+            syscall_handler: Box::new(DefaultSyscallHandler::new()),
+            trace: None,
+            reservation: None,
+            halt_on_ebreak: true,
+            observers: Vec::new(),
+            instruction_cache: InstructionCache::new(),
+            touched_pages: None,
+            htif: None,
         }
     }
 
@@ -54,16 +175,34 @@ impl Vm {
 
         let program_elf_decoded = Elf::decode(&buf)?;
 
-        Ok(Self {
+        Ok(Self::from_elf(&program_elf_decoded))
+    }
+
+    /// Create a new Vm running a decoded ELF file.
+    pub fn from_elf(elf: &Elf) -> Self {
+        // Load every segment (text, data, rodata and the zero filled bss) at its address
+        let mut memory = Memory::new();
+        memory.load_image(elf.memory_image.iter().map(|(addr, word)| (*addr, *word)));
+        for segment in &elf.segments {
+            memory.add_region(segment.clone());
+        }
+
+        Self {
             registers: Registers::new(),
-            memory: Memory::new_with_load_program(
-                &program_elf_decoded.instructions,
-                program_elf_decoded.pc_base,
-            ),
-            pc: program_elf_decoded.pc_start,
+            memory,
+            pc: elf.pc_start,
             running: false,
             exit_code: 0,
-        })
+            csrs: CsrFile::new(),
+            syscall_handler: Box::new(DefaultSyscallHandler::new()),
+            trace: None,
+            reservation: None,
+            halt_on_ebreak: true,
+            observers: Vec::new(),
+            instruction_cache: InstructionCache::new(),
+            touched_pages: None,
+            htif: Htif::from_elf(elf),
+        }
     }
 
     pub fn from_bin(instructions: Vec<u32>) -> Result<Self, anyhow::Error> {
@@ -73,71 +212,438 @@ impl Vm {
             pc: 0,
             running: false,
             exit_code: 0,
+            csrs: CsrFile::new(),
+            syscall_handler: Box::new(DefaultSyscallHandler::new()),
+            trace: None,
+            reservation: None,
+            halt_on_ebreak: true,
+            observers: Vec::new(),
+            instruction_cache: InstructionCache::new(),
+            touched_pages: None,
+            htif: None,
         })
     }
 
+    /// Replace the handler servicing the guest's `ecall`s.
+    pub fn set_syscall_handler(&mut self, handler: impl SyscallHandler + 'static) {
+        self.syscall_handler = Box::new(handler);
+    }
+
+    /// Start recording an execution trace, see [Trace].
+    pub fn enable_trace(&mut self) {
+        self.trace = Some(Trace::new());
+    }
+
+    /// Stop recording and return the execution trace recorded so far.
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+
+    /// Attach an observer, it is called after the observers attached before it.
+    pub fn add_observer(&mut self, observer: impl ExecutionObserver) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Returns the first attached observer of type `T`.
+    pub fn observer<T: ExecutionObserver>(&self) -> Option<&T> {
+        self.observers
+            .iter()
+            .find_map(|observer| (observer.as_ref() as &dyn Any).downcast_ref())
+    }
+
+    /// Returns the first attached observer of type `T`, mutably.
+    pub fn observer_mut<T: ExecutionObserver>(&mut self) -> Option<&mut T> {
+        self.observers
+            .iter_mut()
+            .find_map(|observer| (observer.as_mut() as &mut dyn Any).downcast_mut())
+    }
+
+    /// Detach and return every observer.
+    pub fn take_observers(&mut self) -> Vec<Box<dyn ExecutionObserver>> {
+        std::mem::take(&mut self.observers)
+    }
+
+    /// Read a register, recording the access when tracing.
+    pub fn read_reg(&mut self, reg: u32) -> u32 {
+        let value = self.registers.read_reg(reg);
+
+        if let Some(trace) = &mut self.trace {
+            trace.record_register(RegisterAccess {
+                kind: AccessKind::Read,
+                register: reg as u8,
+                old_value: value,
+                new_value: value,
+            });
+        }
+
+        value
+    }
+
+    /// Write a register, recording the access when tracing and reporting it to the observers.
+    pub fn write_reg(&mut self, reg: u32, value: u32) {
+        if let Some(trace) = &mut self.trace {
+            if reg != 0 {
+                trace.record_register(RegisterAccess {
+                    kind: AccessKind::Write,
+                    register: reg as u8,
+                    old_value: self.registers.read_reg(reg),
+                    new_value: value,
+                });
+            }
+        }
+        if !self.observers.is_empty() && reg != 0 {
+            let old_value = self.registers.read_reg(reg);
+            for observer in &mut self.observers {
+                observer.on_register_write(reg, old_value, value);
+            }
+        }
+
+        self.registers.write_reg(reg, value);
+    }
+
+    /// Read memory, recording the access when tracing and reporting it to the observers.
+    pub fn read_mem(&mut self, addr: u32, size: MemoryChuckSize) -> Option<u32> {
+        let value = self.memory.read_mem(addr, size.clone())?;
+        self.touch(addr, chunk_bytes(&size) as u32);
+
+        if self.trace.is_some() || !self.observers.is_empty() {
+            self.record_memory(MemoryAccess {
+                kind: AccessKind::Read,
+                addr,
+                size: chunk_bytes(&size),
+                old_value: value,
+                new_value: value,
+            });
+        }
+
+        Some(value)
+    }
+
+    /// Write memory, recording the access when tracing and reporting it to the observers.
+    pub fn write_mem(&mut self, addr: u32, size: MemoryChuckSize, value: u32) -> bool {
+        let recording = self.trace.is_some() || !self.observers.is_empty();
+        let old_value = if recording {
+            self.memory.read_mem(addr, size.clone()).unwrap_or(0)
+        } else {
+            0
+        };
+
+        if !self.memory.write_mem(addr, size.clone(), value) {
+            return false;
+        }
+        self.touch(addr, chunk_bytes(&size) as u32);
+
+        // A store to the reserved word breaks the reservation of a pending SC.W
+        if self.reservation == Some(addr & !0x3) {
+            self.reservation = None;
+        }
+        // Self-modifying code, the decoded instructions may be stale
+        self.instruction_cache
+            .invalidate(addr, chunk_bytes(&size) as u32);
+
+        if recording {
+            let mask = match size {
+                MemoryChuckSize::BYTE => 0xff,
+                MemoryChuckSize::HalfWord => 0xffff,
+                MemoryChuckSize::WordSize => 0xffff_ffff,
+            };
+            self.record_memory(MemoryAccess {
+                kind: AccessKind::Write,
+                addr,
+                size: chunk_bytes(&size),
+                old_value,
+                new_value: value & mask,
+            });
+        }
+        // The host answers a command with stores of its own, writing zero never completes one
+        if let Some(htif) = &mut self.htif {
+            for (addr, value) in htif.write(&self.memory, addr, chunk_bytes(&size) as u32) {
+                self.write_mem(addr, MemoryChuckSize::WordSize, value);
+            }
+        }
+
+        true
+    }
+
+    /// Drop the pages between `start` and `end`, both page aligned, they read as zeros afterwards.
+    /// Like a store, this touches the pages and drops the instructions decoded from them.
+    pub fn unmap(&mut self, start: u32, end: u32) {
+        let pages: Vec<u32> = self
+            .memory
+            .pages
+            .range(start / PAGE_SIZE..end.div_ceil(PAGE_SIZE))
+            .map(|(page, _)| *page)
+            .collect();
+        for page in pages {
+            self.memory.pages.remove(&page);
+            self.touch(page * PAGE_SIZE, PAGE_SIZE);
+            self.instruction_cache
+                .invalidate(page * PAGE_SIZE, PAGE_SIZE);
+        }
+        if self
+            .reservation
+            .is_some_and(|addr| (start..end).contains(&addr))
+        {
+            self.reservation = None;
+        }
+    }
+
+    /// Read a CSR, recording the access when tracing.
+    pub fn read_csr(&mut self, addr: u32) -> Result<u32, VMErrors> {
+        let value = self.csrs.read(addr)?;
+        self.record_csr(AccessKind::Read, addr, value, value);
+
+        Ok(value)
+    }
+
+    /// Write a CSR, recording the access when tracing.
+    /// The recorded value is the one the CSR holds afterwards, see [CsrFile::write].
+    pub fn write_csr(&mut self, addr: u32, value: u32) -> Result<(), VMErrors> {
+        let old_value = self.csrs.read(addr)?;
+        self.csrs.write(addr, value)?;
+        if self.trace.is_some() {
+            let new_value = self.csrs.read(addr)?;
+            self.record_csr(AccessKind::Write, addr, old_value, new_value);
+        }
+
+        Ok(())
+    }
+
+    fn record_csr(&mut self, kind: AccessKind, addr: u32, old_value: u32, new_value: u32) {
+        if let Some(trace) = &mut self.trace {
+            trace.record_csr(CsrAccess {
+                kind,
+                csr: addr as u16,
+                old_value,
+                new_value,
+            });
+        }
+    }
+
+    /// Record the writes to the trap CSRs since they held `before`, when tracing.
+    fn record_trap_csrs(&mut self, before: &CsrFile) {
+        if self.trace.is_none() {
+            return;
+        }
+        for (addr, old_value, new_value) in [
+            (MEPC, before.mepc, self.csrs.mepc),
+            (MCAUSE, before.mcause, self.csrs.mcause),
+            (MTVAL, before.mtval, self.csrs.mtval),
+            (MSTATUS, before.mstatus, self.csrs.mstatus),
+        ] {
+            self.record_csr(AccessKind::Write, addr, old_value, new_value);
+        }
+    }
+
+    /// Report a memory access to the observers and record it in the trace.
+    fn record_memory(&mut self, access: MemoryAccess) {
+        for observer in &mut self.observers {
+            observer.on_memory_access(&access);
+        }
+        if let Some(trace) = &mut self.trace {
+            trace.record_memory(access);
+        }
+    }
+
+    /// Record the pages of the `size` bytes at `addr` as touched, when tracking them.
+    #[inline(always)]
+    pub(crate) fn touch(&mut self, addr: u32, size: u32) {
+        if let Some(pages) = &mut self.touched_pages {
+            touch_pages(pages, addr, size);
+        }
+    }
+
+    /// Fetch 16 bits of an instruction from executable memory.
+    fn fetch_half_word(&self, addr: u32) -> Result<u32, VMErrors> {
+        if !addr.is_multiple_of(2) {
+            return Err(VMErrors::InstructionAddressMisaligned(addr));
+        }
+        if !self.memory.is_executable(addr) || !self.memory.is_executable(addr + 1) {
+            return Err(VMErrors::InstructionAccessFault(addr));
+        }
+
+        self.memory
+            .read_mem(addr, MemoryChuckSize::HalfWord)
+            .ok_or(VMErrors::InstructionAccessFault(addr))
+    }
+
+    /// Fetch the instruction at `pc`, 16 bits at a time since instructions
+    /// are only aligned to 2 bytes when compressed instructions are present.
+    pub(crate) fn fetch(&self, pc: u32) -> Result<u32, VMErrors> {
+        let instruction = self.fetch_half_word(pc)?;
+
+        if is_compressed(instruction) {
+            Ok(instruction)
+        } else {
+            Ok(instruction | (self.fetch_half_word(pc.wrapping_add(2))? << 16))
+        }
+    }
+
+    /// Deliver the exception `error` raised by `instruction` to the guest's trap handler,
+    /// completing the row of the instruction when tracing.
+    /// The error is returned as is when it is not an exception or no trap handler is installed.
+    fn raise(&mut self, error: VMErrors, instruction: u32) -> Result<bool, VMErrors> {
+        let trap = match self.deliverable(&error, instruction) {
+            Some(trap) => trap,
+            None => {
+                if let Some(trace) = &mut self.trace {
+                    trace.abort_row();
+                }
+                return Err(error);
+            }
+        };
+
+        for observer in &mut self.observers {
+            observer.on_trap(self.pc, &trap);
+        }
+
+        // The trapped instruction takes a cycle but does not retire
+        let cause = trap.exception as u32;
+        let before = self.csrs.clone();
+        self.csrs.cycle += 1;
+        self.pc = self.csrs.enter_trap(self.pc, cause, trap.tval);
+        self.record_trap_csrs(&before);
+        if let Some(trace) = &mut self.trace {
+            trace.trap_row(cause, self.pc);
+        }
+        Ok(true)
+    }
+
+    /// Returns the trap `error` raises, if it is an exception the guest's trap handler can take.
+    fn deliverable(&self, error: &VMErrors, instruction: u32) -> Option<Trap> {
+        let trap = Trap::from_error(error, instruction).filter(|_| self.csrs.mtvec != 0)?;
+
+        // A handler that can not be fetched would trap forever
+        let fetch_fault = matches!(
+            trap.exception,
+            Exception::InstructionAddressMisaligned | Exception::InstructionAccessFault
+        );
+        if fetch_fault && self.pc == self.csrs.trap_target(trap.exception as u32) {
+            return None;
+        }
+
+        Some(trap)
+    }
+
     /// Step the Vm.
     /// This function will execute the instruction at the current program counter.
     /// If the instruction is a branch, the program counter will be updated accordingly.
     /// If the instruction is a jump, the program counter will be updated accordingly.
-    /// If the instruction is a syscall, the program will be halted.
-    /// If the instruction is a halt, the program will be halted.
-    pub fn step(&mut self, debug_mode: bool) -> Result<bool, VMErrors> {
+    /// If the instruction is a syscall, it is dispatched to the registered syscall handler.
+    /// If the instruction is an ebreak, the program will be halted with the pc on the ebreak.
+    /// Returns `Ok(false)` once the program has halted.
+    pub fn step(&mut self) -> Result<bool, VMErrors> {
         // Fetch the instruction from memory
-        let instruction = self
-            .memory
-            .read_mem(self.pc, MemoryChuckSize::WordSize)
-            .ok_or(VMErrors::InvalidMemoryAccess)?;
+        let instruction = match self.fetch(self.pc) {
+            Ok(instruction) => instruction,
+            Err(e) => {
+                if let Some(trace) = &mut self.trace {
+                    trace.begin_row(self.pc, 0, DecodedFields::default());
+                }
+                return self.raise(e, 0);
+            }
+        };
+        self.touch(self.pc, if is_compressed(instruction) { 2 } else { 4 });
 
         // Decode the instruction
-        let decoded_instruction = InstructionDecoder::decode(&instruction)?;
+        let decoded_instruction = match InstructionDecoder::decode(&instruction) {
+            Ok(decoded_instruction) => decoded_instruction,
+            Err(e) => {
+                if let Some(trace) = &mut self.trace {
+                    trace.begin_row(self.pc, instruction, DecodedFields::default());
+                }
+                return self.raise(e, instruction);
+            }
+        };
 
-        println!(
-            "This is the instruction: {:?} - {} - {:?}",
-            instruction,
-            self.pc,
-            decoded_instruction.to_string()
-        );
+        let pc = self.pc;
+        for observer in &mut self.observers {
+            observer.before_instruction(pc, instruction, &decoded_instruction);
+        }
 
-        // if debug_mode {
-        //     println!("{}", decoded_instruction.to_string());
-        // }
+        if let Some(trace) = &mut self.trace {
+            trace.begin_row(self.pc, instruction, (&decoded_instruction).into());
+        }
 
         // Execute the instruction
+        let result = self.execute(decoded_instruction);
+        // The instruction may have sent the exit command to the host, it does not retire then
+        let result = match result {
+            Ok(true) => match self.htif.as_mut().and_then(Htif::take_exit) {
+                Some(code) => {
+                    self.exit_code = code;
+                    self.running = false;
+                    Ok(false)
+                }
+                None => Ok(true),
+            },
+            result => result,
+        };
+
+        // The row of an instruction raising an exception is completed when it is raised
+        if let (Some(trace), Ok(_)) = (&mut self.trace, &result) {
+            trace.end_row(self.pc);
+        }
+        if result.is_ok() {
+            for observer in &mut self.observers {
+                observer.after_instruction(pc, self.pc);
+            }
+        }
+
+        match result {
+            Ok(true) => {
+                self.csrs.retire();
+                Ok(true)
+            }
+            // Instructions halting the VM do not retire
+            Ok(false) => Ok(false),
+            Err(e) => self.raise(e, instruction),
+        }
+    }
+
+    /// Execute a decoded instruction, updating the registers, memory and program counter.
+    fn execute(&mut self, decoded_instruction: InstructionDecoder) -> Result<bool, VMErrors> {
+        let size = decoded_instruction.size;
+
         match decoded_instruction.decoded_instruction {
-            crate::instructions::DecodedInstruction::RType(rtype) => {
+            DecodedInstruction::RType(rtype) if decoded_instruction.opcode == ATOMIC_CLASS => {
+                process_atomic(self, &rtype)?;
+                self.pc = self.pc.wrapping_add(size);
+                Ok(true)
+            }
+            DecodedInstruction::RType(rtype) => {
                 match rtype.funct3 {
                     0b000 => {
                         // Funct3 for add, sub, mul
                         match rtype.funct7 {
                             0b0000000 => {
                                 // Funct7 for add
-                                let rs1 = self.registers.read_reg(rtype.rs1 as u32);
-                                let rs2 = self.registers.read_reg(rtype.rs2 as u32);
+                                let rs1 = self.read_reg(rtype.rs1 as u32);
+                                let rs2 = self.read_reg(rtype.rs2 as u32);
                                 let rd = rs1.wrapping_add(rs2);
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             0b0100000 => {
                                 // Funct7 for sub
-                                let rs1 = self.registers.read_reg(rtype.rs1 as u32);
-                                let rs2 = self.registers.read_reg(rtype.rs2 as u32);
+                                let rs1 = self.read_reg(rtype.rs1 as u32);
+                                let rs2 = self.read_reg(rtype.rs2 as u32);
                                 let rd = rs1.wrapping_sub(rs2);
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             0b0000001 => {
                                 // Funct7 for mul
-                                let rs1 = self.registers.read_reg(rtype.rs1 as u32);
-                                let rs2 = self.registers.read_reg(rtype.rs2 as u32);
+                                let rs1 = self.read_reg(rtype.rs1 as u32);
+                                let rs2 = self.read_reg(rtype.rs2 as u32);
                                 let rd = rs1.wrapping_mul(rs2);
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
-                            _ => return Err(VMErrors::InvalidFunct7(rtype.funct7)),
+                            _ => Err(VMErrors::InvalidFunct7(rtype.funct7)),
                         }
                     }
                     0b001 => {
@@ -145,25 +651,23 @@ impl Vm {
                         match rtype.funct7 {
                             0b0000000 => {
                                 // Funct7 for sll
-                                let rs1 = self.registers.read_reg(rtype.rs1 as u32);
-                                let rs2 = self.registers.read_reg(rtype.rs2 as u32);
+                                let rs1 = self.read_reg(rtype.rs1 as u32);
+                                let rs2 = self.read_reg(rtype.rs2 as u32);
                                 let rd = rs1.wrapping_shl(rs2);
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             0b0000001 => {
                                 // Funct7 for mulh
-                                let rs1 =
-                                    sign_extend_u32(self.registers.read_reg(rtype.rs1 as u32));
-                                let rs2 =
-                                    sign_extend_u32(self.registers.read_reg(rtype.rs2 as u32));
+                                let rs1 = sign_extend_u32(self.read_reg(rtype.rs1 as u32));
+                                let rs2 = sign_extend_u32(self.read_reg(rtype.rs2 as u32));
                                 let rd = (rs1.wrapping_mul(rs2) >> 32) as u32;
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
-                            _ => return Err(VMErrors::InvalidFunct7(rtype.funct7)),
+                            _ => Err(VMErrors::InvalidFunct7(rtype.funct7)),
                         }
                     }
                     0b010 => {
@@ -171,24 +675,23 @@ impl Vm {
                         match rtype.funct7 {
                             0b0000000 => {
                                 // Funct7 for slt
-                                let rs1 = self.registers.read_reg(rtype.rs1 as u32) as i32;
-                                let rs2 = self.registers.read_reg(rtype.rs2 as u32) as i32;
+                                let rs1 = self.read_reg(rtype.rs1 as u32) as i32;
+                                let rs2 = self.read_reg(rtype.rs2 as u32) as i32;
                                 let rd = if rs1 < rs2 { 1 } else { 0 };
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             0b0000001 => {
                                 // Funct7 for mulhsu
-                                let rs1 =
-                                    sign_extend_u32(self.registers.read_reg(rtype.rs1 as u32));
-                                let rs2 = self.registers.read_reg(rtype.rs2 as u32) as i64;
+                                let rs1 = sign_extend_u32(self.read_reg(rtype.rs1 as u32));
+                                let rs2 = self.read_reg(rtype.rs2 as u32) as i64;
                                 let rd = (rs1.wrapping_mul(rs2) >> 32) as u32;
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
-                            _ => return Err(VMErrors::InvalidFunct7(rtype.funct7)),
+                            _ => Err(VMErrors::InvalidFunct7(rtype.funct7)),
                         }
                     }
                     0b011 => {
@@ -196,23 +699,23 @@ impl Vm {
                         match rtype.funct7 {
                             0b0000000 => {
                                 // Funct7 for sltu
-                                let rs1 = self.registers.read_reg(rtype.rs1 as u32);
-                                let rs2 = self.registers.read_reg(rtype.rs2 as u32);
+                                let rs1 = self.read_reg(rtype.rs1 as u32);
+                                let rs2 = self.read_reg(rtype.rs2 as u32);
                                 let rd = if rs1 < rs2 { 1 } else { 0 };
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             0b0000001 => {
                                 // Funct7 for mulhu
-                                let rs1 = self.registers.read_reg(rtype.rs1 as u32) as u64;
-                                let rs2 = self.registers.read_reg(rtype.rs2 as u32) as u64;
+                                let rs1 = self.read_reg(rtype.rs1 as u32) as u64;
+                                let rs2 = self.read_reg(rtype.rs2 as u32) as u64;
                                 let rd = (rs1.wrapping_mul(rs2) >> 32) as u32;
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
-                            _ => return Err(VMErrors::InvalidFunct7(rtype.funct7)),
+                            _ => Err(VMErrors::InvalidFunct7(rtype.funct7)),
                         }
                     }
                     0b100 => {
@@ -220,27 +723,27 @@ impl Vm {
                         match rtype.funct7 {
                             0b0000000 => {
                                 // Funct7 for xor
-                                let rs1 = self.registers.read_reg(rtype.rs1 as u32);
-                                let rs2 = self.registers.read_reg(rtype.rs2 as u32);
+                                let rs1 = self.read_reg(rtype.rs1 as u32);
+                                let rs2 = self.read_reg(rtype.rs2 as u32);
                                 let rd = rs1 ^ rs2;
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             0b0000001 => {
                                 // Funct7 for div
-                                let rs1 = self.registers.read_reg(rtype.rs1 as u32) as i32;
-                                let rs2 = self.registers.read_reg(rtype.rs2 as u32) as i32;
+                                let rs1 = self.read_reg(rtype.rs1 as u32) as i32;
+                                let rs2 = self.read_reg(rtype.rs2 as u32) as i32;
                                 let rd = if rs2 != 0 {
                                     rs1.wrapping_div(rs2) as u32
                                 } else {
                                     u32::MAX
                                 };
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
-                            _ => return Err(VMErrors::InvalidFunct7(rtype.funct7)),
+                            _ => Err(VMErrors::InvalidFunct7(rtype.funct7)),
                         }
                     }
                     0b101 => {
@@ -248,32 +751,32 @@ impl Vm {
                         match rtype.funct7 {
                             0b0000000 => {
                                 // Funct7 for srl
-                                let rs1 = self.registers.read_reg(rtype.rs1 as u32);
-                                let rs2 = self.registers.read_reg(rtype.rs2 as u32);
+                                let rs1 = self.read_reg(rtype.rs1 as u32);
+                                let rs2 = self.read_reg(rtype.rs2 as u32);
                                 let rd = rs1.wrapping_shr(rs2);
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             0b0000001 => {
                                 // Funct7 for divu
-                                let rs1 = self.registers.read_reg(rtype.rs1 as u32);
-                                let rs2 = self.registers.read_reg(rtype.rs2 as u32);
-                                let rd = if rs2 != 0 { rs1 / rs2 } else { u32::MAX };
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                let rs1 = self.read_reg(rtype.rs1 as u32);
+                                let rs2 = self.read_reg(rtype.rs2 as u32);
+                                let rd = rs1.checked_div(rs2).unwrap_or(u32::MAX);
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             0b0100000 => {
                                 // Funct7 for sra
-                                let rs1 = self.registers.read_reg(rtype.rs1 as u32) as i32;
-                                let rs2 = self.registers.read_reg(rtype.rs2 as u32) as u32;
+                                let rs1 = self.read_reg(rtype.rs1 as u32) as i32;
+                                let rs2 = self.read_reg(rtype.rs2 as u32);
                                 let rd = rs1.wrapping_shr(rs2) as u32;
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
-                            _ => return Err(VMErrors::InvalidFunct7(rtype.funct7)),
+                            _ => Err(VMErrors::InvalidFunct7(rtype.funct7)),
                         }
                     }
                     0b110 => {
@@ -281,27 +784,27 @@ impl Vm {
                         match rtype.funct7 {
                             0b0000000 => {
                                 // Funct7 for or
-                                let rs1 = self.registers.read_reg(rtype.rs1 as u32);
-                                let rs2 = self.registers.read_reg(rtype.rs2 as u32);
+                                let rs1 = self.read_reg(rtype.rs1 as u32);
+                                let rs2 = self.read_reg(rtype.rs2 as u32);
                                 let rd = rs1 | rs2;
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             0b0000001 => {
                                 // Funct7 for rem
-                                let rs1 = self.registers.read_reg(rtype.rs1 as u32) as i32;
-                                let rs2 = self.registers.read_reg(rtype.rs2 as u32) as i32;
+                                let rs1 = self.read_reg(rtype.rs1 as u32) as i32;
+                                let rs2 = self.read_reg(rtype.rs2 as u32) as i32;
                                 let rd = if rs2 != 0 {
                                     rs1.wrapping_rem(rs2) as u32
                                 } else {
                                     rs1 as u32
                                 };
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
-                            _ => return Err(VMErrors::InvalidFunct7(rtype.funct7)),
+                            _ => Err(VMErrors::InvalidFunct7(rtype.funct7)),
                         }
                     }
                     0b111 => {
@@ -309,76 +812,79 @@ impl Vm {
                         match rtype.funct7 {
                             0b0000000 => {
                                 // Funct7 for and
-                                let rs1 = self.registers.read_reg(rtype.rs1 as u32);
-                                let rs2 = self.registers.read_reg(rtype.rs2 as u32);
+                                let rs1 = self.read_reg(rtype.rs1 as u32);
+                                let rs2 = self.read_reg(rtype.rs2 as u32);
                                 let rd = rs1 & rs2;
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             0b0000001 => {
                                 // Funct7 for remu
-                                let rs1 = self.registers.read_reg(rtype.rs1 as u32);
-                                let rs2 = self.registers.read_reg(rtype.rs2 as u32);
+                                let rs1 = self.read_reg(rtype.rs1 as u32);
+                                let rs2 = self.read_reg(rtype.rs2 as u32);
                                 let rd = if rs2 != 0 { rs1 % rs2 } else { rs1 };
-                                self.registers.write_reg(rtype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(rtype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
-                            _ => return Err(VMErrors::InvalidFunct7(rtype.funct7)),
+                            _ => Err(VMErrors::InvalidFunct7(rtype.funct7)),
                         }
                     }
-                    _ => return Err(VMErrors::InvalidFunct3(rtype.funct3)),
+                    _ => Err(VMErrors::InvalidFunct3(rtype.funct3)),
                 }
             }
-            crate::instructions::DecodedInstruction::IType(itype) => {
+            DecodedInstruction::IType(itype) => {
                 match decoded_instruction.opcode {
                     0b0010011 => {
                         // Funct3 for addi, slti, sltiu, xori, ori, andi
                         match itype.funct3 {
                             0b000 => {
                                 // Funct3 for addi
-                                let rs1 = self.registers.read_reg(itype.rs1 as u32);
+                                let rs1 = self.read_reg(itype.rs1 as u32);
                                 let imm = itype.imm as u32;
                                 let rd = rs1.wrapping_add(imm);
-                                self.registers.write_reg(itype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(itype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             0b001 => {
-                                // Funct3 for slli
-                                let rs1 = self.registers.read_reg(itype.rs1 as u32);
+                                // Funct3 for slli, a shift amount of 32 or more is illegal on RV32
+                                if itype.metadata.funct7 != 0 {
+                                    return Err(VMErrors::InvalidFunct7(itype.metadata.funct7));
+                                }
+                                let rs1 = self.read_reg(itype.rs1 as u32);
                                 let imm = itype.metadata.imm_shift_amt;
                                 let rd = rs1.wrapping_shl(imm);
-                                self.registers.write_reg(itype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(itype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             0b010 => {
                                 // Funct3 for slti
-                                let rs1 = self.registers.read_reg(itype.rs1 as u32) as i32;
-                                let imm = itype.imm as i32;
+                                let rs1 = self.read_reg(itype.rs1 as u32) as i32;
+                                let imm = itype.imm;
                                 let rd = if rs1 < imm { 1 } else { 0 };
-                                self.registers.write_reg(itype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(itype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             0b011 => {
                                 // Funct3 for sltiu
-                                let rs1 = self.registers.read_reg(itype.rs1 as u32);
+                                let rs1 = self.read_reg(itype.rs1 as u32);
                                 let imm = itype.imm as u32;
                                 let rd = if rs1 < imm { 1 } else { 0 };
-                                self.registers.write_reg(itype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(itype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             0b100 => {
                                 // Funct3 for xori
-                                let rs1 = self.registers.read_reg(itype.rs1 as u32);
+                                let rs1 = self.read_reg(itype.rs1 as u32);
                                 let imm = itype.imm as u32;
                                 let rd = rs1 ^ imm;
-                                self.registers.write_reg(itype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(itype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             0b101 => {
@@ -386,46 +892,44 @@ impl Vm {
                                 match itype.metadata.funct7 {
                                     0b0000000 => {
                                         // Funct7 for srli
-                                        let rs1 = self.registers.read_reg(itype.rs1 as u32);
+                                        let rs1 = self.read_reg(itype.rs1 as u32);
                                         let imm = itype.metadata.imm_shift_amt;
                                         let rd = rs1.wrapping_shr(imm);
-                                        self.registers.write_reg(itype.rd as u32, rd);
-                                        self.pc += 4;
+                                        self.write_reg(itype.rd as u32, rd);
+                                        self.pc = self.pc.wrapping_add(size);
                                         Ok(true)
                                     }
                                     0b0100000 => {
                                         // Funct7 for srai
-                                        let rs1 = self.registers.read_reg(itype.rs1 as u32) as i32;
+                                        let rs1 = self.read_reg(itype.rs1 as u32) as i32;
                                         let imm = itype.metadata.imm_shift_amt;
                                         let rd = rs1.wrapping_shr(imm) as u32;
-                                        self.registers.write_reg(itype.rd as u32, rd);
-                                        self.pc += 4;
+                                        self.write_reg(itype.rd as u32, rd);
+                                        self.pc = self.pc.wrapping_add(size);
                                         Ok(true)
                                     }
-                                    _ => {
-                                        return Err(VMErrors::InvalidFunct7(itype.metadata.funct7))
-                                    }
+                                    _ => Err(VMErrors::InvalidFunct7(itype.metadata.funct7)),
                                 }
                             }
                             0b110 => {
                                 // Funct3 for ori
-                                let rs1 = self.registers.read_reg(itype.rs1 as u32);
+                                let rs1 = self.read_reg(itype.rs1 as u32);
                                 let imm = itype.imm as u32;
                                 let rd = rs1 | imm;
-                                self.registers.write_reg(itype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(itype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             0b111 => {
                                 // Funct3 for andi
-                                let rs1 = self.registers.read_reg(itype.rs1 as u32);
+                                let rs1 = self.read_reg(itype.rs1 as u32);
                                 let imm = itype.imm as u32;
                                 let rd = rs1 & imm;
-                                self.registers.write_reg(itype.rd as u32, rd);
-                                self.pc += 4;
+                                self.write_reg(itype.rd as u32, rd);
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
-                            _ => return Err(VMErrors::InvalidFunct3(itype.funct3)),
+                            _ => Err(VMErrors::InvalidFunct3(itype.funct3)),
                         }
                     }
                     0b0000011 => {
@@ -436,7 +940,7 @@ impl Vm {
                                 match process_load_to_reg(self, &itype, MemoryChuckSize::BYTE, true)
                                 {
                                     Ok(_) => {
-                                        self.pc += 4;
+                                        self.pc = self.pc.wrapping_add(size);
                                         Ok(true)
                                     }
                                     Err(e) => Err(e),
@@ -451,7 +955,7 @@ impl Vm {
                                     true,
                                 ) {
                                     Ok(_) => {
-                                        self.pc += 4;
+                                        self.pc = self.pc.wrapping_add(size);
                                         Ok(true)
                                     }
                                     Err(e) => Err(e),
@@ -466,7 +970,7 @@ impl Vm {
                                     false,
                                 ) {
                                     Ok(_) => {
-                                        self.pc += 4;
+                                        self.pc = self.pc.wrapping_add(size);
                                         Ok(true)
                                     }
                                     Err(e) => Err(e),
@@ -481,7 +985,7 @@ impl Vm {
                                     false,
                                 ) {
                                     Ok(_) => {
-                                        self.pc += 4;
+                                        self.pc = self.pc.wrapping_add(size);
                                         Ok(true)
                                     }
                                     Err(e) => Err(e),
@@ -496,13 +1000,30 @@ impl Vm {
                                     false,
                                 ) {
                                     Ok(_) => {
-                                        self.pc += 4;
+                                        self.pc = self.pc.wrapping_add(size);
                                         Ok(true)
                                     }
                                     Err(e) => Err(e),
                                 }
                             }
-                            _ => return Err(VMErrors::InvalidFunct3(itype.funct3)),
+                            _ => Err(VMErrors::InvalidFunct3(itype.funct3)),
+                        }
+                    }
+                    0b0001111 => {
+                        // Funct3 for fence, fence.i
+                        match MiscMemOp::new(&itype)? {
+                            MiscMemOp::Fence { .. } | MiscMemOp::FenceTso | MiscMemOp::Pause => {
+                                // There is a single hart and no device, so there is nothing to
+                                // order or to wait for
+                                self.pc = self.pc.wrapping_add(size);
+                                Ok(true)
+                            }
+                            MiscMemOp::FenceI => {
+                                // Stores become visible to instruction fetches
+                                self.instruction_cache.clear();
+                                self.pc = self.pc.wrapping_add(size);
+                                Ok(true)
+                            }
                         }
                     }
                     0b1100111 => {
@@ -510,30 +1031,97 @@ impl Vm {
                         match itype.funct3 {
                             0b000 => {
                                 // Funct3 for jalr
-                                let rs1 = self.registers.read_reg(itype.rs1 as u32);
+                                let rs1 = self.read_reg(itype.rs1 as u32);
                                 let imm = itype.imm as u32;
                                 let mut dest_addr = rs1.wrapping_add(imm);
 
                                 // see that dest_addr is even
                                 dest_addr &= 0xfffffffe;
-                                self.registers.write_reg(itype.rd as u32, self.pc + 4);
+                                self.write_reg(itype.rd as u32, self.pc.wrapping_add(size));
                                 self.pc = dest_addr;
                                 Ok(true)
                             }
-                            _ => return Err(VMErrors::InvalidFunct3(itype.funct3)),
+                            _ => Err(VMErrors::InvalidFunct3(itype.funct3)),
+                        }
+                    }
+                    0b1110011 => {
+                        // Funct3 for ecall, ebreak, mret, wfi and the CSR instructions
+                        match (itype.funct3, itype.imm) {
+                            (0b000, 0) if self.htif.is_some() => {
+                                // Bare-metal programs handle their own ecalls
+                                Err(VMErrors::EnvironmentError)
+                            }
+                            (0b000, 0) => {
+                                // Imm for ecall
+                                let number = self.read_reg(SYSCALL_NUMBER_REGISTER);
+                                let mut handler = std::mem::replace(
+                                    &mut self.syscall_handler,
+                                    Box::new(DetachedSyscallHandler),
+                                );
+                                let outcome =
+                                    handler.handle(number, &mut SyscallContext { vm: self });
+                                self.syscall_handler = handler;
+                                let outcome = outcome?;
+
+                                match outcome {
+                                    SyscallOutcome::Continue => {
+                                        self.pc = self.pc.wrapping_add(size);
+                                        Ok(true)
+                                    }
+                                    SyscallOutcome::Exit(code) => {
+                                        self.pc = self.pc.wrapping_add(size);
+                                        self.exit_code = code;
+                                        self.running = false;
+                                        Ok(false)
+                                    }
+                                    SyscallOutcome::Trap => Err(VMErrors::EnvironmentError),
+                                }
+                            }
+                            (0b000, 1) => {
+                                // Imm for ebreak
+                                if !self.halt_on_ebreak {
+                                    return Err(VMErrors::Breakpoint(self.pc));
+                                }
+                                self.running = false;
+                                Ok(false)
+                            }
+                            (0b000, 0x302) if itype.rs1 == 0 && itype.rd == 0 => {
+                                // Imm for mret
+                                let before = self.csrs.clone();
+                                self.pc = self.csrs.mret();
+                                self.record_csr(AccessKind::Read, MEPC, self.pc, self.pc);
+                                self.record_csr(
+                                    AccessKind::Write,
+                                    MSTATUS,
+                                    before.mstatus,
+                                    self.csrs.mstatus,
+                                );
+                                Ok(true)
+                            }
+                            (0b000, 0x105) if itype.rs1 == 0 && itype.rd == 0 => {
+                                // Imm for wfi, there are no interrupts to wait for
+                                self.pc = self.pc.wrapping_add(size);
+                                Ok(true)
+                            }
+                            (0b001..=0b011 | 0b101..=0b111, _) => {
+                                // Funct3 for csrrw, csrrs, csrrc, csrrwi, csrrsi, csrrci
+                                process_csr(self, &itype)?;
+                                self.pc = self.pc.wrapping_add(size);
+                                Ok(true)
+                            }
+                            _ => Err(VMErrors::InvalidFunct3(itype.funct3)),
                         }
                     }
-                    // not handling enviroment calls because it is halted during encoding
-                    _ => return Err(VMErrors::InvalidOpcode(decoded_instruction.opcode)),
+                    _ => Err(VMErrors::InvalidOpcode(decoded_instruction.opcode)),
                 }
             }
-            crate::instructions::DecodedInstruction::SType(stype) => {
+            DecodedInstruction::SType(stype) => {
                 match stype.funct3 {
                     0b000 => {
                         // Funct3 for sb
                         match process_store_to_memory(self, &stype, MemoryChuckSize::BYTE) {
                             Ok(_) => {
-                                self.pc += 4;
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             Err(e) => Err(e),
@@ -543,7 +1131,7 @@ impl Vm {
                         // Funct3 for sh
                         match process_store_to_memory(self, &stype, MemoryChuckSize::HalfWord) {
                             Ok(_) => {
-                                self.pc += 4;
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             Err(e) => Err(e),
@@ -553,159 +1141,374 @@ impl Vm {
                         // Funct3 for sw
                         match process_store_to_memory(self, &stype, MemoryChuckSize::WordSize) {
                             Ok(_) => {
-                                self.pc += 4;
+                                self.pc = self.pc.wrapping_add(size);
                                 Ok(true)
                             }
                             Err(e) => Err(e),
                         }
                     }
-                    _ => return Err(VMErrors::InvalidFunct3(stype.funct3)),
+                    _ => Err(VMErrors::InvalidFunct3(stype.funct3)),
                 }
             }
-            crate::instructions::DecodedInstruction::BType(btype) => {
+            DecodedInstruction::BType(btype) => {
                 match btype.funct3 {
                     0b000 => {
                         // Funct3 for beq
-                        let rs1 = self.registers.read_reg(btype.rs1 as u32);
-                        let rs2 = self.registers.read_reg(btype.rs2 as u32);
+                        let rs1 = self.read_reg(btype.rs1 as u32);
+                        let rs2 = self.read_reg(btype.rs2 as u32);
 
                         if rs1 == rs2 {
                             let target = self.pc.wrapping_add(btype.imm as u32);
                             self.pc = target;
                         } else {
-                            self.pc += 4;
+                            self.pc = self.pc.wrapping_add(size);
                         }
 
                         Ok(true)
                     }
                     0b001 => {
                         // Funct3 for bne
-                        let rs1 = self.registers.read_reg(btype.rs1 as u32);
-                        let rs2 = self.registers.read_reg(btype.rs2 as u32);
+                        let rs1 = self.read_reg(btype.rs1 as u32);
+                        let rs2 = self.read_reg(btype.rs2 as u32);
 
                         if rs1 != rs2 {
                             let target = self.pc.wrapping_add(btype.imm as u32);
                             self.pc = target;
                         } else {
-                            self.pc += 4;
+                            self.pc = self.pc.wrapping_add(size);
                         }
 
                         Ok(true)
                     }
                     0b100 => {
                         // Funct3 for blt
-                        let rs1 = self.registers.read_reg(btype.rs1 as u32) as i32;
-                        let rs2 = self.registers.read_reg(btype.rs2 as u32) as i32;
+                        let rs1 = self.read_reg(btype.rs1 as u32) as i32;
+                        let rs2 = self.read_reg(btype.rs2 as u32) as i32;
 
                         if rs1 < rs2 {
                             let target = self.pc.wrapping_add(btype.imm as u32);
                             self.pc = target;
                         } else {
-                            self.pc += 4;
+                            self.pc = self.pc.wrapping_add(size);
                         }
 
                         Ok(true)
                     }
                     0b101 => {
                         // Funct3 for bge
-                        let rs1 = self.registers.read_reg(btype.rs1 as u32) as i32;
-                        let rs2 = self.registers.read_reg(btype.rs2 as u32) as i32;
+                        let rs1 = self.read_reg(btype.rs1 as u32) as i32;
+                        let rs2 = self.read_reg(btype.rs2 as u32) as i32;
 
                         if rs1 >= rs2 {
                             let target = self.pc.wrapping_add(btype.imm as u32);
                             self.pc = target;
                         } else {
-                            self.pc += 4;
+                            self.pc = self.pc.wrapping_add(size);
                         }
 
                         Ok(true)
                     }
                     0b110 => {
                         // Funct3 for bltu
-                        let rs1 = self.registers.read_reg(btype.rs1 as u32);
-                        let rs2 = self.registers.read_reg(btype.rs2 as u32);
+                        let rs1 = self.read_reg(btype.rs1 as u32);
+                        let rs2 = self.read_reg(btype.rs2 as u32);
 
                         if rs1 < rs2 {
                             let target = self.pc.wrapping_add(btype.imm as u32);
                             self.pc = target;
                         } else {
-                            self.pc += 4;
+                            self.pc = self.pc.wrapping_add(size);
                         }
 
                         Ok(true)
                     }
                     0b111 => {
                         // Funct3 for bgeu
-                        let rs1 = self.registers.read_reg(btype.rs1 as u32);
-                        let rs2 = self.registers.read_reg(btype.rs2 as u32);
+                        let rs1 = self.read_reg(btype.rs1 as u32);
+                        let rs2 = self.read_reg(btype.rs2 as u32);
 
                         if rs1 >= rs2 {
                             let target = self.pc.wrapping_add(btype.imm as u32);
                             self.pc = target;
                         } else {
-                            self.pc += 4;
+                            self.pc = self.pc.wrapping_add(size);
                         }
 
                         Ok(true)
                     }
-                    _ => return Err(VMErrors::InvalidFunct3(btype.funct3)),
+                    _ => Err(VMErrors::InvalidFunct3(btype.funct3)),
                 }
             }
-            crate::instructions::DecodedInstruction::UType(utype) => {
+            DecodedInstruction::UType(utype) => {
                 match decoded_instruction.opcode {
                     0b0110111 => {
                         // Funct3 for lui
                         let imm = utype.imm as u32;
-                        self.registers.write_reg(utype.rd as u32, imm);
-                        self.pc += 4;
+                        self.write_reg(utype.rd as u32, imm);
+                        self.pc = self.pc.wrapping_add(size);
                         Ok(true)
                     }
                     0b0010111 => {
                         // Funct3 for auipc
                         let imm = utype.imm as u32;
                         let pc = self.pc;
-                        self.registers
-                            .write_reg(utype.rd as u32, pc.wrapping_add(imm));
-                        self.pc += 4;
+                        self.write_reg(utype.rd as u32, pc.wrapping_add(imm));
+                        self.pc = self.pc.wrapping_add(size);
                         Ok(true)
                     }
-                    _ => return Err(VMErrors::InvalidOpcode(decoded_instruction.opcode)),
+                    _ => Err(VMErrors::InvalidOpcode(decoded_instruction.opcode)),
                 }
             }
-            crate::instructions::DecodedInstruction::JType(jtype) => {
+            DecodedInstruction::JType(jtype) => {
                 match decoded_instruction.opcode {
                     0b1101111 => {
                         // Funct3 for jal
-                        self.pc += 4;
-                        self.registers.write_reg(jtype.rd as u32, self.pc);
-                        self.pc += jtype.imm as u32 - 4; // self.pc += imm "not" self.pc = self.pc + 4 + imm
+                        self.write_reg(jtype.rd as u32, self.pc.wrapping_add(size));
+                        self.pc = self.pc.wrapping_add(jtype.imm as u32);
                         Ok(true)
                     }
-                    _ => return Err(VMErrors::InvalidOpcode(decoded_instruction.opcode)),
+                    _ => Err(VMErrors::InvalidOpcode(decoded_instruction.opcode)),
                 }
             }
         }
     }
 
-    /// Run the Vm.
-    /// This function will run the Vm until it halts.
-    /// The Vm will halt if the program counter is out of bounds or if the instruction is a halt.
-    pub fn run(&mut self, debug_mode: bool) {
+    /// Run the Vm until the guest exits, faults or one of the halting conditions of `config` is met.
+    /// Breakpoints are not checked for the first instruction, so a run can resume from one.
+    pub fn run(&mut self, config: RunConfig) -> RunOutcome {
+        let start_instret = self.csrs.instret;
+        let start_cycle = self.csrs.cycle;
+        self.halt_on_ebreak = config.halt_on_ebreak;
         self.running = true;
-        while self.running {
-            match self.step(debug_mode) {
-                Ok(true) => continue,
-                Ok(false) => break,
-                Err(e) => {
-                    match e {
-                        VMErrors::EnvironmentError => {} // would just be halting the program, sysytem calls are not allowed on the VM
-                        _ => {
-                            eprintln!("Error at pc: {:x} - error: {:?}", self.pc, e);
-                        }
-                    }
-                    self.running = false;
+
+        // The host may have written to memory since the blocks were decoded
+        self.instruction_cache.clear();
+
+        let mut first = true;
+        let outcome = loop {
+            // Both budgets are consumed one per instruction by cached blocks, which never trap
+            let remaining = config
+                .max_instructions
+                .map(|max| max.saturating_sub(self.csrs.instret - start_instret))
+                .into_iter()
+                .chain(
+                    config
+                        .max_cycles
+                        .map(|max| max.saturating_sub(self.csrs.cycle - start_cycle)),
+                )
+                .min()
+                .unwrap_or(u64::MAX);
+            if remaining == 0 {
+                break RunOutcome::BudgetExhausted;
+            }
+            if !first && config.breakpoints.contains(&self.pc) {
+                break RunOutcome::Breakpoint(self.pc);
+            }
+            first = false;
+
+            let cache_blocks = config.cache_blocks
+                && config.breakpoints.is_empty()
+                && self.trace.is_none()
+                && self.observers.is_empty();
+            let pc = self.pc;
+            let executed = if cache_blocks {
+                self.execute_block(remaining, config.jit)
+            } else {
+                0
+            };
+            let result = if executed > 0 { Ok(true) } else { self.step() };
+            match result {
+                Ok(true) if config.detect_infinite_loops && executed <= 1 && self.pc == pc => {
+                    break RunOutcome::InfiniteLoop(pc);
                 }
+                Ok(true) => continue,
+                // Only ebreak halts without moving the pc
+                Ok(false) if self.pc == pc => break RunOutcome::Breakpoint(pc),
+                Ok(false) => break RunOutcome::Exited(self.exit_code),
+                Err(error) => break RunOutcome::Faulted { pc, error },
+            }
+        };
+
+        self.running = false;
+        self.halt_on_ebreak = true;
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{addi, csr, jal, EBREAK, ECALL, MRET};
+    use crate::{csr::MTVEC, syscalls::SYS_EXIT};
+
+    #[test]
+    fn test_run_exited() {
+        let mut vm =
+            Vm::from_bin(vec![addi(10, 0, 7), addi(17, 0, SYS_EXIT as i32), ECALL]).unwrap();
+
+        assert_eq!(vm.run(RunConfig::default()), RunOutcome::Exited(7));
+        assert!(!vm.running);
+    }
+
+    #[test]
+    fn test_run_faulted() {
+        let mut vm = Vm::from_bin(vec![addi(1, 0, 1), 0xffff_ffff]).unwrap();
+
+        assert_eq!(
+            vm.run(RunConfig::default()),
+            RunOutcome::Faulted {
+                pc: 4,
+                error: VMErrors::InvalidOpcode(0b1111111)
+            }
+        );
+    }
+
+    #[test]
+    fn test_run_instruction_budget() {
+        let mut vm = Vm::from_bin(vec![addi(1, 1, 1), jal(0, -4)]).unwrap();
+        let config = RunConfig {
+            max_instructions: Some(5),
+            ..RunConfig::default()
+        };
+
+        assert_eq!(vm.run(config.clone()), RunOutcome::BudgetExhausted);
+        assert_eq!(vm.registers.read_reg(1), 3);
+        // The budget applies to each run
+        assert_eq!(vm.run(config), RunOutcome::BudgetExhausted);
+        assert_eq!(vm.csrs.instret, 10);
+    }
+
+    #[test]
+    fn test_run_cycle_budget_counts_traps() {
+        // Every illegal instruction traps to a handler returning to it
+        let mut vm = Vm::from_bin(vec![
+            addi(1, 0, 0x100),
+            csr(0b001, 0, 1, MTVEC), // csrw mtvec, x1
+            0xffff_ffff,
+        ])
+        .unwrap();
+        vm.memory.load_program(&[MRET], 0x100);
+        let outcome = vm.run(RunConfig {
+            max_cycles: Some(10),
+            ..RunConfig::default()
+        });
+
+        assert_eq!(outcome, RunOutcome::BudgetExhausted);
+        assert_eq!(vm.csrs.cycle, 10);
+        assert_eq!(vm.csrs.instret, 6);
+    }
+
+    #[test]
+    fn test_run_breakpoints() {
+        let mut vm = Vm::from_bin(vec![addi(1, 0, 1), addi(2, 0, 2), EBREAK]).unwrap();
+        let config = RunConfig {
+            breakpoints: HashSet::from([4]),
+            ..RunConfig::default()
+        };
+
+        assert_eq!(vm.run(config.clone()), RunOutcome::Breakpoint(4));
+        assert_eq!(vm.registers.read_reg(2), 0);
+        // Resuming executes the instruction at the breakpoint
+        assert_eq!(vm.run(config), RunOutcome::Breakpoint(8));
+        assert_eq!(vm.registers.read_reg(2), 2);
+    }
+
+    #[test]
+    fn test_run_ebreak_traps_when_not_halting() {
+        let mut vm = Vm::from_bin(vec![
+            addi(1, 0, 0x100),
+            csr(0b001, 0, 1, MTVEC), // csrw mtvec, x1
+            EBREAK,
+        ])
+        .unwrap();
+        vm.memory.load_program(
+            &[addi(10, 0, 3), addi(17, 0, SYS_EXIT as i32), ECALL],
+            0x100,
+        );
+        let outcome = vm.run(RunConfig {
+            halt_on_ebreak: false,
+            ..RunConfig::default()
+        });
+
+        assert_eq!(outcome, RunOutcome::Exited(3));
+        assert_eq!(vm.csrs.mcause, Exception::Breakpoint as u32);
+        assert_eq!(vm.csrs.mepc, 8);
+        assert!(vm.halt_on_ebreak);
+    }
+
+    #[test]
+    fn test_fences() {
+        let mut vm = Vm::from_bin(vec![
+            0x0ff0_000f, // fence
+            0x8330_000f, // fence.tso
+            0x0100_000f, // pause
+            // fence with a reserved fence mode, rs1 and rd, executed as a normal fence
+            (0x5ff << 20) | (2 << 15) | (1 << 7) | 0b0001111,
+            // fence.i with its reserved immediate, rs1 and rd
+            (0x123 << 20) | (3 << 15) | (0b001 << 12) | (4 << 7) | 0b0001111,
+            EBREAK,
+        ])
+        .unwrap();
+
+        assert_eq!(vm.run(RunConfig::default()), RunOutcome::Breakpoint(20));
+        assert_eq!(vm.csrs.instret, 5);
+        assert_eq!(vm.registers.read_reg(1), 0);
+        assert_eq!(vm.registers.read_reg(4), 0);
+
+        let mut vm = Vm::from_bin(vec![(0b010 << 12) | 0b0001111]).unwrap();
+        assert_eq!(
+            vm.run(RunConfig::default()),
+            RunOutcome::Faulted {
+                pc: 0,
+                error: VMErrors::InvalidFunct3(0b010)
             }
+        );
+    }
+
+    #[test]
+    fn test_fence_i_self_modifying_code() {
+        let patch = addi(10, 0, 42);
+        let mut vm = Vm::from_bin(vec![
+            ((patch >> 12) << 12) | (5 << 7) | 0b0110111, // lui x5, patch >> 12
+            addi(5, 5, (patch & 0xfff) as i32),
+            (5 << 20) | (0b010 << 12) | (24 << 7) | 0b0100011, // sw x5, 24(x0)
+            0x0000_100f,                                       // fence.i
+            addi(11, 11, 1),
+            jal(0, 4),
+            addi(10, 0, 1), // overwritten with addi x10, x0, 42
+            EBREAK,
+        ])
+        .unwrap();
+
+        assert_eq!(vm.run(RunConfig::default()), RunOutcome::Breakpoint(28));
+        assert_eq!(vm.registers.read_reg(10), 42);
+        assert_eq!(vm.registers.read_reg(11), 1);
+    }
+
+    #[test]
+    fn test_pc_wraps_around() {
+        for cache_blocks in [false, true] {
+            let mut vm = Vm::from_bin(vec![EBREAK]).unwrap();
+            vm.memory.load_program(&[addi(1, 0, 5)], 0xffff_fffc);
+            vm.pc = 0xffff_fffc;
+            let config = RunConfig {
+                cache_blocks,
+                ..RunConfig::default()
+            };
+
+            assert_eq!(vm.run(config), RunOutcome::Breakpoint(0));
+            assert_eq!(vm.registers.read_reg(1), 5);
         }
     }
+
+    #[test]
+    fn test_run_infinite_loop() {
+        let mut vm = Vm::from_bin(vec![addi(1, 0, 1), jal(0, 0)]).unwrap();
+        let outcome = vm.run(RunConfig {
+            detect_infinite_loops: true,
+            ..RunConfig::default()
+        });
+
+        assert_eq!(outcome, RunOutcome::InfiniteLoop(4));
+    }
 }
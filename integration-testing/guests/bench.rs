@@ -0,0 +1,60 @@
+//! A guest that runs to completion under the default syscall handler, built into `rust-elfs/bench` with:
+//! rustc +stable --target riscv32imac-unknown-none-elf -C opt-level=1 -C panic=abort -o rust-elfs/bench guests/bench.rs
+//! It sieves the primes below [LIMIT] a few times and exits with their count.
+#![no_std]
+#![no_main]
+
+use core::{panic::PanicInfo, ptr::addr_of_mut};
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    loop {}
+}
+
+/// The primes are sieved below this
+const LIMIT: usize = 1 << 16;
+const ROUNDS: u32 = 4;
+
+static mut SIEVE: [bool; LIMIT] = [false; LIMIT];
+static mut STACK: [u8; 4096] = [0; 4096];
+
+// The VM starts with a zero sp, point it at the top of `STACK` first
+core::arch::global_asm!(
+    ".globl _start",
+    "_start:",
+    "la sp, {stack} + 4096",
+    "call {main}",
+    stack = sym STACK,
+    main = sym main,
+);
+
+/// Returns the number of primes below [LIMIT]
+#[inline(never)]
+fn sieve(composite: &mut [bool; LIMIT]) -> u32 {
+    composite.fill(false);
+    let mut count = 0;
+    for n in 2..LIMIT {
+        if composite[n] {
+            continue;
+        }
+        count += 1;
+        let mut multiple = n * n;
+        while multiple < LIMIT {
+            composite[multiple] = true;
+            multiple += n;
+        }
+    }
+    count
+}
+
+extern "C" fn main() -> ! {
+    let composite = unsafe { &mut *addr_of_mut!(SIEVE) };
+    let mut count = 0;
+    for _ in 0..core::hint::black_box(ROUNDS) {
+        count = sieve(composite);
+    }
+    unsafe {
+        core::arch::asm!("ecall", in("a0") count, in("a7") 93);
+    }
+    loop {}
+}
@@ -0,0 +1,152 @@
+//! A guest starting like a statically linked Linux program, built into `rust-elfs/linux` with:
+//! rustc +stable --target riscv32imac-unknown-none-elf -C opt-level=1 -C panic=abort -o rust-elfs/linux guests/linux.rs
+//! It reads its arguments, environment and auxiliary vector from the initial stack, goes through
+//! the syscalls of a C library starting up and exits with a bit set for every check that failed.
+//! It stands in for a musl or newlib hello world, which needs a RISC-V C toolchain to build.
+#![no_std]
+#![no_main]
+
+use core::{arch::asm, panic::PanicInfo};
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    exit(0x80)
+}
+
+core::arch::global_asm!(
+    ".section .text._start",
+    ".globl _start",
+    "_start:",
+    "mv a0, sp",
+    "call start",
+);
+
+unsafe fn syscall(number: u32, args: [u32; 6]) -> u32 {
+    let result;
+    asm!(
+        "ecall",
+        in("a7") number,
+        inlateout("a0") args[0] => result,
+        in("a1") args[1],
+        in("a2") args[2],
+        in("a3") args[3],
+        in("a4") args[4],
+        in("a5") args[5],
+    );
+    result
+}
+
+fn exit(code: u32) -> ! {
+    unsafe { syscall(94, [code, 0, 0, 0, 0, 0]) };
+    loop {}
+}
+
+unsafe fn strlen(s: *const u8) -> usize {
+    let mut len = 0;
+    while *s.add(len) != 0 {
+        len += 1;
+    }
+    len
+}
+
+#[no_mangle]
+unsafe extern "C" fn start(sp: *const u32) -> ! {
+    let mut failed = 0;
+    let argc = *sp as usize;
+    let argv = sp.add(1);
+    let envp = argv.add(argc + 1);
+    let mut auxv = envp;
+    while *auxv != 0 {
+        auxv = auxv.add(1);
+    }
+    auxv = auxv.add(1);
+
+    // Greet the last argument, and the first variable of the environment
+    let name = *argv.add(argc - 1) as *const u8;
+    let var = *envp as *const u8;
+    let iov: [u32; 6] = [
+        b"hello, ".as_ptr() as u32,
+        7,
+        name as u32,
+        strlen(name) as u32,
+        b"\n".as_ptr() as u32,
+        1,
+    ];
+    if syscall(66, [1, iov.as_ptr() as u32, 3, 0, 0, 0]) != 8 + strlen(name) as u32 {
+        failed |= 1;
+    }
+    syscall(64, [1, var as u32, strlen(var) as u32, 0, 0, 0]);
+
+    // The page size and the random bytes come in the auxiliary vector
+    let (mut page_size, mut random) = (0, 0);
+    while *auxv != 0 {
+        match *auxv {
+            6 => page_size = *auxv.add(1),
+            25 => random = *auxv.add(1),
+            _ => {}
+        }
+        auxv = auxv.add(2);
+    }
+    if page_size != 4096 || random == 0 {
+        failed |= 2;
+    }
+
+    // The thread setup of a C library: the thread id, and the signals blocked while it runs
+    let tid = syscall(96, [0x1000, 0, 0, 0, 0, 0]);
+    let (all, mut old) = (u64::MAX, 1u64);
+    let action = [0u32; 4];
+    if tid != 1
+        || syscall(172, [0; 6]) != tid
+        || syscall(178, [0; 6]) != tid
+        || syscall(135, [0, &all as *const u64 as u32, &mut old as *mut u64 as u32, 8, 0, 0]) != 0
+        || old != 0
+        || syscall(135, [2, &old as *const u64 as u32, 0, 8, 0, 0]) != 0
+        || syscall(134, [13, action.as_ptr() as u32, 0, 8, 0, 0]) != 0
+    {
+        failed |= 4;
+    }
+
+    // Grow the heap and map a page, both writable
+    let brk = syscall(214, [0, 0, 0, 0, 0, 0]);
+    if syscall(214, [brk + 0x2000, 0, 0, 0, 0, 0]) != brk + 0x2000 {
+        failed |= 8;
+    }
+    *((brk + 0x1ffc) as *mut u32) = 42;
+    let page = syscall(222, [0, 4096, 3, 0x22, u32::MAX, 0]);
+    *(page as *mut u32) = 42;
+    if *(page as *const u32) != 42 {
+        failed |= 16;
+    }
+    // Freed by the allocator, the page reads as zeros
+    if syscall(233, [page, 4096, 4, 0, 0, 0]) != 0
+        || core::ptr::read_volatile(page as *const u32) != 0
+        || syscall(215, [page, 4096, 0, 0, 0, 0]) != 0
+    {
+        failed |= 16;
+    }
+
+    let mut uname = [0u8; 6 * 65];
+    syscall(160, [uname.as_mut_ptr() as u32, 0, 0, 0, 0, 0]);
+    if &uname[4 * 65..4 * 65 + 8] != b"riscv32\0" {
+        failed |= 32;
+    }
+
+    let mut times = [0u64; 4];
+    syscall(403, [1, times.as_mut_ptr() as u32, 0, 0, 0, 0]);
+    syscall(403, [1, times.as_mut_ptr().add(2) as u32, 0, 0, 0, 0]);
+    let mut stat = [0u32; 26];
+    let mut statx = [0u32; 64];
+    let cwd = -100i32 as u32;
+    if times[2..] <= times[..2]
+        || syscall(278, [times.as_mut_ptr() as u32, 8, 0, 0, 0, 0]) != 8
+        || syscall(56, [cwd, b"/etc/passwd\0".as_ptr() as u32, 0, 0, 0, 0]) != -2i32 as u32
+        || syscall(80, [1, stat.as_mut_ptr() as u32, 0, 0, 0, 0]) != 0
+        || stat[4] & 0o170000 != 0o020000
+        || syscall(291, [1, b"\0".as_ptr() as u32, 0x1000, 0x7ff, statx.as_mut_ptr() as u32, 0]) != 0
+        || statx[7] & 0o170000 != 0o020000
+    {
+        failed |= 64;
+    }
+
+    exit(failed)
+}
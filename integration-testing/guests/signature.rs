@@ -0,0 +1,55 @@
+//! A guest laid out like an architectural test, built into `rust-elfs/signature` with:
+//! rustc +stable --target riscv32imac-unknown-none-elf -C opt-level=1 -C panic=abort -o rust-elfs/signature guests/signature.rs
+//! It writes the first fibonacci numbers to its signature and exits through `tohost`.
+#![no_std]
+#![no_main]
+
+use core::{
+    panic::PanicInfo,
+    ptr::{addr_of_mut, write_volatile},
+};
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    loop {}
+}
+
+core::arch::global_asm!(
+    ".section .data.signature, \"aw\"",
+    ".balign 16",
+    ".globl begin_signature",
+    "begin_signature:",
+    ".fill 6, 4, 0xdeadbeef",
+    ".globl end_signature",
+    "end_signature:",
+    ".section .tohost, \"aw\"",
+    ".balign 64",
+    ".globl tohost",
+    "tohost: .dword 0",
+    ".balign 64",
+    ".globl fromhost",
+    "fromhost: .dword 0",
+);
+
+extern "C" {
+    static mut begin_signature: [u32; 6];
+    static mut tohost: [u32; 2];
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    unsafe {
+        let signature = addr_of_mut!(begin_signature) as *mut u32;
+        let (mut a, mut b) = (0u32, 1u32);
+        // The last word keeps its initial value
+        for i in 0..5 {
+            write_volatile(signature.add(i), a);
+            (a, b) = (b, a + b);
+        }
+
+        let tohost_words = addr_of_mut!(tohost) as *mut u32;
+        write_volatile(tohost_words, 1);
+        write_volatile(tohost_words.add(1), 0);
+    }
+    loop {}
+}
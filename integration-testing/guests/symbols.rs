@@ -0,0 +1,43 @@
+//! A guest with symbols and DWARF line info, built into `rust-elfs/symbols` with:
+//! rustc +stable --target riscv32imac-unknown-none-elf -C opt-level=1 -C debuginfo=2 -C panic=abort -o rust-elfs/symbols guests/symbols.rs
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    loop {}
+}
+
+mod math {
+    /// Returns the nth fibonacci number
+    #[inline(never)]
+    pub fn fibonacci(n: u32) -> u32 {
+        let (mut a, mut b) = (0u32, 1u32);
+        for _ in 0..n {
+            let c = a.wrapping_add(b);
+            a = b;
+            b = c;
+        }
+        a
+    }
+}
+
+/// Stops on an ebreak when `value` is odd
+#[inline(never)]
+fn check(value: u32) {
+    if value % 2 == 1 {
+        unsafe { core::arch::asm!("ebreak") };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    let value = math::fibonacci(core::hint::black_box(10));
+    check(value);
+    unsafe {
+        core::arch::asm!("ecall", in("a0") value, in("a7") 93);
+    }
+    loop {}
+}
@@ -0,0 +1,25 @@
+#!/bin/sh
+# Builds <suite>/<test>.S into ../isa-bins/<suite>/<test>, run from this directory.
+# It needs a C preprocessor, llvm-mc and the rust-lld of the Rust toolchain, no RISC-V GCC.
+# These tests are written for this repository, the upstream riscv-tests are the rv32ui binaries of
+# ../ported-bins and are not built here.
+set -e
+
+lld="$(rustc --print sysroot)/lib/rustlib/$(rustc -vV | sed -n 's/^host: //p')/bin/rust-lld"
+out=../isa-bins
+
+for source in */*.S; do
+    suite=$(dirname "$source")
+    test=$(basename "$source" .S)
+    # Only the compressed suite is assembled with the C extension
+    case $suite in
+        c) attrs=+m,+a,+c ;;
+        *) attrs=+m,+a ;;
+    esac
+
+    mkdir -p "$out/$suite"
+    cpp -P -x assembler-with-cpp -Ienv -Imacros "$source" > "/tmp/$suite-$test.s"
+    llvm-mc -triple=riscv32 -mattr=$attrs -filetype=obj -o "/tmp/$suite-$test.o" "/tmp/$suite-$test.s"
+    "$lld" -flavor gnu -T env/link.ld -o "$out/$suite/$test" "/tmp/$suite-$test.o"
+    rm "/tmp/$suite-$test.s" "/tmp/$suite-$test.o"
+done
@@ -0,0 +1,21 @@
+// The CSR fields and exception causes the tests refer to, from the privileged specification.
+#ifndef RISCV_ENCODING_H
+#define RISCV_ENCODING_H
+
+#define MSTATUS_MIE 0x00000008
+#define MSTATUS_MPIE 0x00000080
+#define MSTATUS_MPP 0x00001800
+
+#define CAUSE_MISALIGNED_FETCH 0x0
+#define CAUSE_FETCH_ACCESS 0x1
+#define CAUSE_ILLEGAL_INSTRUCTION 0x2
+#define CAUSE_BREAKPOINT 0x3
+#define CAUSE_MISALIGNED_LOAD 0x4
+#define CAUSE_LOAD_ACCESS 0x5
+#define CAUSE_MISALIGNED_STORE 0x6
+#define CAUSE_STORE_ACCESS 0x7
+#define CAUSE_USER_ECALL 0x8
+#define CAUSE_SUPERVISOR_ECALL 0x9
+#define CAUSE_MACHINE_ECALL 0xb
+
+#endif
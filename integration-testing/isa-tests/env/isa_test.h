@@ -0,0 +1,94 @@
+// The environment of the ISA tests of this repository, written after the "p" environment of the
+// riscv-tests but not a copy of it: a single hart in machine mode without virtual memory.
+// Tests report to the host through HTIF, pass and fail end in an ecall the trap vector turns into
+// a write of TESTNUM to tohost: 1 for a pass and (n << 1) | 1 when test n failed.
+#ifndef ISA_TEST_H
+#define ISA_TEST_H
+
+#include "encoding.h"
+
+#define RVTEST_RV32U .macro init; .endm
+#define RVTEST_RV32M .macro init; .endm
+
+#define TESTNUM gp
+
+#define INIT_XREG \
+  li x1, 0; li x2, 0; li x3, 0; li x4, 0; li x5, 0; li x6, 0; li x7, 0; \
+  li x8, 0; li x9, 0; li x10, 0; li x11, 0; li x12, 0; li x13, 0; li x14, 0; \
+  li x15, 0; li x16, 0; li x17, 0; li x18, 0; li x19, 0; li x20, 0; li x21, 0; \
+  li x22, 0; li x23, 0; li x24, 0; li x25, 0; li x26, 0; li x27, 0; li x28, 0; \
+  li x29, 0; li x30, 0; li x31, 0;
+
+// Exceptions other than the ecalls of pass and fail go to the mtvec_handler of the test, if it
+// defines one, and fail the test otherwise
+#define RVTEST_CODE_BEGIN \
+        .section .text.init; \
+        .align 6; \
+        .weak mtvec_handler; \
+        .globl _start; \
+_start: \
+        j reset_vector; \
+        .align 2; \
+trap_vector: \
+        csrr t5, mcause; \
+        li t6, CAUSE_USER_ECALL; \
+        beq t5, t6, write_tohost; \
+        li t6, CAUSE_SUPERVISOR_ECALL; \
+        beq t5, t6, write_tohost; \
+        li t6, CAUSE_MACHINE_ECALL; \
+        beq t5, t6, write_tohost; \
+        lui t5, %hi(mtvec_handler); \
+        addi t5, t5, %lo(mtvec_handler); \
+        beqz t5, other_exception; \
+        jr t5; \
+other_exception: \
+        ori TESTNUM, TESTNUM, 1337; \
+write_tohost: \
+        sw TESTNUM, tohost, t5; \
+        sw zero, tohost + 4, t5; \
+        j write_tohost; \
+reset_vector: \
+        INIT_XREG; \
+        csrr a0, mhartid; \
+1:      bnez a0, 1b; \
+        la t0, trap_vector; \
+        csrw mtvec, t0; \
+        li TESTNUM, 0; \
+        init; \
+        csrwi mstatus, 0; \
+        la t0, 1f; \
+        csrw mepc, t0; \
+        csrr a0, mhartid; \
+        mret; \
+1:
+
+#define RVTEST_CODE_END \
+        unimp
+
+#define RVTEST_PASS \
+        fence; \
+        li TESTNUM, 1; \
+        li a7, 93; \
+        li a0, 0; \
+        ecall
+
+#define RVTEST_FAIL \
+        fence; \
+1:      beqz TESTNUM, 1b; \
+        sll TESTNUM, TESTNUM, 1; \
+        or TESTNUM, TESTNUM, 1; \
+        li a7, 93; \
+        addi a0, TESTNUM, 0; \
+        ecall
+
+#define RVTEST_DATA_BEGIN \
+        .pushsection .tohost, "aw", @progbits; \
+        .align 6; .global tohost; tohost: .word 0, 0; .size tohost, 8; \
+        .align 6; .global fromhost; fromhost: .word 0, 0; .size fromhost, 8; \
+        .popsection; \
+        .align 4; .global begin_signature; begin_signature:
+
+#define RVTEST_DATA_END \
+        .align 4; .global end_signature; end_signature:
+
+#endif
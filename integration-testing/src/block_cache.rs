@@ -0,0 +1,84 @@
+use emulator_sdk::vm::{RunConfig, RunOutcome, Vm};
+
+/// Run `path` for at most `max_instructions` with both configurations
+fn run_both(path: &str, max_instructions: u64, configs: [RunConfig; 2]) -> [(Vm, RunOutcome); 2] {
+    configs.map(|config| {
+        let mut vm = Vm::from_bin_elf(path.to_string()).unwrap();
+        vm.memory.enforce_permissions = !path.contains("-bins/");
+        let outcome = vm.run(RunConfig {
+            max_instructions: Some(max_instructions),
+            ..config
+        });
+        (vm, outcome)
+    })
+}
+
+/// Check that running `path` with either configuration ends in the same state
+pub(crate) fn assert_same_state(path: &str, max_instructions: u64, configs: [RunConfig; 2]) {
+    let [(step, step_outcome), (blocks, blocks_outcome)] =
+        run_both(path, max_instructions, configs);
+
+    assert_eq!(step_outcome, blocks_outcome, "{path}");
+    assert_eq!(step.pc, blocks.pc, "{path}");
+    assert_eq!(step.csrs, blocks.csrs, "{path}");
+    for reg in 0..32 {
+        assert_eq!(
+            step.registers.read_reg(reg),
+            blocks.registers.read_reg(reg),
+            "{path}: x{reg}"
+        );
+    }
+    assert!(
+        step.memory.pages.iter().eq(blocks.memory.pages.iter()),
+        "{path}: memory differs"
+    );
+}
+
+/// The bundled ELFs, the riscv-tests and the ISA tests
+pub(crate) fn elfs() -> Vec<String> {
+    let mut paths = vec![
+        String::from("rust-elfs/fibonacci"),
+        String::from("plonk_verify"),
+    ];
+    for dir in [
+        "ported-bins",
+        "isa-bins/m",
+        "isa-bins/a",
+        "isa-bins/c",
+        "isa-bins/machine",
+    ] {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            paths.push(entry.unwrap().path().to_str().unwrap().to_string());
+        }
+    }
+    paths
+}
+
+fn single_stepping_and_blocks() -> [RunConfig; 2] {
+    [
+        RunConfig {
+            cache_blocks: false,
+            ..RunConfig::default()
+        },
+        RunConfig::default(),
+    ]
+}
+
+#[test]
+fn test_blocks_match_single_stepping() {
+    for path in elfs() {
+        assert_same_state(&path, 1_000_000, single_stepping_and_blocks());
+    }
+}
+
+#[test]
+fn test_blocks_stop_at_the_same_instruction() {
+    // Budgets ending in the middle of blocks
+    for max_instructions in [1, 7, 100, 12_345] {
+        assert_same_state(
+            "rust-elfs/fibonacci",
+            max_instructions,
+            single_stepping_and_blocks(),
+        );
+    }
+}
@@ -0,0 +1,31 @@
+use crate::block_cache::{assert_same_state, elfs};
+use emulator_sdk::vm::RunConfig;
+
+fn interpreter_and_jit() -> [RunConfig; 2] {
+    [
+        RunConfig::default(),
+        RunConfig {
+            jit: true,
+            ..RunConfig::default()
+        },
+    ]
+}
+
+#[test]
+fn test_jit_matches_the_interpreter() {
+    for path in elfs() {
+        assert_same_state(&path, 1_000_000, interpreter_and_jit());
+    }
+}
+
+#[test]
+fn test_jit_stops_at_the_same_instruction() {
+    // Budgets ending in the middle of compiled blocks
+    for max_instructions in [1, 7, 100, 12_345, 10_000_000] {
+        assert_same_state(
+            "rust-elfs/fibonacci",
+            max_instructions,
+            interpreter_and_jit(),
+        );
+    }
+}
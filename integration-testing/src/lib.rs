@@ -1,4 +1,18 @@
 #[cfg(test)]
+mod block_cache;
+#[cfg(all(test, feature = "jit"))]
+mod jit;
+#[cfg(test)]
+mod linux;
+#[cfg(test)]
+mod memory_model;
+#[cfg(test)]
 mod ported_elf_bins;
 #[cfg(test)]
 mod rust_elf;
+#[cfg(test)]
+mod segments;
+#[cfg(test)]
+mod signature;
+#[cfg(test)]
+mod symbols;
@@ -0,0 +1,51 @@
+use elf_parser::{Elf, ProgramHeaders};
+use emulator_sdk::{
+    linux::{LinuxSyscallHandler, STACK_TOP},
+    vm::{RunConfig, RunOutcome, Vm},
+};
+
+/// `rust-elfs/linux`, built from `guests/linux.rs`, exits with a bit set for every failed check
+fn run(args: &[&str], env: &[&str]) -> Vm {
+    let elf = Elf::decode(&std::fs::read("rust-elfs/linux").unwrap()).unwrap();
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    let env: Vec<String> = env.iter().map(|var| var.to_string()).collect();
+    let mut vm = Vm::from_linux_elf(&elf, &args, &env, LinuxSyscallHandler::default());
+
+    let sp = vm.registers.read_reg(2);
+    assert!(sp < STACK_TOP && sp.is_multiple_of(16));
+    assert_eq!(vm.run(RunConfig::default()), RunOutcome::Exited(0));
+    vm
+}
+
+fn stdout(vm: &Vm) -> String {
+    let handler = vm.syscall_handler.as_ref() as &dyn std::any::Any;
+    let handler: &LinuxSyscallHandler = handler.downcast_ref().unwrap();
+    String::from_utf8(handler.stdout.clone()).unwrap()
+}
+
+#[test]
+fn test_program_headers() {
+    let elf = Elf::decode(&std::fs::read("rust-elfs/linux").unwrap()).unwrap();
+    assert_eq!(
+        elf.program_headers,
+        Some(ProgramHeaders {
+            addr: 0x10034,
+            count: 5,
+            entry_size: 32
+        })
+    );
+
+    // No segment loads the start of the file
+    let elf = Elf::decode(&std::fs::read("ported-bins/rv32ui-p-add").unwrap()).unwrap();
+    assert_eq!(elf.program_headers, None);
+}
+
+#[test]
+fn test_linux_process() {
+    let vm = run(&["linux", "world"], &["HOME=/root", "TERM=dumb"]);
+    assert_eq!(stdout(&vm), "hello, world\nHOME=/root");
+
+    // The last argument is the program name when there is no other
+    let vm = run(&["linux"], &["LANG=C"]);
+    assert_eq!(stdout(&vm), "hello, linux\nLANG=C");
+}
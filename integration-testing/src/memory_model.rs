@@ -0,0 +1,116 @@
+use core::{interfaces::MemoryInterface, MemoryChuckSize};
+use emulator_sdk::{
+    asm::{i_type, lui, s_type},
+    vm::{RunConfig, Vm},
+};
+
+/// File offset, address and size of the read-only data segment of `rust-elfs/fibonacci`
+const RODATA_OFFSET: usize = 0x9110;
+const RODATA_ADDR: u32 = 0x0020a110;
+const RODATA_SIZE: usize = 0x15e8;
+
+#[test]
+fn test_elf_data_is_little_endian() {
+    let file = std::fs::read("rust-elfs/fibonacci").unwrap();
+    let bytes = &file[RODATA_OFFSET..RODATA_OFFSET + RODATA_SIZE];
+    let vm = Vm::from_bin_elf(String::from("rust-elfs/fibonacci")).unwrap();
+
+    for (i, byte) in bytes.iter().enumerate() {
+        let addr = RODATA_ADDR + i as u32;
+        assert_eq!(
+            vm.memory.read_mem(addr, MemoryChuckSize::BYTE),
+            Some(*byte as u32),
+            "byte at {addr:08x}"
+        );
+    }
+
+    for (i, half) in bytes.chunks_exact(2).enumerate() {
+        let addr = RODATA_ADDR + 2 * i as u32;
+        assert_eq!(
+            vm.memory.read_mem(addr, MemoryChuckSize::HalfWord),
+            Some(u16::from_le_bytes([half[0], half[1]]) as u32),
+            "half word at {addr:08x}"
+        );
+    }
+
+    for (i, word) in bytes.chunks_exact(4).enumerate() {
+        let addr = RODATA_ADDR + 4 * i as u32;
+        assert_eq!(
+            vm.memory.read_mem(addr, MemoryChuckSize::WordSize),
+            Some(u32::from_le_bytes([word[0], word[1], word[2], word[3]])),
+            "word at {addr:08x}"
+        );
+    }
+}
+
+#[test]
+fn test_mixed_size_loads_and_stores() {
+    let mut vm = Vm::from_bin_elf(String::from("rust-elfs/fibonacci")).unwrap();
+    let program = vec![
+        // x1 = 0x87654321, stored at 0x100
+        lui(1, 0x87654),
+        i_type(0x13, 0b000, 1, 1, 0x321),
+        s_type(0b010, 0, 1, 0x100),
+        i_type(0x03, 0b000, 2, 0, 0x100), // lb
+        i_type(0x03, 0b000, 3, 0, 0x103), // lb
+        i_type(0x03, 0b100, 4, 0, 0x103), // lbu
+        i_type(0x03, 0b001, 5, 0, 0x102), // lh
+        i_type(0x03, 0b101, 6, 0, 0x100), // lhu
+        // overwrite parts of the word and read it back whole
+        i_type(0x13, 0b000, 7, 0, 0xab),
+        s_type(0b000, 0, 7, 0x101), // sb
+        i_type(0x03, 0b010, 8, 0, 0x100),
+        s_type(0b001, 0, 7, 0x102), // sh
+        i_type(0x03, 0b010, 9, 0, 0x100),
+        // read the "/hom" string from the ELF's .rodata
+        lui(10, RODATA_ADDR >> 12),
+        i_type(0x13, 0b000, 10, 10, (RODATA_ADDR & 0xfff) as i32),
+        i_type(0x03, 0b100, 11, 10, 0), // lbu
+        i_type(0x03, 0b101, 12, 10, 2), // lhu
+        i_type(0x03, 0b010, 13, 10, 0), // lw
+        0x0010_0073,                    // ebreak
+    ];
+    vm.memory.load_program(&program, 0x1000);
+    vm.pc = 0x1000;
+    vm.run(RunConfig::default());
+
+    assert_eq!(vm.pc, 0x1000 + 4 * (program.len() as u32 - 1));
+    assert_eq!(vm.registers.read_reg(2), 0x21);
+    assert_eq!(vm.registers.read_reg(3), 0xffff_ff87);
+    assert_eq!(vm.registers.read_reg(4), 0x87);
+    assert_eq!(vm.registers.read_reg(5), 0xffff_8765);
+    assert_eq!(vm.registers.read_reg(6), 0x4321);
+    assert_eq!(vm.registers.read_reg(8), 0x8765_ab21);
+    assert_eq!(vm.registers.read_reg(9), 0x00ab_ab21);
+    assert_eq!(vm.registers.read_reg(11), b'/' as u32);
+    assert_eq!(vm.registers.read_reg(12), u16::from_le_bytes(*b"om") as u32);
+    assert_eq!(vm.registers.read_reg(13), u32::from_le_bytes(*b"/hom"));
+}
+
+#[test]
+fn test_misaligned_loads_and_stores() {
+    let mut vm = Vm::from_bin_elf(String::from("rust-elfs/fibonacci")).unwrap();
+    let program = vec![
+        // x1 = 0x87654321, stored across the words at 0x100 and 0x104
+        lui(1, 0x87654),
+        i_type(0x13, 0b000, 1, 1, 0x321),
+        s_type(0b010, 0, 1, 0x103),       // sw
+        i_type(0x03, 0b010, 2, 0, 0x103), // lw
+        i_type(0x03, 0b010, 3, 0, 0x100), // lw
+        i_type(0x03, 0b001, 4, 0, 0x105), // lh
+        i_type(0x03, 0b101, 5, 0, 0x105), // lhu
+        s_type(0b001, 0, 1, 0x107),       // sh
+        i_type(0x03, 0b010, 6, 0, 0x104), // lw
+        0x0010_0073,                      // ebreak
+    ];
+    vm.memory.load_program(&program, 0x1000);
+    vm.pc = 0x1000;
+    vm.run(RunConfig::default());
+
+    assert_eq!(vm.pc, 0x1000 + 4 * (program.len() as u32 - 1));
+    assert_eq!(vm.registers.read_reg(2), 0x8765_4321);
+    assert_eq!(vm.registers.read_reg(3), 0x2100_0000);
+    assert_eq!(vm.registers.read_reg(4), 0xffff_8765);
+    assert_eq!(vm.registers.read_reg(5), 0x8765);
+    assert_eq!(vm.registers.read_reg(6), 0x2187_6543);
+}
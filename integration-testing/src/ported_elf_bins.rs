@@ -1,13 +1,118 @@
-use emulator_sdk::vm::Vm;
+use core::{interfaces::MemoryInterface, MemoryChuckSize};
+use elf_parser::Elf;
+use emulator_sdk::{
+    htif::Htif,
+    vm::{RunConfig, Vm},
+};
+use std::path::{Path, PathBuf};
+
+/// The directories of the test suites run against the VM.
+/// `ported-bins` holds the rv32ui binaries of the upstream riscv-tests. The other suites are not
+/// upstream: they are written for this repository after the riscv-tests, in `isa-tests`, and built
+/// by its `build.sh`. They cover the M, A and C extensions and the machine mode, but do not stand
+/// in for the upstream rv32um, rv32ua, rv32uc and rv32mi suites, which are not vendored. Nothing
+/// here corresponds to the upstream rv32mi tests breakpoint, ma_fetch, ma_addr, lh-misaligned,
+/// lw-misaligned, sh-misaligned, sw-misaligned, zicntr, pmpaddr and instret_overflow, nor to the
+/// rv32ui ld_st and st_ld.
+const SUITES: [&str; 5] = [
+    "ported-bins",
+    "isa-bins/m",
+    "isa-bins/a",
+    "isa-bins/c",
+    "isa-bins/machine",
+];
+
+/// Returns the tests of the suite in `dir`, sorted by name.
+fn suite_tests(dir: &str) -> Vec<PathBuf> {
+    let mut tests: Vec<PathBuf> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    tests.sort();
+    tests
+}
+
+fn load_test(path: &Path) -> (Elf, Vm) {
+    let elf = Elf::decode(&std::fs::read(path).unwrap()).unwrap();
+    let mut vm = Vm::from_bin_elf(String::from(path.to_str().unwrap())).unwrap();
+    // The tests run bare-metal on flat memory, rv32ui-p-fence_i executes code it wrote to .data
+    vm.memory.enforce_permissions = false;
+    (elf, vm)
+}
+
+/// Run a test to completion, the error tells which of its tests failed.
+fn run_test(elf: &Elf, mut vm: Vm, cache_blocks: bool) -> Result<(), String> {
+    let outcome = vm.run(RunConfig {
+        max_instructions: Some(1_000_000),
+        detect_infinite_loops: true,
+        // machine/sbreak takes the breakpoint exception in its trap handler
+        halt_on_ebreak: false,
+        cache_blocks,
+        ..RunConfig::default()
+    });
+
+    match vm.htif.as_ref().and_then(Htif::test_result) {
+        Some(Ok(())) => Ok(()),
+        // The tests label each of their test cases `test_<number>`
+        Some(Err(test)) => Err(match elf.symbol(&format!("test_{}", test)) {
+            Some(symbol) => format!("test {} at {:#x} failed", test, symbol.addr),
+            None => format!("test {} failed", test),
+        }),
+        None => Err(format!(
+            "{:?} without reporting to tohost, at pc {:#x}",
+            outcome, vm.pc
+        )),
+    }
+}
 
 #[test]
-fn test_load_elf_program() {
-    for entry in std::fs::read_dir("ported-bins").unwrap() {
-        let path = entry.unwrap().path();
-        println!("running test: {}", path.to_str().unwrap());
-        let mut vm = Vm::from_bin_elf(String::from(path.to_str().unwrap())).unwrap();
-        vm.run(false);
-        assert!(!vm.running);
-        assert_eq!(vm.exit_code, 0);
+fn test_riscv_tests() {
+    let mut failures = Vec::new();
+    for suite in SUITES {
+        let tests = suite_tests(suite);
+        assert!(!tests.is_empty(), "{suite} has no tests");
+        for path in &tests {
+            let (elf, vm) = load_test(path);
+            if let Err(failure) = run_test(&elf, vm, true) {
+                failures.push(format!("{}: {}", path.display(), failure));
+            }
+        }
     }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn test_fence_i() {
+    // The test patches its own code, with and without cached blocks
+    for cache_blocks in [true, false] {
+        let (elf, vm) = load_test(Path::new("ported-bins/rv32ui-p-fence_i"));
+        assert_eq!(
+            run_test(&elf, vm, cache_blocks),
+            Ok(()),
+            "cache_blocks: {cache_blocks}"
+        );
+    }
+}
+
+#[test]
+fn test_failing_test_is_reported() {
+    let (elf, mut vm) = load_test(Path::new("ported-bins/rv32ui-p-add"));
+    let test = elf.symbol("test_5").unwrap().addr;
+    let fail = elf.symbol("fail").unwrap().addr;
+
+    // Jump from test 5 to the failure handler, right after it loads the test number
+    let offset = fail.wrapping_sub(test + 4);
+    let jal = (((offset >> 20) & 1) << 31)
+        | (((offset >> 1) & 0x3ff) << 21)
+        | (((offset >> 11) & 1) << 20)
+        | (((offset >> 12) & 0xff) << 12)
+        | 0b1101111;
+    vm.memory
+        .write_mem(test + 4, MemoryChuckSize::WordSize, jal);
+
+    assert_eq!(
+        run_test(&elf, vm, true),
+        Err(format!("test 5 at {:#x} failed", test))
+    );
 }
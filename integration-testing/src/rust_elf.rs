@@ -1,13 +1,86 @@
-use emulator_sdk::vm::Vm;
+use core::{interfaces::MemoryInterface, MemoryChuckSize};
+use elf_parser::Elf;
+use emulator_sdk::{
+    linux::LinuxSyscallHandler,
+    vm::{RunConfig, RunOutcome, Vm},
+};
+
+/// Returns the outcomes of running the ELF at `path`, set up the way its guest expects. Every
+/// ebreak it stops on is stepped over, so the last outcome is the one it did not resume from.
+fn run(path: &str) -> Vec<RunOutcome> {
+    let elf = Elf::decode(&std::fs::read(path).unwrap()).unwrap();
+    let name = path.rsplit('/').next().unwrap();
+    let mut vm = match name {
+        "linux" => Vm::from_linux_elf(
+            &elf,
+            &["linux".to_string()],
+            &[],
+            LinuxSyscallHandler::default(),
+        ),
+        "fibonacci" => Vm::from_sp1_elf(&elf, []),
+        _ => Vm::from_elf(&elf),
+    };
+
+    let mut outcomes = vec![vm.run(RunConfig::default())];
+    while let Some(RunOutcome::Breakpoint(pc)) = outcomes.last() {
+        // Step over the ebreak, 2 bytes when compressed
+        let instruction = vm.memory.read_mem(*pc, MemoryChuckSize::HalfWord).unwrap();
+        vm.pc += if instruction & 0b11 == 0b11 { 4 } else { 2 };
+        outcomes.push(vm.run(RunConfig::default()));
+    }
+    outcomes
+}
 
 #[test]
 fn test_load_elf_program_rust() {
+    let symbols = Elf::decode(&std::fs::read("rust-elfs/symbols").unwrap()).unwrap();
+    let check = symbols
+        .symbols
+        .iter()
+        .find(|symbol| symbol.demangled() == "symbols::check")
+        .unwrap()
+        .addr;
+
     for entry in std::fs::read_dir("rust-elfs").unwrap() {
         let path = entry.unwrap().path();
-        println!("running test: {}", path.to_str().unwrap());
-        let mut vm = Vm::from_bin_elf(String::from(path.to_str().unwrap())).unwrap();
-        vm.run(true);
-        assert!(!vm.running);
-        assert_eq!(vm.exit_code, 0);
+        let path = path.to_str().unwrap();
+        let expected = match path.rsplit('/').next().unwrap() {
+            // The number of primes below 2^16
+            "bench" => vec![RunOutcome::Exited(6542)],
+            "fibonacci" | "linux" | "signature" => vec![RunOutcome::Exited(0)],
+            // It stops on the ebreak in `check`, then exits with fibonacci(10)
+            "symbols" => vec![RunOutcome::Breakpoint(check + 4), RunOutcome::Exited(55)],
+            name => panic!("no expected outcome for rust-elfs/{name}"),
+        };
+
+        assert_eq!(run(path), expected, "{path}");
     }
 }
+
+#[test]
+fn test_load_elf_data_segments() {
+    let mut vm = Vm::from_bin_elf(String::from("rust-elfs/fibonacci")).unwrap();
+
+    // .rodata starts with the path of the sp1 sources, "/hom" little-endian
+    assert_eq!(
+        vm.memory.read_mem(0x0020a110, MemoryChuckSize::WordSize),
+        Some(0x6d6f682f)
+    );
+    // .bss is zero filled and writable
+    assert_eq!(
+        vm.memory.read_mem(0x0020c750, MemoryChuckSize::WordSize),
+        Some(0)
+    );
+    assert!(vm
+        .memory
+        .write_mem(0x0020c750, MemoryChuckSize::WordSize, 0xdeadbeef));
+    // .rodata and .text are read-only
+    assert!(!vm
+        .memory
+        .write_mem(0x0020a110, MemoryChuckSize::WordSize, 0xdeadbeef));
+    assert!(!vm
+        .memory
+        .write_mem(0x00201204, MemoryChuckSize::WordSize, 0));
+    assert!(vm.memory.is_executable(0x00201204));
+    assert!(!vm.memory.is_executable(0x0020a110));
+}
@@ -0,0 +1,47 @@
+use emulator_sdk::{
+    segment::Segments,
+    vm::{RunConfig, RunOutcome, VMErrors, Vm},
+};
+
+const SEGMENT_CYCLES: u64 = 1 << 20;
+
+#[test]
+fn test_fibonacci_segments() {
+    let path = String::from("rust-elfs/fibonacci");
+    let mut expected = Vm::from_bin_elf(path.clone()).unwrap();
+    let expected_outcome = expected.run(RunConfig::default());
+
+    let segments = Segments::from_elf(path, SEGMENT_CYCLES)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let (last, rest) = segments.split_last().unwrap();
+    assert!(!rest.is_empty());
+    assert!(rest.iter().all(|segment| segment.cycles == SEGMENT_CYCLES));
+    assert_eq!(last.outcome, expected_outcome);
+    assert!(matches!(
+        last.outcome,
+        RunOutcome::Faulted {
+            error: VMErrors::InvalidSyscall(_),
+            ..
+        }
+    ));
+    assert_eq!(last.end, expected.snapshot().unwrap());
+
+    for pair in segments.windows(2) {
+        assert_eq!(pair[1].start_commitment, pair[0].end.commitment().unwrap());
+        // Every page a segment writes to is one it touched
+        for (number, page) in &pair[1].end.pages {
+            let before = pair[0].end.pages.iter().find(|(n, _)| n == number);
+            if before.map(|(_, data)| data) != Some(page) {
+                assert!(pair[1].touched_pages.contains(number));
+            }
+        }
+    }
+
+    // Resume from a boundary in the middle of the execution
+    let middle = &segments[segments.len() / 2];
+    let mut vm = Vm::from_snapshot(&middle.end).unwrap();
+    assert_eq!(vm.run(RunConfig::default()), expected_outcome);
+    assert_eq!(vm.snapshot().unwrap(), expected.snapshot().unwrap());
+}
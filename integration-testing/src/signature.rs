@@ -0,0 +1,24 @@
+use elf_parser::Elf;
+use emulator_sdk::{
+    signature::SignatureRange,
+    vm::{RunConfig, RunOutcome, Vm},
+};
+
+#[test]
+fn test_signature_dump() {
+    // `rust-elfs/signature`, built from `guests/signature.rs`
+    let elf = Elf::decode(&std::fs::read("rust-elfs/signature").unwrap()).unwrap();
+    let range = SignatureRange::from_elf(&elf).unwrap();
+    assert_eq!(range.end - range.begin, 24);
+
+    let mut vm = Vm::from_bin_elf(String::from("rust-elfs/signature")).unwrap();
+    assert_eq!(vm.run(RunConfig::default()), RunOutcome::Exited(0));
+    assert_eq!(
+        vm.dump_signature(range).unwrap(),
+        "00000000\n00000001\n00000001\n00000002\n00000003\ndeadbeef\n"
+    );
+
+    // Programs that are not architectural tests have no signature
+    let elf = Elf::decode(&std::fs::read("rust-elfs/symbols").unwrap()).unwrap();
+    assert_eq!(SignatureRange::from_elf(&elf), None);
+}
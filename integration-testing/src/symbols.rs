@@ -0,0 +1,95 @@
+use elf_parser::Elf;
+use emulator_sdk::{
+    disassembler::Disassembler,
+    vm::{RunConfig, RunOutcome, Vm},
+};
+
+/// `rust-elfs/symbols`, built from `guests/symbols.rs` with debug information
+fn elf() -> Elf {
+    Elf::decode(&std::fs::read("rust-elfs/symbols").unwrap()).unwrap()
+}
+
+#[test]
+fn test_sections_and_symbols() {
+    let elf = elf();
+
+    let text = elf.section(".text").unwrap();
+    assert!(text.is_alloc() && text.is_executable() && !text.is_writable());
+    assert_eq!(text.addr, elf.pc_base);
+    assert!(!elf.section(".debug_line").unwrap().is_alloc());
+
+    let start = elf.symbol("_start").unwrap();
+    assert!(start.is_function);
+    assert_eq!(start.addr, elf.pc_start);
+    let names: Vec<String> = elf
+        .symbols
+        .iter()
+        .map(|symbol| symbol.demangled())
+        .collect();
+    assert_eq!(
+        names,
+        ["symbols::math::fibonacci", "symbols::check", "_start"]
+    );
+    // fibonacci starts the text section, the symbols only know code
+    assert_eq!(elf.symbol_at(text.addr + 6).unwrap().1, 6);
+    assert_eq!(elf.symbol_at(text.addr - 1), None);
+}
+
+#[test]
+fn test_faults_are_symbolized() {
+    let elf = elf();
+    let mut vm = Vm::from_bin_elf(String::from("rust-elfs/symbols")).unwrap();
+
+    // fibonacci(10) is odd, check stops on its ebreak
+    let RunOutcome::Breakpoint(pc) = vm.run(RunConfig::default()) else {
+        panic!("the program must stop on the ebreak");
+    };
+    assert_eq!(
+        elf.symbolize(pc).unwrap(),
+        "symbols::check+0x4 (guests/symbols.rs:31)"
+    );
+    assert_eq!(elf.line_at(elf.pc_start), Some(("guests/symbols.rs", 36)));
+    let fibonacci = elf
+        .symbols
+        .iter()
+        .find(|symbol| symbol.demangled() == "symbols::math::fibonacci")
+        .unwrap();
+    assert_eq!(
+        elf.symbolize(fibonacci.addr).unwrap(),
+        "symbols::math::fibonacci (guests/symbols.rs:18)"
+    );
+
+    let disassembler = Disassembler::from_elf(&elf);
+    assert_eq!(
+        disassembler.symbolize(pc).as_deref(),
+        Some("symbols::check+0x4")
+    );
+}
+
+#[test]
+fn test_stripped_elf() {
+    let elf = Elf::decode(&std::fs::read("rust-elfs/fibonacci").unwrap()).unwrap();
+
+    assert!(elf.line_table.is_none());
+    assert!(elf.symbol("main").is_some());
+    assert_eq!(elf.symbolize(0), None);
+}
+
+#[test]
+fn test_broken_dwarf_is_ignored() {
+    let mut data = std::fs::read("rust-elfs/symbols").unwrap();
+    let debug_line = elf()
+        .sections
+        .into_iter()
+        .find(|section| section.name == ".debug_line")
+        .unwrap();
+    let start = debug_line.offset as usize;
+    data[start..start + debug_line.size as usize].fill(0xff);
+
+    let elf = Elf::decode(&data).unwrap();
+    assert!(elf.line_table.is_none());
+    assert!(elf
+        .symbols
+        .iter()
+        .any(|symbol| symbol.demangled() == "symbols::check"));
+}